@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Range, TextDocumentIdentifier, Url};
+
+// Claude's notes on the code, published as this server's diagnostics: findings of
+// claude-code.review, and the answers of prompt templates that explain code rather
+// than edit it, as hints on the range they explain. Both kinds are kept per file,
+// since publishing diagnostics replaces everything the server published for the
+// file before. Explanations also go to the editor side as a `claude/explanation`
+// notification, for an extension that renders them itself, and
+// claude-code.clear-annotations takes everything down again.
+
+pub const EXPLANATION_SOURCE: &str = "Claude explanation";
+
+/// Params of the custom `claude/explanation` notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplanationParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+    /// Claude's answer, as Markdown
+    pub explanation: String,
+}
+
+pub enum Explanation {}
+
+impl Notification for Explanation {
+    type Params = ExplanationParams;
+    const METHOD: &'static str = "claude/explanation";
+}
+
+/// Params of the custom `claude/clearAnnotations` notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearAnnotationsParams {
+    /// Files whose annotations were cleared
+    pub text_documents: Vec<TextDocumentIdentifier>,
+}
+
+pub enum ClearAnnotations {}
+
+impl Notification for ClearAnnotations {
+    type Params = ClearAnnotationsParams;
+    const METHOD: &'static str = "claude/clearAnnotations";
+}
+
+/// A hint showing `explanation` on `range`
+fn explanation_diagnostic(range: Range, explanation: &str) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::HINT),
+        source: Some(EXPLANATION_SOURCE.to_string()),
+        message: explanation.trim().to_string(),
+        ..Default::default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct FileAnnotations {
+    review: Vec<Diagnostic>,
+    explanations: Vec<Diagnostic>,
+    /// Document version the review was of
+    version: Option<i32>,
+}
+
+impl FileAnnotations {
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.review
+            .iter()
+            .chain(&self.explanations)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Diagnostics to publish for a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published {
+    pub uri: Url,
+    pub diagnostics: Vec<Diagnostic>,
+    pub version: Option<i32>,
+}
+
+/// Claude's annotations of each file
+#[derive(Debug, Default)]
+pub struct Annotations {
+    files: Mutex<HashMap<Url, FileAnnotations>>,
+}
+
+pub type SharedAnnotations = Arc<Annotations>;
+
+impl Annotations {
+    fn update(&self, uri: &Url, change: impl FnOnce(&mut FileAnnotations)) -> Published {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        let file = files.entry(uri.clone()).or_default();
+        change(file);
+        let published = Published {
+            uri: uri.clone(),
+            diagnostics: file.diagnostics(),
+            version: file.version,
+        };
+        if published.diagnostics.is_empty() {
+            files.remove(uri);
+        }
+        published
+    }
+
+    /// Replace the review of `uri` with `diagnostics`
+    pub fn set_review(
+        &self,
+        uri: &Url,
+        diagnostics: Vec<Diagnostic>,
+        version: Option<i32>,
+    ) -> Published {
+        self.update(uri, |file| {
+            file.review = diagnostics;
+            file.version = version;
+        })
+    }
+
+    /// Add an explanation of `range`, replacing an earlier one of the same range
+    pub fn explain(&self, uri: &Url, range: Range, explanation: &str) -> Published {
+        self.update(uri, |file| {
+            file.explanations
+                .retain(|diagnostic| diagnostic.range != range);
+            file.explanations
+                .push(explanation_diagnostic(range, explanation));
+        })
+    }
+
+    /// Drop everything, returning the files that had annotations
+    pub fn clear(&self) -> Vec<Url> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.drain().map(|(uri, _)| uri).collect()
+    }
+
+    /// Drop the annotations of `uri`, returning whether it had any
+    pub fn remove(&self, uri: &Url) -> bool {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.remove(uri).is_some()
+    }
+}
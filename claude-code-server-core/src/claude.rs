@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+
+// Requests to Claude go through the `claude` CLI in non-interactive print mode,
+// so they reuse whatever authentication and model the user configured for the CLI
+
+#[derive(Debug, Clone)]
+pub struct ClaudeClient {
+    binary: String,
+    model: Option<String>,
+    working_dir: Option<PathBuf>,
+}
+
+impl ClaudeClient {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            binary: config.claude_binary.clone(),
+            model: config.model.clone(),
+            working_dir: None,
+        }
+    }
+
+    /// Same client, running Claude from a different directory
+    pub fn with_working_dir(mut self, working_dir: Option<PathBuf>) -> Self {
+        self.working_dir = working_dir;
+        self
+    }
+
+    /// Send a single prompt to Claude and return its full text response. When
+    /// `partial` is given, text is streamed into it as the model produces it.
+    pub async fn ask(
+        &self,
+        prompt: &str,
+        partial: Option<mpsc::UnboundedSender<String>>,
+    ) -> Result<String> {
+        info!("Sending prompt to Claude ({} bytes)", prompt.len());
+
+        let mut command = Command::new(&self.binary);
+        command
+            .args([
+                "--print",
+                "--output-format",
+                "stream-json",
+                "--verbose",
+                "--include-partial-messages",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(model) = &self.model {
+            command.args(["--model", model]);
+        }
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start {}: {}", self.binary, e))?;
+
+        // Prompt goes through stdin so large selections don't hit argument limits
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(prompt.as_bytes()).await?;
+        }
+
+        // Drain stderr concurrently so a chatty CLI can't block on a full pipe
+        let stderr = child.stderr.take().map(|mut stderr| {
+            tokio::spawn(async move {
+                let mut buffer = String::new();
+                let _ = stderr.read_to_string(&mut buffer).await;
+                buffer
+            })
+        });
+
+        let mut streamed = String::new();
+        let mut result = None;
+        if let Some(stdout) = child.stdout.take() {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                let Ok(event) = serde_json::from_str::<Value>(&line) else {
+                    warn!("Ignoring malformed stream event from {}", self.binary);
+                    continue;
+                };
+                match parse_stream_event(&event) {
+                    StreamEvent::Text(text) => {
+                        streamed.push_str(&text);
+                        if let Some(partial) = &partial {
+                            let _ = partial.send(text);
+                        }
+                    }
+                    StreamEvent::Result(Ok(text)) => result = Some(text),
+                    StreamEvent::Result(Err(message)) => return Err(anyhow!(message)),
+                    StreamEvent::Other => {}
+                }
+            }
+        }
+
+        let status = child.wait().await?;
+        if !status.success() {
+            let stderr = match stderr {
+                Some(task) => task.await.unwrap_or_default(),
+                None => String::new(),
+            };
+            return Err(anyhow!(
+                "{} exited with {}: {}",
+                self.binary,
+                status,
+                stderr.trim()
+            ));
+        }
+
+        let response = result.unwrap_or(streamed).trim().to_string();
+        debug!("Claude responded with {} bytes", response.len());
+        Ok(response)
+    }
+}
+
+enum StreamEvent {
+    /// Incremental text from the model
+    Text(String),
+    /// Final outcome: the complete response, or the error the CLI reported
+    Result(std::result::Result<String, String>),
+    Other,
+}
+
+/// Interpret one line of `--output-format stream-json` output
+fn parse_stream_event(event: &Value) -> StreamEvent {
+    match event.get("type").and_then(Value::as_str) {
+        Some("stream_event") => {
+            let delta = &event["event"]["delta"];
+            match (delta["type"].as_str(), delta["text"].as_str()) {
+                (Some("text_delta"), Some(text)) => StreamEvent::Text(text.to_string()),
+                _ => StreamEvent::Other,
+            }
+        }
+        Some("result") => {
+            let text = event["result"].as_str().unwrap_or_default().to_string();
+            if event["is_error"].as_bool().unwrap_or(false) {
+                StreamEvent::Result(Err(format!("Claude reported an error: {}", text)))
+            } else {
+                StreamEvent::Result(Ok(text))
+            }
+        }
+        _ => StreamEvent::Other,
+    }
+}
+
+/// Extract the code from Claude's reply, taking the first fenced block if present
+pub fn extract_code(response: &str) -> String {
+    let mut lines = response.lines();
+    if lines
+        .by_ref()
+        .any(|line| line.trim_start().starts_with("```"))
+    {
+        let code: Vec<&str> = lines
+            .take_while(|line| !line.trim_start().starts_with("```"))
+            .collect();
+        return code.join("\n");
+    }
+
+    response.to_string()
+}
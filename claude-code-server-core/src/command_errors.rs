@@ -0,0 +1,67 @@
+use serde_json::Value;
+use std::fmt::Display;
+use std::path::Path;
+use tower_lsp::jsonrpc::{Error, ErrorCode};
+
+// Why a workspace/executeCommand request failed, answered as a JSON-RPC error
+// instead of a message the editor side can't act on. Each error's `data` has a
+// `reason` to match on, along with what the command was given and needed, or the
+// path that couldn't be read.
+
+/// A request to Claude through claude-code.request failed
+pub const REQUEST_FAILED: i64 = -32000;
+/// A file or log named by the arguments doesn't exist or can't be read
+pub const FILE_NOT_FOUND: i64 = -32001;
+/// Nothing connects this server to Claude
+pub const NOT_CONNECTED: i64 = -32002;
+
+/// `command`'s argument is missing or malformed; `expected` describes its shape,
+/// such as "{ uri, range }"
+pub fn invalid_arguments(command: &str, expected: &str, received: Option<&Value>) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message: format!("{} requires a {} argument", command, expected).into(),
+        data: Some(serde_json::json!({
+            "reason": "invalidArguments",
+            "command": command,
+            "expected": expected,
+            "received": received,
+        })),
+    }
+}
+
+/// No command is called `command`
+pub fn unknown_command(command: &str) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message: format!("Unknown command: {}", command).into(),
+        data: Some(serde_json::json!({
+            "reason": "unknownCommand",
+            "command": command,
+        })),
+    }
+}
+
+/// `path` couldn't be read
+pub fn file_not_found(path: &Path, error: impl Display) -> Error {
+    Error {
+        code: ErrorCode::ServerError(FILE_NOT_FOUND),
+        message: format!("Can't read {}: {}", path.display(), error).into(),
+        data: Some(serde_json::json!({
+            "reason": "fileNotFound",
+            "path": path,
+        })),
+    }
+}
+
+/// `command` needs a connection to Claude that this server doesn't have
+pub fn not_connected(command: &str) -> Error {
+    Error {
+        code: ErrorCode::ServerError(NOT_CONNECTED),
+        message: format!("{} needs a connection to Claude", command).into(),
+        data: Some(serde_json::json!({
+            "reason": "notConnected",
+            "command": command,
+        })),
+    }
+}
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::notification::Notification;
+
+use crate::prompts::PromptTemplate;
+
+// The workspace/executeCommand commands the server offers, described for the
+// editor: a title for the command palette, what the command does and a JSON
+// schema of its argument. `initialize` advertises the built-in commands along
+// with the prompt templates' commands, and claude-code.list-commands describes
+// all of them, so the extension can fill its palette without a list of its own.
+// When the prompt templates or settings files change, the whole list goes to the
+// editor side again in a `claude/commandsChanged` notification.
+
+pub const LIST_COMMANDS: &str = "claude-code.list-commands";
+
+/// A command and how to call it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandInfo {
+    pub command: String,
+    pub title: String,
+    pub description: String,
+    /// JSON schema of the command's argument, null for commands that take none
+    pub arguments: Value,
+    /// "builtin", or "template" for prompt templates
+    pub source: String,
+}
+
+/// Params of the custom `claude/commandsChanged` notification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandsChangedParams {
+    /// Every command, as claude-code.list-commands describes them
+    pub commands: Vec<CommandInfo>,
+}
+
+pub enum CommandsChanged {}
+
+impl Notification for CommandsChanged {
+    type Params = CommandsChangedParams;
+    const METHOD: &'static str = "claude/commandsChanged";
+}
+
+struct Builtin {
+    command: &'static str,
+    title: &'static str,
+    description: &'static str,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        command: "claude-code.review",
+        title: "Review with Claude",
+        description: "Ask Claude to review a file, or only its changes since HEAD, and show the findings as diagnostics",
+    },
+    Builtin {
+        command: "claude-code.at-mention",
+        title: "Mention in Claude",
+        description: "Send Claude lines of a file, every file a directory or glob matches, or a declaration",
+    },
+    Builtin {
+        command: "claude-code.at-mention-diff",
+        title: "Mention changes in Claude",
+        description: "Send Claude every file changed since the last commit",
+    },
+    Builtin {
+        command: "claude-code.show-logs",
+        title: "Show Claude Code logs",
+        description: "Open the latest log file of the server",
+    },
+    Builtin {
+        command: "claude-code.status",
+        title: "Claude Code status",
+        description: "Report the connection to Claude, notification counts and the MCP port",
+    },
+    Builtin {
+        command: "claude-code.request",
+        title: "Send a request to Claude",
+        description: "Send a JSON-RPC request to the connected Claude client and answer with its result",
+    },
+    Builtin {
+        command: "claude-code.undo-last-edit",
+        title: "Undo Claude's last edit",
+        description: "Revert the most recent edit applied for Claude",
+    },
+    Builtin {
+        command: "claude-code.resend-selection",
+        title: "Resend selection to Claude",
+        description: "Send Claude a selection from the history again",
+    },
+    Builtin {
+        command: "claude-code.trust-workspace",
+        title: "Trust workspace for Claude",
+        description: "Ask whether Claude may write to this workspace and run commands in it",
+    },
+    Builtin {
+        command: "claude-code.generate-commit-message",
+        title: "Generate commit message with Claude",
+        description: "Ask Claude for a message describing the staged changes",
+    },
+    Builtin {
+        command: "claude-code.clear-annotations",
+        title: "Clear Claude's annotations",
+        description: "Take down Claude's review findings and explanations",
+    },
+    Builtin {
+        command: LIST_COMMANDS,
+        title: "List Claude Code commands",
+        description: "Describe every command the server offers",
+    },
+];
+
+/// Whether the server handles `command` itself
+pub fn is_builtin(command: &str) -> bool {
+    BUILTINS.iter().any(|builtin| builtin.command == command)
+}
+
+/// Names of the built-in commands
+pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.iter().map(|builtin| builtin.command)
+}
+
+/// The built-in commands, described
+pub fn builtins() -> Vec<CommandInfo> {
+    BUILTINS
+        .iter()
+        .map(|builtin| CommandInfo {
+            command: builtin.command.to_string(),
+            title: builtin.title.to_string(),
+            description: builtin.description.to_string(),
+            arguments: arguments(builtin.command),
+            source: "builtin".to_string(),
+        })
+        .collect()
+}
+
+/// The command running `template`
+pub fn for_template(template: &PromptTemplate) -> CommandInfo {
+    CommandInfo {
+        command: template.command(),
+        title: template.title.clone(),
+        description: format!("Run the {} prompt on a range of code", template.name),
+        arguments: object(
+            json!({
+                "uri": { "type": "string" },
+                "range": { "type": "object" },
+            }),
+            &["uri", "range"],
+        ),
+        source: "template".to_string(),
+    }
+}
+
+fn arguments(command: &str) -> Value {
+    match command {
+        "claude-code.review" => object(
+            json!({
+                "uri": { "type": "string" },
+                "scope": { "enum": ["file", "diff"] },
+            }),
+            &["uri"],
+        ),
+        "claude-code.at-mention" => json!({
+            "oneOf": [
+                object(
+                    json!({
+                        "filePath": { "type": "string" },
+                        "lineStart": { "type": "integer", "minimum": 0 },
+                        "lineEnd": { "type": "integer", "minimum": 0 },
+                        "workspaceRoot": { "type": "string" },
+                    }),
+                    &["filePath"],
+                ),
+                object(json!({ "symbol": { "type": "string" } }), &["symbol"]),
+            ]
+        }),
+        "claude-code.at-mention-diff" => object(json!({ "uri": { "type": "string" } }), &[]),
+        "claude-code.show-logs" => json!({ "enum": ["lsp", "mcp", "commands"] }),
+        "claude-code.request" => object(
+            json!({
+                "method": { "type": "string" },
+                "params": {},
+                "timeoutMs": { "type": "integer", "minimum": 0 },
+            }),
+            &["method"],
+        ),
+        "claude-code.resend-selection" => object(
+            json!({
+                "filePath": { "type": "string" },
+                "index": { "type": "integer", "minimum": 0 },
+            }),
+            &[],
+        ),
+        "claude-code.generate-commit-message" => object(
+            json!({
+                "uri": { "type": "string" },
+                "write": { "type": "boolean" },
+            }),
+            &[],
+        ),
+        _ => Value::Null,
+    }
+}
+
+fn object(properties: Value, required: &[&str]) -> Value {
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
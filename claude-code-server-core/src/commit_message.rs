@@ -0,0 +1,89 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::claude::{self, ClaudeClient};
+use crate::git;
+
+// Commit messages written by Claude for the staged changes, behind the
+// claude-code.generate-commit-message command and the generateCommitMessage MCP
+// tool. Claude gets the staged diff and the latest commit subjects, so the message
+// follows the repository's own style where it has one, and Conventional Commits
+// otherwise. The message goes to the repository's COMMIT_EDITMSG, where
+// `git commit` (or the editor's git panel) picks it up.
+
+// Subjects of earlier commits shown to Claude as examples
+const RECENT_SUBJECTS: usize = 10;
+
+/// A generated message, and the file it was written to if it was
+#[derive(Debug, Clone)]
+pub struct CommitMessage {
+    pub message: String,
+    pub written_to: Option<PathBuf>,
+}
+
+/// Ask Claude for a message describing what is staged in the repository
+/// containing `dir`, writing it to COMMIT_EDITMSG when `write` is set
+pub async fn generate(claude: &ClaudeClient, dir: &Path, write: bool) -> Result<CommitMessage> {
+    let options = git::DiffOptions {
+        staged: true,
+        ..Default::default()
+    };
+    let diff = git::diff(dir, &options).await?;
+    if diff.diff.trim().is_empty() {
+        bail!("Nothing is staged in {}", diff.repository);
+    }
+    let subjects = git::recent_subjects(dir, RECENT_SUBJECTS).await?;
+
+    info!("Generating a commit message for {}", diff.repository);
+    let response = claude
+        .ask(&prompt(&diff.diff, diff.truncated, &subjects), None)
+        .await?;
+    let message = clean(&response);
+    if message.is_empty() {
+        bail!("Claude didn't reply with a commit message");
+    }
+
+    let written_to = if write {
+        let path = git::git_dir(dir).await?.join("COMMIT_EDITMSG");
+        std::fs::write(&path, format!("{}\n", message))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Some(path)
+    } else {
+        None
+    };
+    Ok(CommitMessage {
+        message,
+        written_to,
+    })
+}
+
+/// Prompt asking for a message for `diff`, in the style of `subjects`
+fn prompt(diff: &str, truncated: bool, subjects: &[String]) -> String {
+    let mut prompt = String::from(
+        "Write a commit message for the following staged changes. Use the Conventional \
+         Commits format (`type(scope): summary`, with types such as feat, fix, refactor, \
+         docs, test and chore), a summary line of at most 72 characters in the imperative \
+         mood, and, when the change needs explaining, a body after a blank line saying what \
+         changed and why. Reply with only the commit message.",
+    );
+    if !subjects.is_empty() {
+        prompt.push_str(
+            "\n\nThese are the repository's latest commit subjects; if they follow a \
+             different convention, follow theirs instead:\n\n",
+        );
+        for subject in subjects {
+            prompt.push_str(&format!("{}\n", subject));
+        }
+    }
+    prompt.push_str(&format!("\n\n```diff\n{}\n```", diff.trim_end()));
+    if truncated {
+        prompt.push_str("\n\nThe diff was too long and is cut short.");
+    }
+    prompt
+}
+
+/// The message in Claude's reply, without a code fence around it
+fn clean(response: &str) -> String {
+    claude::extract_code(response).trim().to_string()
+}
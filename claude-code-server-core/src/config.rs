@@ -0,0 +1,183 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::context::ContextBudget;
+use crate::hover::HoverSettings;
+use crate::imports::PromptContextSettings;
+use crate::metrics::MetricsSettings;
+use crate::notifications::NotificationSettings;
+use crate::privacy::PrivacySettings;
+use crate::shell_env::ShellEnvironmentSettings;
+
+// Server settings, read from the `claudeCode` section of the initialization options
+// and replaced whenever workspace/didChangeConfiguration delivers a new one, merged
+// with the settings files (see settings.rs). Unknown keys are ignored and missing
+// keys keep their defaults.
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// Quiet period before a selection change is forwarded to Claude
+    pub selection_debounce_ms: u64,
+    /// Quiet period before an at-mention is forwarded, merging repeated mentions of
+    /// the same range; 0 forwards it at once
+    pub at_mention_debounce_ms: u64,
+    /// Send the mentioned lines and the file's line count along with a mention of
+    /// one file
+    pub at_mention_text: bool,
+    /// Quiet period before subscribers to ide://diagnostics hear that it changed
+    pub diagnostics_debounce_ms: u64,
+    /// Quiet period after edits to an open document before subscribers to its
+    /// ide://open-files resource hear that it changed
+    pub document_change_debounce_ms: u64,
+    /// Whole lines above and below a selection sent along with it, apart from the
+    /// selected text; 0 sends none
+    pub selection_context_lines: u32,
+    /// Maximum number of workspace files offered as @-mention completions
+    pub mention_file_limit: usize,
+    /// Path or name of the Claude CLI
+    pub claude_binary: String,
+    /// Path or name of the Zed CLI, used when showDocument is unavailable
+    pub zed_binary: String,
+    /// Longest a zed CLI run may take before it is given up on
+    pub zed_cli_timeout_ms: u64,
+    /// Model passed to the Claude CLI; the CLI's default when unset
+    pub model: Option<String>,
+    /// Write runCommand's commands to .zed/tasks.json for the user to run
+    /// instead of running them
+    pub run_commands_as_tasks: bool,
+    /// MCP tools offered to Claude; every tool when unset
+    pub enabled_tools: Option<Vec<String>>,
+    /// Commands runCommand runs without asking: a command matches when it is one
+    /// of these or starts with one followed by a space
+    pub allowed_commands: Vec<String>,
+    /// Loading the user's shell environment for runCommand's commands
+    pub shell_environment: ShellEnvironmentSettings,
+    pub features: Features,
+    pub hover_explain: HoverSettings,
+    /// Size limit for selected text and mentioned files sent to Claude
+    pub context_budget: ContextBudget,
+    /// What prompt templates send along with the code
+    pub prompt_context: PromptContextSettings,
+    /// Coalescing and rate limiting of notifications sent to Claude
+    pub notifications: NotificationSettings,
+    /// Files whose contents are left out of selections sent to Claude
+    pub privacy: PrivacySettings,
+    /// GET /metrics and OTLP export of notification counts and timings
+    pub metrics: MetricsSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Features {
+    /// Code actions for prompt templates (explain/improve/fix and custom ones)
+    pub code_actions: bool,
+    /// Explain/improve/write tests lenses above functions
+    pub code_lens: bool,
+    /// @-mention completions for files and symbols
+    pub mentions: bool,
+    /// Send mentions typed into buffers ("@src/main.rs:10-20 ", "@claude fix
+    /// this" and Enter) to Claude
+    pub typed_mentions: bool,
+    /// Forward selection changes to Claude and serve them over MCP
+    pub selection_tracking: bool,
+    /// Collect diagnostics and serve them over MCP
+    pub diagnostics: bool,
+    /// Keep answers of templates that don't edit code on the code they explain, as
+    /// hints
+    pub explanations: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            selection_debounce_ms: 150,
+            at_mention_debounce_ms: 0,
+            at_mention_text: false,
+            diagnostics_debounce_ms: 500,
+            document_change_debounce_ms: 300,
+            selection_context_lines: 0,
+            mention_file_limit: 500,
+            claude_binary: "claude".to_string(),
+            zed_binary: "zed".to_string(),
+            zed_cli_timeout_ms: 10_000,
+            model: None,
+            run_commands_as_tasks: false,
+            enabled_tools: None,
+            allowed_commands: Vec::new(),
+            shell_environment: ShellEnvironmentSettings::default(),
+            features: Features::default(),
+            hover_explain: HoverSettings::default(),
+            context_budget: ContextBudget::default(),
+            prompt_context: PromptContextSettings::default(),
+            notifications: NotificationSettings::default(),
+            privacy: PrivacySettings::default(),
+            metrics: MetricsSettings::default(),
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            code_actions: true,
+            code_lens: true,
+            mentions: true,
+            typed_mentions: true,
+            selection_tracking: true,
+            diagnostics: true,
+            explanations: true,
+        }
+    }
+}
+
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+impl Config {
+    /// The `claudeCode` section of initialization options or settings, if present
+    pub fn section(settings: Option<&Value>) -> Option<&Value> {
+        settings?
+            .get("claudeCode")
+            .filter(|section| !section.is_null())
+    }
+
+    /// Parse the contents of a `claudeCode` section
+    pub fn from_section(section: Value) -> Option<Self> {
+        if section.is_null() {
+            return None;
+        }
+        match serde_json::from_value(section) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Ignoring invalid claudeCode settings: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Whether the MCP tool `name` is offered to Claude
+    pub fn tool_enabled(&self, name: &str) -> bool {
+        self.enabled_tools
+            .as_ref()
+            .is_none_or(|tools| tools.iter().any(|tool| tool == name))
+    }
+
+    /// Whether runCommand may run `command` without asking the user
+    pub fn command_allowed(&self, command: &str) -> bool {
+        let command = command.trim();
+        // Chained, piped or redirected commands always ask
+        if command.contains(['\n', ';', '&', '|', '`', '$', '<', '>']) {
+            return false;
+        }
+        self.allowed_commands.iter().any(|allowed| {
+            let allowed = allowed.trim();
+            !allowed.is_empty()
+                && command
+                    .strip_prefix(allowed)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+        })
+    }
+}
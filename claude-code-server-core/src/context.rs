@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::text::{LineIndex, PositionEncoding};
+
+// Keeps the context sent to Claude in selection_changed and at_mentioned
+// notifications within a size budget. Payloads over it are cut short and carry a
+// `truncated` annotation with the full size and what was left out, so Claude can
+// read the rest itself when it needs to. Selections in files over a size
+// threshold aren't sent at all: a `reference` says where they are, and Claude
+// pulls what it needs with the readFileRange tool. A selection can also bring the
+// lines around it, kept apart from the selected text, when they fit the budget
+// along with it. Whatever is sent is counted in estimated tokens (see
+// Metrics::context_sent), and a single payload over `warnTokens` is pointed out to
+// the user.
+
+const DEFAULT_MAX_BYTES: usize = 64 * 1024;
+const DEFAULT_LARGE_FILE_BYTES: u64 = 1024 * 1024;
+// Rough conversion used for token budgets, matching the hover estimate
+const BYTES_PER_TOKEN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ContextBudget {
+    /// Bytes of selected text (or mentioned file contents) sent at once
+    pub max_bytes: usize,
+    /// Optional limit in estimated tokens; the smaller of the two applies
+    pub max_tokens: Option<usize>,
+    /// Files bigger than this have their selections sent by reference
+    pub large_file_bytes: u64,
+    /// Warn the user when one notification or tool result sends Claude more
+    /// estimated tokens than this; no warning when unset
+    pub warn_tokens: Option<usize>,
+}
+
+impl Default for ContextBudget {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_tokens: None,
+            large_file_bytes: DEFAULT_LARGE_FILE_BYTES,
+            warn_tokens: None,
+        }
+    }
+}
+
+impl ContextBudget {
+    /// Effective limit in bytes
+    pub fn limit(&self) -> usize {
+        match self.max_tokens {
+            Some(tokens) => self.max_bytes.min(tokens.saturating_mul(BYTES_PER_TOKEN)),
+            None => self.max_bytes,
+        }
+    }
+
+    /// The warning for sending Claude `tokens` at once from `source` (a
+    /// notification method or a tool), when that is over `warn_tokens`
+    pub fn size_warning(&self, source: &str, tokens: usize) -> Option<String> {
+        let limit = self.warn_tokens?;
+        (tokens > limit).then(|| {
+            format!(
+                "{} sent Claude about {} tokens at once, over the {} set in contextBudget.warnTokens",
+                source, tokens, limit
+            )
+        })
+    }
+}
+
+/// Estimated tokens in `bytes` of text
+pub fn estimate_tokens(bytes: usize) -> usize {
+    bytes.div_ceil(BYTES_PER_TOKEN)
+}
+
+/// How a selection was shortened: its full size, the range whose text was sent
+/// and the range that was left out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextTruncation {
+    pub total_bytes: usize,
+    pub total_lines: usize,
+    pub included_range: Range,
+    pub omitted_range: Range,
+}
+
+/// Where a selection in a large file is, instead of its text. `hash` identifies
+/// the file's contents at the time, for readFileRange to tell whether it changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileReference {
+    pub file_path: String,
+    pub range: Range,
+    pub hash: String,
+    pub total_bytes: u64,
+}
+
+/// Whole lines around a selection, sent apart from its text so Claude can tell
+/// what was selected from what surrounds it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SurroundingLines {
+    /// Lines from `before_start_line` up to the selection's first line
+    pub before: String,
+    pub before_start_line: u32,
+    /// Lines from the one after the selection's last line through
+    /// `after_end_line`, if any
+    pub after: String,
+    pub after_end_line: u32,
+}
+
+/// How a directory or glob mention was shortened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileTruncation {
+    pub total_files: usize,
+    pub total_bytes: u64,
+    /// Files left out of `files`, in the same order
+    pub omitted_files: Vec<String>,
+}
+
+/// Cut `text`, the contents of `range`, down to the budget. The cut falls after
+/// the last whole line that fits, or mid-line (on a character boundary) when
+/// even the first line is too long.
+pub fn fit_text(
+    text: &str,
+    range: Range,
+    budget: &ContextBudget,
+    encoding: PositionEncoding,
+) -> (String, Option<TextTruncation>) {
+    let limit = budget.limit();
+    if text.len() <= limit {
+        return (text.to_string(), None);
+    }
+
+    let head = &text[..floor_char_boundary(text, limit)];
+    let cut = match head.rfind('\n') {
+        Some(newline) => newline + 1,
+        None => head.len(),
+    };
+
+    let index = LineIndex::new(text);
+    let relative = index.position(cut, encoding);
+    // Positions inside the text are relative to the start of the selection
+    let cut_position = if relative.line == 0 {
+        Position::new(range.start.line, range.start.character + relative.character)
+    } else {
+        Position::new(range.start.line + relative.line, relative.character)
+    };
+
+    let truncation = TextTruncation {
+        total_bytes: text.len(),
+        total_lines: text.lines().count(),
+        included_range: Range::new(range.start, cut_position),
+        omitted_range: Range::new(cut_position, range.end),
+    };
+    (text[..cut].to_string(), Some(truncation))
+}
+
+/// Up to `count` whole lines of `content` above and below `range`, each ending in
+/// a line break. A selection ending at the start of a line leaves that line out of
+/// it, so it counts as below.
+pub fn surrounding_lines(content: &str, range: Range, count: u32) -> Option<SurroundingLines> {
+    let index = LineIndex::new(content);
+    // A trailing line break doesn't start another line here
+    let line_count = content.lines().count() as u32;
+    let lines = |from: u32, to: u32| {
+        (from..to.min(line_count))
+            .filter_map(|line| index.line(line))
+            .map(|line| format!("{}\n", line))
+            .collect::<String>()
+    };
+
+    let before_start_line = range.start.line.saturating_sub(count);
+    let after_start_line = if range.end.character == 0 && range.end.line > range.start.line {
+        range.end.line
+    } else {
+        range.end.line + 1
+    };
+    let after_end_line = (after_start_line + count).min(line_count);
+    let surrounding = SurroundingLines {
+        before: lines(before_start_line, range.start.line),
+        before_start_line,
+        after: lines(after_start_line, after_end_line),
+        after_end_line: after_end_line.saturating_sub(1),
+    };
+    if surrounding.before.is_empty() && surrounding.after.is_empty() {
+        return None;
+    }
+    Some(surrounding)
+}
+
+/// Keep the leading files whose combined size fits the budget. The first file is
+/// always kept, so a mention never comes out empty.
+pub fn fit_files(
+    files: Vec<PathBuf>,
+    budget: &ContextBudget,
+) -> (Vec<PathBuf>, Option<FileTruncation>) {
+    let limit = budget.limit() as u64;
+    let sizes: Vec<u64> = files
+        .iter()
+        .map(|file| {
+            fs::metadata(file)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0)
+        })
+        .collect();
+    let total_bytes: u64 = sizes.iter().sum();
+    if total_bytes <= limit {
+        return (files, None);
+    }
+
+    let mut used = 0;
+    let kept = sizes
+        .iter()
+        .take_while(|&&size| {
+            used += size;
+            used <= limit
+        })
+        .count()
+        .max(1);
+
+    let mut files = files;
+    let omitted = files.split_off(kept);
+    let truncation = FileTruncation {
+        total_files: files.len() + omitted.len(),
+        total_bytes,
+        omitted_files: omitted
+            .iter()
+            .map(|file| file.to_string_lossy().to_string())
+            .collect(),
+    };
+    (files, Some(truncation))
+}
+
+/// Largest index at or below `index` that starts a character
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len()))
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0)
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Keyed trailing-edge debouncing. A value pushed for a key is held until that key
+// has been quiet for the window it was pushed with, replacing whatever was still
+// held for it, so a burst of selections or edits to one document comes out as its
+// last event while another key's burst runs on its own clock. Windows are given
+// per push, which lets each kind of event use its own setting and picks up
+// setting changes live. The owner polls `ready` next to its other work, typically
+// in a select! loop.
+
+#[derive(Debug)]
+pub struct Debouncer<K, T> {
+    pending: HashMap<K, Held<T>>,
+}
+
+#[derive(Debug)]
+struct Held<T> {
+    value: T,
+    due: Instant,
+}
+
+impl<K, T> Default for Debouncer<K, T> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, T> Debouncer<K, T> {
+    /// Hold `value` until `key` has been quiet for `window`; a zero window lets it
+    /// out on the next call to `ready`. Returns the value it replaced, if any.
+    pub fn push(&mut self, key: K, value: T, window: Duration) -> Option<T> {
+        let due = Instant::now() + window;
+        self.pending
+            .insert(key, Held { value, due })
+            .map(|held| held.value)
+    }
+
+    /// The next value whose key went quiet, waiting for one while anything is
+    /// held and forever otherwise. Nothing is taken until it resolves, so it can
+    /// be dropped (as a select! branch) without losing a value.
+    pub async fn ready(&mut self) -> (K, T) {
+        loop {
+            let Some((key, due)) = self
+                .pending
+                .iter()
+                .min_by_key(|(_, held)| held.due)
+                .map(|(key, held)| (key.clone(), held.due))
+            else {
+                return std::future::pending().await;
+            };
+            tokio::time::sleep_until(due).await;
+            if let Some(held) = self.pending.remove(&key) {
+                return (key, held.value);
+            }
+        }
+    }
+}
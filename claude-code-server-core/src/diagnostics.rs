@@ -0,0 +1,118 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{watch, RwLock};
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Range, Url};
+
+// Diagnostics cache shared between the LSP layer (which collects them) and the
+// MCP server (which exposes them to Claude through the getDiagnostics tool and
+// the ide://diagnostics resource)
+#[derive(Debug, Default)]
+pub struct DiagnosticsCache {
+    files: RwLock<HashMap<Url, Vec<Diagnostic>>>,
+    /// Signalled after every change
+    changed: watch::Sender<()>,
+}
+
+pub type SharedDiagnostics = Arc<DiagnosticsCache>;
+
+impl DiagnosticsCache {
+    /// Replace every cached diagnostic for a file (publishDiagnostics semantics)
+    pub async fn publish(&self, uri: Url, diagnostics: Vec<Diagnostic>) {
+        let mut files = self.files.write().await;
+        let previous = if diagnostics.is_empty() {
+            files.remove(&uri)
+        } else {
+            files.insert(uri, diagnostics.clone())
+        };
+        if previous.unwrap_or_default() != diagnostics {
+            self.changed.send_replace(());
+        }
+    }
+
+    /// Replace the cached diagnostics overlapping `range` with the ones the editor
+    /// reported for that range (used for codeAction context diagnostics, which only
+    /// cover the requested range rather than the whole file)
+    pub async fn merge_range(&self, uri: &Url, range: Range, diagnostics: Vec<Diagnostic>) {
+        let mut files = self.files.write().await;
+        let previous = files.get(uri).cloned().unwrap_or_default();
+        let entry = files.entry(uri.clone()).or_default();
+        entry.retain(|existing| !ranges_overlap(&existing.range, &range));
+        entry.extend(diagnostics);
+        entry.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+
+        if *entry != previous {
+            self.changed.send_replace(());
+        }
+        if entry.is_empty() {
+            files.remove(uri);
+        }
+    }
+
+    /// Receiver that sees every change made after this call
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    /// Snapshot of cached diagnostics, either for a single file or the whole workspace
+    pub async fn snapshot(&self, uri: Option<&Url>) -> Vec<(Url, Vec<Diagnostic>)> {
+        let files = self.files.read().await;
+        let mut result: Vec<(Url, Vec<Diagnostic>)> = match uri {
+            Some(uri) => vec![(uri.clone(), files.get(uri).cloned().unwrap_or_default())],
+            None => files
+                .iter()
+                .map(|(uri, diagnostics)| (uri.clone(), diagnostics.clone()))
+                .collect(),
+        };
+        result.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        result
+    }
+}
+
+fn ranges_overlap(a: &Range, b: &Range) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::ERROR) => "Error",
+        Some(DiagnosticSeverity::WARNING) => "Warning",
+        Some(DiagnosticSeverity::INFORMATION) => "Information",
+        Some(DiagnosticSeverity::HINT) => "Hint",
+        // Per the LSP spec a missing severity is interpreted by the client, treat as error
+        _ => "Error",
+    }
+}
+
+/// Convert a diagnostics snapshot into the JSON shape used by the getDiagnostics tool
+pub fn to_mcp_json(snapshot: &[(Url, Vec<Diagnostic>)]) -> Value {
+    let files: Vec<Value> = snapshot
+        .iter()
+        .map(|(uri, diagnostics)| {
+            let diagnostics: Vec<Value> = diagnostics
+                .iter()
+                .map(|d| {
+                    let code = d.code.as_ref().map(|code| match code {
+                        NumberOrString::Number(n) => n.to_string(),
+                        NumberOrString::String(s) => s.clone(),
+                    });
+
+                    serde_json::json!({
+                        "message": d.message,
+                        "severity": severity_name(d.severity),
+                        "range": d.range,
+                        "source": d.source,
+                        "code": code,
+                    })
+                })
+                .collect();
+
+            serde_json::json!({
+                "uri": uri,
+                "diagnostics": diagnostics
+            })
+        })
+        .collect();
+
+    Value::Array(files)
+}
@@ -0,0 +1,329 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::{Range, TextDocumentIdentifier, TextEdit};
+
+use crate::symbols::{self, is_identifier_char, SymbolMatch};
+use crate::text::{LineEnding, LineIndex, PositionEncoding};
+
+// Hunks between what the editor shows for a file and the contents Claude proposes
+// in openDiff, sent to the editor side as a `claude/diffPreview` notification so a
+// diff view can show small, targeted changes rather than a whole-file replacement.
+// Lines are compared with Myers' algorithm, and lines replaced one for one are
+// compared again by word, so a renamed variable shows as just that. A run of
+// changed lines spanning several declarations is split where a declaration of the
+// same name starts on both sides, giving one hunk per function.
+
+// Most edits the diff looks for before giving up and replacing everything between
+// the common start and end. Bounds the time and memory a diff of unrelated files
+// can take.
+const MAX_EDIT_DISTANCE: usize = 1000;
+
+/// Lines of the current contents replaced by Claude's
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hunk {
+    /// The lines replaced, as whole lines; empty for an insertion
+    pub range: Range,
+    pub new_text: String,
+    /// Innermost declaration the hunk falls in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    /// The same change as word-level edits, for hunks replacing as many lines as
+    /// they bring
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inline_changes: Vec<TextEdit>,
+}
+
+/// Params of the custom `claude/diffPreview` notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffPreviewParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Name Claude gave the diff tab, as later passed to close_tab
+    pub tab_name: String,
+    pub hunks: Vec<Hunk>,
+}
+
+pub enum DiffPreview {}
+
+impl Notification for DiffPreview {
+    type Params = DiffPreviewParams;
+    const METHOD: &'static str = "claude/diffPreview";
+}
+
+/// Hunks turning `old` into `new`. `new` takes on `old`'s line breaks first, so a
+/// proposal with different ones doesn't replace every line. Ranges are in
+/// `encoding`, and declarations are found as for `language_id`.
+pub fn hunks(old: &str, new: &str, language_id: &str, encoding: PositionEncoding) -> Vec<Hunk> {
+    let new = LineEnding::detect(old).apply(new);
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let old_symbols = symbols::extract_symbols(old, language_id);
+    let new_symbols = symbols::extract_symbols(&new, language_id);
+
+    let index = LineIndex::new(old);
+    let old_starts = line_starts(&old_lines);
+    let mut hunks = Vec::new();
+    for region in changed_regions(&old_lines, &new_lines) {
+        for region in split_at_declarations(region, &old_symbols, &new_symbols) {
+            let range = Range::new(
+                index.position(old_starts[region.old.start], encoding),
+                index.position(old_starts[region.old.end], encoding),
+            );
+            let inline_changes = if region.old.len() == region.new.len() {
+                region
+                    .old
+                    .clone()
+                    .zip(region.new.clone())
+                    .flat_map(|(old_line, new_line)| {
+                        inline_changes(
+                            old_lines[old_line],
+                            new_lines[new_line],
+                            old_starts[old_line],
+                            &index,
+                            encoding,
+                        )
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let symbol =
+                symbols::enclosing_symbols(&old_symbols, Range::new(range.start, range.start))
+                    .last()
+                    .map(|symbol| symbol.name.clone());
+            hunks.push(Hunk {
+                range,
+                new_text: new_lines[region.new].concat(),
+                symbol,
+                inline_changes,
+            });
+        }
+    }
+    hunks
+}
+
+/// Lines `old` of the current contents, replaced by lines `new` of Claude's
+#[derive(Debug, Clone, PartialEq)]
+struct Region {
+    old: std::ops::Range<usize>,
+    new: std::ops::Range<usize>,
+}
+
+/// Byte offset where each line starts, and the end of the text
+fn line_starts(lines: &[&str]) -> Vec<usize> {
+    let mut starts = vec![0];
+    for line in lines {
+        starts.push(starts.last().unwrap() + line.len());
+    }
+    starts
+}
+
+/// The runs of items that differ between `old` and `new`
+fn changed_regions<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Region> {
+    // Common ends are cheap to skip, and usually most of a file
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let matches = common_subsequence(old_middle, new_middle).unwrap_or_default();
+    let mut regions = Vec::new();
+    let (mut old_next, mut new_next) = (0, 0);
+    let end = (old_middle.len(), new_middle.len());
+    for (old_match, new_match) in matches.into_iter().chain([end]) {
+        if old_match > old_next || new_match > new_next {
+            regions.push(Region {
+                old: prefix + old_next..prefix + old_match,
+                new: prefix + new_next..prefix + new_match,
+            });
+        }
+        (old_next, new_next) = (old_match + 1, new_match + 1);
+    }
+    regions
+}
+
+/// Index pairs of a shortest edit script's matching items, in order (Myers' O(ND)
+/// algorithm). None when more than MAX_EDIT_DISTANCE edits are needed.
+fn common_subsequence<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<(usize, usize)>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (old.len() + new.len()).min(MAX_EDIT_DISTANCE) as isize;
+    // trace[d][k + d]: furthest x reached on diagonal k = x - y with d edits
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let previous_k = |trace: &[Vec<isize>], d: isize, k: isize| {
+        let at = |k: isize| trace[d as usize - 1][(k + d - 1) as usize];
+        if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        }
+    };
+
+    'search: for d in 0..=max {
+        let mut furthest = vec![0; 2 * d as usize + 1];
+        for k in (-d..=d).step_by(2) {
+            let mut x = if d == 0 {
+                0
+            } else {
+                let from = previous_k(&trace, d, k);
+                let x = trace[d as usize - 1][(from + d - 1) as usize];
+                // Down (an insertion) keeps x; right (a deletion) moves it on
+                if from == k + 1 {
+                    x
+                } else {
+                    x + 1
+                }
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[(k + d) as usize] = x;
+            if x >= n && y >= m {
+                trace.push(furthest);
+                break 'search;
+            }
+        }
+        trace.push(furthest);
+        if d == max {
+            return None;
+        }
+    }
+
+    // Walk back from the end, collecting the diagonal moves
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let k = x - y;
+        let (snake_x, snake_y, previous) = if d == 0 {
+            (0, 0, (0, 0))
+        } else {
+            let from = previous_k(&trace, d, k);
+            let from_x = trace[d as usize - 1][(from + d - 1) as usize];
+            let from_y = from_x - from;
+            if from == k + 1 {
+                (from_x, from_y + 1, (from_x, from_y))
+            } else {
+                (from_x + 1, from_y, (from_x, from_y))
+            }
+        };
+        while x > snake_x && y > snake_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        (x, y) = previous;
+    }
+    matches.reverse();
+    Some(matches)
+}
+
+/// `region` cut where a declaration starts on both sides, pairing declarations by
+/// name in order
+fn split_at_declarations(
+    region: Region,
+    old_symbols: &[SymbolMatch],
+    new_symbols: &[SymbolMatch],
+) -> Vec<Region> {
+    let starts_inside = |symbols: &[SymbolMatch], lines: &std::ops::Range<usize>| {
+        let mut starts: Vec<(usize, String)> = symbols
+            .iter()
+            .map(|symbol| (symbol.line as usize, symbol.name.clone()))
+            .filter(|(line, _)| *line > lines.start && *line < lines.end)
+            .collect();
+        starts.sort();
+        starts
+    };
+    let new_starts = starts_inside(new_symbols, &region.new);
+
+    let mut cuts = Vec::new();
+    let mut new_from = 0;
+    for (old_line, name) in starts_inside(old_symbols, &region.old) {
+        if let Some(found) = new_starts[new_from..].iter().position(|(_, n)| *n == name) {
+            cuts.push((old_line, new_starts[new_from + found].0));
+            new_from += found + 1;
+        }
+    }
+
+    let mut regions = Vec::new();
+    let (mut old_start, mut new_start) = (region.old.start, region.new.start);
+    for (old_cut, new_cut) in cuts.into_iter().chain([(region.old.end, region.new.end)]) {
+        regions.push(Region {
+            old: old_start..old_cut,
+            new: new_start..new_cut,
+        });
+        (old_start, new_start) = (old_cut, new_cut);
+    }
+    regions
+}
+
+/// Word-level edits turning `old_line`, which starts at byte `line_start`, into
+/// `new_line`. Identifiers, runs of whitespace and single other characters are
+/// compared as units.
+fn inline_changes(
+    old_line: &str,
+    new_line: &str,
+    line_start: usize,
+    index: &LineIndex,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    let old_tokens = tokens(old_line);
+    let new_tokens = tokens(new_line);
+    let old_text: Vec<&str> = old_tokens.iter().map(|(_, token)| *token).collect();
+    let new_text: Vec<&str> = new_tokens.iter().map(|(_, token)| *token).collect();
+    let offset = |tokens: &[(usize, &str)], at: usize, line: &str| {
+        tokens.get(at).map_or(line.len(), |(offset, _)| *offset)
+    };
+
+    changed_regions(&old_text, &new_text)
+        .into_iter()
+        .map(|region| {
+            let start = line_start + offset(&old_tokens, region.old.start, old_line);
+            let end = line_start + offset(&old_tokens, region.old.end, old_line);
+            TextEdit {
+                range: Range::new(
+                    index.position(start, encoding),
+                    index.position(end, encoding),
+                ),
+                new_text: new_text[region.new].concat(),
+            }
+        })
+        .collect()
+}
+
+/// `line` cut into identifiers, runs of whitespace and single other characters,
+/// each with its byte offset
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let class = |ch: char| {
+        if is_identifier_char(ch) {
+            1
+        } else if ch.is_whitespace() {
+            2
+        } else {
+            0
+        }
+    };
+    let mut tokens: Vec<(usize, &str)> = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((_, ch)) = chars.next() {
+        let end = chars.peek().map_or(line.len(), |(offset, _)| *offset);
+        let joins = class(ch) != 0
+            && chars
+                .peek()
+                .is_some_and(|(_, next)| class(*next) == class(ch));
+        if !joins {
+            tokens.push((start, &line[start..end]));
+            start = end;
+        }
+    }
+    tokens
+}
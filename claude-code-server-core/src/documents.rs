@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{broadcast, RwLock};
+use tower_lsp::lsp_types::{TextDocumentContentChangeEvent, Url};
+use tracing::warn;
+
+use crate::file_events;
+use crate::language;
+use crate::paths;
+use crate::text::{self, LineIndex, PositionEncoding};
+
+// Registry of documents the editor has open, fed by didOpen/didChange/didSave/didClose
+// and shared with the MCP server for the getOpenEditors tool
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenDocument {
+    pub uri: Url,
+    #[serde(rename = "languageId")]
+    pub language_id: String,
+    pub version: i32,
+    #[serde(rename = "isDirty")]
+    pub is_dirty: bool,
+    /// Current buffer contents, kept in sync with incremental didChange events
+    #[serde(skip)]
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    documents: HashMap<Url, OpenDocument>,
+    /// Most recently interacted-with document, reported as the active tab
+    active: Option<Url>,
+}
+
+// Edits announced to MCP connections that haven't caught up yet; a connection that
+// falls further behind misses some
+const EDITS_CAPACITY: usize = 64;
+
+#[derive(Debug)]
+pub struct OpenDocuments {
+    registry: RwLock<Registry>,
+    /// Position encoding negotiated with the client, for every range in these documents
+    encoding: OnceLock<PositionEncoding>,
+    /// URI of each document edited through didChange
+    edits: broadcast::Sender<Url>,
+}
+
+impl Default for OpenDocuments {
+    fn default() -> Self {
+        Self {
+            registry: RwLock::default(),
+            encoding: OnceLock::new(),
+            edits: broadcast::Sender::new(EDITS_CAPACITY),
+        }
+    }
+}
+
+pub type SharedDocuments = Arc<OpenDocuments>;
+
+impl OpenDocuments {
+    /// Record the encoding chosen at initialize; later calls are ignored
+    pub fn set_encoding(&self, encoding: PositionEncoding) {
+        let _ = self.encoding.set(encoding);
+    }
+
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding.get().copied().unwrap_or_default()
+    }
+
+    pub async fn open(&self, uri: Url, language_id: String, version: i32, text: String) {
+        let mut registry = self.registry.write().await;
+        registry.active = Some(uri.clone());
+        registry.documents.insert(
+            uri.clone(),
+            OpenDocument {
+                uri,
+                language_id,
+                version,
+                is_dirty: false,
+                text,
+            },
+        );
+    }
+
+    pub async fn changed(
+        &self,
+        uri: &Url,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) {
+        let encoding = self.encoding();
+        let mut registry = self.registry.write().await;
+        if let Some(document) = registry.documents.get_mut(uri) {
+            document.version = version;
+            document.is_dirty = true;
+            for change in changes {
+                apply_change(&mut document.text, change, encoding, uri);
+            }
+            let _ = self.edits.send(uri.clone());
+        }
+        registry.active = Some(uri.clone());
+    }
+
+    /// URIs of documents as they are edited
+    pub fn subscribe_edits(&self) -> broadcast::Receiver<Url> {
+        self.edits.subscribe()
+    }
+
+    pub async fn saved(&self, uri: &Url) {
+        let mut registry = self.registry.write().await;
+        if let Some(document) = registry.documents.get_mut(uri) {
+            document.is_dirty = false;
+        }
+    }
+
+    /// Remove a document, returning it if it was tracked
+    pub async fn close(&self, uri: &Url) -> Option<OpenDocument> {
+        let mut registry = self.registry.write().await;
+        if registry.active.as_ref() == Some(uri) {
+            registry.active = None;
+        }
+        registry.documents.remove(uri)
+    }
+
+    /// Follow a file or directory renamed on disk, re-keying the documents under
+    /// it. Returns the old and new URI of each document moved.
+    pub async fn rename(&self, from: &Path, to: &Path) -> Vec<(Url, Url)> {
+        let mut registry = self.registry.write().await;
+        let moved: Vec<(Url, Url)> = registry
+            .documents
+            .keys()
+            .filter_map(|uri| {
+                let path = uri.to_file_path().ok()?;
+                let new_path = file_events::moved_path(&path, from, to)?;
+                Some((uri.clone(), Url::from_file_path(new_path).ok()?))
+            })
+            .collect();
+        for (old_uri, new_uri) in &moved {
+            if let Some(mut document) = registry.documents.remove(old_uri) {
+                document.uri = new_uri.clone();
+                registry.documents.insert(new_uri.clone(), document);
+            }
+            if registry.active.as_ref() == Some(old_uri) {
+                registry.active = Some(new_uri.clone());
+            }
+        }
+        moved
+    }
+
+    /// Mark the documents of a file or directory deleted on disk as dirty: the
+    /// editor keeps their buffers, which no longer match anything saved
+    pub async fn deleted(&self, path: &Path) {
+        let mut registry = self.registry.write().await;
+        for document in registry.documents.values_mut() {
+            if document
+                .uri
+                .to_file_path()
+                .is_ok_and(|document_path| document_path.starts_with(path))
+            {
+                document.is_dirty = true;
+            }
+        }
+    }
+
+    pub async fn mark_active(&self, uri: &Url) {
+        let mut registry = self.registry.write().await;
+        if registry.documents.contains_key(uri) {
+            registry.active = Some(uri.clone());
+        }
+    }
+
+    /// Write the buffer text of dirty documents (all of them, or just `only`) to
+    /// disk and mark them saved. LSP has no request asking the client to save, so
+    /// this stands in for one; the editor picks the new contents up from disk.
+    pub async fn write_dirty(&self, only: Option<&Url>) -> Vec<(Url, Result<()>)> {
+        let mut registry = self.registry.write().await;
+        let mut results = Vec::new();
+        for document in registry.documents.values_mut() {
+            if !document.is_dirty || only.is_some_and(|uri| *uri != document.uri) {
+                continue;
+            }
+            let result = match document.uri.to_file_path() {
+                Ok(path) => text::write_file(&path, &document.text)
+                    .await
+                    .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e)),
+                Err(()) => Err(anyhow!("{} is not a file on disk", document.uri)),
+            };
+            if result.is_ok() {
+                document.is_dirty = false;
+            }
+            results.push((document.uri.clone(), result));
+        }
+        results
+    }
+
+    /// The document at `uri`, if it is open
+    pub async fn get(&self, uri: &Url) -> Option<OpenDocument> {
+        self.registry.read().await.documents.get(uri).cloned()
+    }
+
+    /// languageId of the document at `uri`: the editor's if it is open, otherwise
+    /// guessed from the file name
+    pub async fn language_id(&self, uri: &Url) -> String {
+        match self.registry.read().await.documents.get(uri) {
+            Some(document) => document.language_id.clone(),
+            None => language::from_path(&paths::file_path(uri)).to_string(),
+        }
+    }
+
+    /// Snapshot of open documents (sorted by URI) and the active one
+    pub async fn list(&self) -> (Vec<OpenDocument>, Option<Url>) {
+        let registry = self.registry.read().await;
+        let mut documents: Vec<OpenDocument> = registry.documents.values().cloned().collect();
+        documents.sort_by(|a, b| a.uri.as_str().cmp(b.uri.as_str()));
+        (documents, registry.active.clone())
+    }
+}
+
+fn apply_change(
+    text: &mut String,
+    change: TextDocumentContentChangeEvent,
+    encoding: PositionEncoding,
+    uri: &Url,
+) {
+    let Some(range) = change.range else {
+        *text = change.text;
+        return;
+    };
+    let index = LineIndex::new(text);
+    match (
+        index.offset(range.start, encoding),
+        index.offset(range.end, encoding),
+    ) {
+        (Some(start), Some(end)) if start <= end => text.replace_range(start..end, &change.text),
+        _ => warn!("Ignoring out of range change {:?} to {}", range, uri),
+    }
+}
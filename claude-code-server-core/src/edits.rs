@@ -0,0 +1,732 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{
+    CreateFile, CreateFileOptions, DeleteFile, DeleteFileOptions, DocumentChangeOperation,
+    DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier, Position, Range, RenameFile,
+    RenameFileOptions, ResourceOp, ResourceOperationKind, TextDocumentEdit, TextEdit, Url,
+    WorkspaceEdit,
+};
+use tower_lsp::Client;
+use tracing::{info, warn};
+
+use crate::documents::OpenDocuments;
+use crate::journal::FileSnapshot;
+use crate::paths;
+use crate::text::{self, LineEnding, LineIndex, PositionEncoding};
+use crate::workspace;
+
+// Edits proposed by Claude, either as a unified diff or as a list of range
+// replacements. They are checked against what the editor currently shows (the
+// open buffer, or the file on disk when it isn't open) and sent to the client as a
+// single workspace/applyEdit. If the client applies some files and then fails,
+// those files are restored. Files are created, deleted and renamed through
+// workspace/applyEdit resource operations too, so the editor keeps its buffers
+// and history in step; editors without them get the change on disk instead.
+
+/// One replacement in a file. The range is 0-based, like LSP ranges.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEdit {
+    pub file_path: String,
+    pub range: Range,
+    pub new_text: String,
+    /// Text expected in `range`. The edit is rejected if the buffer differs.
+    #[serde(default)]
+    pub old_text: Option<String>,
+}
+
+/// A file created, deleted or renamed for Claude
+#[derive(Debug, Clone)]
+pub enum FileOperation {
+    Create {
+        uri: Url,
+        contents: String,
+        overwrite: bool,
+    },
+    Delete {
+        uri: Url,
+    },
+    Rename {
+        from: Url,
+        to: Url,
+        overwrite: bool,
+    },
+}
+
+/// Validated edits to one file, with the contents they were computed against
+#[derive(Debug)]
+pub struct PreparedFile {
+    pub uri: Url,
+    /// Buffer version, or None when the file isn't open in the editor
+    version: Option<i32>,
+    original: String,
+    updated: String,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(Debug)]
+pub struct EditPlan {
+    pub files: Vec<PreparedFile>,
+    /// Encoding the ranges are expressed in
+    encoding: PositionEncoding,
+}
+
+impl PreparedFile {
+    /// The contents the edits were validated against
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    /// Whether the editor has the file open
+    pub fn is_open(&self) -> bool {
+        self.version.is_some()
+    }
+
+    /// The contents once the edits are applied
+    pub fn updated(&self) -> &str {
+        &self.updated
+    }
+}
+
+/// Group `edits` by file and validate them against the current contents
+pub async fn prepare(
+    edits: Vec<FileEdit>,
+    roots: &[PathBuf],
+    documents: &OpenDocuments,
+) -> Result<EditPlan> {
+    let mut by_file: BTreeMap<PathBuf, Vec<FileEdit>> = BTreeMap::new();
+    for edit in edits {
+        let path = workspace::resolve_path(roots, &edit.file_path);
+        by_file.entry(path).or_default().push(edit);
+    }
+    if by_file.is_empty() {
+        bail!("No edits given");
+    }
+
+    let encoding = documents.encoding();
+    let mut files = Vec::new();
+    for (path, edits) in by_file {
+        let uri = Url::from_file_path(&path)
+            .map_err(|()| anyhow!("Cannot build file URI for {}", path.display()))?;
+        let (original, version) = current_contents(&uri, documents).await?;
+        files.push(prepare_file(uri, version, original, edits, encoding)?);
+    }
+    Ok(EditPlan { files, encoding })
+}
+
+/// A plan replacing the whole of the file at `path` with `contents`, taking on
+/// the file's line breaks
+pub async fn replace(path: &Path, contents: &str, documents: &OpenDocuments) -> Result<EditPlan> {
+    let uri = Url::from_file_path(path)
+        .map_err(|()| anyhow!("Cannot build file URI for {}", path.display()))?;
+    let (original, version) = current_contents(&uri, documents).await?;
+    let encoding = documents.encoding();
+    let end = LineIndex::new(&original).position(original.len(), encoding);
+    let edit = FileEdit {
+        file_path: paths::path_string(&uri),
+        range: Range::new(Position::new(0, 0), end),
+        new_text: contents.to_string(),
+        old_text: None,
+    };
+    let file = prepare_file(uri, version, original, vec![edit], encoding)?;
+    Ok(EditPlan {
+        files: vec![file],
+        encoding,
+    })
+}
+
+/// A plan putting files back to their contents before an edit. Refused when any
+/// of them no longer shows what the edit left.
+pub async fn revert(files: &[FileSnapshot], documents: &OpenDocuments) -> Result<EditPlan> {
+    let encoding = documents.encoding();
+    let mut prepared = Vec::new();
+    for file in files {
+        let (current, version) = current_contents(&file.uri, documents).await?;
+        if current != file.after {
+            bail!(
+                "{} has changed since Claude edited it",
+                paths::file_path(&file.uri).display()
+            );
+        }
+        let end = LineIndex::new(&current).position(current.len(), encoding);
+        let edit = TextEdit {
+            range: Range::new(Position::new(0, 0), end),
+            new_text: file.before.clone(),
+        };
+        prepared.push(PreparedFile {
+            uri: file.uri.clone(),
+            version,
+            original: current,
+            updated: file.before.clone(),
+            edits: vec![edit],
+        });
+    }
+    Ok(EditPlan {
+        files: prepared,
+        encoding,
+    })
+}
+
+/// The open buffer and its version, or the file on disk when it isn't open
+async fn current_contents(uri: &Url, documents: &OpenDocuments) -> Result<(String, Option<i32>)> {
+    if let Some(document) = documents.get(uri).await {
+        return Ok((document.text, Some(document.version)));
+    }
+    let path = uri
+        .to_file_path()
+        .map_err(|()| anyhow!("{} is not a file", uri))?;
+    let text = text::read_file(&path).with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok((text, None))
+}
+
+fn prepare_file(
+    uri: Url,
+    version: Option<i32>,
+    original: String,
+    edits: Vec<FileEdit>,
+    encoding: PositionEncoding,
+) -> Result<PreparedFile> {
+    let path = paths::path_string(&uri);
+    let index = LineIndex::new(&original);
+    // New text takes on the file's line breaks, and old text matches whichever
+    let line_ending = LineEnding::detect(&original);
+
+    let mut resolved = Vec::new();
+    for edit in edits {
+        let line = edit.range.start.line + 1;
+        let start = index.offset(edit.range.start, encoding);
+        let end = index.offset(edit.range.end, encoding);
+        let (Some(start), Some(end)) = (start, end) else {
+            bail!("Edit at line {} is outside {}", line, path);
+        };
+        if start > end {
+            bail!("Edit at line {} of {} ends before it starts", line, path);
+        }
+        if edit.old_text.as_ref().is_some_and(|old_text| {
+            !text::same_ignoring_line_endings(&original[start..end], old_text)
+        }) {
+            bail!(
+                "{} doesn't match the editor's contents at line {}",
+                path,
+                line
+            );
+        }
+        resolved.push((start, end, edit));
+    }
+
+    resolved.sort_by_key(|(start, end, _)| (*start, *end));
+    if let Some(pair) = resolved.windows(2).find(|pair| pair[1].0 < pair[0].1) {
+        bail!(
+            "Overlapping edits in {} at line {}",
+            path,
+            pair[1].2.range.start.line + 1
+        );
+    }
+
+    for (_, _, edit) in &mut resolved {
+        edit.new_text = line_ending.apply(&edit.new_text);
+    }
+    let mut updated = original.clone();
+    for (start, end, edit) in resolved.iter().rev() {
+        updated.replace_range(*start..*end, &edit.new_text);
+    }
+
+    Ok(PreparedFile {
+        uri,
+        version,
+        original,
+        updated,
+        edits: resolved
+            .into_iter()
+            .map(|(_, _, edit)| TextEdit {
+                range: edit.range,
+                new_text: edit.new_text,
+            })
+            .collect(),
+    })
+}
+
+/// Turn a unified diff into line-range edits whose old text must match the buffer.
+/// Creating and deleting files isn't supported.
+pub fn parse_unified_diff(diff: &str) -> Result<Vec<FileEdit>> {
+    let mut edits = Vec::new();
+    let mut file_path = None;
+    let mut lines = diff.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(old) = line.strip_prefix("--- ") {
+            let new = lines
+                .next()
+                .and_then(|line| line.strip_prefix("+++ "))
+                .ok_or_else(|| anyhow!("Expected a +++ line after {:?}", line))?;
+            match (diff_path(old), diff_path(new)) {
+                (Some(_), Some(new)) => file_path = Some(new),
+                _ => bail!("Creating or deleting files isn't supported"),
+            }
+            continue;
+        }
+        let Some(header) = line.strip_prefix("@@ ") else {
+            // diff --git, index and other extended header lines
+            continue;
+        };
+        let file_path = file_path
+            .clone()
+            .ok_or_else(|| anyhow!("Hunk {:?} comes before any file header", line))?;
+        let (old_start, old_count, new_count) = parse_hunk_header(header)?;
+
+        let mut old_text = String::new();
+        let mut new_text = String::new();
+        let (mut old_seen, mut new_seen) = (0, 0);
+        let mut old_has_newline = true;
+        // Which sides the previous line belonged to, for "\ No newline at end of file"
+        let mut last = (false, false);
+        while old_seen < old_count
+            || new_seen < new_count
+            || lines.peek().is_some_and(|line| line.starts_with('\\'))
+        {
+            let line = lines
+                .next()
+                .ok_or_else(|| anyhow!("Hunk {:?} in {} ends early", header, file_path))?;
+            let content = line.get(1..).unwrap_or_default();
+            let sides = match line.chars().next() {
+                // Some tools strip the trailing space from empty context lines
+                Some(' ') | None => (true, true),
+                Some('-') => (true, false),
+                Some('+') => (false, true),
+                Some('\\') => {
+                    if last.0 {
+                        old_text.pop();
+                        old_has_newline = false;
+                    }
+                    if last.1 {
+                        new_text.pop();
+                    }
+                    continue;
+                }
+                _ => bail!("Unexpected line {:?} in hunk {:?}", line, header),
+            };
+            if sides.0 {
+                old_text.push_str(content);
+                old_text.push('\n');
+                old_seen += 1;
+            }
+            if sides.1 {
+                new_text.push_str(content);
+                new_text.push('\n');
+                new_seen += 1;
+            }
+            last = sides;
+        }
+        // A context line counts on both sides, so it can overrun one of them
+        if (old_seen, new_seen) != (old_count, new_count) {
+            bail!(
+                "Hunk {:?} in {} has {} old and {} new lines",
+                header,
+                file_path,
+                old_seen,
+                new_seen
+            );
+        }
+
+        // An empty old side inserts after line `old_start`; otherwise the hunk
+        // replaces lines starting at `old_start` (both 1-based)
+        let invalid = || anyhow!("Hunk {:?} in {} is out of range", header, file_path);
+        let start_line = if old_count == 0 {
+            old_start
+        } else {
+            old_start.checked_sub(1).ok_or_else(invalid)?
+        };
+        let end_line = start_line.checked_add(old_count).ok_or_else(invalid)?;
+        let end = if old_has_newline {
+            Position::new(end_line, 0)
+        } else {
+            // The old text runs to the end of a file without a final newline
+            Position::new(end_line.checked_sub(1).ok_or_else(invalid)?, u32::MAX)
+        };
+        edits.push(FileEdit {
+            file_path,
+            range: Range::new(Position::new(start_line, 0), end),
+            new_text,
+            old_text: Some(old_text),
+        });
+    }
+
+    if edits.is_empty() {
+        bail!("The diff contains no hunks");
+    }
+    Ok(edits)
+}
+
+/// Path from a ---/+++ line: None for /dev/null, without the a/ or b/ prefix
+fn diff_path(header: &str) -> Option<String> {
+    // Some tools append a tab and a timestamp
+    let path = header.split('\t').next().unwrap_or_default().trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// Parse "-l,s +l,s @@" into the old start line and both line counts
+fn parse_hunk_header(header: &str) -> Result<(u32, u32, u32)> {
+    let invalid = || anyhow!("Invalid hunk header {:?}", header);
+    let mut ranges = header.split_whitespace();
+    let old = ranges.next().and_then(|range| range.strip_prefix('-'));
+    let new = ranges.next().and_then(|range| range.strip_prefix('+'));
+    let (Some(old), Some(new)) = (old, new) else {
+        return Err(invalid());
+    };
+
+    let parse = |range: &str| -> Option<(u32, u32)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = parse(old).ok_or_else(invalid)?;
+    let (_, new_count) = parse(new).ok_or_else(invalid)?;
+    if old_start == 0 && old_count > 0 {
+        return Err(invalid());
+    }
+    Ok((old_start, old_count, new_count))
+}
+
+impl EditPlan {
+    /// Each file's contents before and after the plan, for the undo journal
+    pub fn snapshots(&self) -> Vec<FileSnapshot> {
+        self.files
+            .iter()
+            .map(|file| FileSnapshot {
+                uri: file.uri.clone(),
+                before: file.original.clone(),
+                after: file.updated.clone(),
+            })
+            .collect()
+    }
+
+    /// The whole plan as one edit. With `document_changes`, each file carries the
+    /// buffer version it was validated against, so the client rejects stale edits.
+    fn workspace_edit(&self, document_changes: bool) -> WorkspaceEdit {
+        if document_changes {
+            let edits = self
+                .files
+                .iter()
+                .map(|file| TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: file.uri.clone(),
+                        version: file.version,
+                    },
+                    edits: file.edits.iter().cloned().map(OneOf::Left).collect(),
+                })
+                .collect();
+            WorkspaceEdit {
+                document_changes: Some(DocumentChanges::Edits(edits)),
+                ..Default::default()
+            }
+        } else {
+            let changes: HashMap<Url, Vec<TextEdit>> = self
+                .files
+                .iter()
+                .map(|file| (file.uri.clone(), file.edits.clone()))
+                .collect();
+            WorkspaceEdit {
+                changes: Some(changes),
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Fail when a file no longer has the contents the plan was made against
+    pub async fn check_current(&self, documents: &OpenDocuments) -> Result<()> {
+        for file in &self.files {
+            let (current, _) = current_contents(&file.uri, documents).await?;
+            if current != file.original {
+                bail!(
+                    "{} has changed since the edit was planned",
+                    paths::path_string(&file.uri)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Write each file's edited contents straight to disk, for files the editor
+    /// doesn't have open
+    pub async fn write_to_disk(&self) -> Result<()> {
+        for file in &self.files {
+            let path = paths::file_path(&file.uri);
+            text::write_file(&path, &file.updated)
+                .await
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        info!("Wrote edits to {} files on disk", self.files.len());
+        Ok(())
+    }
+
+    /// Restore the first `applied` files to their original contents
+    fn rollback_edit(&self, applied: usize) -> WorkspaceEdit {
+        let changes = self
+            .files
+            .iter()
+            .take(applied)
+            .map(|file| {
+                let end = LineIndex::new(&file.updated).position(file.updated.len(), self.encoding);
+                let edit = TextEdit {
+                    range: Range::new(Position::new(0, 0), end),
+                    new_text: file.original.clone(),
+                };
+                (file.uri.clone(), vec![edit])
+            })
+            .collect();
+        WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }
+    }
+}
+
+/// Send the plan to the client as a single workspace/applyEdit. When the client
+/// reports that it failed part way through, the files it had already changed are
+/// put back.
+pub async fn apply(client: &Client, plan: &EditPlan, document_changes: bool) -> Result<()> {
+    let response = client
+        .apply_edit(plan.workspace_edit(document_changes))
+        .await
+        .map_err(|e| anyhow!("workspace/applyEdit failed: {}", e))?;
+    if response.applied {
+        info!("Applied edits to {} files", plan.files.len());
+        return Ok(());
+    }
+
+    let reason = response
+        .failure_reason
+        .unwrap_or_else(|| "no reason given".to_string());
+    // Only documentChanges are ordered, so only then does the index say what was applied
+    let applied = match response.failed_change {
+        Some(failed) if document_changes => failed as usize,
+        _ => 0,
+    };
+    if applied > 0 {
+        warn!("Edit failed after {} files, rolling them back", applied);
+        match client.apply_edit(plan.rollback_edit(applied)).await {
+            Ok(rollback) if rollback.applied => {}
+            Ok(_) | Err(_) => warn!("Rolling back partially applied edits failed"),
+        }
+    }
+    bail!("The editor didn't apply the edits: {}", reason)
+}
+
+impl FileOperation {
+    pub fn kind(&self) -> ResourceOperationKind {
+        match self {
+            FileOperation::Create { .. } => ResourceOperationKind::Create,
+            FileOperation::Delete { .. } => ResourceOperationKind::Delete,
+            FileOperation::Rename { .. } => ResourceOperationKind::Rename,
+        }
+    }
+
+    /// The operation as resource operations, followed by the created file's text
+    fn workspace_edit(&self) -> WorkspaceEdit {
+        let mut operations = Vec::new();
+        match self {
+            FileOperation::Create {
+                uri,
+                contents,
+                overwrite,
+            } => {
+                operations.push(DocumentChangeOperation::Op(ResourceOp::Create(
+                    CreateFile {
+                        uri: uri.clone(),
+                        options: Some(CreateFileOptions {
+                            overwrite: Some(*overwrite),
+                            ignore_if_exists: Some(false),
+                        }),
+                        annotation_id: None,
+                    },
+                )));
+                if !contents.is_empty() {
+                    operations.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri: uri.clone(),
+                            version: None,
+                        },
+                        edits: vec![OneOf::Left(TextEdit {
+                            range: Range::default(),
+                            new_text: contents.clone(),
+                        })],
+                    }));
+                }
+            }
+            FileOperation::Delete { uri } => {
+                operations.push(DocumentChangeOperation::Op(ResourceOp::Delete(
+                    DeleteFile {
+                        uri: uri.clone(),
+                        options: Some(DeleteFileOptions {
+                            recursive: Some(false),
+                            ignore_if_not_exists: Some(false),
+                            annotation_id: None,
+                        }),
+                    },
+                )));
+            }
+            FileOperation::Rename {
+                from,
+                to,
+                overwrite,
+            } => {
+                operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(
+                    RenameFile {
+                        old_uri: from.clone(),
+                        new_uri: to.clone(),
+                        options: Some(RenameFileOptions {
+                            overwrite: Some(*overwrite),
+                            ignore_if_exists: Some(false),
+                        }),
+                        annotation_id: None,
+                    },
+                )));
+            }
+        }
+        WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(operations)),
+            ..Default::default()
+        }
+    }
+
+    /// Carry the operation out on disk, for editors that can't
+    pub async fn apply_to_disk(&self) -> Result<()> {
+        match self {
+            FileOperation::Create {
+                uri,
+                contents,
+                overwrite,
+            } => {
+                let path = paths::file_path(uri);
+                if !overwrite && path.exists() {
+                    bail!("{} already exists", path.display());
+                }
+                if let Some(dir) = path.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                tokio::fs::write(&path, contents)
+                    .await
+                    .with_context(|| format!("Failed to create {}", path.display()))
+            }
+            FileOperation::Delete { uri } => {
+                let path = paths::file_path(uri);
+                tokio::fs::remove_file(&path)
+                    .await
+                    .with_context(|| format!("Failed to delete {}", path.display()))
+            }
+            FileOperation::Rename {
+                from,
+                to,
+                overwrite,
+            } => {
+                let (from, to) = (paths::file_path(from), paths::file_path(to));
+                if !overwrite && to.exists() {
+                    bail!("{} already exists", to.display());
+                }
+                if let Some(dir) = to.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                tokio::fs::rename(&from, &to).await.with_context(|| {
+                    format!("Failed to rename {} to {}", from.display(), to.display())
+                })
+            }
+        }
+    }
+}
+
+/// Have the client carry out `operation` with workspace/applyEdit when it
+/// supports that kind of resource operation, or do it on disk otherwise
+pub async fn apply_operation(
+    client: &Client,
+    operation: &FileOperation,
+    resource_operations: bool,
+) -> Result<()> {
+    if !resource_operations {
+        return operation.apply_to_disk().await;
+    }
+    let response = client
+        .apply_edit(operation.workspace_edit())
+        .await
+        .map_err(|e| anyhow!("workspace/applyEdit failed: {}", e))?;
+    if !response.applied {
+        let reason = response
+            .failure_reason
+            .unwrap_or_else(|| "no reason given".to_string());
+        bail!("The editor didn't apply the change: {}", reason);
+    }
+    info!("Applied {:?} through the editor", operation.kind());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hunks_become_edits_of_the_old_lines() {
+        let diff = "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -2,2 +2,2 @@\n fn a() {}\n-fn b() {}\n+fn c() {}\n";
+        let edits = parse_unified_diff(diff).unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].file_path, "src/lib.rs");
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(1, 0), Position::new(3, 0))
+        );
+        assert_eq!(edits[0].old_text.as_deref(), Some("fn a() {}\nfn b() {}\n"));
+        assert_eq!(edits[0].new_text, "fn a() {}\nfn c() {}\n");
+    }
+
+    #[test]
+    fn insertions_go_after_the_old_start_line() {
+        let diff = "--- a/notes.md\n+++ b/notes.md\n@@ -0,0 +1 @@\n+first\n";
+        let edits = parse_unified_diff(diff).unwrap();
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(0, 0), Position::new(0, 0))
+        );
+        assert_eq!(edits[0].old_text.as_deref(), Some(""));
+        assert_eq!(edits[0].new_text, "first\n");
+    }
+
+    #[test]
+    fn a_missing_final_newline_ends_the_range_at_the_end_of_the_file() {
+        let diff = "--- a/notes.md\n+++ b/notes.md\n@@ -1 +1 @@\n-one\n\\ No newline at end of file\n+two\n";
+        let edits = parse_unified_diff(diff).unwrap();
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(0, 0), Position::new(0, u32::MAX))
+        );
+        assert_eq!(edits[0].old_text.as_deref(), Some("one"));
+        assert_eq!(edits[0].new_text, "two\n");
+    }
+
+    #[test]
+    fn hunks_that_disagree_with_their_header_are_refused() {
+        for hunk in [
+            // A context line where the header promises no old lines
+            "@@ -0,0 +1 @@\n x\n\\ No newline at end of file\n",
+            "@@ -1 +1,2 @@\n x\n y\n",
+            "@@ -1,2 +1 @@\n-x\n",
+            "@@ -0,1 +1 @@\n-x\n+y\n",
+        ] {
+            let diff = format!("--- a/f\n+++ b/f\n{}", hunk);
+            assert!(parse_unified_diff(&diff).is_err(), "{:?}", hunk);
+        }
+    }
+
+    #[test]
+    fn new_and_deleted_files_are_refused() {
+        let diff = "--- /dev/null\n+++ b/new.rs\n@@ -0,0 +1 @@\n+x\n";
+        assert!(parse_unified_diff(diff).is_err());
+    }
+}
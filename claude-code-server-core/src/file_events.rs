@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tower_lsp::lsp_types::{FileChangeType, FileEvent};
+
+// Files renamed, deleted or changed on disk, from workspace/didChangeWatchedFiles
+// and the workspace/didRenameFiles and didDeleteFiles file operations. The
+// watcher only knows creations and deletions, so renames are recognised by
+// pairing the two within one batch.
+
+// Editors supporting file operations report a rename or deletion, then their file
+// watcher reports it again; a removal seen within this long is only handled once
+const REPEAT_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    /// A file or directory moved
+    Renamed { from: PathBuf, to: PathBuf },
+    /// A file or directory removed
+    Deleted(PathBuf),
+    /// A file or directory created or modified
+    Changed(PathBuf),
+}
+
+/// Turn a batch of watcher events into changes. A deletion and a creation of the
+/// same file name (a move) or in the same directory (a rename in place) make a
+/// rename; when several creations qualify, the same file name wins.
+pub fn from_watched(events: Vec<FileEvent>) -> Vec<FileChange> {
+    let mut deleted = Vec::new();
+    let mut created = Vec::new();
+    let mut changes = Vec::new();
+    for event in events {
+        let Ok(path) = event.uri.to_file_path() else {
+            continue;
+        };
+        match event.typ {
+            FileChangeType::DELETED => deleted.push(path),
+            FileChangeType::CREATED => created.push(path),
+            _ => changes.push(FileChange::Changed(path)),
+        }
+    }
+
+    for from in deleted {
+        let same_name = created
+            .iter()
+            .position(|to| to.file_name() == from.file_name());
+        let same_dir = || created.iter().position(|to| to.parent() == from.parent());
+        match same_name.or_else(same_dir) {
+            Some(index) => changes.push(FileChange::Renamed {
+                from,
+                to: created.remove(index),
+            }),
+            None => changes.push(FileChange::Deleted(from)),
+        }
+    }
+    changes.extend(created.into_iter().map(FileChange::Changed));
+    changes
+}
+
+/// Paths recently reported as renamed away or deleted
+#[derive(Debug, Default)]
+pub struct RecentRemovals {
+    paths: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl RecentRemovals {
+    /// Whether the removal of `path` hasn't been handled within REPEAT_WINDOW,
+    /// remembering it
+    pub fn is_new(&self, path: &Path) -> bool {
+        let mut paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        paths.retain(|_, seen| seen.elapsed() < REPEAT_WINDOW);
+        paths.insert(path.to_path_buf(), Instant::now()).is_none()
+    }
+}
+
+/// `path` with its `from` prefix replaced by `to`, if it is `from` or inside it
+pub fn moved_path(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(from).ok()?;
+    if relative.as_os_str().is_empty() {
+        Some(to.to_path_buf())
+    } else {
+        Some(to.join(relative))
+    }
+}
@@ -0,0 +1,116 @@
+use ignore::WalkBuilder;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::workspace;
+
+// The project's layout for the listWorkspaceFiles MCP tool, so Claude can find its
+// way around a new project in one call. Directories are walked in name order down
+// to a depth limit, respecting .gitignore and skipping hidden files unless asked
+// not to. Directories left unexpanded at the depth limit are flagged, so Claude
+// knows where to look further.
+
+pub const DEFAULT_MAX_DEPTH: usize = 3;
+pub const DEFAULT_MAX_ENTRIES: usize = 500;
+// Most entries returned by one call, whatever is asked for
+pub const MAX_ENTRIES: usize = 5000;
+
+#[derive(Debug, Clone)]
+pub struct TreeOptions {
+    /// Levels below the listed directory; 1 lists only its own entries
+    pub max_depth: usize,
+    pub max_entries: usize,
+    /// Leave out what .gitignore and .ignore files exclude
+    pub respect_gitignore: bool,
+    /// List dotfiles and dot-directories (never .git itself)
+    pub include_hidden: bool,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            respect_gitignore: true,
+            include_hidden: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeEntry {
+    /// Relative to the listed directory, with forward slashes
+    pub path: String,
+    /// "file" or "directory"
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Bytes, for files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// A directory at the depth limit that has entries of its own
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unexpanded: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tree {
+    pub root: String,
+    pub entries: Vec<TreeEntry>,
+    /// Whether entries were left out to stay within max_entries
+    pub truncated: bool,
+}
+
+/// Files and directories under `root`, in name order with each directory
+/// followed by its contents
+pub fn list(root: &Path, options: &TreeOptions) -> Tree {
+    let mut walk = WalkBuilder::new(root);
+    walk.max_depth(Some(options.max_depth))
+        .hidden(!options.include_hidden)
+        .git_ignore(options.respect_gitignore)
+        .git_exclude(options.respect_gitignore)
+        .git_global(options.respect_gitignore)
+        .ignore(options.respect_gitignore)
+        .parents(options.respect_gitignore)
+        // Not only inside git repositories
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .sort_by_file_name(|a, b| a.cmp(b));
+
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    for entry in walk.build().filter_map(|entry| entry.ok()) {
+        // The root itself
+        if entry.depth() == 0 {
+            continue;
+        }
+        if entries.len() == options.max_entries {
+            truncated = true;
+            break;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        entries.push(TreeEntry {
+            path: workspace::display_relative(relative),
+            kind: if is_dir { "directory" } else { "file" },
+            size: if is_dir {
+                None
+            } else {
+                entry.metadata().ok().map(|metadata| metadata.len())
+            },
+            unexpanded: is_dir
+                && entry.depth() == options.max_depth
+                && fs::read_dir(entry.path()).is_ok_and(|mut dir| dir.next().is_some()),
+        });
+    }
+
+    Tree {
+        root: root.to_string_lossy().to_string(),
+        entries,
+        truncated,
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::debug;
+
+// Read-only git queries behind the getGitStatus, getGitDiff and getCurrentBranch
+// MCP tools, commit message generation and claude-code.at-mention-diff. They run
+// the git CLI directly (no shell) in a workspace directory.
+
+const GIT_TIMEOUT: Duration = Duration::from_secs(30);
+// Diff text returned at once; anything beyond is dropped and flagged as truncated
+const MAX_DIFF_BYTES: usize = 512 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStatus {
+    pub file_path: String,
+    /// changed, renamed, copied, unmerged or untracked
+    pub kind: &'static str,
+    /// Status letter in the index (staged), e.g. "M", "A", "D"; "." when unchanged
+    pub index: String,
+    /// Status letter in the working tree (unstaged)
+    pub worktree: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchInfo {
+    /// None when HEAD is detached
+    pub branch: Option<String>,
+    /// None before the first commit
+    pub commit: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: u32,
+    pub behind: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub repository: String,
+    #[serde(flatten)]
+    pub branch: BranchInfo,
+    pub files: Vec<FileStatus>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Diff the index against HEAD instead of the working tree against the index
+    pub staged: bool,
+    /// Compare against this commit instead
+    pub base: Option<String>,
+    /// Restrict the diff to a file or directory
+    pub path: Option<PathBuf>,
+    pub context_lines: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diff {
+    pub repository: String,
+    pub diff: String,
+    pub truncated: bool,
+}
+
+/// Run git in `dir` and return its stdout, failing with its stderr when it exits
+/// unsuccessfully
+async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    debug!("Running git {:?} in {}", args, dir.display());
+    let output = Command::new("git")
+        // Don't take locks a concurrent git command in the editor would trip over
+        .arg("--no-optional-locks")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(GIT_TIMEOUT, output)
+        .await
+        .map_err(|_| anyhow!("git {} timed out", args.join(" ")))?
+        .context("Failed to run git")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git {} failed: {}", args.join(" "), stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Top-level directory of the repository containing `dir`
+pub async fn repository_root(dir: &Path) -> Result<PathBuf> {
+    let root = git(dir, &["rev-parse", "--show-toplevel"])
+        .await
+        .map_err(|_| anyhow!("{} is not inside a git repository", dir.display()))?;
+    Ok(PathBuf::from(root.trim_end()))
+}
+
+/// The repository's git directory (`.git`, or elsewhere for linked worktrees)
+pub async fn git_dir(dir: &Path) -> Result<PathBuf> {
+    let git_dir = git(dir, &["rev-parse", "--absolute-git-dir"]).await?;
+    Ok(PathBuf::from(git_dir.trim_end()))
+}
+
+/// Subject lines of the latest `count` commits, newest first; none before the
+/// first commit
+pub async fn recent_subjects(dir: &Path, count: usize) -> Result<Vec<String>> {
+    if current_branch(dir).await?.commit.is_none() {
+        return Ok(Vec::new());
+    }
+    let count = format!("--max-count={}", count);
+    let output = git(dir, &["log", &count, "--format=%s"]).await?;
+    Ok(output.lines().map(str::to_string).collect())
+}
+
+/// Branch, upstream and changed files of the repository containing `dir`
+pub async fn status(dir: &Path) -> Result<Status> {
+    let root = repository_root(dir).await?;
+    let output = git(
+        &root,
+        &[
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "-z",
+            "--untracked-files=all",
+        ],
+    )
+    .await?;
+
+    let mut branch = BranchInfo::default();
+    let mut files = Vec::new();
+    // Entries are NUL-terminated; a rename's original path is the following entry
+    let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        if let Some(header) = entry.strip_prefix("# ") {
+            parse_branch_header(header, &mut branch);
+            continue;
+        }
+
+        let (kind, fields) = match entry.chars().next() {
+            Some('1') => ("changed", 9),
+            Some('2') => ("renamed", 10),
+            Some('u') => ("unmerged", 11),
+            Some('?') => ("untracked", 2),
+            // Ignored files aren't listed without --ignored
+            _ => continue,
+        };
+        let parts: Vec<&str> = entry.splitn(fields, ' ').collect();
+        let Some(path) = parts.get(fields - 1) else {
+            continue;
+        };
+        let (index, worktree) = match parts.get(1).filter(|_| kind != "untracked") {
+            Some(xy) => (xy[..1].to_string(), xy[1..].to_string()),
+            None => ("?".to_string(), "?".to_string()),
+        };
+        let (kind, original_path) = if kind == "renamed" {
+            // The score field says whether git saw a rename (R) or a copy (C)
+            let kind = if parts[8].starts_with('C') {
+                "copied"
+            } else {
+                "renamed"
+            };
+            let original = entries.next().map(|original| absolute(&root, original));
+            (kind, original)
+        } else {
+            (kind, None)
+        };
+
+        files.push(FileStatus {
+            file_path: absolute(&root, path),
+            kind,
+            index,
+            worktree,
+            original_path,
+        });
+    }
+
+    Ok(Status {
+        repository: root.to_string_lossy().to_string(),
+        branch,
+        files,
+    })
+}
+
+/// Fill in `branch` from a porcelain v2 `# branch.*` header
+fn parse_branch_header(header: &str, branch: &mut BranchInfo) {
+    let Some((key, value)) = header.split_once(' ') else {
+        return;
+    };
+    match key {
+        "branch.oid" if value != "(initial)" => branch.commit = Some(value.to_string()),
+        "branch.head" if value != "(detached)" => branch.branch = Some(value.to_string()),
+        "branch.upstream" => branch.upstream = Some(value.to_string()),
+        "branch.ab" => {
+            for count in value.split_whitespace() {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    branch.ahead = ahead.parse().unwrap_or(0);
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    branch.behind = behind.parse().unwrap_or(0);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Current branch (or detached commit) of the repository containing `dir`
+pub async fn current_branch(dir: &Path) -> Result<BranchInfo> {
+    let output = git(
+        dir,
+        &[
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "--untracked-files=no",
+        ],
+    )
+    .await?;
+    let mut branch = BranchInfo::default();
+    for header in output.lines().filter_map(|line| line.strip_prefix("# ")) {
+        parse_branch_header(header, &mut branch);
+    }
+    Ok(branch)
+}
+
+/// Uncommitted changes in the repository containing `dir`, as a unified diff
+pub async fn diff(dir: &Path, options: &DiffOptions) -> Result<Diff> {
+    let root = repository_root(dir).await?;
+
+    let mut args = vec![
+        "diff".to_string(),
+        "--no-color".to_string(),
+        "--no-ext-diff".to_string(),
+    ];
+    if options.staged {
+        args.push("--cached".to_string());
+    }
+    if let Some(lines) = options.context_lines {
+        args.push(format!("--unified={}", lines));
+    }
+    if let Some(base) = &options.base {
+        // Anything starting with - would be taken as an option (e.g. --output)
+        if base.starts_with('-') {
+            bail!("Invalid base commit {:?}", base);
+        }
+        args.push(base.clone());
+    }
+    args.push("--".to_string());
+    if let Some(path) = &options.path {
+        args.push(path.to_string_lossy().to_string());
+    }
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut diff = git(&root, &args).await?;
+    let truncated = diff.len() > MAX_DIFF_BYTES;
+    if truncated {
+        let mut end = MAX_DIFF_BYTES;
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+        diff.truncate(end);
+    }
+
+    Ok(Diff {
+        repository: root.to_string_lossy().to_string(),
+        diff,
+        truncated,
+    })
+}
+
+/// A file with uncommitted changes, and the lines they span
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    /// 0-based first and last line touched by the changes; None for a file git
+    /// doesn't track yet, or any file before the first commit
+    pub lines: Option<(u32, u32)>,
+}
+
+/// Files changed since HEAD in the repository containing `dir`, staged or not,
+/// along with untracked ones. Deleted files are left out.
+pub async fn changed_files(dir: &Path) -> Result<Vec<ChangedFile>> {
+    let status = status(dir).await?;
+    let root = PathBuf::from(&status.repository);
+    let whole = |file: &FileStatus| ChangedFile {
+        path: PathBuf::from(&file.file_path),
+        lines: None,
+    };
+    if status.branch.commit.is_none() {
+        return Ok(status
+            .files
+            .iter()
+            .filter(|file| Path::new(&file.file_path).is_file())
+            .map(whole)
+            .collect());
+    }
+
+    let diff = git(
+        &root,
+        &[
+            "diff",
+            "HEAD",
+            "--unified=0",
+            "--no-color",
+            "--no-ext-diff",
+            "--",
+        ],
+    )
+    .await?;
+    let mut files = parse_changed_lines(&root, &diff);
+    files.extend(
+        status
+            .files
+            .iter()
+            .filter(|file| file.kind == "untracked")
+            .map(whole),
+    );
+    Ok(files)
+}
+
+/// The new side's changed lines of each file in a `--unified=0` diff
+fn parse_changed_lines(root: &Path, diff: &str) -> Vec<ChangedFile> {
+    let mut files: Vec<ChangedFile> = Vec::new();
+    // None while in a deleted file's hunks
+    let mut current: Option<usize> = None;
+    for line in diff.lines() {
+        if let Some(new) = line.strip_prefix("+++ ") {
+            current = new.trim_matches('"').strip_prefix("b/").map(|path| {
+                files.push(ChangedFile {
+                    path: root.join(path),
+                    lines: None,
+                });
+                files.len() - 1
+            });
+            continue;
+        }
+        let (Some(header), Some(file)) = (line.strip_prefix("@@ "), current) else {
+            continue;
+        };
+        // @@ -old[,count] +new[,count] @@
+        let Some(new) = header
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix('+'))
+        else {
+            continue;
+        };
+        let (start, count) = match new.split_once(',') {
+            Some((start, count)) => (start.parse::<u32>(), count.parse::<u32>()),
+            None => (new.parse::<u32>(), Ok(1)),
+        };
+        let (Ok(start), Ok(count)) = (start, count) else {
+            continue;
+        };
+        // Lines only removed sit after line `start`; otherwise `start` is the
+        // first changed line (both 1-based)
+        let (first, last) = if count == 0 {
+            (start.saturating_sub(1), start.saturating_sub(1))
+        } else {
+            (start - 1, start - 1 + count - 1)
+        };
+        let lines = &mut files[file].lines;
+        *lines = Some(match *lines {
+            Some((start, end)) => (start.min(first), end.max(last)),
+            None => (first, last),
+        });
+    }
+    files
+}
+
+fn absolute(root: &Path, relative: &str) -> String {
+    root.join(relative).to_string_lossy().to_string()
+}
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+use regex_automata::meta::Regex;
+use regex_automata::util::syntax;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::text::LineIndex;
+use crate::workspace;
+
+// Full-text search for the grepWorkspace MCP tool: a regular expression (or a
+// literal string) matched line by line across the workspace folders, respecting
+// .gitignore. Open documents are searched as they are in the editor.
+
+// Files searched per workspace folder
+const MAX_FILES: usize = 20_000;
+// Matching lines longer than this are cut short in results
+const MAX_LINE_CHARS: usize = 300;
+pub const DEFAULT_MAX_RESULTS: usize = 100;
+pub const MAX_RESULTS: usize = 2000;
+
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+    pub pattern: String,
+    /// Match `pattern` literally instead of as a regular expression
+    pub fixed_strings: bool,
+    pub case_insensitive: bool,
+    /// Only search files matching this gitignore-style glob, relative to each folder
+    pub glob: Option<String>,
+    pub max_results: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrepMatch {
+    pub file_path: String,
+    /// 1-based, as in grep output
+    pub line_number: u32,
+    /// 1-based character where the first match on the line starts
+    pub column: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrepResults {
+    pub matches: Vec<GrepMatch>,
+    pub files_searched: usize,
+    /// Set when the search stopped at max_results
+    pub truncated: bool,
+}
+
+/// Search every file under `roots`. `buffers` holds the text of open documents,
+/// used instead of the file on disk.
+pub fn grep(
+    roots: &[PathBuf],
+    buffers: &HashMap<PathBuf, String>,
+    options: &GrepOptions,
+) -> Result<GrepResults> {
+    let pattern = if options.fixed_strings {
+        regex_syntax::escape(&options.pattern)
+    } else {
+        options.pattern.clone()
+    };
+    // Multi-line mode so ^ and $ also hold for the whole-file check below
+    let regex = Regex::builder()
+        .syntax(
+            syntax::Config::new()
+                .case_insensitive(options.case_insensitive)
+                .multi_line(true)
+                .crlf(true),
+        )
+        .build(&pattern)
+        .map_err(|e| match e.syntax_error() {
+            Some(syntax_error) => anyhow!("Invalid pattern:\n{}", syntax_error),
+            None => anyhow!("Invalid pattern {:?}: {}", options.pattern, e),
+        })?;
+
+    let mut results = GrepResults::default();
+    for root in roots {
+        let files = match &options.glob {
+            Some(glob) => workspace::list_matching(root, glob, MAX_FILES)?,
+            None => workspace::list_files(root, MAX_FILES),
+        };
+        for relative in files {
+            let path = root.join(relative);
+            let Some(text) = workspace::searchable_text(&path, buffers) else {
+                continue;
+            };
+            results.files_searched += 1;
+            if !regex.is_match(&text) {
+                continue;
+            }
+
+            let index = LineIndex::new(&text);
+            let mut line_number = 0;
+            while let Some(line) = index.line(line_number) {
+                line_number += 1;
+                let Some(found) = regex.find(line) else {
+                    continue;
+                };
+                if results.matches.len() == options.max_results {
+                    results.truncated = true;
+                    return Ok(results);
+                }
+                results.matches.push(GrepMatch {
+                    file_path: path.to_string_lossy().to_string(),
+                    line_number,
+                    column: line[..found.start()].chars().count() + 1,
+                    text: line.chars().take(MAX_LINE_CHARS).collect(),
+                });
+            }
+        }
+    }
+    Ok(results)
+}
@@ -0,0 +1,87 @@
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tower_lsp::lsp_types::Url;
+use tracing::debug;
+
+use crate::documents::SharedDocuments;
+use crate::text;
+
+// What files looked like when Claude was last sent context from them (a
+// selection or a mention), as content hashes taken in the background. Before
+// Claude's edits are applied, their files are hashed again: one that drifted
+// changed under Claude, and the user is asked again rather than having those
+// changes clobbered without a word.
+
+// Files remembered; the least recently captured are forgotten first
+const MAX_FILES: usize = 1000;
+
+pub fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A file's hash as given to Claude in file references
+pub fn fingerprint(text: &str) -> String {
+    format!("{:016x}", hash(text))
+}
+
+#[derive(Debug)]
+pub struct ContentHashes {
+    seen: Mutex<LruCache<Url, u64>>,
+}
+
+pub type SharedContentHashes = Arc<ContentHashes>;
+
+impl Default for ContentHashes {
+    fn default() -> Self {
+        Self {
+            seen: Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_FILES).expect("capacity is non-zero"),
+            )),
+        }
+    }
+}
+
+impl ContentHashes {
+    /// Hash `uri` as the editor shows it now (the file on disk when it isn't
+    /// open), without holding up the caller
+    pub fn capture(self: &Arc<Self>, uri: Url, documents: SharedDocuments) {
+        let hashes = self.clone();
+        tokio::spawn(async move {
+            let text = match documents.get(&uri).await {
+                Some(document) => document.text,
+                None => {
+                    let Ok(path) = uri.to_file_path() else {
+                        return;
+                    };
+                    match tokio::fs::read_to_string(&path).await {
+                        Ok(text) => text::strip_bom(text),
+                        Err(e) => {
+                            debug!("Not hashing {}: {}", path.display(), e);
+                            return;
+                        }
+                    }
+                }
+            };
+            if let Ok(hash) = tokio::task::spawn_blocking(move || hash(&text)).await {
+                hashes.lock().put(uri, hash);
+            }
+        });
+    }
+
+    /// Whether `uri` no longer has the contents Claude was shown. Files Claude got
+    /// no context from never count as drifted.
+    pub fn drifted(&self, uri: &Url, current: &str) -> bool {
+        self.lock()
+            .peek(uri)
+            .is_some_and(|&seen| seen != hash(current))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LruCache<Url, u64>> {
+        self.seen.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,136 @@
+use lru::LruCache;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::claude::ClaudeClient;
+
+// Optional "hover explain" mode: hovering a symbol asks Claude for a short
+// explanation. Answers are cached and the total spend is capped by a token budget.
+
+const CACHE_CAPACITY: usize = 128;
+// Lines of surrounding code sent along with the hovered symbol
+const CONTEXT_LINES: usize = 40;
+const DEFAULT_TOKEN_BUDGET: usize = 20_000;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HoverSettings {
+    pub enabled: bool,
+    /// Estimated tokens (prompt + response) hover explanations may use per session
+    pub token_budget: usize,
+}
+
+impl Default for HoverSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            token_budget: DEFAULT_TOKEN_BUDGET,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    file: String,
+    symbol: String,
+    content_hash: u64,
+}
+
+#[derive(Debug)]
+pub struct HoverExplainer {
+    cache: Mutex<LruCache<CacheKey, String>>,
+    spent_tokens: AtomicUsize,
+}
+
+impl Default for HoverExplainer {
+    fn default() -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(CACHE_CAPACITY).expect("cache capacity is non-zero"),
+            )),
+            spent_tokens: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl HoverExplainer {
+    /// Explain `symbol` on 0-based `line` of `file`, from the cache when the
+    /// surrounding code is unchanged. None when disabled, over budget, or on error.
+    pub async fn explain(
+        &self,
+        settings: &HoverSettings,
+        claude: &ClaudeClient,
+        file: &str,
+        text: &str,
+        symbol: &str,
+        line: usize,
+    ) -> Option<String> {
+        if !settings.enabled {
+            return None;
+        }
+
+        let context = context_around(text, line);
+        let key = CacheKey {
+            file: file.to_string(),
+            symbol: symbol.to_string(),
+            content_hash: content_hash(&context),
+        };
+        if let Some(explanation) = self.cache.lock().await.get(&key) {
+            debug!("Hover explanation for {} served from cache", symbol);
+            return Some(explanation.clone());
+        }
+
+        let prompt = format!(
+            "In one short paragraph, explain what `{}` is and does in the following code \
+             from {}. Reply in plain Markdown without headings.\n\n```\n{}\n```",
+            symbol, file, context
+        );
+        let spent = self.spent_tokens.load(Ordering::Relaxed);
+        if spent + estimate_tokens(&prompt) > settings.token_budget {
+            debug!(
+                "Hover explain budget exhausted ({} of {} tokens used)",
+                spent, settings.token_budget
+            );
+            return None;
+        }
+
+        let explanation = match claude.ask(&prompt, None).await {
+            Ok(explanation) => explanation,
+            Err(e) => {
+                warn!("Hover explanation for {} failed: {}", symbol, e);
+                return None;
+            }
+        };
+        self.spent_tokens.fetch_add(
+            estimate_tokens(&prompt) + estimate_tokens(&explanation),
+            Ordering::Relaxed,
+        );
+
+        self.cache.lock().await.put(key, explanation.clone());
+        Some(explanation)
+    }
+}
+
+/// The lines within CONTEXT_LINES of `line`
+fn context_around(text: &str, line: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = line.saturating_sub(CONTEXT_LINES);
+    let end = (line + CONTEXT_LINES + 1).min(lines.len());
+    lines.get(start..end).unwrap_or_default().join("\n")
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Rough token count (about four bytes per token), good enough for budgeting
+fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
@@ -0,0 +1,643 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::indexer::SymbolIndex;
+use crate::paths;
+
+// Workspace files the code a prompt template runs on imports from, so Claude
+// sees what it refers to in other files. The file's import statements are read
+// line by line; those in the selected lines, and those bringing in a name the
+// selected code uses, are followed to files in the workspace:
+//
+// - JavaScript and TypeScript: relative specifiers of `import ... from` and
+//   `require(...)`, with or without their extension, or a directory's index file
+// - Python: `from ... import` and `import`, relative to the file or to a
+//   workspace folder, as a module file, a package's __init__.py or a submodule
+// - Rust: `use crate::`, `self::` and `super::` paths, as the module file they
+//   name, or else the file the symbol index finds their item in
+// - C and C++: `#include "..."`, next to the file or in a workspace folder. An
+//   include names nothing, so every one counts as used, as do glob imports.
+//
+// Other languages import nothing here, and packages outside the workspace aren't
+// followed. With `promptContext.imports` on, the files go into the prompt after
+// the code, capped by `importFiles` and `importBytes`.
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PromptContextSettings {
+    /// Add the files the code imports from to template prompts
+    pub imports: bool,
+    /// Most bytes of imported files in one prompt
+    pub import_bytes: usize,
+    /// Most imported files in one prompt
+    pub import_files: usize,
+}
+
+impl Default for PromptContextSettings {
+    fn default() -> Self {
+        Self {
+            imports: false,
+            import_bytes: 16 * 1024,
+            import_files: 5,
+        }
+    }
+}
+
+/// An imported file, as it goes into a prompt
+#[derive(Debug, Clone)]
+pub struct ImportedFile {
+    pub path: PathBuf,
+    pub text: String,
+    /// Whether `text` stops short of the end of the file
+    pub truncated: bool,
+}
+
+/// One import statement, or one module of a statement importing several
+#[derive(Debug, Clone, PartialEq)]
+struct Import {
+    /// Line the statement starts on, 0-based
+    line: u32,
+    /// The module as written: a specifier, a dotted module or a `::` path
+    module: String,
+    /// Names the statement brings into scope; None when it can't tell, as for
+    /// includes and glob imports
+    names: Option<Vec<String>>,
+    /// Whether the names are the module's members (`from m import a`), which
+    /// may be submodules, rather than the module itself
+    members: bool,
+}
+
+// Longest statement followed over several lines
+const MAX_STATEMENT_LINES: usize = 50;
+const SCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Files imported by statements in `lines` of `text`, or by those bringing in a
+/// name `code` uses, in the order they are imported. `file` itself and files
+/// outside the workspace, through `..` or a symlink, are left out.
+pub fn imported_paths(
+    file: &Path,
+    text: &str,
+    language_id: &str,
+    code: &str,
+    lines: RangeInclusive<u32>,
+    roots: &[PathBuf],
+    index: &SymbolIndex,
+) -> Vec<PathBuf> {
+    let used: HashSet<&str> = code
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let canonical_roots: Vec<(PathBuf, PathBuf)> = roots
+        .iter()
+        .filter_map(|root| Some((root.clone(), root.canonicalize().ok()?)))
+        .collect();
+    let file = inside_workspace(file, &canonical_roots).unwrap_or_else(|| file.to_path_buf());
+    let file = file.as_path();
+    let mut found: Vec<PathBuf> = Vec::new();
+    for import in parse(text, language_id) {
+        let names: Vec<&str> = match &import.names {
+            _ if lines.contains(&import.line) => Vec::new(),
+            None => Vec::new(),
+            Some(names) => {
+                let names: Vec<&str> = names
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|name| used.contains(name))
+                    .collect();
+                if names.is_empty() {
+                    continue;
+                }
+                names
+            }
+        };
+        let paths = match language_id {
+            "python" if import.members => resolve_python(file, &import.module, &names, roots),
+            "python" => resolve_python(file, &import.module, &[], roots),
+            "rust" => resolve_rust(file, &import.module, index),
+            "c" | "cpp" | "objective-c" | "objective-cpp" => {
+                resolve_include(file, &import.module, roots)
+            }
+            _ => resolve_script(file, &import.module),
+        };
+        for path in paths
+            .iter()
+            .filter_map(|path| inside_workspace(path, &canonical_roots))
+        {
+            if path != file && !found.contains(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+/// `path` under the workspace folder it is in once `..` and symlinks are
+/// resolved, or None when that is outside every folder (or it doesn't exist).
+/// `roots` pairs each folder with its canonical path.
+fn inside_workspace(path: &Path, roots: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    roots.iter().find_map(|(root, canonical)| {
+        path.strip_prefix(canonical)
+            .ok()
+            .map(|relative| root.join(relative))
+    })
+}
+
+/// `files` cut down to the settings' limits: whole files while they fit, then
+/// the leading lines of the next one that doesn't
+pub fn fit(files: Vec<(PathBuf, String)>, settings: &PromptContextSettings) -> Vec<ImportedFile> {
+    let mut left = settings.import_bytes;
+    let mut fitted = Vec::new();
+    for (path, text) in files.into_iter().take(settings.import_files) {
+        if left == 0 {
+            break;
+        }
+        if text.len() <= left {
+            left -= text.len();
+            fitted.push(ImportedFile {
+                path,
+                text,
+                truncated: false,
+            });
+            continue;
+        }
+        let mut end = 0;
+        for line in text.split_inclusive('\n') {
+            if end + line.len() > left {
+                break;
+            }
+            end += line.len();
+        }
+        if end > 0 {
+            fitted.push(ImportedFile {
+                path,
+                text: text[..end].to_string(),
+                truncated: true,
+            });
+        }
+        break;
+    }
+    fitted
+}
+
+/// The part of a prompt giving Claude the imported files, empty without any
+pub fn describe(files: &[ImportedFile], roots: &[PathBuf]) -> String {
+    if files.is_empty() {
+        return String::new();
+    }
+    let mut text = "\n\nFor reference only (don't include them in your reply), the files the \
+                    code imports from:"
+        .to_string();
+    for file in files {
+        let name = roots
+            .iter()
+            .find_map(|root| paths::relative(&file.path, root))
+            .unwrap_or_else(|| file.path.to_string_lossy().to_string());
+        let cut = if file.truncated {
+            " (its first lines)"
+        } else {
+            ""
+        };
+        text.push_str(&format!(
+            "\n\n`{}`{}:\n\n```\n{}\n```",
+            name,
+            cut,
+            file.text.trim_end_matches('\n')
+        ));
+    }
+    text
+}
+
+/// The import statements of `text`
+fn parse(text: &str, language_id: &str) -> Vec<Import> {
+    match language_id {
+        "javascript" | "javascriptreact" | "typescript" | "typescriptreact" | "tsx" => {
+            parse_script(text)
+        }
+        "python" => parse_python(text),
+        "rust" => parse_rust(text),
+        "c" | "cpp" | "objective-c" | "objective-cpp" => parse_includes(text),
+        _ => Vec::new(),
+    }
+}
+
+/// Statements of `text` starting on a line `starts` accepts, joined into one
+/// line up to the line where `ends` accepts them
+fn statements(
+    text: &str,
+    starts: impl Fn(&str) -> bool,
+    ends: impl Fn(&str) -> bool,
+) -> Vec<(u32, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut found = Vec::new();
+    let mut number = 0;
+    while number < lines.len() {
+        let line = lines[number].trim();
+        if !starts(line) {
+            number += 1;
+            continue;
+        }
+        let mut statement = line.to_string();
+        let mut last = number;
+        while !ends(&statement) && last + 1 < lines.len() && last - number < MAX_STATEMENT_LINES {
+            last += 1;
+            statement.push(' ');
+            statement.push_str(lines[last].trim());
+        }
+        found.push((number as u32, statement));
+        number = last + 1;
+    }
+    found
+}
+
+/// The first quoted string in `text`, without its quotes
+fn quoted(text: &str) -> Option<(usize, &str)> {
+    let start = text.find(['\'', '"', '`'])?;
+    let quote = text[start..].chars().next()?;
+    let length = text[start + 1..].find(quote)?;
+    Some((start, &text[start + 1..start + 1 + length]))
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Names of a comma-separated list such as `a, b as c` or `{ a, b: c }`, the
+/// last word of each
+fn names_of(list: &str, alias: &str) -> Vec<String> {
+    list.replace(['{', '}', '(', ')'], ",")
+        .split(',')
+        .filter_map(|part| {
+            let name = part.rsplit(alias).next()?.trim();
+            is_identifier(name).then(|| name.to_string())
+        })
+        .collect()
+}
+
+fn parse_script(text: &str) -> Vec<Import> {
+    let starts = |line: &str| {
+        line.strip_prefix("import")
+            .is_some_and(|rest| rest.starts_with([' ', '{', '*', '\'', '"']))
+    };
+    let mut imports: Vec<Import> =
+        statements(text, starts, |statement| quoted(statement).is_some())
+            .into_iter()
+            .filter_map(|(line, statement)| {
+                let (start, module) = quoted(&statement)?;
+                let clause = statement["import".len()..start].trim_end();
+                let names = match clause.strip_suffix("from") {
+                    Some(clause) => {
+                        let clause = clause.trim();
+                        names_of(clause.strip_prefix("type ").unwrap_or(clause), " as ")
+                    }
+                    // Imported for its side effects
+                    None => Vec::new(),
+                };
+                Some(Import {
+                    line,
+                    module: module.to_string(),
+                    names: Some(names),
+                    members: false,
+                })
+            })
+            .collect();
+
+    for (line, text) in text.lines().enumerate() {
+        let Some(call) = text.find("require(") else {
+            continue;
+        };
+        let Some((_, module)) = quoted(&text[call..]) else {
+            continue;
+        };
+        let names = match text[..call].split_once('=') {
+            Some((binding, _)) => {
+                let binding = binding.trim();
+                let binding = ["const ", "let ", "var "]
+                    .iter()
+                    .find_map(|keyword| binding.strip_prefix(keyword))
+                    .unwrap_or(binding);
+                names_of(binding, ":")
+            }
+            None => Vec::new(),
+        };
+        imports.push(Import {
+            line: line as u32,
+            module: module.to_string(),
+            names: Some(names),
+            members: false,
+        });
+    }
+    imports.retain(|import| import.module.starts_with("./") || import.module.starts_with("../"));
+    imports.sort_by_key(|import| import.line);
+    imports
+}
+
+fn parse_python(text: &str) -> Vec<Import> {
+    let starts = |line: &str| line.starts_with("from ") || line.starts_with("import ");
+    let ends = |statement: &str| {
+        !statement.ends_with('\\') && (!statement.contains('(') || statement.contains(')'))
+    };
+    let mut imports = Vec::new();
+    for (line, statement) in statements(text, starts, ends) {
+        let statement = statement.replace('\\', " ");
+        if let Some(rest) = statement.strip_prefix("from ") {
+            let Some((module, list)) = rest.split_once(" import ") else {
+                continue;
+            };
+            let names = if list.trim() == "*" {
+                None
+            } else {
+                Some(names_of(list, " as "))
+            };
+            imports.push(Import {
+                line,
+                module: module.trim().to_string(),
+                names,
+                members: true,
+            });
+        } else if let Some(list) = statement.strip_prefix("import ") {
+            for part in list.split(',') {
+                let (module, name) = match part.split_once(" as ") {
+                    Some((module, alias)) => (module.trim(), alias.trim()),
+                    None => (part.trim(), part.trim().split('.').next().unwrap_or("")),
+                };
+                imports.push(Import {
+                    line,
+                    module: module.to_string(),
+                    names: Some(vec![name.to_string()]),
+                    members: false,
+                });
+            }
+        }
+    }
+    imports
+}
+
+fn parse_rust(text: &str) -> Vec<Import> {
+    let starts = |line: &str| {
+        let line = line
+            .strip_prefix("pub(crate) ")
+            .or_else(|| line.strip_prefix("pub "))
+            .unwrap_or(line);
+        line.starts_with("use ")
+    };
+    let mut imports = Vec::new();
+    for (line, statement) in statements(text, starts, |statement| statement.contains(';')) {
+        let Some(tree) = statement
+            .split_once("use ")
+            .and_then(|(_, rest)| rest.split_once(';'))
+            .map(|(tree, _)| tree.split_whitespace().collect::<Vec<_>>().join(" "))
+        else {
+            continue;
+        };
+        for (path, name) in expand_use_tree("", &tree) {
+            if ["crate::", "self::", "super::"]
+                .iter()
+                .any(|start| path.starts_with(start))
+            {
+                imports.push(Import {
+                    line,
+                    module: path,
+                    names: name.map(|name| vec![name]),
+                    members: false,
+                });
+            }
+        }
+    }
+    imports
+}
+
+/// The paths a `use` tree such as `a::{b, c::{self, D as E}}` imports, each with
+/// the name it brings into scope (None for globs)
+fn expand_use_tree(prefix: &str, tree: &str) -> Vec<(String, Option<String>)> {
+    let tree = tree.trim();
+    let join = |path: &str| match prefix {
+        "" => path.to_string(),
+        _ if path.is_empty() => prefix.to_string(),
+        _ => format!("{}::{}", prefix, path),
+    };
+    if let Some(open) = tree.find('{') {
+        let base = tree[..open].trim_end().trim_end_matches("::");
+        let inner = tree[open + 1..]
+            .strip_suffix('}')
+            .unwrap_or(&tree[open + 1..]);
+        let prefix = join(base);
+        let mut depth = 0;
+        let mut start = 0;
+        let mut paths = Vec::new();
+        for (index, c) in inner.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    paths.extend(expand_use_tree(&prefix, &inner[start..index]));
+                    start = index + 1;
+                }
+                _ => {}
+            }
+        }
+        paths.extend(expand_use_tree(&prefix, &inner[start..]));
+        return paths;
+    }
+    match tree {
+        "" => Vec::new(),
+        "*" => vec![(prefix.to_string(), None)],
+        "self" => {
+            let name = prefix.rsplit("::").next().map(str::to_string);
+            vec![(prefix.to_string(), name)]
+        }
+        _ => {
+            let (path, alias) = match tree.split_once(" as ") {
+                Some((path, alias)) => (path, Some(alias)),
+                None => (tree, None),
+            };
+            let path = path.trim_end_matches("::");
+            let name = alias
+                .or_else(|| path.rsplit("::").next())
+                .map(str::to_string);
+            vec![(join(path), name)]
+        }
+    }
+}
+
+fn parse_includes(text: &str) -> Vec<Import> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let directive = text.trim().strip_prefix('#')?.trim_start();
+            let rest = directive.strip_prefix("include")?.trim();
+            let header = rest.strip_prefix('"')?.split('"').next()?;
+            Some(Import {
+                line: line as u32,
+                module: header.to_string(),
+                names: None,
+                members: false,
+            })
+        })
+        .collect()
+}
+
+/// `path` with `.extension` appended, as `./util` is found at util.ts
+fn with_suffix(path: &Path, extension: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+fn resolve_script(file: &Path, specifier: &str) -> Vec<PathBuf> {
+    let Some(dir) = file.parent() else {
+        return Vec::new();
+    };
+    let base = dir.join(specifier);
+    let mut candidates = vec![base.clone()];
+    // TypeScript imports its files by their compiled name
+    if let Some(stem) = specifier
+        .strip_suffix(".js")
+        .or_else(|| specifier.strip_suffix(".jsx"))
+    {
+        candidates.extend(
+            ["ts", "tsx"]
+                .iter()
+                .map(|ext| with_suffix(&dir.join(stem), ext)),
+        );
+    }
+    candidates.extend(SCRIPT_EXTENSIONS.iter().map(|ext| with_suffix(&base, ext)));
+    candidates.extend(
+        SCRIPT_EXTENSIONS
+            .iter()
+            .map(|ext| base.join(format!("index.{}", ext))),
+    );
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .into_iter()
+        .collect()
+}
+
+/// Module files `from module import names` or `import module` loads: a submodule
+/// for each of `names` there is one for, otherwise the module itself
+fn resolve_python(file: &Path, module: &str, names: &[&str], roots: &[PathBuf]) -> Vec<PathBuf> {
+    let dots = module.chars().take_while(|c| *c == '.').count();
+    let bases: Vec<PathBuf> = if dots > 0 {
+        file.ancestors()
+            .nth(dots)
+            .into_iter()
+            .map(Path::to_path_buf)
+            .collect()
+    } else {
+        file.parent()
+            .into_iter()
+            .map(Path::to_path_buf)
+            .chain(
+                roots
+                    .iter()
+                    .flat_map(|root| [root.clone(), root.join("src")]),
+            )
+            .collect()
+    };
+    let relative: PathBuf = module[dots..]
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .collect();
+    let module_file = |dir: &Path| {
+        [with_suffix(dir, "py"), dir.join("__init__.py")]
+            .into_iter()
+            .find(|path| path.is_file())
+    };
+
+    for base in bases {
+        let dir = base.join(&relative);
+        let submodules: Vec<PathBuf> = names
+            .iter()
+            .map(|name| with_suffix(&dir.join(name), "py"))
+            .filter(|path| path.is_file())
+            .collect();
+        let whole = if names.is_empty() || submodules.len() < names.len() {
+            module_file(&dir)
+        } else {
+            None
+        };
+        if whole.is_some() || !submodules.is_empty() {
+            return submodules.into_iter().chain(whole).collect();
+        }
+    }
+    Vec::new()
+}
+
+/// The file a `crate::`, `self::` or `super::` path comes from: the module it
+/// names, the file the symbol index has its item in, or the nearest module above
+fn resolve_rust(file: &Path, path: &str, index: &SymbolIndex) -> Vec<PathBuf> {
+    let mut segments: Vec<&str> = path.split("::").collect();
+    let Some(src) = file
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .map(|dir| dir.join("src"))
+    else {
+        return Vec::new();
+    };
+    // The directory of the file's own module's submodules
+    let own = match file.file_name().and_then(|name| name.to_str()) {
+        Some("mod.rs" | "lib.rs" | "main.rs") | None => file.parent().map(Path::to_path_buf),
+        Some(_) => Some(file.with_extension("")),
+    };
+    let mut base = match segments.first() {
+        Some(&"crate") => src.clone(),
+        _ => match own {
+            Some(own) => own,
+            None => return Vec::new(),
+        },
+    };
+    segments.remove(0);
+    while segments.first() == Some(&"super") {
+        segments.remove(0);
+        match base.parent() {
+            Some(parent) => base = parent.to_path_buf(),
+            None => return Vec::new(),
+        }
+    }
+
+    let module_file = |segments: &[&str]| -> Option<PathBuf> {
+        let dir: PathBuf = segments
+            .iter()
+            .fold(base.clone(), |dir, segment| dir.join(segment));
+        let candidates = if dir == src {
+            vec![src.join("lib.rs"), src.join("main.rs")]
+        } else {
+            vec![dir.with_extension("rs"), dir.join("mod.rs")]
+        };
+        candidates.into_iter().find(|path| path.is_file())
+    };
+    if let Some(found) = module_file(&segments) {
+        return vec![found];
+    }
+    if let Some(found) = index.resolve(path) {
+        return vec![found.path];
+    }
+    (0..segments.len())
+        .rev()
+        .find_map(|length| module_file(&segments[..length]))
+        .into_iter()
+        .collect()
+}
+
+fn resolve_include(file: &Path, header: &str, roots: &[PathBuf]) -> Vec<PathBuf> {
+    file.parent()
+        .map(|dir| dir.join(header))
+        .into_iter()
+        .chain(
+            roots
+                .iter()
+                .flat_map(|root| [root.join(header), root.join("include").join(header)]),
+        )
+        .find(|path| path.is_file())
+        .into_iter()
+        .collect()
+}
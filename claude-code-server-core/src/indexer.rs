@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info};
+
+use crate::file_events;
+use crate::language;
+use crate::symbols::{self, SymbolMatch, Symbols};
+use crate::text;
+use crate::workspace;
+
+// Index of the declarations in every workspace file, serving workspace/symbol and
+// the searchSymbols MCP tool. Symbols come from the keyword scanner in symbols.rs.
+// Refreshing walks the folders (respecting .gitignore) and only scans files whose
+// modification time changed, so it is cheap to repeat. It happens after
+// initialize, when the folders change, and before a search once the index is
+// older than REFRESH_INTERVAL; saved files are rescanned right away, under the
+// same .gitignore and size rules.
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+// Files indexed per workspace folder
+const MAX_FILES: usize = 20_000;
+// Larger files (typically generated or minified) are skipped
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+pub const MAX_RESULTS: usize = 500;
+
+#[derive(Debug, Clone)]
+struct IndexedFile {
+    modified: Option<SystemTime>,
+    symbols: Symbols,
+}
+
+#[derive(Debug, Default)]
+struct IndexState {
+    roots: Vec<PathBuf>,
+    files: HashMap<PathBuf, IndexedFile>,
+    refreshed: Option<Instant>,
+}
+
+/// Where the refreshes for new directories are at
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum DirectoryRefresh {
+    #[default]
+    Idle,
+    Running,
+    /// Running, and another directory appeared meanwhile
+    Again,
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolIndex {
+    state: RwLock<IndexState>,
+    directory_refresh: Mutex<DirectoryRefresh>,
+}
+
+pub type SharedSymbolIndex = Arc<SymbolIndex>;
+
+/// A search hit: a declaration and the file it is in
+#[derive(Debug, Clone)]
+pub struct IndexedSymbol {
+    pub path: PathBuf,
+    pub symbol: SymbolMatch,
+}
+
+impl SymbolIndex {
+    /// Bring the index up to date with the files under `roots`. Blocks while the
+    /// folders are walked, so call it from a blocking task.
+    pub fn refresh(&self, roots: &[PathBuf]) {
+        let started = Instant::now();
+        let mut found = Vec::new();
+        for root in roots {
+            for relative in workspace::list_files(root, MAX_FILES) {
+                let path = root.join(relative);
+                let Ok(metadata) = fs::metadata(&path) else {
+                    continue;
+                };
+                if metadata.len() <= MAX_FILE_BYTES {
+                    found.push((path, metadata.modified().ok()));
+                }
+            }
+        }
+
+        // Reuse what is unchanged; only new or modified files are read
+        let previous = self
+            .state
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .files
+            .clone();
+        let mut scanned = 0;
+        let files: HashMap<PathBuf, IndexedFile> = found
+            .into_iter()
+            .map(|(path, modified)| {
+                let file = match previous.get(&path) {
+                    Some(file) if file.modified.is_some() && file.modified == modified => {
+                        file.clone()
+                    }
+                    _ => {
+                        scanned += 1;
+                        IndexedFile {
+                            modified,
+                            symbols: Arc::new(scan(&path)),
+                        }
+                    }
+                };
+                (path, file)
+            })
+            .collect();
+
+        debug!(
+            "Indexed {} files ({} scanned) in {:?}",
+            files.len(),
+            scanned,
+            started.elapsed()
+        );
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.roots = roots.to_vec();
+        state.files = files;
+        state.refreshed = Some(Instant::now());
+    }
+
+    /// Refresh when the folders changed or the index is older than REFRESH_INTERVAL
+    pub fn refresh_if_stale(&self, roots: &[PathBuf]) {
+        let stale = {
+            let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+            state.roots != roots
+                || state
+                    .refreshed
+                    .is_none_or(|refreshed| refreshed.elapsed() > REFRESH_INTERVAL)
+        };
+        if stale {
+            self.refresh(roots);
+        }
+    }
+
+    /// Rescan one file, e.g. after it was saved. Files outside the indexed
+    /// folders are ignored; files a refresh would skip (ignored or too large)
+    /// are dropped from the index.
+    pub fn update_file(&self, path: &Path) {
+        let Some(root) = self
+            .state
+            .read()
+            .unwrap()
+            .roots
+            .iter()
+            .find(|root| path.starts_with(root))
+            .cloned()
+        else {
+            return;
+        };
+
+        let file = fs::metadata(path)
+            .ok()
+            .filter(|metadata| metadata.len() <= MAX_FILE_BYTES)
+            .filter(|_| workspace::is_listed(&root, path))
+            .map(|metadata| IndexedFile {
+                modified: metadata.modified().ok(),
+                symbols: Arc::new(scan(path)),
+            });
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        match file {
+            Some(file) => {
+                state.files.insert(path.to_path_buf(), file);
+            }
+            None => {
+                state.files.remove(path);
+            }
+        }
+    }
+
+    /// Refresh after a directory appeared. Calls made while such a refresh runs
+    /// are folded into one more refresh once it is done, so a burst of new
+    /// directories doesn't walk the folders once per directory.
+    pub fn refresh_for_new_directory(&self, roots: &[PathBuf]) {
+        {
+            let mut pending = self
+                .directory_refresh
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if *pending != DirectoryRefresh::Idle {
+                *pending = DirectoryRefresh::Again;
+                return;
+            }
+            *pending = DirectoryRefresh::Running;
+        }
+        loop {
+            self.refresh(roots);
+            let mut pending = self
+                .directory_refresh
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if *pending == DirectoryRefresh::Again {
+                *pending = DirectoryRefresh::Running;
+                continue;
+            }
+            *pending = DirectoryRefresh::Idle;
+            return;
+        }
+    }
+
+    /// Move the entries of a renamed file, or of every file under a renamed
+    /// directory
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        let moved: Vec<PathBuf> = state
+            .files
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let file = state.files.remove(&path);
+            let new_path = file_events::moved_path(&path, from, to);
+            if let (Some(file), Some(new_path)) = (file, new_path) {
+                state.files.insert(new_path, file);
+            }
+        }
+    }
+
+    /// Drop a deleted file, or every file under a deleted directory
+    pub fn remove(&self, path: &Path) {
+        self.state
+            .write()
+            .unwrap()
+            .files
+            .retain(|indexed, _| !indexed.starts_with(path));
+    }
+
+    /// Symbols whose name matches `query`, best matches first. Names containing
+    /// the query (ignoring case) rank by how early it appears; names that merely
+    /// contain its characters in order come last. An empty query matches
+    /// everything.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<IndexedSymbol> {
+        let query = query.to_lowercase();
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+
+        let mut hits: Vec<(usize, IndexedSymbol)> = state
+            .files
+            .iter()
+            .flat_map(|(path, file)| file.symbols.iter().map(move |symbol| (path, symbol)))
+            .filter_map(|(path, symbol)| {
+                let score = match_score(&symbol.name.to_lowercase(), &query)?;
+                let hit = IndexedSymbol {
+                    path: path.clone(),
+                    symbol: symbol.clone(),
+                };
+                Some((score, hit))
+            })
+            .collect();
+        hits.sort_by(|(a_score, a), (b_score, b)| {
+            (a_score, a.symbol.name.len(), &a.path, a.symbol.line).cmp(&(
+                b_score,
+                b.symbol.name.len(),
+                &b.path,
+                b.symbol.line,
+            ))
+        });
+        hits.into_iter().take(limit).map(|(_, hit)| hit).collect()
+    }
+
+    /// The declaration a qualified name such as "foo::bar" or "Parser::parse"
+    /// points at: a declaration of its last segment whose enclosing declarations
+    /// and path (file stem and directories) include every other segment. A name
+    /// declared once is taken even when its qualifiers don't match.
+    pub fn resolve(&self, qualified: &str) -> Option<IndexedSymbol> {
+        let mut segments: Vec<&str> = qualified
+            .split("::")
+            .filter(|segment| !matches!(*segment, "" | "crate" | "self" | "super"))
+            .collect();
+        let name = segments.pop()?;
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+
+        let mut declared: Vec<(&PathBuf, &Symbols, &SymbolMatch)> = state
+            .files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.symbols
+                    .iter()
+                    .filter(|symbol| symbol.name == name)
+                    .map(move |symbol| (path, &file.symbols, symbol))
+            })
+            .collect();
+        declared.sort_by_key(|(path, _, symbol)| (*path, symbol.line));
+
+        let qualifies = |path: &Path, symbols: &Symbols, symbol: &SymbolMatch| {
+            let mut scopes: HashSet<&str> = symbols
+                .iter()
+                .filter(|outer| {
+                    outer.line <= symbol.line
+                        && outer.end_line >= symbol.end_line
+                        && !std::ptr::eq(*outer, symbol)
+                })
+                .map(|outer| outer.name.as_str())
+                .collect();
+            let relative = state
+                .roots
+                .iter()
+                .find_map(|root| path.strip_prefix(root).ok())
+                .unwrap_or(path);
+            let relative = relative.with_extension("");
+            scopes.extend(
+                relative
+                    .components()
+                    .filter_map(|component| component.as_os_str().to_str()),
+            );
+            segments.iter().all(|segment| scopes.contains(segment))
+        };
+        let found = declared
+            .iter()
+            .find(|(path, symbols, symbol)| qualifies(path, symbols, symbol))
+            .or(match declared.as_slice() {
+                [only] => Some(only),
+                _ => None,
+            })?;
+        Some(IndexedSymbol {
+            path: found.0.clone(),
+            symbol: found.2.clone(),
+        })
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.state
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .files
+            .len()
+    }
+}
+
+/// Read a file and extract its declarations. Unreadable and non-UTF-8 files have none.
+fn scan(path: &Path) -> Vec<SymbolMatch> {
+    text::read_file(path)
+        .map(|text| symbols::extract_symbols(&text, language::from_path(path)))
+        .unwrap_or_default()
+}
+
+/// Lower is better: 0 for an exact match, then by where the query starts in the
+/// name, and past all of those for a fuzzy (in-order characters) match
+fn match_score(name: &str, query: &str) -> Option<usize> {
+    if name == query {
+        return Some(0);
+    }
+    if let Some(position) = name.find(query) {
+        return Some(1 + position);
+    }
+    let mut characters = name.chars();
+    query
+        .chars()
+        .all(|wanted| characters.any(|c| c == wanted))
+        .then_some(usize::MAX)
+}
+
+/// Refresh `index` on a blocking thread, logging how many files it holds
+pub async fn refresh_in_background(index: SharedSymbolIndex, roots: Vec<PathBuf>) {
+    let result = tokio::task::spawn_blocking(move || {
+        index.refresh(&roots);
+        index.file_count()
+    })
+    .await;
+    if let Ok(count) = result {
+        info!("Symbol index holds {} files", count);
+    }
+}
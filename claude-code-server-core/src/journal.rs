@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tower_lsp::lsp_types::Url;
+use tower_lsp::Client;
+use tracing::{info, warn};
+
+use crate::documents::OpenDocuments;
+use crate::edits;
+
+// Undo journal of the edits applied on Claude's behalf: applyEdits, and prompt
+// templates that edit code. Each entry keeps every file's text before and after,
+// so the most recent one can be reverted (claude-code.undo-last-edit, or the
+// undoLastEdit tool) after the editor's own undo history has lost it. The journal
+// is saved to <workspace>/.claude/ide-undo.json, so it also survives restarts.
+
+const JOURNAL_FILE: &str = ".claude/ide-undo.json";
+const FORMAT_VERSION: u32 = 1;
+const MAX_ENTRIES: usize = 20;
+
+/// One file's contents around an edit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSnapshot {
+    pub uri: Url,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    /// What made the edit: applyEdits or a prompt template's name
+    pub source: String,
+    /// Milliseconds since the Unix epoch
+    pub applied_at: u64,
+    pub files: Vec<FileSnapshot>,
+}
+
+impl JournalEntry {
+    pub fn new(source: &str, files: Vec<FileSnapshot>) -> Self {
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Self {
+            source: source.to_string(),
+            applied_at,
+            files,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalFile {
+    version: u32,
+    entries: VecDeque<JournalEntry>,
+}
+
+/// The last MAX_ENTRIES edits, oldest first. Without a workspace they are only
+/// kept until the server stops.
+#[derive(Debug, Default)]
+pub struct UndoJournal {
+    path: Option<PathBuf>,
+    entries: Mutex<VecDeque<JournalEntry>>,
+}
+
+pub type SharedUndoJournal = Arc<UndoJournal>;
+
+impl UndoJournal {
+    /// The journal saved in `workspace`, if any, kept there from now on
+    pub fn load(workspace: &Path) -> Self {
+        let path = workspace.join(JOURNAL_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<JournalFile>(&text) {
+                Ok(journal) if journal.version == FORMAT_VERSION => journal.entries,
+                Ok(journal) => {
+                    warn!(
+                        "Ignoring undo journal {} with unsupported version {}",
+                        path.display(),
+                        journal.version
+                    );
+                    VecDeque::new()
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid undo journal {}: {}", path.display(), e);
+                    VecDeque::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                warn!("Failed to read undo journal {}: {}", path.display(), e);
+                VecDeque::new()
+            }
+        };
+        Self {
+            path: Some(path),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn record(&self, entry: JournalEntry) {
+        let mut entries = self.lock();
+        entries.push_back(entry);
+        while entries.len() > MAX_ENTRIES {
+            entries.pop_front();
+        }
+        self.save(&entries);
+    }
+
+    pub fn last(&self) -> Option<JournalEntry> {
+        self.lock().back().cloned()
+    }
+
+    /// Drop the entry applied at `applied_at`, once it has been undone
+    fn forget(&self, applied_at: u64) {
+        let mut entries = self.lock();
+        if let Some(index) = entries
+            .iter()
+            .rposition(|entry| entry.applied_at == applied_at)
+        {
+            entries.remove(index);
+            self.save(&entries);
+        }
+    }
+
+    fn save(&self, entries: &VecDeque<JournalEntry>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let journal = JournalFile {
+            version: FORMAT_VERSION,
+            entries: entries.clone(),
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let json = serde_json::to_string(&journal).map_err(std::io::Error::other)?;
+                fs::write(path, json)
+            });
+        if let Err(e) = result {
+            warn!("Failed to save undo journal {}: {}", path.display(), e);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<JournalEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Put the files of the most recent entry back the way they were, through
+/// workspace/applyEdit. Refused when any of them changed after the edit, so later
+/// work is never lost. Returns the entry undone.
+pub async fn undo_last(
+    client: &Client,
+    journal: &UndoJournal,
+    documents: &OpenDocuments,
+    document_changes: bool,
+) -> Result<JournalEntry> {
+    let entry = journal
+        .last()
+        .ok_or_else(|| anyhow!("No Claude edits to undo"))?;
+    let plan = edits::revert(&entry.files, documents).await?;
+    edits::apply(client, &plan, document_changes).await?;
+    journal.forget(entry.applied_at);
+    info!("Undid {} edit to {} files", entry.source, entry.files.len());
+    Ok(entry)
+}
@@ -0,0 +1,107 @@
+use std::path::Path;
+
+// Language identifiers for files the editor hasn't opened. Open documents carry
+// the languageId Zed sent with didOpen (mapped in extension.toml); anything read
+// from disk gets one from its extension or file name: the identifier extension.toml
+// maps the language to where there is one, so Claude sees the same name either
+// way, and otherwise the one the LSP specification lists.
+
+/// Identifier for files whose language isn't known
+const PLAIN_TEXT: &str = "plaintext";
+
+/// File extension (lowercase) to languageId, sorted for binary search
+const BY_EXTENSION: &[(&str, &str)] = &[
+    ("bash", "shellscript"),
+    ("bat", "bat"),
+    ("c", "c"),
+    ("cc", "cpp"),
+    ("cjs", "javascript"),
+    ("clj", "clojure"),
+    ("cpp", "cpp"),
+    ("cs", "csharp"),
+    ("css", "css"),
+    ("cts", "typescript"),
+    ("cxx", "cpp"),
+    ("dart", "dart"),
+    ("diff", "diff"),
+    ("erl", "erlang"),
+    ("ex", "elixir"),
+    ("exs", "elixir"),
+    ("fs", "fsharp"),
+    ("go", "go"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("hrl", "erlang"),
+    ("hs", "haskell"),
+    ("htm", "html"),
+    ("html", "html"),
+    ("ini", "ini"),
+    ("java", "java"),
+    ("js", "javascript"),
+    ("json", "json"),
+    ("jsx", "javascriptreact"),
+    ("kt", "kotlin"),
+    ("kts", "kotlin"),
+    ("less", "less"),
+    ("lua", "lua"),
+    ("m", "objective-c"),
+    ("markdown", "markdown"),
+    ("md", "markdown"),
+    ("mjs", "javascript"),
+    ("ml", "ocaml"),
+    ("mm", "objective-cpp"),
+    ("mts", "typescript"),
+    ("php", "php"),
+    ("pl", "perl"),
+    ("ps1", "powershell"),
+    ("py", "python"),
+    ("pyi", "python"),
+    ("r", "r"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sass", "sass"),
+    ("scala", "scala"),
+    ("scss", "scss"),
+    ("sh", "shellscript"),
+    ("sql", "sql"),
+    ("swift", "swift"),
+    ("tex", "latex"),
+    ("toml", "toml"),
+    ("ts", "typescript"),
+    ("tsx", "tsx"),
+    ("typ", "typst"),
+    ("vue", "vue"),
+    ("xml", "xml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("zig", "zig"),
+    ("zsh", "shellscript"),
+];
+
+/// Whole file names, for files without a telling extension
+const BY_FILE_NAME: &[(&str, &str)] = &[
+    ("Dockerfile", "dockerfile"),
+    ("Gemfile", "ruby"),
+    ("Makefile", "makefile"),
+    ("Rakefile", "ruby"),
+    ("makefile", "makefile"),
+];
+
+/// languageId for a file by its name, or PLAIN_TEXT
+pub fn from_path(path: &Path) -> &'static str {
+    if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+        if let Some((_, id)) = BY_FILE_NAME
+            .iter()
+            .find(|(file_name, _)| *file_name == name)
+        {
+            return id;
+        }
+    }
+    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+        return PLAIN_TEXT;
+    };
+    let extension = extension.to_ascii_lowercase();
+    BY_EXTENSION
+        .binary_search_by(|(candidate, _)| candidate.cmp(&extension.as_str()))
+        .map_or(PLAIN_TEXT, |index| BY_EXTENSION[index].1)
+}
@@ -0,0 +1,76 @@
+//! The Claude Code companion server for Zed: an LSP server for the editor, an
+//! MCP server over WebSocket for the Claude CLI, and the bridge between them.
+//! `ServerBuilder` runs them in-process; the claude-code-server binary is a CLI
+//! over it.
+
+mod annotations;
+mod claude;
+mod command_errors;
+mod commands;
+mod commit_message;
+mod config;
+mod context;
+mod debounce;
+mod diagnostics;
+mod diff;
+mod documents;
+mod edits;
+mod file_events;
+mod file_tree;
+mod git;
+mod grep;
+mod hashes;
+mod hover;
+mod imports;
+mod indexer;
+mod journal;
+mod language;
+mod lock_file;
+pub mod logging;
+mod lsp;
+mod mcp;
+mod mentions;
+mod metrics;
+mod navigation;
+mod notification_schemas;
+mod notifications;
+mod paths;
+mod permissions;
+mod privacy;
+mod process;
+mod progress;
+mod prompts;
+mod proxy;
+mod refactor;
+mod resources;
+mod review;
+mod rpc;
+mod selections;
+mod server;
+mod session;
+mod settings;
+mod shell;
+mod shell_env;
+mod shutdown;
+mod sinks;
+mod sse;
+mod state;
+mod status;
+mod symbols;
+mod syntax;
+mod tasks;
+mod test_files;
+mod text;
+mod trust;
+mod websocket;
+mod workspace;
+mod zed_cli;
+
+pub use lsp::{
+    AtMentionedNotification, JsonRpcNotification, LspTransport, SelectionChangedNotification,
+};
+pub use proxy::LanguageServer;
+pub use server::ServerBuilder;
+pub use shutdown::Shutdown;
+pub use sinks::NotificationSink;
+pub use websocket::{McpTransport, DEFAULT_PORT};
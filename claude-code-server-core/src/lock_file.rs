@@ -0,0 +1,377 @@
+use anyhow::{anyhow, Result};
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::websocket::McpTransport;
+use crate::workspace::WorkspaceFolders;
+
+// Lock files in ~/.claude/ide/ are how the Claude CLI discovers IDE servers: one
+// per port, naming the workspace folders served there. A server that crashed
+// leaves its lock file behind, so on startup lock files of this server whose
+// process is gone are removed. As the pid may since have been given to another
+// process, a lock file only counts as live while its port or socket answers too.
+// A workspace is only registered by one running instance at a time; the pid and
+// start time in each lock file let the CLI tell instances apart, and a later
+// instance for the same folders waits until the earlier one is gone before
+// advertising itself.
+
+const IDE_NAME: &str = "claude-code-server";
+// How long a lock file's port gets to accept a connection
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockFile {
+    pub pid: u32,
+    /// When the server started, in milliseconds since the Unix epoch
+    #[serde(rename = "startedAt", default)]
+    pub started_at: u64,
+    #[serde(rename = "workspaceFolders")]
+    pub workspace_folders: Vec<String>,
+    #[serde(rename = "ideName")]
+    pub ide_name: String,
+    pub transport: String,
+    #[serde(rename = "runningInWindows")]
+    pub running_in_windows: bool,
+    #[serde(rename = "authToken")]
+    pub auth_token: String,
+    /// The Unix socket serving MCP, when the server listens on one rather than on
+    /// the port
+    #[serde(
+        rename = "socketPath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub socket_path: Option<String>,
+}
+
+impl LockFile {
+    /// Whether this server wrote it, from a process on the same side of WSL
+    fn is_ours(&self) -> bool {
+        self.ide_name == IDE_NAME && self.running_in_windows == cfg!(windows)
+    }
+
+    /// Whether the server that wrote it, to `path`, is still there: its process
+    /// runs and its socket or port answers. A pid reused by an unrelated process
+    /// therefore doesn't keep the lock file alive.
+    fn is_live(&self, path: &Path) -> bool {
+        if self.pid == process::id() {
+            return true;
+        }
+        if !is_running(self.pid) {
+            return false;
+        }
+        if let Some(socket_path) = &self.socket_path {
+            #[cfg(unix)]
+            return std::os::unix::net::UnixStream::connect(socket_path).is_ok();
+            #[cfg(not(unix))]
+            return Path::new(socket_path).exists();
+        }
+        // Lock files are named after their port
+        let Some(port) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u16>().ok())
+        else {
+            return true;
+        };
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        TcpStream::connect_timeout(&address, PROBE_TIMEOUT).is_ok()
+    }
+}
+
+/// Removes the lock file, and the socket it advertises, when dropped so the Claude
+/// CLI never discovers a server that is no longer listening
+pub struct LockFileGuard {
+    pub path: PathBuf,
+    pub socket_path: Option<PathBuf>,
+}
+
+impl Drop for LockFileGuard {
+    fn drop(&mut self) {
+        for path in std::iter::once(&self.path).chain(&self.socket_path) {
+            match fs::remove_file(path) {
+                Ok(()) => info!("Removed {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// What goes in this server's lock file besides its workspace folders
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub port: u16,
+    pub transport: McpTransport,
+    pub socket_path: Option<PathBuf>,
+    pub auth_token: String,
+    pub started_at: u64,
+}
+
+impl Registration {
+    pub fn new(
+        port: u16,
+        transport: McpTransport,
+        socket_path: Option<PathBuf>,
+        auth_token: String,
+    ) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Self {
+            port,
+            transport,
+            socket_path,
+            auth_token,
+            started_at,
+        }
+    }
+
+    pub fn path(&self) -> Result<PathBuf> {
+        Ok(ide_dir()?.join(format!("{}.lock", self.port)))
+    }
+}
+
+fn ide_dir() -> Result<PathBuf> {
+    let home = home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home.join(".claude").join("ide"))
+}
+
+pub async fn cleanup_existing_lock_file(port: u16) -> Result<()> {
+    let claude_dir = ide_dir()?;
+
+    if !claude_dir.exists() {
+        // Directory doesn't exist, nothing to clean up
+        return Ok(());
+    }
+
+    let lock_file_path = claude_dir.join(format!("{}.lock", port));
+
+    if lock_file_path.exists() {
+        info!("Removing existing lock file: {}", lock_file_path.display());
+        fs::remove_file(&lock_file_path)?;
+    }
+
+    Ok(())
+}
+
+/// Lock files of this server in ~/.claude/ide/, with their paths. Unreadable ones
+/// and those of other IDEs are skipped.
+fn our_lock_files() -> Vec<(PathBuf, LockFile)> {
+    let Ok(entries) = ide_dir().and_then(|dir| Ok(fs::read_dir(dir)?)) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "lock")
+        })
+        .filter_map(|path| {
+            let lock: LockFile = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+            lock.is_ours().then_some((path, lock))
+        })
+        .collect()
+}
+
+/// Remove lock files left by instances of this server that are no longer
+/// running, and the sockets they advertised. Blocks while probing them.
+pub fn remove_stale_lock_files() {
+    for (path, lock) in our_lock_files() {
+        if lock.is_live(&path) {
+            continue;
+        }
+        info!(
+            "Removing stale lock file {} of process {}, which is no longer serving",
+            path.display(),
+            lock.pid
+        );
+        drop(LockFileGuard {
+            path,
+            socket_path: lock.socket_path.map(PathBuf::from),
+        });
+    }
+}
+
+/// Whether the lock file for `port` belongs to another process that is still
+/// running, of this server or another IDE
+pub fn held_elsewhere(port: u16) -> bool {
+    let Ok(path) = ide_dir().map(|dir| dir.join(format!("{}.lock", port))) else {
+        return false;
+    };
+    fs::read(&path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<LockFile>(&bytes).ok())
+        .is_some_and(|lock| lock.pid != process::id() && lock.is_live(&path))
+}
+
+/// Other running instances that already advertise one of `folders`, as the path
+/// of each one's lock file, its pid and the folder it shares
+fn registered_elsewhere(folders: &[String]) -> Vec<(PathBuf, u32, String)> {
+    our_lock_files()
+        .into_iter()
+        .filter(|(_, lock)| lock.pid != process::id())
+        .filter_map(|(path, lock)| {
+            let shared = lock
+                .workspace_folders
+                .iter()
+                .find(|folder| folders.contains(folder))?
+                .clone();
+            lock.is_live(&path).then_some((path, lock.pid, shared))
+        })
+        .collect()
+}
+
+/// Folders advertised in the lock file: the known workspace roots, falling back to
+/// the worktree or the current directory
+pub fn lock_file_folders(
+    worktree: Option<&Path>,
+    workspace_folders: Option<&WorkspaceFolders>,
+) -> Result<Vec<String>> {
+    let roots = workspace_folders
+        .map(WorkspaceFolders::roots)
+        .unwrap_or_default();
+    if !roots.is_empty() {
+        return Ok(roots
+            .iter()
+            .map(|root| root.to_string_lossy().to_string())
+            .collect());
+    }
+
+    let workspace_folder = match worktree {
+        Some(wt) => wt.to_path_buf(),
+        None => env::current_dir()?,
+    };
+    Ok(vec![workspace_folder.to_string_lossy().to_string()])
+}
+
+/// Write the lock file advertising `folders`, unless another running instance
+/// already advertises one of them. Returns that instance's lock file and the
+/// folder it shares when it does. This reads every lock file and probes the
+/// servers behind them, so it blocks.
+pub fn register(
+    registration: &Registration,
+    folders: Vec<String>,
+) -> Result<Option<(PathBuf, String)>> {
+    if let Some((path, _, shared)) = registered_elsewhere(&folders).into_iter().next() {
+        return Ok(Some((path, shared)));
+    }
+    create_lock_file(registration, folders.clone())?;
+
+    // Another instance may have checked at the same time and written its own lock
+    // file too. Both look again, and the one written first keeps the folders.
+    let own_path = registration.path()?;
+    let own = (modified(&own_path), process::id());
+    let earlier = registered_elsewhere(&folders)
+        .into_iter()
+        .find(|(path, pid, _)| (modified(path), *pid) < own);
+    if let Some((path, _, shared)) = earlier {
+        fs::remove_file(&own_path)?;
+        return Ok(Some((path, shared)));
+    }
+    Ok(None)
+}
+
+/// When the file at `path` was last written
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn create_lock_file(registration: &Registration, workspace_folders: Vec<String>) -> Result<()> {
+    let claude_dir = ide_dir()?;
+
+    // Create directories if they don't exist, private to the current user
+    if !claude_dir.exists() {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&claude_dir)?;
+        info!("Created directory: {}", claude_dir.display());
+    }
+
+    let lock_file_data = LockFile {
+        pid: process::id(),
+        started_at: registration.started_at,
+        workspace_folders,
+        ide_name: IDE_NAME.to_string(),
+        transport: registration.transport.lock_file_name().to_string(),
+        running_in_windows: cfg!(windows),
+        auth_token: registration.auth_token.clone(),
+        socket_path: registration
+            .socket_path
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string()),
+    };
+
+    let lock_file_path = registration.path()?;
+    let json_data = serde_json::to_string_pretty(&lock_file_data)?;
+
+    // Write to a temporary file and rename, so the CLI never reads a partial lock
+    // file. The pid keeps instances writing for the same port apart.
+    let tmp_path = claude_dir.join(format!("{}.lock.{}.tmp", registration.port, process::id()));
+    match fs::remove_file(&tmp_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // The lock file holds the auth token, so it is private to the current user
+    // from the moment it exists
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut tmp_file = options.open(&tmp_path)?;
+    tmp_file.write_all(json_data.as_bytes())?;
+    drop(tmp_file);
+
+    fs::rename(&tmp_path, &lock_file_path)?;
+    info!("Created lock file: {}", lock_file_path.display());
+
+    Ok(())
+}
+
+/// Whether process `pid` is still running. When that can't be told, it is
+/// assumed to be, so a live server's lock file is never removed.
+fn is_running(pid: u32) -> bool {
+    if pid == process::id() {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Path::new("/proc").join(pid.to_string()).exists()
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    {
+        // ps finds processes of other users too, unlike kill -0
+        process::Command::new("ps")
+            .args(["-p", &pid.to_string()])
+            .output()
+            .map_or(true, |output| output.status.success())
+    }
+    #[cfg(windows)]
+    {
+        process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"])
+            .output()
+            .map_or(true, |output| {
+                String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid))
+            })
+    }
+}
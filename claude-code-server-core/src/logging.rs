@@ -0,0 +1,214 @@
+use anyhow::Result;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::{warn, Event, Level, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{filter_fn, FilterExt, LevelFilter, Targets};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer, Registry};
+
+// Logging setup. Everything goes to stderr, since stdout belongs to the LSP
+// transport. When a workspace is known, each component also writes its own
+// daily-rotated log file under <workspace>/.claude/logs. The latest error is
+// also kept in memory for the status report.
+
+const LOG_DIR: &str = ".claude/logs";
+const MAX_LOG_FILES: usize = 7;
+pub const DEFAULT_COMPONENT: &str = "lsp";
+
+/// Log file name prefix and the modules whose events it collects. Modules not
+/// listed here log to DEFAULT_COMPONENT.
+const COMPONENTS: &[(&str, &[&str])] = &[
+    (
+        "lsp",
+        &[
+            "lsp",
+            "config",
+            "context",
+            "debounce",
+            "diagnostics",
+            "documents",
+            "edits",
+            "hover",
+            "indexer",
+            "journal",
+            "permissions",
+            "progress",
+            "prompts",
+            "review",
+            "shutdown",
+            "symbols",
+            "text",
+            "trust",
+            "workspace",
+        ],
+    ),
+    (
+        "mcp",
+        &[
+            "mcp",
+            "grep",
+            "lock_file",
+            "navigation",
+            "websocket",
+            "notifications",
+            "proxy",
+            "resources",
+            "server",
+            "session",
+            "sinks",
+            "sse",
+            "status",
+        ],
+    ),
+    (
+        "commands",
+        &["shell", "shell_env", "git", "process", "claude"],
+    ),
+];
+
+static LOG_DIR_PATH: OnceLock<PathBuf> = OnceLock::new();
+static LAST_ERROR: Mutex<Option<LoggedError>> = Mutex::new(None);
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Install the global subscriber. File logging is skipped (with a warning) if the
+/// log directory can't be created.
+pub fn init(level: Level, workspace: Option<&Path>) -> Result<()> {
+    let stderr = fmt::layer()
+        .with_file(true)
+        .with_line_number(true)
+        .with_thread_ids(true)
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .with_filter(LevelFilter::from_level(level));
+
+    let mut files = Vec::new();
+    let mut file_error = None;
+    if let Some(workspace) = workspace {
+        let dir = workspace.join(LOG_DIR);
+        match component_layers(&dir, level) {
+            Ok(layers) => {
+                files = layers;
+                let _ = LOG_DIR_PATH.set(dir);
+            }
+            Err(e) => file_error = Some(e),
+        }
+    }
+
+    tracing_subscriber::registry()
+        .with(files)
+        .with(stderr)
+        .with(LastErrorLayer)
+        .try_init()?;
+
+    if let Some(e) = file_error {
+        warn!("Logging to files is disabled: {}", e);
+    }
+    Ok(())
+}
+
+fn component_layers(dir: &Path, level: Level) -> Result<Vec<BoxedLayer>> {
+    fs::create_dir_all(dir)?;
+    // Module paths are relative to the crate root this module lives in
+    let crate_name = module_path!()
+        .rsplit_once("::")
+        .map_or(module_path!(), |(root, _)| root);
+
+    let listed = Targets::new().with_targets(COMPONENTS.iter().flat_map(|(_, modules)| {
+        modules
+            .iter()
+            .map(move |module| (format!("{}::{}", crate_name, module), level))
+    }));
+    let crate_targets = Targets::new().with_target(crate_name, level);
+
+    let mut layers = Vec::new();
+    for (component, modules) in COMPONENTS {
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(*component)
+            .filename_suffix("log")
+            .max_log_files(MAX_LOG_FILES)
+            .build(dir)?;
+        let targets = Targets::new().with_targets(
+            modules
+                .iter()
+                .map(|module| (format!("{}::{}", crate_name, module), level)),
+        );
+        let layer = fmt::layer().with_ansi(false).with_writer(appender);
+        if *component != DEFAULT_COMPONENT {
+            layers.push(layer.with_filter(targets).boxed());
+            continue;
+        }
+        // The rest of the crate goes to the default component's file as well
+        let (listed, crate_targets) = (listed.clone(), crate_targets.clone());
+        let unlisted = filter_fn(move |metadata| {
+            let (target, level) = (metadata.target(), metadata.level());
+            crate_targets.would_enable(target, level) && !listed.would_enable(target, level)
+        });
+        layers.push(layer.with_filter(targets.or(unlisted)).boxed());
+    }
+    Ok(layers)
+}
+
+/// Directory the log files are written to, if file logging is enabled
+pub fn log_dir() -> Option<&'static Path> {
+    LOG_DIR_PATH.get().map(PathBuf::as_path)
+}
+
+/// The most recently written log file for `component` (lsp, mcp or commands)
+pub fn latest_log_file(component: &str) -> Option<PathBuf> {
+    let prefix = format!("{}.", component);
+    fs::read_dir(log_dir()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// An event logged at ERROR level
+#[derive(Debug, Clone)]
+pub struct LoggedError {
+    pub message: String,
+    pub at: Instant,
+}
+
+/// The most recent error logged by any component
+pub fn last_error() -> Option<LoggedError> {
+    LAST_ERROR.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Records the message of every error event in LAST_ERROR
+struct LastErrorLayer;
+
+impl<S: Subscriber> Layer<S> for LastErrorLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        *LAST_ERROR.lock().unwrap_or_else(|e| e.into_inner()) = Some(LoggedError {
+            message: message.0,
+            at: Instant::now(),
+        });
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
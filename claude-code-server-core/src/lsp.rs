@@ -0,0 +1,4432 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot, Notify, RwLock};
+use tower_lsp::jsonrpc::{Request, Result as LspResult};
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tracing::{debug, error, info, warn};
+
+use crate::annotations::{
+    self, ClearAnnotations, ClearAnnotationsParams, Explanation, ExplanationParams, Published,
+    SharedAnnotations,
+};
+use crate::claude::{self, ClaudeClient};
+use crate::command_errors;
+use crate::commands::{self, CommandInfo, CommandsChanged, CommandsChangedParams};
+use crate::commit_message;
+use crate::config::{Config, SharedConfig};
+use crate::context::{
+    self, ContextBudget, FileReference, FileTruncation, SurroundingLines, TextTruncation,
+};
+use crate::debounce::Debouncer;
+use crate::diff::{DiffPreview, DiffPreviewParams, Hunk};
+use crate::documents::OpenDocuments;
+use crate::edits::{self, EditPlan, FileEdit, FileOperation};
+use crate::file_events::{self, FileChange, RecentRemovals};
+use crate::git;
+use crate::hashes;
+use crate::hover::HoverExplainer;
+use crate::imports::{self, ImportedFile};
+use crate::indexer;
+use crate::journal::{self, JournalEntry, UndoJournal};
+use crate::language;
+use crate::logging;
+use crate::mentions::{self, SentMentions, TypedMention};
+use crate::metrics::SharedMetrics;
+use crate::notifications::{
+    NotificationCoalescer, SharedNotificationBus, SharedNotificationCoalescer,
+};
+use crate::paths;
+use crate::permissions::{Action, Decision, PermissionRequest};
+use crate::privacy;
+use crate::progress::{Cancellation, SharedCancellableWork, WorkDone};
+use crate::prompts::{self, Enclosing, PromptLibrary, PromptTemplate, PromptTemplates};
+use crate::refactor::{self, RefactorOutcome, Step};
+use crate::review::{self, ReviewScope, ReviewTarget};
+use crate::rpc::{self, RequestError};
+use crate::selections::SharedSelectionHistory;
+use crate::settings::{self, SettingsSources, SharedSettingsSources};
+use crate::shutdown::{Shutdown, EXIT_GRACE};
+use crate::state::{ServerState, SharedServerState};
+use crate::symbols::{self, SymbolCache, Symbols};
+use crate::syntax;
+use crate::test_files::{self, TestFile};
+use crate::text::{self, LineIndex, PositionEncoding};
+use crate::trust::{self, Trust, WorkspaceTrust};
+use crate::workspace::{self, WorkspaceFolders};
+use crate::zed_cli::ZedCli;
+
+// Notification structures for IDE to Claude communication
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectionChangedNotification {
+    pub text: String,
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "fileUrl")]
+    pub file_url: String,
+    /// Language of the file: the editor's languageId when it is open, otherwise
+    /// guessed from the file name
+    #[serde(
+        rename = "languageId",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub language_id: Option<String>,
+    pub selection: SelectionInfo,
+    /// Workspace folder containing the file, for disambiguating multi-root projects
+    #[serde(
+        rename = "workspaceRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workspace_root: Option<String>,
+    /// Path relative to `workspaceRoot`
+    #[serde(
+        rename = "relativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub relative_path: Option<String>,
+    /// Set when `text` was cut short to fit the context budget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<TextTruncation>,
+    /// Set instead of `text` when the file is too large to send from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<FileReference>,
+    /// Lines above and below the selection, with `selectionContextLines` set
+    #[serde(
+        rename = "surroundingLines",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub surrounding: Option<SurroundingLines>,
+    /// Every cursor, in the editor's order, when there are several; `selection`
+    /// and `text` are the first one's
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selections: Vec<SelectionInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SelectionInfo {
+    pub start: Position,
+    pub end: Position,
+    #[serde(rename = "isEmpty")]
+    pub is_empty: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AtMentionedNotification {
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    /// Language of a single mentioned file: the editor's languageId when it is open, otherwise
+    /// guessed from the file name
+    #[serde(
+        rename = "languageId",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub language_id: Option<String>,
+    #[serde(rename = "lineStart")]
+    pub line_start: u32,
+    #[serde(rename = "lineEnd")]
+    pub line_end: u32,
+    /// Workspace folder containing the file, for disambiguating multi-root projects
+    #[serde(
+        rename = "workspaceRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workspace_root: Option<String>,
+    /// Path relative to `workspaceRoot`
+    #[serde(
+        rename = "relativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub relative_path: Option<String>,
+    /// Files a directory or glob mention resolved to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    /// Set when `files` was cut short to fit the context budget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<FileTruncation>,
+    /// What to do with the mentioned code, from an "@claude <instruction>" typed
+    /// in it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instruction: Option<String>,
+    /// Qualified name ("foo::bar") the lines were resolved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    /// The mentioned lines, with atMentionText on and when they fit the context
+    /// budget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Lines in the whole file, with atMentionText on
+    #[serde(
+        rename = "totalLines",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub total_lines: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenedFileNotification {
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "fileUrl")]
+    pub file_url: String,
+    #[serde(rename = "languageId")]
+    pub language_id: String,
+    /// Workspace folder containing the file, for disambiguating multi-root projects
+    #[serde(
+        rename = "workspaceRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workspace_root: Option<String>,
+    /// Path relative to `workspaceRoot`
+    #[serde(
+        rename = "relativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub relative_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClosedFileNotification {
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "fileUrl")]
+    pub file_url: String,
+    /// Workspace folder containing the file, for disambiguating multi-root projects
+    #[serde(
+        rename = "workspaceRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workspace_root: Option<String>,
+    /// Path relative to `workspaceRoot`
+    #[serde(
+        rename = "relativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub relative_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileRenamedNotification {
+    #[serde(rename = "oldFilePath")]
+    pub old_file_path: String,
+    /// Where the file was, relative to its workspace folder
+    #[serde(
+        rename = "oldRelativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub old_relative_path: Option<String>,
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "fileUrl")]
+    pub file_url: String,
+    /// Whether a whole directory moved
+    #[serde(rename = "isDirectory")]
+    pub is_directory: bool,
+    /// Workspace folder containing the file, for disambiguating multi-root projects
+    #[serde(
+        rename = "workspaceRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workspace_root: Option<String>,
+    /// Path relative to `workspaceRoot`
+    #[serde(
+        rename = "relativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub relative_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileDeletedNotification {
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "fileUrl")]
+    pub file_url: String,
+    /// Workspace folder containing the file, for disambiguating multi-root projects
+    #[serde(
+        rename = "workspaceRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workspace_root: Option<String>,
+    /// Path relative to `workspaceRoot`
+    #[serde(
+        rename = "relativePath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub relative_path: Option<String>,
+}
+
+/// Params of the custom `claude/selectionDidChange` notification, sent by the
+/// editor side on every cursor/selection change
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectionDidChangeParams {
+    #[serde(rename = "textDocument")]
+    pub text_document: TextDocumentIdentifier,
+    /// The primary selection
+    pub selection: Range,
+    /// Every selection, the primary one first, when there are several cursors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selections: Vec<Range>,
+}
+
+/// Target of a Claude code action: carried in code action data and command arguments
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ActionTarget {
+    pub uri: Url,
+    pub range: Range,
+    /// Problem reported by another server, for "Fix with Claude" quick fixes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic: Option<Diagnostic>,
+}
+
+/// Where write-tests puts Claude's tests
+#[derive(Debug, Clone)]
+struct PlannedTests {
+    file: TestFile,
+    uri: Url,
+    /// The test file's text, when it exists
+    existing: Option<String>,
+    /// The text of the project's example test file
+    example: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+// Most recent debounced selection, readable by the MCP server on demand
+pub type SharedSelection = Arc<RwLock<Option<SelectionChangedNotification>>>;
+
+/// Where to put the cursor when opening a file, and what to select. Lines and
+/// columns are 1-based, matching the zed CLI `path:line:column` syntax, and the
+/// end is inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFileRange {
+    pub start_line: u32,
+    pub start_column: Option<u32>,
+    /// Last line selected; the start line when unset
+    pub end_line: Option<u32>,
+    /// Last column selected; the whole of the end line when only `end_line` is
+    /// set, and nothing when neither is
+    pub end_column: Option<u32>,
+}
+
+impl OpenFileRange {
+    /// The range as an LSP range, for window/showDocument
+    fn to_lsp(self) -> Range {
+        let start = Position {
+            line: self.start_line.saturating_sub(1),
+            character: self.start_column.unwrap_or(1).saturating_sub(1),
+        };
+        let end = match (self.end_line, self.end_column) {
+            (_, Some(column)) => Position {
+                line: self.end_line.unwrap_or(self.start_line).saturating_sub(1),
+                character: column,
+            },
+            // The whole line: up to the start of the next one
+            (Some(line), None) => Position { line, character: 0 },
+            (None, None) => start,
+        };
+        Range { start, end }
+    }
+}
+
+// Commands from WebSocket/MCP to LSP (for bidirectional communication)
+#[derive(Debug)]
+pub enum LspCommand {
+    /// Open a file in the editor, replying with whether it opened
+    OpenFile {
+        file_path: String,
+        range: Option<OpenFileRange>,
+        take_focus: bool,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
+    /// Dismiss a diff view. Zed offers no way to close a tab from a language server
+    /// or its CLI, so the file the diff was for is brought to the front instead.
+    CloseTab {
+        tab_name: String,
+        file_path: PathBuf,
+    },
+    /// Dismiss every diff view opened through openDiff, as with CloseTab
+    CloseAllDiffTabs { file_paths: Vec<PathBuf> },
+    /// Show the editor side how openDiff would change a file, through the custom
+    /// `claude/diffPreview` notification
+    PreviewDiff {
+        uri: Url,
+        tab_name: String,
+        hunks: Vec<Hunk>,
+    },
+    /// Ask the user, via window/showMessageRequest, whether Claude may use a
+    /// tool that is hard to undo
+    RequestPermission {
+        request: PermissionRequest,
+        reply: oneshot::Sender<Decision>,
+    },
+    /// Write unsaved buffers to disk: the document at `uri`, or every dirty one
+    SaveDocuments {
+        uri: Option<Url>,
+        reply: oneshot::Sender<Vec<(Url, anyhow::Result<()>)>>,
+    },
+    /// Apply validated edits with workspace/applyEdit, recording them in the undo
+    /// journal
+    ApplyEdits {
+        plan: EditPlan,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
+    /// Edit the files of an approved refactor plan one at a time, reporting
+    /// $/progress, until one fails
+    ApplyRefactor {
+        title: String,
+        steps: Vec<Step>,
+        reply: oneshot::Sender<RefactorOutcome>,
+    },
+    /// Revert the most recent edit in the undo journal
+    UndoLastEdit {
+        reply: oneshot::Sender<anyhow::Result<JournalEntry>>,
+    },
+    /// Create, delete or rename a file, through workspace/applyEdit when the
+    /// client supports it
+    ApplyFileOperation {
+        operation: FileOperation,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
+    /// Tell the user something with window/showMessage
+    ShowMessage { message: String },
+}
+
+// Channel types for commands
+pub type CommandSender = mpsc::Sender<LspCommand>;
+pub type CommandReceiver = mpsc::Receiver<LspCommand>;
+
+/// Notifications held by the debounce task until their key goes quiet
+#[derive(Debug)]
+enum Debounced {
+    /// Also kept as the latest selection once let through
+    Selection(Box<SelectionChangedNotification>),
+    Notification(JsonRpcNotification),
+}
+
+impl Debounced {
+    fn method(&self) -> &str {
+        match self {
+            Debounced::Selection(_) => "selection_changed",
+            Debounced::Notification(notification) => &notification.method,
+        }
+    }
+}
+
+/// Key, notification and debounce window, for the debounce task
+type DebounceSender = mpsc::UnboundedSender<(String, Debounced, Duration)>;
+type DebounceReceiver = mpsc::UnboundedReceiver<(String, Debounced, Duration)>;
+
+/// Called with each notification the debounce task lets through, just before it
+/// is sent. Tests make it panic to exercise the task's supervisor.
+#[derive(Clone)]
+pub struct DebounceHook(Arc<dyn Fn(&JsonRpcNotification) + Send + Sync>);
+
+impl DebounceHook {
+    pub fn new(hook: impl Fn(&JsonRpcNotification) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl std::fmt::Debug for DebounceHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DebounceHook")
+    }
+}
+
+/// Holds notifications until their key goes quiet, then sends them to Claude.
+/// Runs under a supervisor that starts it again if it panics, so selections keep
+/// flowing; whatever it held at the time is lost.
+#[derive(Clone)]
+struct DebounceTask {
+    /// Locked by the running task, and released for the next one if it panics
+    receiver: Arc<tokio::sync::Mutex<DebounceReceiver>>,
+    notification_sender: SharedNotificationCoalescer,
+    latest_selection: SharedSelection,
+    selection_history: SharedSelectionHistory,
+    shutdown: Shutdown,
+    metrics: SharedMetrics,
+    hook: Option<DebounceHook>,
+}
+
+impl DebounceTask {
+    async fn supervise(self) {
+        loop {
+            match tokio::spawn(self.clone().run()).await {
+                Ok(()) => return,
+                Err(e) if e.is_panic() => {
+                    error!("The debounce task panicked, restarting it: {}", e);
+                    self.metrics.debounce_restarted();
+                    // A task that keeps panicking is restarted at a bounded rate
+                    tokio::select! {
+                        _ = tokio::time::sleep(DEBOUNCE_RESTART_DELAY) => {}
+                        _ = self.shutdown.triggered() => return,
+                    }
+                }
+                Err(e) => {
+                    warn!("The debounce task stopped: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Until the channel closes or the server shuts down
+    async fn run(self) {
+        let mut receiver = self.receiver.lock().await;
+        let mut debouncer = Debouncer::default();
+        let mut last_sent: Option<SelectionChangedNotification> = None;
+
+        loop {
+            let debounced = tokio::select! {
+                pushed = receiver.recv() => match pushed {
+                    Some((key, debounced, window)) => {
+                        if let Some(dropped) = debouncer.push(key, debounced, window) {
+                            self.metrics.debounce_dropped(dropped.method());
+                        }
+                        continue;
+                    }
+                    None => break, // Channel closed
+                },
+                (_, debounced) = debouncer.ready() => debounced,
+                _ = self.shutdown.triggered() => break,
+            };
+
+            let notification = match debounced {
+                Debounced::Selection(selection) => {
+                    *self.latest_selection.write().await = Some((*selection).clone());
+
+                    // Only send if different from last sent
+                    let unchanged = last_sent.as_ref().is_some_and(|last| {
+                        last.file_path == selection.file_path
+                            && last.selection.start == selection.selection.start
+                            && last.selection.end == selection.selection.end
+                            && last.selections == selection.selections
+                    });
+                    if unchanged {
+                        continue;
+                    }
+                    self.selection_history.record(&selection);
+                    let notification = JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "selection_changed".to_string(),
+                        params: serde_json::to_value(&selection).unwrap_or_default(),
+                    };
+                    last_sent = Some(*selection);
+                    notification
+                }
+                Debounced::Notification(notification) => notification,
+            };
+            if let Some(hook) = &self.hook {
+                (hook.0)(&notification);
+            }
+            debug!("Sending debounced {} notification", notification.method);
+            self.notification_sender.send(notification).await;
+        }
+    }
+}
+
+// Requests tower-lsp handles at once. Claude actions keep their executeCommand
+// request open until Claude is done, so they can be cancelled, and shouldn't hold
+// up the rest.
+const MAX_CONCURRENT_REQUESTS: usize = 32;
+
+// Pause before the debounce task is started again after a panic
+const DEBOUNCE_RESTART_DELAY: Duration = Duration::from_millis(100);
+
+// Id of the workspace/didChangeWatchedFiles registration, to replace it when the
+// workspace folders change
+const WATCHED_FILES_REGISTRATION: &str = "claude-code-watched-files";
+
+// Client capabilities captured at initialize, shared with the command handler
+pub type SharedClientCapabilities = Arc<OnceLock<ClientCapabilities>>;
+
+#[derive(Debug)]
+pub struct ClaudeCodeLanguageServer {
+    client: Client,
+    /// Editor state shared with the MCP server and the command handler
+    state: SharedServerState,
+    /// Coalesces notifications before they reach the bus
+    notification_sender: Option<SharedNotificationCoalescer>,
+    /// The bus itself, for requests to Claude
+    notification_bus: Option<SharedNotificationBus>,
+    /// Selections and at-mentions go through the debounce task first
+    debouncer: Option<DebounceSender>,
+    client_capabilities: SharedClientCapabilities,
+    /// The editor's settings, merged with the settings files into `config`
+    settings: SharedSettingsSources,
+    hover: HoverExplainer,
+    /// Declarations found in open documents, reused until a document changes
+    symbols: SymbolCache,
+    file_removals: RecentRemovals,
+    /// Prompt templates per worktree, behind the Claude code actions
+    prompts: PromptLibrary,
+    /// Review findings and explanations published as diagnostics, cleared when
+    /// their documents close
+    annotations: SharedAnnotations,
+    /// Mentions typed into documents and sent, for their inlay hints
+    sent_mentions: SentMentions,
+    /// Claude requests the client can cancel through their progress
+    cancellable_work: SharedCancellableWork,
+}
+
+impl ClaudeCodeLanguageServer {
+    pub fn new(client: Client, state: SharedServerState) -> Self {
+        Self {
+            client,
+            state,
+            settings: SharedSettingsSources::default(),
+            notification_sender: None,
+            notification_bus: None,
+            debouncer: None,
+            client_capabilities: SharedClientCapabilities::default(),
+            hover: HoverExplainer::default(),
+            symbols: SymbolCache::default(),
+            file_removals: RecentRemovals::default(),
+            prompts: PromptLibrary::default(),
+            annotations: SharedAnnotations::default(),
+            sent_mentions: SentMentions::default(),
+            cancellable_work: SharedCancellableWork::default(),
+        }
+    }
+
+    pub fn with_client_capabilities(mut self, capabilities: SharedClientCapabilities) -> Self {
+        self.client_capabilities = capabilities;
+        self
+    }
+
+    pub fn with_notification_sender(mut self, bus: SharedNotificationBus) -> Self {
+        let sender = Arc::new(NotificationCoalescer::new(
+            bus.clone(),
+            self.state.config.clone(),
+            self.state.status.metrics().clone(),
+            self.client.clone(),
+        ));
+        self.notification_bus = Some(bus);
+
+        let (debounce_tx, debounce_rx) = mpsc::unbounded_channel();
+        self.debouncer = Some(debounce_tx);
+
+        let task = DebounceTask {
+            receiver: Arc::new(tokio::sync::Mutex::new(debounce_rx)),
+            notification_sender: sender.clone(),
+            latest_selection: self.state.latest_selection.clone(),
+            selection_history: self.state.selection_history.clone(),
+            shutdown: self.state.shutdown.clone(),
+            metrics: self.state.status.metrics().clone(),
+            hook: self.state.debounce_hook.clone(),
+        };
+        tokio::spawn(task.supervise());
+
+        self.notification_sender = Some(sender);
+        self
+    }
+
+    /// The client's support for workspace/didChangeWatchedFiles
+    fn watched_files_capabilities(&self) -> Option<DidChangeWatchedFilesClientCapabilities> {
+        self.client_capabilities
+            .get()
+            .and_then(|capabilities| capabilities.workspace.as_ref())
+            .and_then(|workspace| workspace.did_change_watched_files)
+    }
+
+    /// Ask the client to report changes to every file in the workspace through
+    /// workspace/didChangeWatchedFiles, when it supports registering for them
+    async fn watch_files(&self) {
+        let capabilities = self.watched_files_capabilities().unwrap_or_default();
+        if !capabilities.dynamic_registration.unwrap_or(false) {
+            debug!("Client can't register file watchers, renames on disk go unnoticed");
+            return;
+        }
+
+        // Where the client can, every file is watched in the workspace folders
+        // only rather than anywhere it looks
+        let folders = self.state.workspace_folders.list();
+        let every_file =
+            if capabilities.relative_pattern_support.unwrap_or(false) && !folders.is_empty() {
+                folders
+                    .into_iter()
+                    .map(|folder| {
+                        GlobPattern::Relative(RelativePattern {
+                            base_uri: OneOf::Left(folder),
+                            pattern: "**/*".to_string(),
+                        })
+                    })
+                    .collect()
+            } else {
+                vec![GlobPattern::String("**/*".to_string())]
+            };
+        // The settings files and prompt templates are named as well, since clients
+        // may leave ignored directories like .claude out of **/*
+        let watchers = every_file
+            .into_iter()
+            .chain(
+                ["**/.claude/settings.json", "**/.claude/commands/**"]
+                    .map(|pattern| GlobPattern::String(pattern.to_string())),
+            )
+            .map(|glob_pattern| FileSystemWatcher {
+                glob_pattern,
+                kind: None,
+            })
+            .collect();
+        let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+        let registration = Registration {
+            id: WATCHED_FILES_REGISTRATION.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            warn!("Failed to register file watchers: {}", e);
+        }
+    }
+
+    /// Register the file watchers again after the workspace folders changed, when
+    /// they are scoped to the folders
+    async fn rewatch_files(&self) {
+        let capabilities = self.watched_files_capabilities().unwrap_or_default();
+        if !capabilities.dynamic_registration.unwrap_or(false)
+            || !capabilities.relative_pattern_support.unwrap_or(false)
+        {
+            return;
+        }
+        let unregistration = Unregistration {
+            id: WATCHED_FILES_REGISTRATION.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+        };
+        if let Err(e) = self
+            .client
+            .unregister_capability(vec![unregistration])
+            .await
+        {
+            warn!("Failed to unregister file watchers: {}", e);
+        }
+        self.watch_files().await;
+    }
+
+    /// Bring open documents, the symbol index and diff tabs in line with files
+    /// renamed, deleted or changed on disk, and tell Claude about renames and
+    /// deletions. Changed prompt templates and settings files are loaded again,
+    /// and the editor side told about the commands they bring.
+    async fn apply_file_changes(&self, changes: Vec<FileChange>) {
+        let roots = self.state.workspace_folders.roots();
+        let (mut templates_changed, mut settings_changed) = (false, false);
+        let mut reindex = Vec::new();
+        for change in changes {
+            let touched = match &change {
+                FileChange::Renamed { from, to } => vec![from.clone(), to.clone()],
+                FileChange::Deleted(path) | FileChange::Changed(path) => vec![path.clone()],
+            };
+            for path in &touched {
+                self.prompts.changed(path);
+                templates_changed |= roots
+                    .iter()
+                    .any(|root| prompts::affects_templates(root, path));
+                settings_changed |= settings::is_settings_path(path, &roots);
+            }
+            match change {
+                FileChange::Renamed { from, to } => {
+                    if !self.file_removals.is_new(&from) {
+                        continue;
+                    }
+                    info!("{} renamed to {}", from.display(), to.display());
+                    for (old_uri, _) in self.state.documents.rename(&from, &to).await {
+                        self.symbols.remove(&old_uri);
+                    }
+                    for file_path in self.state.diff_tabs.write().await.values_mut() {
+                        if let Some(moved) = file_events::moved_path(file_path, &from, &to) {
+                            *file_path = moved;
+                        }
+                    }
+                    reindex.push(FileChange::Renamed {
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+
+                    let Some(file_url) = paths::to_url(&to) else {
+                        continue;
+                    };
+                    let notification = FileRenamedNotification {
+                        old_file_path: from.to_string_lossy().to_string(),
+                        old_relative_path: self.relative_path(&from),
+                        file_path: to.to_string_lossy().to_string(),
+                        workspace_root: self.root_string(&file_url),
+                        relative_path: self.relative_path(&to),
+                        file_url: file_url.to_string(),
+                        is_directory: to.is_dir(),
+                    };
+                    self.send_notification(
+                        "file_renamed",
+                        serde_json::to_value(notification).unwrap_or_default(),
+                    )
+                    .await;
+                }
+                FileChange::Deleted(path) => {
+                    if !self.file_removals.is_new(&path) {
+                        continue;
+                    }
+                    info!("{} deleted", path.display());
+                    self.state.documents.deleted(&path).await;
+                    self.state
+                        .diff_tabs
+                        .write()
+                        .await
+                        .retain(|_, file_path| !file_path.starts_with(&path));
+                    reindex.push(FileChange::Deleted(path.clone()));
+
+                    let Some(file_url) = paths::to_url(&path) else {
+                        continue;
+                    };
+                    let notification = FileDeletedNotification {
+                        file_path: path.to_string_lossy().to_string(),
+                        workspace_root: self.root_string(&file_url),
+                        relative_path: self.relative_path(&path),
+                        file_url: file_url.to_string(),
+                    };
+                    self.send_notification(
+                        "file_deleted",
+                        serde_json::to_value(notification).unwrap_or_default(),
+                    )
+                    .await;
+                }
+                FileChange::Changed(_) => reindex.push(change),
+            }
+        }
+
+        if settings_changed {
+            info!("Settings files changed, reloading settings");
+            self.reload_config().await;
+        }
+        if templates_changed || settings_changed {
+            self.commands_changed().await;
+        }
+
+        if reindex.is_empty() {
+            return;
+        }
+        let index = self.state.symbol_index.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut new_directory = false;
+            for change in reindex {
+                match change {
+                    FileChange::Renamed { from, to } => index.rename(&from, &to),
+                    FileChange::Deleted(path) => index.remove(&path),
+                    FileChange::Changed(path) if path.is_dir() => new_directory = true,
+                    FileChange::Changed(path) => index.update_file(&path),
+                }
+            }
+            // A new directory brings files the index has never seen
+            if new_directory {
+                index.refresh_for_new_directory(&roots);
+            }
+        });
+    }
+
+    async fn send_notification(&self, method: &str, params: serde_json::Value) {
+        if let Some(sender) = &self.notification_sender {
+            let notification = JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: method.to_string(),
+                params,
+            };
+
+            sender.send(notification).await;
+        }
+    }
+
+    /// Custom `claude/publishDiagnostics` notification: lets the editor side forward
+    /// publishDiagnostics from other language servers for a whole file
+    pub async fn publish_diagnostics(&self, params: PublishDiagnosticsParams) {
+        debug!(
+            "Received {} diagnostics for {}",
+            params.diagnostics.len(),
+            params.uri
+        );
+        if !self.state.config.read().await.features.diagnostics {
+            return;
+        }
+        self.state
+            .diagnostics
+            .publish(params.uri, params.diagnostics)
+            .await;
+    }
+
+    /// Merge the editor's settings with the settings files again, and apply them
+    async fn reload_config(&self) {
+        reload_config(
+            &self.settings,
+            &self.state.workspace_folders,
+            &self.state.trust,
+            &self.state.config,
+            self.notification_sender.as_ref(),
+        )
+        .await;
+    }
+
+    /// Ask the user whether they trust the workspace, then apply the answer: the
+    /// workspace's settings files and Claude's write tools follow it
+    async fn ask_trust(&self) -> Trust {
+        let trust = ask_trust(
+            &self.client,
+            &self.state.trust,
+            &self.state.workspace_folders,
+        )
+        .await;
+        self.reload_config().await;
+        if let Some(sender) = &self.notification_sender {
+            send_tools_list_changed(sender).await;
+        }
+        trust
+    }
+
+    /// Claude client using the configured binary and model, run from the project
+    /// `uri` belongs to
+    async fn claude_client(&self, uri: &Url) -> ClaudeClient {
+        ClaudeClient::new(&*self.state.config.read().await)
+            .with_working_dir(self.workspace_root(uri))
+    }
+
+    /// Root of the workspace folder containing `uri`, if any
+    fn workspace_root(&self, uri: &Url) -> Option<PathBuf> {
+        self.state
+            .workspace_folders
+            .root_for(&paths::file_path(uri))
+    }
+
+    /// `path` relative to the workspace folder containing it
+    fn relative_path(&self, path: &Path) -> Option<String> {
+        let root = self.state.workspace_folders.root_for(path)?;
+        paths::relative(path, &root)
+    }
+
+    /// Whether the client can resolve the `command` of a code action lazily
+    fn supports_code_action_resolve(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|caps| caps.text_document.as_ref())
+            .and_then(|text_document| text_document.code_action.as_ref())
+            .and_then(|code_action| code_action.resolve_support.as_ref())
+            .is_some_and(|resolve| resolve.properties.iter().any(|p| p == "command"))
+    }
+
+    /// Send the editor side every command again, as the prompt templates or
+    /// settings that shape them changed, and have it request code lenses again
+    async fn commands_changed(&self) {
+        let commands = self.list_commands();
+        info!("Commands changed, {} commands now", commands.len());
+        self.client
+            .send_notification::<CommandsChanged>(CommandsChangedParams { commands })
+            .await;
+        if self.supports_code_lens_refresh() {
+            if let Err(e) = self.client.code_lens_refresh().await {
+                debug!("workspace/codeLens/refresh failed: {}", e);
+            }
+        }
+    }
+
+    /// Whether the client can be asked to request code lenses again
+    fn supports_code_lens_refresh(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|caps| caps.workspace.as_ref())
+            .and_then(|workspace| workspace.code_lens.as_ref())
+            .and_then(|code_lens| code_lens.refresh_support)
+            .unwrap_or(false)
+    }
+
+    /// Whether the client can be asked to request inlay hints again
+    fn supports_inlay_hint_refresh(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|caps| caps.workspace.as_ref())
+            .and_then(|workspace| workspace.inlay_hint.as_ref())
+            .and_then(|inlay_hint| inlay_hint.refresh_support)
+            .unwrap_or(false)
+    }
+
+    /// Whether the client expands snippets in completion items
+    fn supports_completion_snippets(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|caps| caps.text_document.as_ref())
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|item| item.snippet_support)
+            .unwrap_or(false)
+    }
+
+    /// Whether the client accepts server-initiated window/workDoneProgress/create
+    fn supports_work_done_progress(&self) -> bool {
+        supports_work_done_progress(&self.client_capabilities)
+    }
+
+    /// Run a prompt template, streaming its output into $/progress: answers are
+    /// shown to the user, unless the template edits code, in which case they
+    /// replace the target range via workspace/applyEdit. Returns once Claude is
+    /// done. Cancelling the request, or its progress, stops Claude and shows what it
+    /// had written so far, marked as cancelled.
+    async fn run_claude_action(
+        &self,
+        template: PromptTemplate,
+        target: ActionTarget,
+        progress_token: Option<ProgressToken>,
+    ) {
+        let code = self.read_text_from_range(&paths::file_path(&target.uri), target.range);
+        if code.is_empty() {
+            warn!("No code to {} at {:?}", template.name, target.range);
+        }
+        let enclosing = self.enclosing(&target.uri, target.range).await;
+        let imported = self.imported_files(&target.uri, target.range, &code).await;
+        let roots = self.state.workspace_folders.roots();
+        // What the file looked like when Claude was asked, to notice it changing
+        // while Claude works
+        let seen = match self.state.documents.get(&target.uri).await {
+            Some(document) => Some(hashes::hash(&document.text)),
+            None => target
+                .uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| text::read_file(path).ok())
+                .map(|text| hashes::hash(&text)),
+        };
+
+        let tests = if template.writes_tests() {
+            self.planned_tests(&target.uri).await
+        } else {
+            None
+        };
+        let resource_operations =
+            supports_resource_operation(&self.client_capabilities, ResourceOperationKind::Create);
+
+        let client = self.client.clone();
+        let claude = self.claude_client(&target.uri).await;
+        let can_create_progress = self.supports_work_done_progress();
+        let document_changes = supports_document_changes(&self.client_capabilities);
+        let documents = self.state.documents.clone();
+        let undo_journal = self.state.undo_journal.clone();
+        let annotations = self
+            .state
+            .config
+            .read()
+            .await
+            .features
+            .explanations
+            .then(|| self.annotations.clone());
+        let cancellable_work = self.cancellable_work.clone();
+        let cancellation = Cancellation::default();
+        let name = template.name.clone();
+        let job = tokio::spawn({
+            let cancellation = cancellation.clone();
+            async move {
+                let progress = WorkDone::begin(
+                    &client,
+                    progress_token,
+                    can_create_progress,
+                    &template.title,
+                    Some((&cancellable_work, &cancellation)),
+                )
+                .await;
+                if !progress.is_active() {
+                    client
+                        .show_message(
+                            MessageType::INFO,
+                            format!("Claude Code: {}...", template.title),
+                        )
+                        .await;
+                }
+
+                let (partial_sender, mut partial_receiver) = mpsc::unbounded_channel();
+                let file_path = paths::path_string(&target.uri);
+                let mut prompt = match &target.diagnostic {
+                    Some(diagnostic) => template.prompt_for_diagnostic(
+                        &file_path,
+                        &code,
+                        enclosing.as_ref(),
+                        diagnostic,
+                    ),
+                    None => template.prompt(&file_path, &code, enclosing.as_ref()),
+                };
+                prompt.push_str(&imports::describe(&imported, &roots));
+                if let Some(tests) = &tests {
+                    prompt.push_str(
+                        &tests
+                            .file
+                            .instructions(tests.existing.as_deref(), tests.example.as_deref()),
+                    );
+                }
+                let mut streamed = String::new();
+                // Leaving this block drops the request, which stops the CLI
+                let response = {
+                    let request = claude.ask(&prompt, Some(partial_sender));
+                    tokio::pin!(request);
+                    loop {
+                        tokio::select! {
+                            response = &mut request => break Some(response),
+                            Some(text) = partial_receiver.recv() => {
+                                streamed.push_str(&text);
+                                // Fold whatever else already arrived into the same report
+                                while let Ok(text) = partial_receiver.try_recv() {
+                                    streamed.push_str(&text);
+                                }
+                                progress.report_text(&streamed).await;
+                            }
+                            _ = cancellation.cancelled() => break None,
+                        }
+                    }
+                };
+
+                let response = match response {
+                    None => {
+                        progress.end(Some("Cancelled".to_string())).await;
+                        info!("Claude {} request cancelled", template.name);
+                        client
+                            .show_message(
+                                MessageType::INFO,
+                                cancelled_message(&template.title, &streamed),
+                            )
+                            .await;
+                        return;
+                    }
+                    Some(Ok(response)) => {
+                        progress.end(None).await;
+                        response
+                    }
+                    Some(Err(e)) => {
+                        progress.end(Some("Failed".to_string())).await;
+                        error!("Claude {} request failed: {}", template.name, e);
+                        client
+                            .show_message(MessageType::ERROR, format!("Claude Code: {}", e))
+                            .await;
+                        return;
+                    }
+                };
+
+                if let Some(tests) = tests {
+                    let written = write_tests(
+                        &client,
+                        &tests,
+                        &claude::extract_code(&response),
+                        &documents,
+                        &undo_journal,
+                        document_changes,
+                        resource_operations,
+                    )
+                    .await;
+                    let (kind, message) = match written {
+                        Ok(()) => {
+                            info!("Wrote Claude's tests to {}", tests.file.path.display());
+                            (
+                                MessageType::INFO,
+                                format!(
+                                    "Claude Code: wrote tests to {}",
+                                    tests.file.path.display()
+                                ),
+                            )
+                        }
+                        Err(e) => {
+                            warn!("Claude's tests were not written: {}", e);
+                            (
+                                MessageType::WARNING,
+                                format!("Claude Code: tests were not written: {}", e),
+                            )
+                        }
+                    };
+                    client.show_message(kind, message).await;
+                    return;
+                }
+                if !template.edits_code {
+                    // The answer explains the code, so it stays next to it
+                    if let Some(annotations) = annotations {
+                        client
+                            .send_notification::<Explanation>(ExplanationParams {
+                                text_document: TextDocumentIdentifier {
+                                    uri: target.uri.clone(),
+                                },
+                                range: target.range,
+                                explanation: response.clone(),
+                            })
+                            .await;
+                        publish(
+                            &client,
+                            annotations.explain(&target.uri, target.range, &response),
+                        )
+                        .await;
+                    }
+                    client.show_message(MessageType::INFO, response).await;
+                    return;
+                }
+
+                let edit = FileEdit {
+                    file_path: target
+                        .uri
+                        .to_file_path()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    range: target.range,
+                    // The range usually covers whole lines
+                    new_text: text::keep_trailing_newline(&claude::extract_code(&response), &code),
+                    old_text: None,
+                };
+                let plan = match edits::prepare(vec![edit], &[], &documents).await {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        warn!("Claude {} edit was not applied: {}", template.name, e);
+                        client
+                            .show_message(
+                                MessageType::WARNING,
+                                format!("Claude Code: edit was not applied: {}", e),
+                            )
+                            .await;
+                        return;
+                    }
+                };
+                let drifted = plan
+                    .files
+                    .iter()
+                    .any(|file| seen.is_some_and(|seen| hashes::hash(file.original()) != seen));
+                if drifted {
+                    let request = PermissionRequest {
+                        action: Action::ApplyEdits,
+                        message: format!(
+                            "{} changed while Claude was working on it. Apply Claude's {} edit anyway?",
+                            paths::file_path(&target.uri).display(),
+                            template.name
+                        ),
+                    };
+                    if !request_permission(&client, request).await.allowed() {
+                        info!(
+                            "User dropped the {} edit to changed {}",
+                            template.name, target.uri
+                        );
+                        return;
+                    }
+                }
+                let applied = edits::apply(&client, &plan, document_changes)
+                    .await
+                    .map(|()| plan);
+
+                match applied {
+                    Ok(plan) => {
+                        info!("Applied Claude {} edit to {}", template.name, target.uri);
+                        undo_journal.record(JournalEntry::new(&template.name, plan.snapshots()));
+                    }
+                    Err(e) => {
+                        warn!("Claude {} edit was not applied: {}", template.name, e);
+                        client
+                            .show_message(
+                                MessageType::WARNING,
+                                format!("Claude Code: edit was not applied: {}", e),
+                            )
+                            .await;
+                    }
+                }
+            }
+        });
+
+        // tower-lsp drops this handler when the client cancels the request
+        let _cancel_on_drop = cancellation.cancel_on_drop();
+        if let Err(e) = job.await {
+            error!("Claude {} task failed: {}", name, e);
+        }
+    }
+
+    /// Where write-tests puts tests for the file at `uri`, following the layout
+    /// of its workspace folder. None for languages without a known layout.
+    async fn planned_tests(&self, uri: &Url) -> Option<PlannedTests> {
+        let source = paths::file_path(uri);
+        let root = self.workspace_root(uri)?;
+        let file = test_files::locate(&source, &root)?;
+        let uri = Url::from_file_path(&file.path).ok()?;
+        let existing = match self.state.documents.get(&uri).await {
+            Some(document) => Some(document.text),
+            None => text::read_file(&file.path).ok(),
+        };
+        let example = file.example.as_deref().and_then(test_files::read_example);
+        Some(PlannedTests {
+            file,
+            uri,
+            existing,
+            example,
+        })
+    }
+
+    /// Run the prompt template behind a `claude-code.<name>` command on its
+    /// `{ uri, range }` argument
+    async fn execute_prompt_command(&self, params: &ExecuteCommandParams) -> LspResult<()> {
+        let args = params.arguments.first();
+        let target =
+            args.and_then(|args| serde_json::from_value::<ActionTarget>(args.clone()).ok());
+        let Some(target) = target else {
+            // Without a file, any worktree's template of that name will do
+            let roots = self.state.workspace_folders.roots();
+            let known = std::iter::once(None)
+                .chain(roots.iter().map(|root| Some(root.as_path())))
+                .any(|root| {
+                    self.prompts
+                        .for_root(root)
+                        .for_command(&params.command)
+                        .is_some()
+                });
+            if !known {
+                return Err(command_errors::unknown_command(&params.command));
+            }
+            return Err(command_errors::invalid_arguments(
+                &params.command,
+                "{ uri, range }",
+                args,
+            ));
+        };
+
+        let templates = self
+            .prompts
+            .for_root(self.workspace_root(&target.uri).as_deref());
+        let Some(template) = templates.for_command(&params.command) else {
+            return Err(command_errors::unknown_command(&params.command));
+        };
+        self.readable(&target.uri).await?;
+        self.run_claude_action(
+            template.clone(),
+            target,
+            params.work_done_progress_params.work_done_token.clone(),
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Fails unless `uri` is open or a file that can be read
+    async fn readable(&self, uri: &Url) -> LspResult<()> {
+        if self.state.documents.get(uri).await.is_some() {
+            return Ok(());
+        }
+        let path = paths::file_path(uri);
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => Ok(()),
+            Ok(_) => Err(command_errors::file_not_found(&path, "not a file")),
+            Err(e) => Err(command_errors::file_not_found(&path, e)),
+        }
+    }
+
+    /// Review a file, or its changes since HEAD, and publish Claude's findings as
+    /// diagnostics, replacing those of an earlier review. Cancellable like
+    /// run_claude_action, leaving earlier findings in place.
+    async fn run_review(
+        &self,
+        target: ReviewTarget,
+        progress_token: Option<ProgressToken>,
+    ) -> LspResult<()> {
+        let path = paths::file_path(&target.uri);
+        // The buffer when the file is open, so findings line up with it
+        let (text, version) = match self.state.documents.get(&target.uri).await {
+            Some(document) => (document.text, Some(document.version)),
+            None => match text::read_file(&path) {
+                Ok(text) => (text, None),
+                Err(e) => return Err(command_errors::file_not_found(&path, e)),
+            },
+        };
+
+        let diff = match target.scope {
+            ReviewScope::File => None,
+            ReviewScope::Diff => {
+                let options = git::DiffOptions {
+                    base: Some("HEAD".to_string()),
+                    path: Some(path.clone()),
+                    ..Default::default()
+                };
+                let dir = path.parent().unwrap_or(&path);
+                match git::diff(dir, &options).await {
+                    Ok(diff) if !diff.diff.trim().is_empty() => Some(diff.diff),
+                    Ok(_) => {
+                        self.client
+                            .show_message(
+                                MessageType::INFO,
+                                format!(
+                                    "Claude Code: {} has no changes since the last commit",
+                                    path.display()
+                                ),
+                            )
+                            .await;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        self.client
+                            .show_message(MessageType::ERROR, format!("Claude Code: {}", e))
+                            .await;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let prompt = review::prompt(&paths::path_string(&target.uri), &text, diff.as_deref());
+        let client = self.client.clone();
+        let claude = self.claude_client(&target.uri).await;
+        let can_create_progress = self.supports_work_done_progress();
+        let encoding = self.state.documents.encoding();
+        let cancellable_work = self.cancellable_work.clone();
+        let cancellation = Cancellation::default();
+        let annotations = self.annotations.clone();
+        let job = tokio::spawn({
+            let cancellation = cancellation.clone();
+            async move {
+                let title = "Reviewing with Claude";
+                let progress = WorkDone::begin(
+                    &client,
+                    progress_token,
+                    can_create_progress,
+                    title,
+                    Some((&cancellable_work, &cancellation)),
+                )
+                .await;
+                if !progress.is_active() {
+                    client
+                        .show_message(MessageType::INFO, format!("Claude Code: {}...", title))
+                        .await;
+                }
+
+                let response = tokio::select! {
+                    response = claude.ask(&prompt, None) => response,
+                    _ = cancellation.cancelled() => {
+                        progress.end(Some("Cancelled".to_string())).await;
+                        info!("Claude review of {} cancelled", target.uri);
+                        client
+                            .show_message(MessageType::INFO, "Claude Code: review cancelled")
+                            .await;
+                        return;
+                    }
+                };
+                let findings =
+                    response.and_then(|response| review::diagnostics(&response, &text, encoding));
+                let diagnostics = match findings {
+                    Ok(diagnostics) => {
+                        progress
+                            .end(Some(format!("Findings: {}", diagnostics.len())))
+                            .await;
+                        diagnostics
+                    }
+                    Err(e) => {
+                        progress.end(Some("Failed".to_string())).await;
+                        error!("Claude review of {} failed: {}", target.uri, e);
+                        client
+                            .show_message(MessageType::ERROR, format!("Claude Code: {}", e))
+                            .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    "Claude review of {}: {} findings",
+                    target.uri,
+                    diagnostics.len()
+                );
+                if diagnostics.is_empty() {
+                    client
+                        .show_message(MessageType::INFO, "Claude Code: review found no problems")
+                        .await;
+                }
+                publish(
+                    &client,
+                    annotations.set_review(&target.uri, diagnostics, version),
+                )
+                .await;
+            }
+        });
+
+        // tower-lsp drops this handler when the client cancels the request
+        let _cancel_on_drop = cancellation.cancel_on_drop();
+        if let Err(e) = job.await {
+            error!("Claude review task failed: {}", e);
+        }
+        Ok(())
+    }
+
+    /// `range` grown to whole lines: from the start of its first line to the start
+    /// of the line after it (or the end of the text)
+    async fn whole_lines(&self, uri: &Url, range: Range) -> Range {
+        let text = match self.state.documents.get(uri).await {
+            Some(document) => document.text,
+            None => text::read_file(paths::file_path(uri)).unwrap_or_default(),
+        };
+        let index = LineIndex::new(&text);
+        let start = Position::new(range.start.line, 0);
+        let end = match index.line(range.end.line + 1) {
+            Some(_) => Position::new(range.end.line + 1, 0),
+            None => {
+                let last = index.line(range.end.line).unwrap_or_default();
+                Position::new(
+                    range.end.line,
+                    self.state.documents.encoding().measure(last),
+                )
+            }
+        };
+        Range::new(start, end)
+    }
+
+    /// Range of the completion being typed before `position` and its text: an
+    /// "@mention" or "@claude <command>" from its "@", otherwise the word there
+    async fn completion_prefix(&self, uri: &Url, position: Position) -> (Range, String) {
+        let text = match self.state.documents.get(uri).await {
+            Some(document) => document.text,
+            None => text::read_file(paths::file_path(uri)).unwrap_or_default(),
+        };
+        let encoding = self.state.documents.encoding();
+        let index = LineIndex::new(&text);
+        let Some(line) = index.line(position.line) else {
+            return (Range::new(position, position), String::new());
+        };
+        let line_start = index
+            .offset(Position::new(position.line, 0), encoding)
+            .unwrap_or_default();
+        let cursor = index
+            .offset(position, encoding)
+            .map_or(line.len(), |offset| offset - line_start);
+
+        let before_cursor = &line[..cursor];
+        let typed = typed_prefix(before_cursor);
+        let start = encoding.measure(&before_cursor[..cursor - typed.len()]);
+        let end = encoding.measure(before_cursor);
+        (
+            Range::new(
+                Position::new(position.line, start),
+                Position::new(position.line, end),
+            ),
+            typed.to_string(),
+        )
+    }
+
+    /// Completions for "@": files from every workspace folder plus symbols declared
+    /// in the current and other open documents. Each item runs claude-code.at-mention
+    /// once accepted.
+    async fn mention_completions(&self, current: &Url) -> Vec<CompletionItem> {
+        let mut items = Vec::new();
+
+        // With several roots, labels are prefixed with the folder name to tell
+        // same-named files apart
+        let file_limit = self.state.config.read().await.mention_file_limit;
+        let folders = self.state.workspace_folders.list();
+        let multi_root = folders.len() > 1;
+        for folder in folders {
+            let Ok(root) = folder.uri.to_file_path() else {
+                continue;
+            };
+            let walk_root = root.clone();
+            let files =
+                tokio::task::spawn_blocking(move || workspace::list_files(&walk_root, file_limit))
+                    .await
+                    .unwrap_or_default();
+
+            let label_for = |relative: &Path| {
+                if multi_root {
+                    format!("{}/{}", folder.name, workspace::display_relative(relative))
+                } else {
+                    workspace::display_relative(relative)
+                }
+            };
+
+            // Directories mention every file beneath them
+            items.extend(workspace::parent_dirs(&files).into_iter().map(|relative| {
+                CompletionItem {
+                    label: format!("@{}/", label_for(&relative)),
+                    kind: Some(CompletionItemKind::FOLDER),
+                    detail: Some("Mention directory in Claude".to_string()),
+                    command: Some(Command {
+                        title: "Mention in Claude".to_string(),
+                        command: "claude-code.at-mention".to_string(),
+                        arguments: Some(vec![serde_json::json!({
+                            "filePath": root.join(&relative).to_string_lossy(),
+                            "workspaceRoot": root.to_string_lossy(),
+                        })]),
+                    }),
+                    ..Default::default()
+                }
+            }));
+
+            items.extend(files.into_iter().map(|relative| CompletionItem {
+                label: format!("@{}", label_for(&relative)),
+                kind: Some(CompletionItemKind::FILE),
+                detail: Some("Mention file in Claude".to_string()),
+                command: Some(Command {
+                    title: "Mention in Claude".to_string(),
+                    command: "claude-code.at-mention".to_string(),
+                    arguments: Some(vec![serde_json::json!({
+                        "filePath": root.join(&relative).to_string_lossy(),
+                        "workspaceRoot": root.to_string_lossy(),
+                    })]),
+                }),
+                ..Default::default()
+            }));
+        }
+
+        let (open_documents, _) = self.state.documents.list().await;
+        let mut uris = vec![current.clone()];
+        uris.extend(
+            open_documents
+                .into_iter()
+                .map(|document| document.uri)
+                .filter(|uri| uri != current),
+        );
+
+        for uri in uris {
+            let Some((_, symbols)) = self.document_symbols(&uri).await else {
+                continue;
+            };
+            let root = self.workspace_root(&uri);
+            let file_path = paths::file_path(&uri);
+            let location = root
+                .as_ref()
+                .and_then(|root| paths::relative(&file_path, root))
+                .unwrap_or_else(|| file_path.to_string_lossy().to_string());
+
+            items.extend(symbols.iter().map(|symbol| CompletionItem {
+                label: format!("@{}", symbol.name),
+                kind: Some(symbol_completion_kind(symbol.kind)),
+                detail: Some(format!("{}:{}", location, symbol.line + 1)),
+                command: Some(Command {
+                    title: "Mention in Claude".to_string(),
+                    command: "claude-code.at-mention".to_string(),
+                    arguments: Some(vec![serde_json::json!({
+                        "filePath": file_path,
+                        "lineStart": symbol.line,
+                        "lineEnd": symbol.end_line,
+                        "workspaceRoot": root.as_ref().map(|root| root.to_string_lossy()),
+                    })]),
+                }),
+                ..Default::default()
+            }));
+        }
+
+        items
+    }
+
+    /// Text and declarations of a document: the buffer (with cached symbols) when
+    /// it is open, otherwise the file on disk
+    async fn document_symbols(&self, uri: &Url) -> Option<(String, Symbols)> {
+        if let Some(document) = self.state.documents.get(uri).await {
+            let symbols =
+                self.symbols
+                    .get(uri, document.version, &document.text, &document.language_id);
+            return Some((document.text, symbols));
+        }
+        let path = paths::file_path(uri);
+        let text = text::read_file(&path).ok()?;
+        let language_id = language::from_path(&path);
+        let symbols = Arc::new(symbols::extract_symbols(&text, language_id));
+        Some((text, symbols))
+    }
+
+    /// The declarations around `range`, for giving Claude syntactic context. The
+    /// innermost one's text is included (within the context budget) unless the
+    /// range already covers it.
+    async fn enclosing(&self, uri: &Url, range: Range) -> Option<Enclosing> {
+        let (text, symbols) = self.document_symbols(uri).await?;
+        let enclosing = symbols::enclosing_symbols(&symbols, range);
+        let innermost = enclosing.last()?;
+        let path = enclosing
+            .iter()
+            .map(|symbol| format!("`{}`", symbol.name))
+            .collect::<Vec<_>>()
+            .join(" > ");
+
+        let covers_symbol = range.start.line <= innermost.line
+            && range.end.line >= innermost.end_line
+            && range.start.character == 0;
+        let text = if covers_symbol {
+            None
+        } else {
+            let encoding = self.state.documents.encoding();
+            let expanded = symbols::expand_to_symbol(&symbols, range);
+            let budget = self.state.config.read().await.context_budget.clone();
+            LineIndex::new(&text)
+                .slice(expanded, encoding)
+                .map(|declaration| context::fit_text(declaration, expanded, &budget, encoding).0)
+        };
+        Some(Enclosing { path, text })
+    }
+
+    /// The files the code at `range` imports from, when promptContext.imports is
+    /// on, with their open buffers' text. Sensitive files are left out.
+    async fn imported_files(&self, uri: &Url, range: Range, code: &str) -> Vec<ImportedFile> {
+        let (settings, privacy) = {
+            let config = self.state.config.read().await;
+            (config.prompt_context.clone(), config.privacy.clone())
+        };
+        if !settings.imports {
+            return Vec::new();
+        }
+        let path = paths::file_path(uri);
+        let text = match self.state.documents.get(uri).await {
+            Some(document) => document.text,
+            None => match text::read_file(&path) {
+                Ok(text) => text,
+                Err(_) => return Vec::new(),
+            },
+        };
+        let language_id = self.state.documents.language_id(uri).await;
+        let index = self.state.symbol_index.clone();
+        let roots = self.state.workspace_folders.roots();
+        let code = code.to_string();
+        let found = tokio::task::spawn_blocking(move || {
+            index.refresh_if_stale(&roots);
+            imports::imported_paths(
+                &path,
+                &text,
+                &language_id,
+                &code,
+                range.start.line..=range.end.line,
+                &roots,
+                &index,
+            )
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut files = Vec::new();
+        for path in found {
+            let root = self.state.workspace_folders.root_for(&path);
+            if privacy.is_sensitive(&path, root.as_deref()) {
+                debug!("Leaving {} out of the prompt", path.display());
+                continue;
+            }
+            let open = match paths::to_url(&path) {
+                Some(url) => self.state.documents.get(&url).await,
+                None => None,
+            };
+            let text = match open {
+                Some(document) => document.text,
+                None => match text::read_file(&path) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+            };
+            files.push((path, text));
+        }
+        imports::fit(files, &settings)
+    }
+
+    /// Mention a directory or glob: one at_mentioned notification listing the
+    /// files it resolves to (respecting .gitignore), capped at mentionFileLimit
+    async fn mention_files(&self, mention: &str) {
+        let (limit, budget) = {
+            let config = self.state.config.read().await;
+            (config.mention_file_limit, config.context_budget.clone())
+        };
+        let roots = self.state.workspace_folders.roots();
+        let pattern = mention.to_string();
+        let files = tokio::task::spawn_blocking(move || {
+            workspace::expand_mention(&roots, &pattern, limit)
+                .map(|files| context::fit_files(files, &budget))
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|files| files);
+
+        let (files, truncated) = match files {
+            Ok((files, truncated)) if !files.is_empty() => (files, truncated),
+            Ok(_) => {
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("Claude Code: no files match {}", mention),
+                    )
+                    .await;
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to expand mention {}: {}", mention, e);
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("Claude Code: cannot mention {}: {}", mention, e),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        // Globs are matched in every root, so they only name one with a single root
+        let roots = self.state.workspace_folders.roots();
+        let (file_path, workspace_root, relative_path) = if workspace::is_glob(mention) {
+            let root = match roots.as_slice() {
+                [root] => Some(root.to_string_lossy().to_string()),
+                _ => None,
+            };
+            (mention.to_string(), root, None)
+        } else {
+            let dir = workspace::resolve_path(&roots, mention);
+            let root = self
+                .state
+                .workspace_folders
+                .root_for(&dir)
+                .map(|root| root.to_string_lossy().to_string());
+            let relative_path = self.relative_path(&dir);
+            (dir.to_string_lossy().to_string(), root, relative_path)
+        };
+
+        let count = files.len();
+        let total = truncated.as_ref().map_or(count, |t| t.total_files);
+        let notification = AtMentionedNotification {
+            file_path: file_path.clone(),
+            line_start: 0,
+            line_end: 0,
+            language_id: None,
+            workspace_root,
+            relative_path,
+            files: Some(
+                files
+                    .iter()
+                    .map(|file| file.to_string_lossy().to_string())
+                    .collect(),
+            ),
+            truncated,
+            instruction: None,
+            symbol: None,
+            text: None,
+            total_lines: None,
+        };
+        self.send_mention(notification).await;
+
+        if total >= limit {
+            warn!("Mention of {} was capped at {} files", file_path, limit);
+        }
+        let message = if count < total {
+            format!(
+                "At-mention sent for {} ({} of {} files, context budget reached)",
+                file_path, count, total
+            )
+        } else {
+            format!("At-mention sent for {} ({} files)", file_path, count)
+        };
+        self.client.show_message(MessageType::INFO, message).await;
+    }
+
+    /// Custom `claude/selectionDidChange` notification: selection updates pushed by
+    /// the editor, independent of selectionRange/codeAction requests
+    pub async fn selection_did_change(&self, params: SelectionDidChangeParams) {
+        debug!(
+            "Selection changed in {}: {:?}",
+            params.text_document.uri, params.selection
+        );
+        self.state
+            .documents
+            .mark_active(&params.text_document.uri)
+            .await;
+        let mut selection = self
+            .selection_notification(&params.text_document.uri, params.selection)
+            .await;
+        if params.selections.len() > 1 {
+            selection.selections = params
+                .selections
+                .iter()
+                .map(|range| SelectionInfo {
+                    start: range.start,
+                    end: range.end,
+                    is_empty: range.start == range.end,
+                })
+                .collect();
+        }
+        self.send_selection_debounced(selection).await;
+    }
+
+    /// `window/workDoneProgress/cancel`: the user cancelled a Claude request from
+    /// its progress
+    pub async fn work_done_progress_cancel(&self, params: WorkDoneProgressCancelParams) {
+        if self.cancellable_work.cancel(&params.token) {
+            info!("Cancelling the request behind progress {:?}", params.token);
+        } else {
+            debug!("No cancellable work for progress {:?}", params.token);
+        }
+    }
+
+    async fn selection_notification(
+        &self,
+        uri: &Url,
+        range: Range,
+    ) -> SelectionChangedNotification {
+        let (budget, context_lines) = {
+            let config = self.state.config.read().await;
+            (
+                config.context_budget.clone(),
+                config.selection_context_lines,
+            )
+        };
+        let file_path = paths::file_path(uri);
+        let size = fs::metadata(&file_path).map_or(0, |metadata| metadata.len());
+        let mut surrounding = None;
+        let (text, truncated, reference) = if size > budget.large_file_bytes {
+            debug!("Selection in {} ({} bytes) sent by reference", uri, size);
+            let reference = text::read_file(&file_path).ok().map(|text| FileReference {
+                file_path: file_path.to_string_lossy().to_string(),
+                range,
+                hash: hashes::fingerprint(&text),
+                total_bytes: size,
+            });
+            (String::new(), None, reference)
+        } else {
+            let content = text::read_file(&file_path).unwrap_or_else(|e| {
+                warn!("Failed to read file {}: {}", file_path.display(), e);
+                String::new()
+            });
+            // Keeps the file's own line breaks
+            let selected = LineIndex::new(&content)
+                .slice(range, self.state.documents.encoding())
+                .unwrap_or_default();
+            let (text, truncated) =
+                context::fit_text(selected, range, &budget, self.state.documents.encoding());
+            if truncated.is_some() {
+                debug!("Selection in {} truncated to {} bytes", uri, text.len());
+            } else if context_lines > 0 {
+                // Only when the selection and the lines around it fit together
+                surrounding =
+                    context::surrounding_lines(&content, range, context_lines).filter(|lines| {
+                        text.len() + lines.before.len() + lines.after.len() <= budget.limit()
+                    });
+            }
+            (text, truncated, None)
+        };
+
+        SelectionChangedNotification {
+            text,
+            relative_path: self.relative_path(&file_path),
+            file_path: file_path.to_string_lossy().to_string(),
+            file_url: uri.to_string(),
+            language_id: Some(self.state.documents.language_id(uri).await),
+            selection: SelectionInfo {
+                start: range.start,
+                end: range.end,
+                is_empty: range.start == range.end,
+            },
+            workspace_root: self.root_string(uri),
+            truncated,
+            reference,
+            surrounding,
+            selections: Vec::new(),
+        }
+    }
+
+    fn root_string(&self, uri: &Url) -> Option<String> {
+        self.workspace_root(uri)
+            .map(|root| root.to_string_lossy().to_string())
+    }
+
+    /// Send a selection notification through the debouncer, unless selection
+    /// tracking is turned off. Selections in sensitive files keep their range but
+    /// not their text.
+    async fn send_selection_debounced(&self, mut selection: SelectionChangedNotification) {
+        let window = {
+            let config = self.state.config.read().await;
+            if !config.features.selection_tracking {
+                return;
+            }
+            let path = Path::new(&selection.file_path);
+            let root = self.state.workspace_folders.root_for(path);
+            if config.privacy.is_sensitive(path, root.as_deref()) {
+                debug!("Redacting selection in {}", selection.file_path);
+                selection.text = privacy::REDACTED.to_string();
+                selection.truncated = None;
+                selection.reference = None;
+                selection.surrounding = None;
+            }
+            Duration::from_millis(config.selection_debounce_ms)
+        };
+        if let Ok(uri) = Url::parse(&selection.file_url) {
+            self.state
+                .content_hashes
+                .capture(uri, self.state.documents.clone());
+        }
+        // One key: a newer selection anywhere replaces the held one
+        self.debounce(
+            "selection".to_string(),
+            Debounced::Selection(Box::new(selection)),
+            window,
+        );
+    }
+
+    /// claude-code.undo-last-edit: revert the most recent edit applied for Claude
+    async fn undo_last_edit(&self) {
+        let undone = journal::undo_last(
+            &self.client,
+            &self.state.undo_journal,
+            &self.state.documents,
+            supports_document_changes(&self.client_capabilities),
+        )
+        .await;
+        let (kind, message) = match undone {
+            Ok(entry) => (
+                MessageType::INFO,
+                format!(
+                    "Claude Code: undid the {} edit to {} file(s)",
+                    entry.source,
+                    entry.files.len()
+                ),
+            ),
+            Err(e) => {
+                warn!("Undoing the last Claude edit failed: {}", e);
+                (MessageType::WARNING, format!("Claude Code: {}", e))
+            }
+        };
+        self.client.show_message(kind, message).await;
+    }
+
+    /// claude-code.resend-selection with `{ filePath?, index? }`: send Claude the
+    /// selection `index` steps back (0, the latest, by default) in the history of
+    /// `filePath` or of every file, even when it was sent before. Answers with the
+    /// selection sent.
+    async fn resend_selection(&self, args: Option<&Value>) -> LspResult<Option<Value>> {
+        let invalid = || {
+            command_errors::invalid_arguments(
+                "claude-code.resend-selection",
+                "{ filePath?, index? }",
+                args,
+            )
+        };
+        let argument = |name: &str| args.and_then(|args| args.get(name));
+        let file = match argument("filePath") {
+            None => None,
+            Some(path) => {
+                let path = path.as_str().ok_or_else(invalid)?;
+                Some(workspace::resolve_path(
+                    &self.state.workspace_folders.roots(),
+                    path,
+                ))
+            }
+        };
+        let index = match argument("index") {
+            None => 0,
+            Some(index) => index.as_u64().ok_or_else(invalid)? as usize,
+        };
+        let Some(sender) = &self.notification_sender else {
+            return Err(command_errors::not_connected(
+                "claude-code.resend-selection",
+            ));
+        };
+
+        let record = self
+            .state
+            .selection_history
+            .recent(file.as_deref(), index + 1)
+            .into_iter()
+            .nth(index);
+        let Some(record) = record else {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    "Claude Code: no earlier selection to send again",
+                )
+                .await;
+            return Ok(None);
+        };
+
+        let selection = record.selection;
+        info!(
+            "Sending selection in {} again ({} back)",
+            selection.file_path, index
+        );
+        *self.state.latest_selection.write().await = Some(selection.clone());
+        let params = serde_json::to_value(&selection).unwrap_or_default();
+        sender
+            .send(JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "selection_changed".to_string(),
+                params: params.clone(),
+            })
+            .await;
+        Ok(Some(params))
+    }
+
+    /// claude-code.clear-annotations: take down every review finding and
+    /// explanation Claude left in the editor
+    async fn clear_annotations(&self) {
+        let cleared = self.annotations.clear();
+        info!("Clearing Claude's annotations of {} files", cleared.len());
+        for uri in &cleared {
+            self.client
+                .publish_diagnostics(uri.clone(), Vec::new(), None)
+                .await;
+        }
+        self.client
+            .send_notification::<ClearAnnotations>(ClearAnnotationsParams {
+                text_documents: cleared
+                    .into_iter()
+                    .map(|uri| TextDocumentIdentifier { uri })
+                    .collect(),
+            })
+            .await;
+    }
+
+    /// Where to look for the git repository of a command given `uri`: its
+    /// workspace folder or directory, or else the first workspace folder
+    fn repository_dir(&self, uri: Option<&Url>) -> Option<PathBuf> {
+        match uri {
+            Some(uri) => self
+                .workspace_root(uri)
+                .or_else(|| paths::file_path(uri).parent().map(Path::to_path_buf)),
+            None => self.state.workspace_folders.roots().into_iter().next(),
+        }
+    }
+
+    /// claude-code.at-mention-diff with `{ uri? }`: mention every file changed
+    /// since HEAD in the repository `uri` (or the first workspace folder) is in,
+    /// each with the lines its changes span, and untracked files whole. Answers
+    /// with the mentions sent.
+    async fn mention_diff(&self, args: Option<&Value>) -> LspResult<Option<Value>> {
+        let uri = match args.and_then(|args| args.get("uri")) {
+            None => None,
+            Some(uri) => Some(
+                uri.as_str()
+                    .and_then(|uri| Url::parse(uri).ok())
+                    .ok_or_else(|| {
+                        command_errors::invalid_arguments(
+                            "claude-code.at-mention-diff",
+                            "{ uri? }",
+                            args,
+                        )
+                    })?,
+            ),
+        };
+        if self
+            .debouncer
+            .as_ref()
+            .is_none_or(|debouncer| debouncer.is_closed())
+        {
+            return Err(command_errors::not_connected("claude-code.at-mention-diff"));
+        }
+        let changed = match self.repository_dir(uri.as_ref()) {
+            Some(dir) => git::changed_files(&dir).await,
+            None => Err(anyhow::anyhow!("no workspace folder")),
+        };
+        let changed = match changed {
+            Ok(changed) if !changed.is_empty() => changed,
+            Ok(_) => {
+                self.client
+                    .show_message(MessageType::INFO, "Claude Code: no uncommitted changes")
+                    .await;
+                return Ok(None);
+            }
+            Err(e) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("Claude Code: can't mention changes: {}", e),
+                    )
+                    .await;
+                return Ok(None);
+            }
+        };
+
+        let limit = self.state.config.read().await.mention_file_limit;
+        let total = changed.len();
+        let mut mentions = Vec::new();
+        for file in changed.into_iter().take(limit) {
+            let file_path = file.path.to_string_lossy().to_string();
+            let (line_start, line_end) = match file.lines {
+                Some((start, end)) => (start, Some(end)),
+                None => (0, None),
+            };
+            let (line_start, line_end) = self
+                .mention_file(&file_path, line_start, line_end, None, None)
+                .await;
+            mentions.push(serde_json::json!({
+                "filePath": file_path,
+                "lineStart": line_start,
+                "lineEnd": line_end,
+            }));
+        }
+
+        info!("Mentioned {} of {} changed files", mentions.len(), total);
+        let message = if mentions.len() < total {
+            format!(
+                "Claude Code: at-mention sent for {} of {} changed files",
+                mentions.len(),
+                total
+            )
+        } else {
+            format!("Claude Code: at-mention sent for {} changed files", total)
+        };
+        self.client.show_message(MessageType::INFO, message).await;
+        Ok(Some(Value::Array(mentions)))
+    }
+
+    /// claude-code.generate-commit-message with `{ uri?, write? }`: ask Claude for a
+    /// message describing the staged changes of the repository `uri` (or the first
+    /// workspace folder) is in, show it, and write it to COMMIT_EDITMSG unless
+    /// `write` is false. Answers with the message.
+    async fn generate_commit_message(
+        &self,
+        args: Option<&Value>,
+        progress_token: Option<ProgressToken>,
+    ) -> LspResult<Option<Value>> {
+        let invalid = || {
+            command_errors::invalid_arguments(
+                "claude-code.generate-commit-message",
+                "{ uri?, write? }",
+                args,
+            )
+        };
+        let argument = |name: &str| args.and_then(|args| args.get(name));
+        let uri = match argument("uri") {
+            None => None,
+            Some(uri) => Some(
+                uri.as_str()
+                    .and_then(|uri| Url::parse(uri).ok())
+                    .ok_or_else(invalid)?,
+            ),
+        };
+        let write = match argument("write") {
+            None => true,
+            Some(write) => write.as_bool().ok_or_else(invalid)?,
+        };
+        let Some(dir) = self.repository_dir(uri.as_ref()) else {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    "Claude Code: no repository to write a commit message for",
+                )
+                .await;
+            return Ok(None);
+        };
+
+        let claude =
+            ClaudeClient::new(&*self.state.config.read().await).with_working_dir(Some(dir.clone()));
+        let progress = WorkDone::begin(
+            &self.client,
+            progress_token,
+            self.supports_work_done_progress(),
+            "Writing a commit message with Claude",
+            None,
+        )
+        .await;
+        let generated = commit_message::generate(&claude, &dir, write).await;
+        progress.end(None).await;
+
+        match generated {
+            Ok(generated) => {
+                let mut message = format!("Claude Code: {}", generated.message);
+                if let Some(path) = &generated.written_to {
+                    message.push_str(&format!("\n\n(written to {})", path.display()));
+                }
+                self.client.show_message(MessageType::INFO, message).await;
+                Ok(Some(Value::String(generated.message)))
+            }
+            Err(e) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("Claude Code: no commit message: {}", e),
+                    )
+                    .await;
+                Ok(None)
+            }
+        }
+    }
+
+    /// claude-code.request with `{ method, params?, timeoutMs? }`: ask the connected
+    /// Claude client and answer with its result, or with its error object
+    async fn request_claude(&self, args: Option<&Value>) -> LspResult<Option<Value>> {
+        let argument = |name: &str| args.and_then(|args| args.get(name));
+        let Some(method) = argument("method").and_then(Value::as_str) else {
+            return Err(command_errors::invalid_arguments(
+                "claude-code.request",
+                "{ method, params?, timeoutMs? }",
+                args,
+            ));
+        };
+        let params = argument("params").cloned().unwrap_or_default();
+        let timeout = argument("timeoutMs")
+            .and_then(Value::as_u64)
+            .map_or(rpc::REQUEST_TIMEOUT, Duration::from_millis);
+
+        let result = match &self.notification_bus {
+            Some(bus) => bus.request(method, params, timeout).await,
+            None => Err(RequestError::NotConnected),
+        };
+        match result {
+            Ok(result) => Ok(Some(result)),
+            Err(RequestError::Failed(error)) => Err(tower_lsp::jsonrpc::Error {
+                code: tower_lsp::jsonrpc::ErrorCode::from(i64::from(error.code)),
+                message: error.message.into(),
+                data: error.data,
+            }),
+            Err(e) => {
+                warn!("{} request to Claude failed: {}", method, e);
+                let reason = match e {
+                    RequestError::NotConnected => "notConnected",
+                    RequestError::Timeout(_) => "timeout",
+                    _ => "disconnected",
+                };
+                Err(tower_lsp::jsonrpc::Error {
+                    code: tower_lsp::jsonrpc::ErrorCode::ServerError(
+                        command_errors::REQUEST_FAILED,
+                    ),
+                    message: format!("Claude: {}", e).into(),
+                    data: Some(serde_json::json!({ "reason": reason })),
+                })
+            }
+        }
+    }
+
+    /// Mention lines of a file, the whole file without `line_end`. Lines are
+    /// clamped to the file as the editor has it, and a range given backwards is
+    /// turned around. Returns the lines mentioned.
+    async fn mention_file(
+        &self,
+        file_path: &str,
+        line_start: u32,
+        line_end: Option<u32>,
+        workspace_root: Option<String>,
+        symbol: Option<String>,
+    ) -> (u32, u32) {
+        let last_line = self.last_line(Path::new(file_path)).await;
+        let (line_start, line_end) = match (line_end, last_line) {
+            (Some(line_end), Some(last_line)) => (
+                line_start.min(line_end).min(last_line),
+                line_start.max(line_end).min(last_line),
+            ),
+            (Some(line_end), None) => (line_start.min(line_end), line_start.max(line_end)),
+            (None, last_line) => {
+                let last_line = last_line.unwrap_or(0);
+                (line_start.min(last_line), last_line)
+            }
+        };
+        let workspace_root = workspace_root.or_else(|| {
+            self.state
+                .workspace_folders
+                .root_for(Path::new(file_path))
+                .map(|root| root.to_string_lossy().to_string())
+        });
+        let language_id = match paths::to_url(Path::new(file_path)) {
+            Some(uri) => self.state.documents.language_id(&uri).await,
+            None => language::from_path(Path::new(file_path)).to_string(),
+        };
+
+        self.send_mention(AtMentionedNotification {
+            file_path: file_path.to_string(),
+            relative_path: self.relative_path(Path::new(file_path)),
+            line_start,
+            line_end,
+            language_id: Some(language_id),
+            workspace_root,
+            files: None,
+            truncated: None,
+            instruction: None,
+            symbol,
+            text: None,
+            total_lines: None,
+        })
+        .await;
+        (line_start, line_end)
+    }
+
+    /// 0-based number of the last line of a file: the open buffer's, or the file
+    /// on disk's. None when it can't be read.
+    async fn last_line(&self, path: &Path) -> Option<u32> {
+        let text = self.file_text(path).await?;
+        Some(text.lines().count().saturating_sub(1) as u32)
+    }
+
+    /// A file as the editor has it: the open buffer, or the file on disk
+    async fn file_text(&self, path: &Path) -> Option<String> {
+        match paths::to_url(path) {
+            Some(uri) => match self.state.documents.get(&uri).await {
+                Some(document) => Some(document.text),
+                None => text::read_file(path).ok(),
+            },
+            None => text::read_file(path).ok(),
+        }
+    }
+
+    /// Put the file's line count in a mention of one file, and the mentioned
+    /// lines when they fit `budget`. Sensitive files only get their line count.
+    async fn attach_text(
+        &self,
+        mention: &mut AtMentionedNotification,
+        budget: &ContextBudget,
+        sensitive: bool,
+    ) {
+        let Some(content) = self.file_text(Path::new(&mention.file_path)).await else {
+            return;
+        };
+        mention.total_lines = Some(content.lines().count() as u32);
+        if sensitive {
+            return;
+        }
+        // Whole lines, each with its own line break
+        let lines = mention.line_end.saturating_sub(mention.line_start) as usize + 1;
+        let text: String = content
+            .split_inclusive('\n')
+            .skip(mention.line_start as usize)
+            .take(lines)
+            .collect();
+        if text.len() <= budget.limit() {
+            mention.text = Some(text);
+        } else {
+            debug!(
+                "Lines {}-{} of {} are over the context budget, sent without them",
+                mention.line_start, mention.line_end, mention.file_path
+            );
+        }
+    }
+
+    /// Mention the declaration a qualified name ("foo::bar") points at, found in
+    /// the symbol index. Returns the file and lines mentioned, or None when it
+    /// names nothing.
+    async fn mention_symbol(&self, qualified: &str) -> Option<(String, u32, u32)> {
+        let index = self.state.symbol_index.clone();
+        let roots = self.state.workspace_folders.roots();
+        let name = qualified.to_string();
+        let found = tokio::task::spawn_blocking(move || {
+            index.refresh_if_stale(&roots);
+            // A miss may be a declaration added since; refreshing only rescans
+            // files that changed
+            index.resolve(&name).or_else(|| {
+                index.refresh(&roots);
+                index.resolve(&name)
+            })
+        })
+        .await
+        .ok()??;
+
+        let file_path = found.path.to_string_lossy().to_string();
+        let (line_start, line_end) = self
+            .mention_file(
+                &file_path,
+                found.symbol.line,
+                Some(found.symbol.end_line),
+                None,
+                Some(qualified.to_string()),
+            )
+            .await;
+        Some((file_path, line_start, line_end))
+    }
+
+    /// Send the mentions typed into `uri` that an edit finished: those whose
+    /// finishing whitespace or line break is among the `length` bytes it inserted
+    /// at `start`
+    async fn send_typed_mentions(&self, uri: &Url, start: Position, length: usize) {
+        if !self.state.config.read().await.features.typed_mentions {
+            return;
+        }
+        let Some(document) = self.state.documents.get(uri).await else {
+            return;
+        };
+        let encoding = self.state.documents.encoding();
+        let index = LineIndex::new(&document.text);
+        let Some(inserted_start) = index.offset(start, encoding) else {
+            return;
+        };
+        let inserted = inserted_start..inserted_start + length;
+
+        let mut finished = Vec::new();
+        let mut line = start.line;
+        while let Some(content) = index.line(line) {
+            let Some(line_start) = index.offset(Position::new(line, 0), encoding) else {
+                break;
+            };
+            if line_start > inserted.end {
+                break;
+            }
+            // Every line but the last has a line break
+            let ended = index.line(line + 1).is_some();
+            finished.extend(
+                mentions::find(content, ended)
+                    .into_iter()
+                    .filter(|found| inserted.contains(&(line_start + found.terminator)))
+                    .map(|found| (line, found)),
+            );
+            line += 1;
+        }
+
+        let mut sent = false;
+        for (line, found) in finished {
+            let mentioned = match &found.mention {
+                TypedMention::Path { path, lines } => {
+                    self.mention_typed_path(uri, path, *lines).await
+                }
+                TypedMention::Claude { instruction } => {
+                    self.mention_instruction(uri, line, instruction).await;
+                    true
+                }
+            };
+            if mentioned {
+                info!("Typed mention {} sent from {}", found.token, uri);
+                self.sent_mentions.insert(uri, found.token);
+                sent = true;
+            }
+        }
+        if sent && self.supports_inlay_hint_refresh() {
+            if let Err(e) = self.client.inlay_hint_refresh().await {
+                debug!("Inlay hint refresh failed: {}", e);
+            }
+        }
+    }
+
+    /// Mention a path typed into `uri`, relative to a workspace folder or the
+    /// document's directory. Returns false when it names nothing, as with the
+    /// "@" of a decorator or doc tag.
+    async fn mention_typed_path(&self, uri: &Url, path: &str, lines: Option<(u32, u32)>) -> bool {
+        // "@foo::bar" names a declaration
+        if path.contains("::") {
+            let found = self.mention_symbol(path).await;
+            if found.is_none() {
+                debug!("Typed mention @{} names no declaration", path);
+            }
+            return found.is_some();
+        }
+        // Only globs with a wildcard, so "@Component({" stays a decorator
+        if workspace::is_glob(path) {
+            if !path.contains('*') {
+                return false;
+            }
+            self.mention_files(path).await;
+            return true;
+        }
+
+        let roots = self.state.workspace_folders.roots();
+        let mut resolved = workspace::resolve_path(&roots, path);
+        if !resolved.exists() {
+            let beside = uri
+                .to_file_path()
+                .ok()
+                .and_then(|document| Some(document.parent()?.join(path)));
+            match beside {
+                Some(beside) if beside.exists() => resolved = beside,
+                _ => {
+                    debug!("Typed mention @{} names no file", path);
+                    return false;
+                }
+            }
+        }
+
+        let resolved = resolved.to_string_lossy();
+        if Path::new(resolved.as_ref()).is_dir() {
+            self.mention_files(&resolved).await;
+            return true;
+        }
+        // Typed lines are 1-based
+        let (line_start, line_end) = match lines {
+            Some((start, end)) => (start.saturating_sub(1), Some(end.saturating_sub(1))),
+            None => (0, None),
+        };
+        self.mention_file(&resolved, line_start, line_end, None, None)
+            .await;
+        true
+    }
+
+    /// Mention the code an "@claude <instruction>" was typed in, along with the
+    /// instruction: the innermost declaration around it, or just its line
+    async fn mention_instruction(&self, uri: &Url, line: u32, instruction: &str) {
+        let at = Range::new(Position::new(line, 0), Position::new(line, 0));
+        let lines = match self.document_symbols(uri).await {
+            Some((_, symbols)) => symbols::enclosing_symbols(&symbols, at)
+                .last()
+                .map(|symbol| (symbol.line, symbol.end_line)),
+            None => None,
+        };
+        let (line_start, line_end) = lines.unwrap_or((line, line));
+
+        let file_path = paths::file_path(uri);
+        self.send_mention(AtMentionedNotification {
+            file_path: file_path.to_string_lossy().to_string(),
+            line_start,
+            line_end,
+            language_id: Some(self.state.documents.language_id(uri).await),
+            workspace_root: self.root_string(uri),
+            relative_path: self.relative_path(&file_path),
+            files: None,
+            truncated: None,
+            instruction: Some(instruction.to_string()),
+            symbol: None,
+            text: None,
+            total_lines: None,
+        })
+        .await;
+    }
+
+    /// Send an at_mentioned notification through the debouncer, keyed by what it
+    /// mentions so repeats of one mention are merged
+    async fn send_mention(&self, mut mention: AtMentionedNotification) {
+        let (window, attach) = {
+            let config = self.state.config.read().await;
+            // Only mentions of one file get their text
+            let attach = (config.at_mention_text && mention.files.is_none()).then(|| {
+                let path = Path::new(&mention.file_path);
+                let root = self.state.workspace_folders.root_for(path);
+                (
+                    config.context_budget.clone(),
+                    config.privacy.is_sensitive(path, root.as_deref()),
+                )
+            });
+            (Duration::from_millis(config.at_mention_debounce_ms), attach)
+        };
+        if let Some((budget, sensitive)) = attach {
+            self.attach_text(&mut mention, &budget, sensitive).await;
+        }
+        if let Some(uri) = paths::to_url(Path::new(&mention.file_path)) {
+            self.state
+                .content_hashes
+                .capture(uri, self.state.documents.clone());
+        }
+        let key = format!(
+            "mention {}:{}-{}",
+            mention.file_path, mention.line_start, mention.line_end
+        );
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "at_mentioned".to_string(),
+            params: serde_json::to_value(mention).unwrap_or_default(),
+        };
+        self.debounce(key, Debounced::Notification(notification), window);
+    }
+
+    fn debounce(&self, key: String, debounced: Debounced, window: Duration) {
+        if let Some(debouncer) = &self.debouncer {
+            let _ = debouncer.send((key, debounced, window));
+        }
+    }
+
+    /// claude-code.at-mention with `{ filePath, lineStart?, lineEnd?, workspaceRoot? }`
+    /// or `{ symbol }`: mention lines of a file, every file a directory or glob
+    /// matches, or a declaration
+    async fn at_mention(&self, args: Option<&Value>) -> LspResult<()> {
+        info!("At-mention command executed with args: {:?}", args);
+        let invalid = || {
+            command_errors::invalid_arguments(
+                "claude-code.at-mention",
+                "{ filePath, lineStart?, lineEnd? } or { symbol }",
+                args,
+            )
+        };
+        if self
+            .debouncer
+            .as_ref()
+            .is_none_or(|debouncer| debouncer.is_closed())
+        {
+            return Err(command_errors::not_connected("claude-code.at-mention"));
+        }
+        let Some(mention_data) = args.filter(|args| args.is_object()) else {
+            return Err(invalid());
+        };
+
+        // { symbol: "foo::bar" } mentions that declaration
+        if let Some(symbol) = mention_data.get("symbol") {
+            let symbol = symbol.as_str().ok_or_else(invalid)?;
+            let (kind, message) = match self.mention_symbol(symbol).await {
+                Some((file_path, line_start, line_end)) => (
+                    MessageType::INFO,
+                    format!(
+                        "At-mention sent for {} ({}:{}-{})",
+                        symbol, file_path, line_start, line_end
+                    ),
+                ),
+                None => (
+                    MessageType::WARNING,
+                    format!("Claude Code: no declaration of {} found", symbol),
+                ),
+            };
+            self.client.show_message(kind, message).await;
+            return Ok(());
+        }
+
+        let file_path = mention_data
+            .get("filePath")
+            .and_then(|v| v.as_str())
+            .filter(|file_path| !file_path.is_empty())
+            .ok_or_else(invalid)?;
+
+        // "@src/" or "@**/*.test.ts" mention every matching file
+        if workspace::is_glob(file_path)
+            || workspace::resolve_path(&self.state.workspace_folders.roots(), file_path).is_dir()
+            || file_path.ends_with('/')
+        {
+            self.mention_files(file_path).await;
+            return Ok(());
+        }
+        let line = |name: &str| match mention_data.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(line) => line
+                .as_u64()
+                .map(|line| Some(line as u32))
+                .ok_or_else(invalid),
+        };
+        let line_start = line("lineStart")?.unwrap_or(0);
+        let line_end = line("lineEnd")?;
+        // Completion items carry their root
+        let workspace_root = mention_data
+            .get("workspaceRoot")
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        if let Some(uri) = paths::to_url(Path::new(file_path)) {
+            self.readable(&uri).await?;
+        }
+
+        let (line_start, line_end) = self
+            .mention_file(file_path, line_start, line_end, workspace_root, None)
+            .await;
+
+        self.client
+            .show_message(
+                MessageType::INFO,
+                format!(
+                    "At-mention sent for {}:{}-{}",
+                    file_path, line_start, line_end
+                ),
+            )
+            .await;
+        Ok(())
+    }
+
+    /// workspace/executeCommand, timed by `execute_command`
+    async fn run_command(&self, params: ExecuteCommandParams) -> LspResult<Option<Value>> {
+        info!("Execute command: {}", params.command);
+
+        match params.command.as_str() {
+            "claude-code.review" => {
+                let args = params.arguments.first();
+                let target =
+                    args.and_then(|args| serde_json::from_value::<ReviewTarget>(args.clone()).ok());
+                let Some(target) = target else {
+                    return Err(command_errors::invalid_arguments(
+                        &params.command,
+                        "{ uri, scope? }",
+                        args,
+                    ));
+                };
+                self.run_review(target, params.work_done_progress_params.work_done_token)
+                    .await?;
+            }
+            "claude-code.at-mention" => {
+                self.at_mention(params.arguments.first()).await?;
+            }
+            "claude-code.at-mention-diff" => {
+                return self.mention_diff(params.arguments.first()).await;
+            }
+            "claude-code.show-logs" => {
+                // Optional argument: which component's log (lsp, mcp or commands)
+                let component = match params.arguments.first() {
+                    None | Some(Value::Null) => logging::DEFAULT_COMPONENT,
+                    Some(Value::String(component)) => component.as_str(),
+                    Some(args) => {
+                        return Err(command_errors::invalid_arguments(
+                            &params.command,
+                            "\"lsp\", \"mcp\" or \"commands\"",
+                            Some(args),
+                        ))
+                    }
+                };
+
+                match logging::latest_log_file(component) {
+                    Some(path) => {
+                        let zed = ZedCli::new(&*self.state.config.read().await);
+                        let opened = open_file(
+                            &self.client,
+                            &self.client_capabilities,
+                            &zed,
+                            &path,
+                            None,
+                            true,
+                        )
+                        .await;
+                        if let Err(e) = opened {
+                            self.client
+                                .show_message(
+                                    MessageType::WARNING,
+                                    format!("Claude Code: couldn't open {}: {}", path.display(), e),
+                                )
+                                .await;
+                        }
+                    }
+                    None => {
+                        self.client
+                            .show_message(
+                                MessageType::WARNING,
+                                format!(
+                                    "Claude Code: no {} log file; log files need a worktree",
+                                    component
+                                ),
+                            )
+                            .await;
+                    }
+                }
+            }
+            "claude-code.status" => {
+                let report = self.state.status.report().await;
+                return Ok(serde_json::to_value(report).ok());
+            }
+            "claude-code.request" => {
+                return self.request_claude(params.arguments.first()).await;
+            }
+            "claude-code.undo-last-edit" => {
+                self.undo_last_edit().await;
+            }
+            "claude-code.resend-selection" => {
+                return self.resend_selection(params.arguments.first()).await;
+            }
+            "claude-code.clear-annotations" => {
+                self.clear_annotations().await;
+            }
+            "claude-code.generate-commit-message" => {
+                return self
+                    .generate_commit_message(
+                        params.arguments.first(),
+                        params.work_done_progress_params.work_done_token,
+                    )
+                    .await;
+            }
+            "claude-code.trust-workspace" => {
+                let trust = self.ask_trust().await;
+                return Ok(serde_json::to_value(trust).ok());
+            }
+            commands::LIST_COMMANDS => {
+                return Ok(Some(
+                    serde_json::json!({ "commands": self.list_commands() }),
+                ));
+            }
+            command if command.starts_with(prompts::COMMAND_PREFIX) => {
+                self.execute_prompt_command(&params).await?;
+            }
+            _ => return Err(command_errors::unknown_command(&params.command)),
+        }
+
+        Ok(None)
+    }
+
+    /// The prompt templates of each workspace folder, or those of no worktree
+    /// when there are no folders
+    fn templates(&self) -> Vec<Arc<PromptTemplates>> {
+        let roots = self.state.workspace_folders.roots();
+        if roots.is_empty() {
+            return vec![self.prompts.for_root(None)];
+        }
+        roots
+            .iter()
+            .map(|root| self.prompts.for_root(Some(root)))
+            .collect()
+    }
+
+    /// claude-code.list-commands: every command the server offers, with the
+    /// prompt templates of the workspace folders as they are now
+    fn list_commands(&self) -> Vec<CommandInfo> {
+        let mut commands: Vec<CommandInfo> = Vec::new();
+        for template in self
+            .templates()
+            .iter()
+            .flat_map(|templates| templates.iter())
+        {
+            let command = commands::for_template(template);
+            if !commands
+                .iter()
+                .any(|known| known.command == command.command)
+            {
+                commands.push(command);
+            }
+        }
+        commands.extend(commands::builtins());
+        commands
+    }
+
+    fn read_text_from_range(&self, file_path: &Path, range: Range) -> String {
+        match text::read_file(file_path) {
+            Ok(content) => {
+                // Keeps the file's own line breaks
+                let index = LineIndex::new(&content);
+                if let Some(text) = index.slice(range, self.state.documents.encoding()) {
+                    return text.to_string();
+                }
+            }
+            Err(e) => {
+                warn!("Failed to read file {}: {}", file_path.display(), e);
+            }
+        }
+
+        String::new()
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for ClaudeCodeLanguageServer {
+    async fn initialize(&self, params: InitializeParams) -> LspResult<InitializeResult> {
+        info!("LSP Server initializing...");
+        if let Some(workspace_folders) = &params.workspace_folders {
+            for folder in workspace_folders {
+                info!("Workspace folder: {}", folder.uri);
+                self.state.workspace_folders.add(folder.clone());
+            }
+        }
+        for root in self.state.workspace_folders.roots() {
+            info!("Workspace root: {}", root.display());
+        }
+        let position_encoding = PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        info!("Using {:?} positions", position_encoding);
+        self.state.documents.set_encoding(position_encoding);
+        let _ = self.client_capabilities.set(params.capabilities.clone());
+        if let Some(section) = Config::section(params.initialization_options.as_ref()) {
+            self.settings.set_editor(section.clone());
+        }
+        self.reload_config().await;
+        let all_files = FileOperationRegistrationOptions {
+            filters: vec![FileOperationFilter {
+                scheme: Some("file".to_string()),
+                pattern: FileOperationPattern {
+                    glob: "**/*".to_string(),
+                    matches: None,
+                    options: None,
+                },
+            }],
+        };
+
+        // Templates added to a worktree later still run, only unlisted
+        let mut commands = Vec::new();
+        for template in self
+            .templates()
+            .iter()
+            .flat_map(|templates| templates.iter())
+        {
+            let command = template.command();
+            if !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+        commands.extend(commands::builtin_names().map(str::to_string));
+
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                position_encoding: Some(position_encoding.kind()),
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(false),
+                    trigger_characters: Some(vec!["@".to_string()]),
+                    work_done_progress_options: Default::default(),
+                    all_commit_characters: None,
+                    completion_item: None,
+                }),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    // Renames and deletions made in the editor; the file watcher
+                    // registered in initialized covers the rest
+                    file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                        did_rename: Some(all_files.clone()),
+                        did_delete: Some(all_files),
+                        ..Default::default()
+                    }),
+                }),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR,
+                            CodeActionKind::REFACTOR_REWRITE,
+                        ]),
+                        resolve_provider: Some(true),
+                        work_done_progress_options: Default::default(),
+                    },
+                )),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands,
+                    work_done_progress_options: Default::default(),
+                }),
+                ..ServerCapabilities::default()
+            },
+            server_info: Some(ServerInfo {
+                name: "Claude Code Language Server".to_string(),
+                version: Some("0.1.0".to_string()),
+            }),
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        info!("Claude Code LSP server initialized!");
+        tokio::spawn(indexer::refresh_in_background(
+            self.state.symbol_index.clone(),
+            self.state.workspace_folders.roots(),
+        ));
+        self.watch_files().await;
+        let (sources, config, folders, trust, sender) = (
+            self.settings.clone(),
+            self.state.config.clone(),
+            self.state.workspace_folders.clone(),
+            self.state.trust.clone(),
+            self.notification_sender.clone(),
+        );
+        tokio::spawn(settings::watch(
+            self.state.workspace_folders.clone(),
+            self.state.shutdown.clone(),
+            move || {
+                let (sources, config, folders, trust, sender) = (
+                    sources.clone(),
+                    config.clone(),
+                    folders.clone(),
+                    trust.clone(),
+                    sender.clone(),
+                );
+                async move {
+                    reload_config(&sources, &folders, &trust, &config, sender.as_ref()).await;
+                }
+            },
+        ));
+
+        // On the first run in a workspace, ask whether Claude may change it. The
+        // workspace stays read-only to Claude until the user answers.
+        if self.state.trust.get() == Trust::Undecided && self.state.trust.workspace().is_some() {
+            let (client, sources, config, folders, trust, sender) = (
+                self.client.clone(),
+                self.settings.clone(),
+                self.state.config.clone(),
+                self.state.workspace_folders.clone(),
+                self.state.trust.clone(),
+                self.notification_sender.clone(),
+            );
+            tokio::spawn(async move {
+                if ask_trust(&client, &trust, &folders).await == Trust::Undecided {
+                    return;
+                }
+                reload_config(&sources, &folders, &trust, &config, sender.as_ref()).await;
+                if let Some(sender) = &sender {
+                    send_tools_list_changed(sender).await;
+                }
+            });
+        }
+
+        self.client
+            .log_message(MessageType::INFO, "Claude Code Language Server is ready!")
+            .await;
+    }
+
+    async fn shutdown(&self) -> LspResult<()> {
+        info!("LSP Server shutting down...");
+        self.state.shutdown.trigger();
+        Ok(())
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // Clients either push the settings or send null and expect a pull
+        let section = match Config::section(Some(&params.settings)) {
+            Some(section) => Some(section.clone()),
+            None => {
+                let item = ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("claudeCode".to_string()),
+                };
+                match self.client.configuration(vec![item]).await {
+                    Ok(mut sections) if !sections.is_empty() => {
+                        Some(sections.remove(0)).filter(|section| !section.is_null())
+                    }
+                    Ok(_) => None,
+                    Err(e) => {
+                        debug!("workspace/configuration failed: {}", e);
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some(section) = section {
+            self.settings.set_editor(section);
+            self.reload_config().await;
+        }
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        for folder in params.event.removed {
+            info!("Workspace folder removed: {}", folder.uri);
+            self.state.workspace_folders.remove(&folder.uri);
+        }
+        for folder in params.event.added {
+            info!("Workspace folder added: {}", folder.uri);
+            self.state.workspace_folders.add(folder);
+        }
+        // Their settings files came or went with them
+        self.reload_config().await;
+        self.rewatch_files().await;
+        tokio::spawn(indexer::refresh_in_background(
+            self.state.symbol_index.clone(),
+            self.state.workspace_folders.roots(),
+        ));
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        info!("Document opened: {}", params.text_document.uri);
+
+        let document = params.text_document;
+        self.state
+            .documents
+            .open(
+                document.uri.clone(),
+                document.language_id.clone(),
+                document.version,
+                document.text,
+            )
+            .await;
+
+        let file_path = paths::file_path(&document.uri);
+        let opened_notification = OpenedFileNotification {
+            relative_path: self.relative_path(&file_path),
+            file_path: file_path.to_string_lossy().to_string(),
+            file_url: document.uri.to_string(),
+            language_id: document.language_id,
+            workspace_root: self.root_string(&document.uri),
+        };
+        self.send_notification(
+            "opened_file",
+            serde_json::to_value(opened_notification).unwrap_or_default(),
+        )
+        .await;
+
+        self.client
+            .log_message(
+                MessageType::INFO,
+                format!("Opened document: {}", document.uri),
+            )
+            .await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        info!("Document changed: {}", params.text_document.uri);
+
+        // Typing sends one change at a time, and only typing finishes a mention
+        let typed = match params.content_changes.as_slice() {
+            [change] if !change.text.is_empty() => {
+                change.range.map(|range| (range.start, change.text.len()))
+            }
+            _ => None,
+        };
+        self.state
+            .documents
+            .changed(
+                &params.text_document.uri,
+                params.text_document.version,
+                params.content_changes,
+            )
+            .await;
+        if let Some((start, length)) = typed {
+            self.send_typed_mentions(&params.text_document.uri, start, length)
+                .await;
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        info!("Document saved: {}", params.text_document.uri);
+
+        self.state.documents.saved(&params.text_document.uri).await;
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            let index = self.state.symbol_index.clone();
+            tokio::task::spawn_blocking(move || index.update_file(&path));
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        info!("Document closed: {}", params.text_document.uri);
+
+        let uri = params.text_document.uri;
+        self.symbols.remove(&uri);
+        self.sent_mentions.remove(&uri);
+        if self.annotations.remove(&uri) {
+            self.client
+                .publish_diagnostics(uri.clone(), Vec::new(), None)
+                .await;
+        }
+        if self.state.documents.close(&uri).await.is_some() {
+            let file_path = paths::file_path(&uri);
+            let closed_notification = ClosedFileNotification {
+                relative_path: self.relative_path(&file_path),
+                file_path: file_path.to_string_lossy().to_string(),
+                file_url: uri.to_string(),
+                workspace_root: self.root_string(&uri),
+            };
+            self.send_notification(
+                "closed_file",
+                serde_json::to_value(closed_notification).unwrap_or_default(),
+            )
+            .await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        debug!("{} watched file events", params.changes.len());
+        self.apply_file_changes(file_events::from_watched(params.changes))
+            .await;
+    }
+
+    async fn did_rename_files(&self, params: RenameFilesParams) {
+        let changes = params
+            .files
+            .iter()
+            .filter_map(|rename| {
+                Some(FileChange::Renamed {
+                    from: Url::parse(&rename.old_uri).ok()?.to_file_path().ok()?,
+                    to: Url::parse(&rename.new_uri).ok()?.to_file_path().ok()?,
+                })
+            })
+            .collect();
+        self.apply_file_changes(changes).await;
+    }
+
+    async fn did_delete_files(&self, params: DeleteFilesParams) {
+        let changes = params
+            .files
+            .iter()
+            .filter_map(|delete| {
+                let path = Url::parse(&delete.uri).ok()?.to_file_path().ok()?;
+                Some(FileChange::Deleted(path))
+            })
+            .collect();
+        self.apply_file_changes(changes).await;
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> LspResult<Option<Vec<SymbolInformation>>> {
+        info!("Workspace symbols requested for {:?}", params.query);
+
+        let index = self.state.symbol_index.clone();
+        let roots = self.state.workspace_folders.roots();
+        let hits = tokio::task::spawn_blocking(move || {
+            index.refresh_if_stale(&roots);
+            index.search(&params.query, indexer::MAX_RESULTS)
+        })
+        .await
+        .unwrap_or_default();
+
+        #[allow(deprecated)]
+        let symbols = hits
+            .into_iter()
+            .filter_map(|hit| {
+                Some(SymbolInformation {
+                    name: hit.symbol.name,
+                    kind: hit.symbol.kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location {
+                        uri: Url::from_file_path(&hit.path).ok()?,
+                        range: Range::new(
+                            Position::new(hit.symbol.line, 0),
+                            Position::new(hit.symbol.end_line + 1, 0),
+                        ),
+                    },
+                    container_name: None,
+                })
+            })
+            .collect();
+        Ok(Some(symbols))
+    }
+
+    async fn hover(&self, params: HoverParams) -> LspResult<Option<Hover>> {
+        let position = params.text_document_position_params.position;
+        info!(
+            "Hover requested at {}:{}",
+            position.line, position.character
+        );
+
+        let uri = params.text_document_position_params.text_document.uri;
+        let Ok(text) = text::read_file(paths::file_path(&uri)) else {
+            return Ok(None);
+        };
+        let Some((symbol, range)) =
+            symbols::identifier_at(&text, position, self.state.documents.encoding())
+        else {
+            return Ok(None);
+        };
+
+        let claude = self.claude_client(&uri).await;
+        let settings = self.state.config.read().await.hover_explain.clone();
+        let explanation = self
+            .hover
+            .explain(
+                &settings,
+                &claude,
+                &paths::path_string(&uri),
+                &text,
+                &symbol,
+                position.line as usize,
+            )
+            .await;
+
+        Ok(explanation.map(|value| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range),
+        }))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> LspResult<Option<CompletionResponse>> {
+        let position = params.text_document_position.position;
+        info!(
+            "Completion requested at {}:{}",
+            position.line, position.character
+        );
+
+        let uri = &params.text_document_position.text_document.uri;
+        let (range, typed) = self.completion_prefix(uri, position).await;
+        let snippets = self.supports_completion_snippets();
+
+        let mut completions: Vec<CompletionItem> = CLAUDE_COMPLETIONS
+            .iter()
+            .map(|&(command, placeholder, detail, documentation)| {
+                let label = format!("@claude {}", command);
+                let (insert_text, format) = if snippets {
+                    let snippet = format!("{} ${{1:{}}}", label, placeholder);
+                    (snippet, InsertTextFormat::SNIPPET)
+                } else {
+                    (label.clone(), InsertTextFormat::PLAIN_TEXT)
+                };
+                CompletionItem {
+                    label,
+                    kind: Some(CompletionItemKind::TEXT),
+                    detail: Some(detail.to_string()),
+                    documentation: Some(Documentation::String(documentation.to_string())),
+                    insert_text: Some(insert_text),
+                    insert_text_format: Some(format),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        // Mentions are offered right after "@" and for any "@..." typed since,
+        // but not for "@claude <command>"
+        let mentioning = typed.starts_with('@') && !typed.contains(' ');
+        if mentioning && self.state.config.read().await.features.mentions {
+            completions.extend(self.mention_completions(uri).await);
+        }
+
+        // Every item replaces what was typed, "@" included, and only those that
+        // complete it are offered
+        completions
+            .retain(|item| completes(item.filter_text.as_deref().unwrap_or(&item.label), &typed));
+        for item in &mut completions {
+            let new_text = item
+                .insert_text
+                .take()
+                .unwrap_or_else(|| item.label.clone());
+            item.text_edit = Some(CompletionTextEdit::Edit(TextEdit::new(range, new_text)));
+        }
+
+        Ok(Some(CompletionResponse::Array(completions)))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> LspResult<Option<CodeActionResponse>> {
+        info!("Code action requested for range: {:?}", params.range);
+
+        // Code action requests carry the editor's diagnostics for the range,
+        // which is the only place other servers' diagnostics reach us
+        if self.state.config.read().await.features.diagnostics {
+            self.state
+                .diagnostics
+                .merge_range(
+                    &params.text_document.uri,
+                    params.range,
+                    params.context.diagnostics.clone(),
+                )
+                .await;
+        }
+        self.state
+            .documents
+            .mark_active(&params.text_document.uri)
+            .await;
+
+        // Send selection_changed notification when code action is requested
+        let selection_notification = self
+            .selection_notification(&params.text_document.uri, params.range)
+            .await;
+
+        debug!(
+            "Queueing debounced selection_changed for range: {:?}",
+            params.range
+        );
+        self.send_selection_debounced(selection_notification).await;
+
+        if !self.state.config.read().await.features.code_actions {
+            return Ok(None);
+        }
+
+        // Actions only carry data; the command is filled in by codeAction/resolve
+        // unless the client can't resolve lazily
+        let resolve_lazily = self.supports_code_action_resolve();
+        let target = ActionTarget {
+            uri: params.text_document.uri.clone(),
+            range: params.range,
+            diagnostic: None,
+        };
+
+        // Fixes suggested by a review come first
+        let mut actions: Vec<CodeActionOrCommand> =
+            review::fixes(&target.uri, &params.context.diagnostics)
+                .into_iter()
+                .map(CodeActionOrCommand::CodeAction)
+                .collect();
+
+        let templates = self
+            .prompts
+            .for_root(self.workspace_root(&target.uri).as_deref());
+
+        // Then a fix template run on the lines of each problem other servers
+        // reported, with the problem spelled out in the prompt
+        if let Some(fix) = templates.get("fix") {
+            for diagnostic in &params.context.diagnostics {
+                if matches!(
+                    diagnostic.source.as_deref(),
+                    Some(review::REVIEW_SOURCE | annotations::EXPLANATION_SOURCE)
+                ) {
+                    continue;
+                }
+                let diagnostic_target = ActionTarget {
+                    uri: target.uri.clone(),
+                    range: self.whole_lines(&target.uri, diagnostic.range).await,
+                    diagnostic: Some(diagnostic.clone()),
+                };
+                let mut data = serde_json::to_value(&diagnostic_target).unwrap_or_default();
+                data["action"] = serde_json::json!(fix.name);
+                let mut code_action = CodeAction {
+                    title: format!(
+                        "{}: {}",
+                        fix.title,
+                        review::short_message(&diagnostic.message)
+                    ),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    is_preferred: Some(false),
+                    data: Some(data),
+                    ..Default::default()
+                };
+                if !resolve_lazily {
+                    code_action.command = Some(action_command(fix, &diagnostic_target));
+                }
+                actions.push(CodeActionOrCommand::CodeAction(code_action));
+            }
+        }
+        actions.extend(templates.iter().map(|template| {
+            let mut code_action = CodeAction {
+                title: template.title.clone(),
+                kind: Some(template.kind.clone()),
+                diagnostics: None,
+                edit: None,
+                command: None,
+                is_preferred: Some(false),
+                disabled: None,
+                data: Some(serde_json::json!({
+                    "action": template.name,
+                    "uri": target.uri,
+                    "range": target.range
+                })),
+            };
+            if !resolve_lazily {
+                code_action.command = Some(action_command(template, &target));
+            }
+            CodeActionOrCommand::CodeAction(code_action)
+        }));
+
+        for (title, scope) in [
+            ("Review file with Claude", "file"),
+            ("Review changes with Claude", "diff"),
+        ] {
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: title.to_string(),
+                kind: Some(CodeActionKind::SOURCE),
+                command: Some(Command {
+                    title: title.to_string(),
+                    command: "claude-code.review".to_string(),
+                    arguments: Some(vec![serde_json::json!({
+                        "uri": target.uri,
+                        "scope": scope
+                    })]),
+                }),
+                ..Default::default()
+            }));
+        }
+
+        Ok(Some(actions))
+    }
+
+    async fn code_action_resolve(&self, mut params: CodeAction) -> LspResult<CodeAction> {
+        info!("Resolving code action: {}", params.title);
+        // Review actions and fixes are complete already
+        if params.command.is_some() || params.edit.is_some() {
+            return Ok(params);
+        }
+
+        let data = params.data.clone().unwrap_or_default();
+        let target = serde_json::from_value::<ActionTarget>(data.clone()).ok();
+        let templates = target.as_ref().map(|target| {
+            self.prompts
+                .for_root(self.workspace_root(&target.uri).as_deref())
+        });
+        let template = data
+            .get("action")
+            .and_then(|v| v.as_str())
+            .zip(templates.as_deref())
+            .and_then(|(name, templates)| templates.get(name));
+
+        match (template, &target) {
+            (Some(template), Some(target)) => {
+                params.command = Some(action_command(template, target));
+                Ok(params)
+            }
+            _ => Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "Code action is missing Claude action data",
+            )),
+        }
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> LspResult<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        debug!("Document symbols requested for {}", uri);
+        let Some((text, symbols)) = self.document_symbols(&uri).await else {
+            return Ok(None);
+        };
+        let outline = symbols::document_symbols(&symbols, &text, self.state.documents.encoding());
+        Ok(Some(DocumentSymbolResponse::Nested(outline)))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> LspResult<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+        debug!("Code lens requested for {}", uri);
+        if !self.state.config.read().await.features.code_lens {
+            return Ok(None);
+        }
+        let Some((text, symbols)) = self.document_symbols(&uri).await else {
+            return Ok(None);
+        };
+
+        let templates = self.prompts.for_root(self.workspace_root(&uri).as_deref());
+        let lens_templates: Vec<_> = CODE_LENS_TEMPLATES
+            .iter()
+            .filter_map(|(name, title)| Some((templates.get(name)?, *title)))
+            .collect();
+
+        let index = LineIndex::new(&text);
+        let encoding = self.state.documents.encoding();
+        let mut lenses = Vec::new();
+        for symbol in symbols
+            .iter()
+            .filter(|symbol| matches!(symbol.kind, SymbolKind::FUNCTION | SymbolKind::METHOD))
+        {
+            // The lens sits on the declaration line; its commands get the whole
+            // declaration as their selection
+            let end_character = index
+                .line(symbol.end_line)
+                .map_or(0, |line| encoding.measure(line));
+            let target = ActionTarget {
+                uri: uri.clone(),
+                range: Range::new(
+                    Position::new(symbol.line, 0),
+                    Position::new(symbol.end_line, end_character),
+                ),
+                diagnostic: None,
+            };
+            for (template, title) in &lens_templates {
+                let mut command = action_command(template, &target);
+                command.title = title.to_string();
+                lenses.push(CodeLens {
+                    range: Range::new(Position::new(symbol.line, 0), Position::new(symbol.line, 0)),
+                    command: Some(command),
+                    data: None,
+                });
+            }
+        }
+
+        Ok(Some(lenses))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> LspResult<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        let Some(document) = self.state.documents.get(&uri).await else {
+            return Ok(None);
+        };
+
+        // Mentions typed here and sent to Claude are marked where they end
+        let index = LineIndex::new(&document.text);
+        let encoding = self.state.documents.encoding();
+        let mut hints = Vec::new();
+        for line in params.range.start.line..=params.range.end.line {
+            let Some(content) = index.line(line) else {
+                break;
+            };
+            let ended = index.line(line + 1).is_some();
+            for found in mentions::find(content, ended) {
+                if !self.sent_mentions.contains(&uri, &found.token) {
+                    continue;
+                }
+                hints.push(InlayHint {
+                    position: Position::new(line, encoding.measure(&content[..found.end])),
+                    label: InlayHintLabel::String("sent to Claude".to_string()),
+                    kind: None,
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: Some(true),
+                    padding_right: None,
+                    data: None,
+                });
+            }
+        }
+
+        Ok(Some(hints))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> LspResult<Option<Value>> {
+        let command = params.command.clone();
+        let started = Instant::now();
+        let result = self.run_command(params).await;
+        self.state
+            .status
+            .metrics()
+            .command_finished(&command, started.elapsed());
+        result
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> LspResult<Option<Vec<SelectionRange>>> {
+        info!(
+            "Selection range requested for {} positions",
+            params.positions.len()
+        );
+
+        self.state
+            .documents
+            .mark_active(&params.text_document.uri)
+            .await;
+
+        let uri = &params.text_document.uri;
+        // One selection_changed for all the cursors, with the first as the primary
+        if let Some(&primary) = params.positions.first() {
+            let mut selection_notification = self
+                .selection_notification(uri, Range::new(primary, primary))
+                .await;
+            if params.positions.len() > 1 {
+                selection_notification.selections = params
+                    .positions
+                    .iter()
+                    .map(|&position| SelectionInfo {
+                        start: position,
+                        end: position,
+                        is_empty: true,
+                    })
+                    .collect();
+            }
+            self.send_selection_debounced(selection_notification).await;
+        }
+
+        let text = match self.state.documents.get(uri).await {
+            Some(document) => document.text,
+            None => text::read_file(paths::file_path(uri)).unwrap_or_default(),
+        };
+        let language_id = self.state.documents.language_id(uri).await;
+        let ranges = selection_ranges(
+            &text,
+            &language_id,
+            &params.positions,
+            self.state.documents.encoding(),
+        );
+        Ok(Some(ranges))
+    }
+}
+
+/// The ranges a selection at each of `positions` expands through: the syntax
+/// nodes around it, innermost first, for languages with a grammar, and otherwise
+/// its line. The whole text comes last.
+fn selection_ranges(
+    text: &str,
+    language_id: &str,
+    positions: &[Position],
+    encoding: PositionEncoding,
+) -> Vec<SelectionRange> {
+    let index = LineIndex::new(text);
+    let offsets: Vec<usize> = positions
+        .iter()
+        .map(|&position| index.offset(position, encoding).unwrap_or(text.len()))
+        .collect();
+    let nodes = syntax::enclosing_ranges(text, language_id, &offsets);
+    let whole = Range::new(Position::new(0, 0), index.position(text.len(), encoding));
+
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| {
+            let mut ranges: Vec<Range> = match &nodes {
+                Some(nodes) => nodes[i]
+                    .iter()
+                    .map(|bytes| {
+                        Range::new(
+                            index.position(bytes.start, encoding),
+                            index.position(bytes.end, encoding),
+                        )
+                    })
+                    .collect(),
+                None => {
+                    let line = index.line(position.line).unwrap_or_default();
+                    let line = Range::new(
+                        Position::new(position.line, 0),
+                        Position::new(position.line, encoding.measure(line)),
+                    );
+                    vec![line, whole]
+                }
+            };
+            ranges.dedup();
+            // Outermost first, so each wraps the ones inside it
+            let mut selection = None;
+            for range in ranges.into_iter().rev() {
+                selection = Some(SelectionRange {
+                    range,
+                    parent: selection.map(Box::new),
+                });
+            }
+            selection.unwrap_or(SelectionRange {
+                range: whole,
+                parent: None,
+            })
+        })
+        .collect()
+}
+
+/// The "@claude" commands offered as completions: the command, a placeholder for
+/// what it is about, and the item's detail and documentation
+const CLAUDE_COMPLETIONS: [(&str, &str, &str, &str); 3] = [
+    (
+        "explain",
+        "what",
+        "Explain this code with Claude",
+        "Ask Claude to explain the selected code or current context",
+    ),
+    (
+        "improve",
+        "how",
+        "Improve this code with Claude",
+        "Ask Claude to suggest improvements for the selected code",
+    ),
+    (
+        "fix",
+        "what's wrong",
+        "Fix issues in this code with Claude",
+        "Ask Claude to identify and fix issues in the selected code",
+    ),
+];
+
+/// The end of `before_cursor` a completion would replace: from the last "@" in
+/// the current word, or from an "@claude " just before it, otherwise the word
+fn typed_prefix(before_cursor: &str) -> &str {
+    let word_start = before_cursor
+        .char_indices()
+        .rev()
+        .find(|(_, ch)| ch.is_whitespace())
+        .map_or(0, |(index, ch)| index + ch.len_utf8());
+    let word = &before_cursor[word_start..];
+    if let Some(at) = word.rfind('@') {
+        return &word[at..];
+    }
+
+    // "@claude ex" spans a space
+    let before_word = &before_cursor[..word_start];
+    match before_word.strip_suffix("@claude ") {
+        Some(rest) if rest.is_empty() || rest.ends_with(char::is_whitespace) => {
+            &before_cursor[rest.len()..]
+        }
+        _ => word,
+    }
+}
+
+/// Whether an item filtered by `candidate` completes `typed`, ignoring case:
+/// `typed` starts it, or, for "@" mentions, starts one of its path segments
+fn completes(candidate: &str, typed: &str) -> bool {
+    let candidate = candidate.to_lowercase();
+    let typed = typed.to_lowercase();
+    if candidate.starts_with(&typed) {
+        return true;
+    }
+    match typed.strip_prefix('@') {
+        Some(name) if !name.is_empty() => candidate
+            .split('/')
+            .skip(1)
+            .any(|segment| segment.starts_with(name)),
+        _ => false,
+    }
+}
+
+fn symbol_completion_kind(kind: SymbolKind) -> CompletionItemKind {
+    match kind {
+        SymbolKind::FUNCTION => CompletionItemKind::FUNCTION,
+        SymbolKind::METHOD => CompletionItemKind::METHOD,
+        SymbolKind::CONSTRUCTOR => CompletionItemKind::CONSTRUCTOR,
+        SymbolKind::STRUCT => CompletionItemKind::STRUCT,
+        SymbolKind::CLASS => CompletionItemKind::CLASS,
+        SymbolKind::ENUM => CompletionItemKind::ENUM,
+        SymbolKind::INTERFACE => CompletionItemKind::INTERFACE,
+        SymbolKind::MODULE => CompletionItemKind::MODULE,
+        SymbolKind::CONSTANT => CompletionItemKind::CONSTANT,
+        _ => CompletionItemKind::REFERENCE,
+    }
+}
+
+/// What a cancelled request had written so far, marked as cancelled
+fn cancelled_message(title: &str, streamed: &str) -> String {
+    if streamed.trim().is_empty() {
+        format!("Claude Code: {} cancelled", title)
+    } else {
+        format!("{}\n\n[cancelled]", streamed.trim_end())
+    }
+}
+
+/// Prompt templates offered as lenses above functions, with their lens titles
+const CODE_LENS_TEMPLATES: [(&str, &str); 3] = [
+    ("explain", "Explain"),
+    ("improve", "Improve"),
+    ("write-tests", "Write tests"),
+];
+
+fn action_command(template: &PromptTemplate, target: &ActionTarget) -> Command {
+    Command {
+        title: template.title.clone(),
+        command: template.command(),
+        arguments: Some(vec![serde_json::to_value(target).unwrap_or_default()]),
+    }
+}
+
+/// Where LSP messages are read from and written to: stdio when Zed runs the
+/// server, any byte stream when it is embedded
+pub struct LspTransport {
+    input: Box<dyn AsyncRead + Send + Unpin>,
+    output: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl LspTransport {
+    pub fn new(
+        input: impl AsyncRead + Send + Unpin + 'static,
+        output: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+
+    pub fn stdio() -> Self {
+        Self::new(tokio::io::stdin(), tokio::io::stdout())
+    }
+
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        Box<dyn AsyncRead + Send + Unpin>,
+        Box<dyn AsyncWrite + Send + Unpin>,
+    ) {
+        (self.input, self.output)
+    }
+}
+
+/// Replace the configuration. Claude is told when the tools on offer changed.
+async fn replace_config(
+    config: &SharedConfig,
+    new: Config,
+    notification_sender: Option<&SharedNotificationCoalescer>,
+) {
+    let mut current = config.write().await;
+    if *current == new {
+        return;
+    }
+    info!("Configuration updated: {:?}", new);
+    let tools_changed = current.enabled_tools != new.enabled_tools;
+    *current = new;
+    drop(current);
+
+    if let (true, Some(sender)) = (tools_changed, notification_sender) {
+        send_tools_list_changed(sender).await;
+    }
+}
+
+/// Merge the editor's settings with the settings files again, and apply them.
+/// The workspace folders' own settings files only count once the user trusts
+/// the workspace.
+async fn reload_config(
+    sources: &SettingsSources,
+    folders: &WorkspaceFolders,
+    trust: &WorkspaceTrust,
+    config: &SharedConfig,
+    notification_sender: Option<&SharedNotificationCoalescer>,
+) {
+    let roots = if trust.trusted() {
+        folders.roots()
+    } else {
+        Vec::new()
+    };
+    if let Some(merged) = sources.config(&roots) {
+        replace_config(config, merged, notification_sender).await;
+    }
+}
+
+/// Tell Claude to list the MCP tools again
+async fn send_tools_list_changed(notification_sender: &SharedNotificationCoalescer) {
+    notification_sender
+        .send(JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/tools/list_changed".to_string(),
+            params: serde_json::json!({}),
+        })
+        .await;
+}
+
+/// Ask the user, with window/showMessageRequest, whether they trust the
+/// workspace, and record the answer. Dismissing the prompt leaves it as it was.
+async fn ask_trust(client: &Client, trust: &WorkspaceTrust, folders: &WorkspaceFolders) -> Trust {
+    let name = trust
+        .workspace()
+        .map(Path::to_path_buf)
+        .or_else(|| folders.roots().into_iter().next())
+        .map_or("this workspace".to_string(), |path| {
+            path.display().to_string()
+        });
+    let message = format!(
+        "Claude Code: do you trust the files in {}? Claude can edit files and run commands in a trusted workspace, and only read an untrusted one.",
+        name
+    );
+    let actions = [trust::TRUST, trust::DISTRUST]
+        .into_iter()
+        .map(|title| MessageActionItem {
+            title: title.to_string(),
+            properties: Default::default(),
+        })
+        .collect();
+
+    match client
+        .show_message_request(MessageType::WARNING, message, Some(actions))
+        .await
+    {
+        Ok(Some(action)) if action.title == trust::TRUST => trust.decide(true),
+        Ok(Some(action)) if action.title == trust::DISTRUST => trust.decide(false),
+        Ok(_) => info!("Workspace trust prompt dismissed"),
+        Err(e) => warn!("window/showMessageRequest failed: {}", e),
+    }
+    trust.get()
+}
+
+/// `workspace_folders` should already contain the worktree passed on the command
+/// line; folders from initialize and didChangeWorkspaceFolders are added to it
+#[allow(clippy::too_many_arguments)]
+pub async fn run_lsp_server_with_notifications(
+    notification_sender: Option<SharedNotificationBus>,
+    command_receiver: Option<CommandReceiver>,
+    state: SharedServerState,
+    transport: LspTransport,
+) -> Result<()> {
+    info!("Starting LSP server mode");
+
+    let client_capabilities = SharedClientCapabilities::default();
+    let mut lsp_client = None;
+
+    let (service, socket) = LspService::build(|client| {
+        lsp_client = Some(client.clone());
+        let mut server = ClaudeCodeLanguageServer::new(client, state.clone())
+            .with_client_capabilities(client_capabilities.clone());
+        if let Some(sender) = notification_sender.clone() {
+            server = server.with_notification_sender(sender);
+        }
+        server
+    })
+    .custom_method(
+        "claude/publishDiagnostics",
+        ClaudeCodeLanguageServer::publish_diagnostics,
+    )
+    .custom_method(
+        "claude/selectionDidChange",
+        ClaudeCodeLanguageServer::selection_did_change,
+    )
+    // Not part of tower-lsp's LanguageServer trait
+    .custom_method(
+        "window/workDoneProgress/cancel",
+        ClaudeCodeLanguageServer::work_done_progress_cancel,
+    )
+    .finish();
+
+    let ServerState {
+        config,
+        workspace_folders,
+        documents,
+        undo_journal,
+        shutdown,
+        ..
+    } = (*state).clone();
+
+    // Spawn command handler if we have a receiver
+    if let (Some(mut receiver), Some(client)) = (command_receiver, lsp_client) {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            info!("Command handler ready, waiting for commands...");
+
+            loop {
+                let command = tokio::select! {
+                    command = receiver.recv() => match command {
+                        Some(command) => command,
+                        None => break,
+                    },
+                    _ = shutdown.triggered() => {
+                        // Commands sent from now on fail right away instead of
+                        // waiting for an editor that is going away
+                        receiver.close();
+                        break;
+                    }
+                };
+                match command {
+                    LspCommand::OpenFile {
+                        file_path,
+                        range,
+                        take_focus,
+                        reply,
+                    } => {
+                        info!(
+                            "Handling OpenFile command: {} (take_focus: {})",
+                            file_path, take_focus
+                        );
+                        let zed = ZedCli::new(&*config.read().await);
+                        let path = workspace::resolve_path(&workspace_folders.roots(), &file_path);
+                        // The zed CLI may take a while, so don't hold up other commands
+                        let (client, client_capabilities) =
+                            (client.clone(), client_capabilities.clone());
+                        tokio::spawn(async move {
+                            let opened = open_file(
+                                &client,
+                                &client_capabilities,
+                                &zed,
+                                &path,
+                                range,
+                                take_focus,
+                            )
+                            .await;
+                            let _ = reply.send(opened);
+                        });
+                    }
+                    LspCommand::CloseTab {
+                        tab_name,
+                        file_path,
+                    } => {
+                        info!("Handling CloseTab command: {}", tab_name);
+                        let zed = ZedCli::new(&*config.read().await);
+                        if let Err(e) =
+                            open_file(&client, &client_capabilities, &zed, &file_path, None, true)
+                                .await
+                        {
+                            warn!(
+                                "Failed to bring {} to the front: {}",
+                                file_path.display(),
+                                e
+                            );
+                        }
+                    }
+                    LspCommand::PreviewDiff {
+                        uri,
+                        tab_name,
+                        hunks,
+                    } => {
+                        info!("Previewing {} hunks for {}", hunks.len(), uri);
+                        client
+                            .send_notification::<DiffPreview>(DiffPreviewParams {
+                                text_document: TextDocumentIdentifier { uri },
+                                tab_name,
+                                hunks,
+                            })
+                            .await;
+                    }
+                    LspCommand::CloseAllDiffTabs { file_paths } => {
+                        info!(
+                            "Handling CloseAllDiffTabs command for {} tabs",
+                            file_paths.len()
+                        );
+                        // Focusing the most recently diffed file leaves it in front
+                        if let Some(file_path) = file_paths.last() {
+                            let zed = ZedCli::new(&*config.read().await);
+                            if let Err(e) = open_file(
+                                &client,
+                                &client_capabilities,
+                                &zed,
+                                file_path,
+                                None,
+                                true,
+                            )
+                            .await
+                            {
+                                warn!(
+                                    "Failed to bring {} to the front: {}",
+                                    file_path.display(),
+                                    e
+                                );
+                            }
+                        }
+                    }
+                    LspCommand::RequestPermission { request, reply } => {
+                        info!("Asking the user to allow {:?}", request.action);
+                        // The user may take a while to answer, so don't hold up other commands
+                        let client = client.clone();
+                        tokio::spawn(async move {
+                            let decision = request_permission(&client, request).await;
+                            let _ = reply.send(decision);
+                        });
+                    }
+                    LspCommand::SaveDocuments { uri, reply } => {
+                        info!("Handling SaveDocuments command for {:?}", uri);
+                        let results = documents.write_dirty(uri.as_ref()).await;
+                        for (uri, result) in &results {
+                            match result {
+                                Ok(()) => info!("Saved {}", uri),
+                                Err(e) => warn!("{}", e),
+                            }
+                        }
+                        let _ = reply.send(results);
+                    }
+                    LspCommand::ApplyEdits { plan, reply } => {
+                        info!("Handling ApplyEdits command for {} files", plan.files.len());
+                        let document_changes = supports_document_changes(&client_capabilities);
+                        let client = client.clone();
+                        let undo_journal = undo_journal.clone();
+                        tokio::spawn(async move {
+                            let result = edits::apply(&client, &plan, document_changes).await;
+                            if result.is_ok() {
+                                undo_journal
+                                    .record(JournalEntry::new("applyEdits", plan.snapshots()));
+                            }
+                            let _ = reply.send(result);
+                        });
+                    }
+                    LspCommand::ApplyRefactor {
+                        title,
+                        steps,
+                        reply,
+                    } => {
+                        info!("Handling ApplyRefactor command for {} files", steps.len());
+                        let document_changes = supports_document_changes(&client_capabilities);
+                        let can_create_progress = supports_work_done_progress(&client_capabilities);
+                        let client = client.clone();
+                        let undo_journal = undo_journal.clone();
+                        let documents = documents.clone();
+                        tokio::spawn(async move {
+                            let progress = WorkDone::begin(
+                                &client,
+                                None,
+                                can_create_progress,
+                                &format!("Applying {}", title),
+                                None,
+                            )
+                            .await;
+                            let outcome = refactor::apply(
+                                &client,
+                                steps,
+                                &documents,
+                                document_changes,
+                                &undo_journal,
+                                &progress,
+                            )
+                            .await;
+                            progress.end(Some(outcome.summary())).await;
+                            let _ = reply.send(outcome);
+                        });
+                    }
+                    LspCommand::UndoLastEdit { reply } => {
+                        info!("Handling UndoLastEdit command");
+                        let document_changes = supports_document_changes(&client_capabilities);
+                        let client = client.clone();
+                        let undo_journal = undo_journal.clone();
+                        let documents = documents.clone();
+                        tokio::spawn(async move {
+                            let result = journal::undo_last(
+                                &client,
+                                &undo_journal,
+                                &documents,
+                                document_changes,
+                            )
+                            .await;
+                            let _ = reply.send(result);
+                        });
+                    }
+                    LspCommand::ApplyFileOperation { operation, reply } => {
+                        info!("Handling ApplyFileOperation command: {:?}", operation);
+                        let resource_operations =
+                            supports_resource_operation(&client_capabilities, operation.kind());
+                        let client = client.clone();
+                        tokio::spawn(async move {
+                            let result =
+                                edits::apply_operation(&client, &operation, resource_operations)
+                                    .await;
+                            let _ = reply.send(result);
+                        });
+                    }
+                    LspCommand::ShowMessage { message } => {
+                        client
+                            .show_message(MessageType::INFO, format!("Claude Code: {}", message))
+                            .await;
+                    }
+                }
+            }
+
+            info!("Command handler shutting down");
+        });
+    }
+
+    let exited = Arc::new(Notify::new());
+    let service = ExitWatch {
+        inner: service,
+        exited: exited.clone(),
+    };
+    tokio::select! {
+        _ = Server::new(transport.input, transport.output, socket)
+            .concurrency_level(MAX_CONCURRENT_REQUESTS)
+            .serve(service) => {
+            info!("LSP transport closed");
+        }
+        _ = exited.notified() => {}
+        // After the shutdown request the editor sends exit right away; after a
+        // signal nothing more is coming
+        _ = async {
+            shutdown.triggered().await;
+            tokio::time::sleep(EXIT_GRACE).await;
+        } => info!("No exit notification after shutdown, stopping"),
+    }
+    // The editor sent exit or closed the transport
+    shutdown.trigger();
+
+    Ok(())
+}
+
+/// Passes messages through to the language server, noting the exit notification.
+/// tower-lsp keeps reading the transport after it, so serve() alone would only
+/// return once the editor closes it.
+struct ExitWatch<S> {
+    inner: S,
+    exited: Arc<Notify>,
+}
+
+impl<S: tower_service::Service<Request>> tower_service::Service<Request> for ExitWatch<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let exit = request.method() == "exit";
+        let response = self.inner.call(request);
+        if exit {
+            info!("Exit notification received");
+            self.exited.notify_one();
+        }
+        response
+    }
+}
+
+/// Open a file in the editor, preferring window/showDocument and falling back to
+/// the zed CLI when the client doesn't advertise showDocument support (or
+/// declines). The zed CLI can't select text, so it only moves the cursor to the
+/// start of `range`.
+async fn open_file(
+    client: &Client,
+    capabilities: &OnceLock<ClientCapabilities>,
+    zed: &ZedCli,
+    path: &Path,
+    range: Option<OpenFileRange>,
+    take_focus: bool,
+) -> anyhow::Result<()> {
+    let show_document_supported = capabilities
+        .get()
+        .and_then(|caps| caps.window.as_ref())
+        .and_then(|window| window.show_document.as_ref())
+        .is_some_and(|show_document| show_document.support);
+
+    if show_document_supported {
+        match Url::from_file_path(path) {
+            Ok(uri) => {
+                let params = ShowDocumentParams {
+                    uri,
+                    external: Some(false),
+                    take_focus: Some(take_focus),
+                    selection: range.map(OpenFileRange::to_lsp),
+                };
+
+                match client.show_document(params).await {
+                    Ok(true) => {
+                        info!("Opened file via window/showDocument: {}", path.display());
+                        return Ok(());
+                    }
+                    Ok(false) => warn!("Client declined to show {}", path.display()),
+                    Err(e) => warn!("window/showDocument failed for {}: {}", path.display(), e),
+                }
+            }
+            Err(()) => warn!("Cannot build file URI for {}", path.display()),
+        }
+    }
+
+    // Build the zed CLI argument with optional line:column
+    let path = path.to_string_lossy();
+    let zed_arg = match range {
+        Some(OpenFileRange {
+            start_line,
+            start_column: Some(column),
+            ..
+        }) => format!("{}:{}:{}", path, start_line, column),
+        Some(range) => format!("{}:{}", path, range.start_line),
+        None => path.to_string(),
+    };
+
+    zed.open(&zed_arg).await.inspect_err(|e| {
+        error!("Failed to open file via zed CLI: {}", e);
+    })
+}
+
+/// Whether the client takes versioned documentChanges in workspace edits
+/// Whether the client accepts server-initiated window/workDoneProgress/create
+fn supports_work_done_progress(capabilities: &SharedClientCapabilities) -> bool {
+    capabilities
+        .get()
+        .and_then(|caps| caps.window.as_ref())
+        .and_then(|window| window.work_done_progress)
+        .unwrap_or(false)
+}
+
+fn supports_document_changes(capabilities: &SharedClientCapabilities) -> bool {
+    capabilities
+        .get()
+        .and_then(|caps| caps.workspace.as_ref())
+        .and_then(|workspace| workspace.workspace_edit.as_ref())
+        .and_then(|workspace_edit| workspace_edit.document_changes)
+        .unwrap_or(false)
+}
+
+/// Publish a file's annotations as this server's diagnostics for it
+async fn publish(client: &Client, published: Published) {
+    client
+        .publish_diagnostics(published.uri, published.diagnostics, published.version)
+        .await;
+}
+
+/// Put Claude's tests in their file: added to the end when it exists, as an edit
+/// the undo journal records, or in a new file
+async fn write_tests(
+    client: &Client,
+    tests: &PlannedTests,
+    code: &str,
+    documents: &OpenDocuments,
+    undo_journal: &UndoJournal,
+    document_changes: bool,
+    resource_operations: bool,
+) -> Result<()> {
+    let code = format!("{}\n", code.trim_end());
+    let current = match documents.get(&tests.uri).await {
+        Some(document) => Some(document.text),
+        None => text::read_file(&tests.file.path).ok(),
+    };
+    let Some(current) = current else {
+        let operation = FileOperation::Create {
+            uri: tests.uri.clone(),
+            contents: code,
+            overwrite: false,
+        };
+        return edits::apply_operation(client, &operation, resource_operations).await;
+    };
+
+    // A blank line between what was there and the new tests
+    let separator = if current.is_empty() || current.ends_with("\n\n") {
+        ""
+    } else if current.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    let end = LineIndex::new(&current).position(current.len(), documents.encoding());
+    let edit = FileEdit {
+        file_path: tests.file.path.to_string_lossy().into_owned(),
+        range: Range::new(end, end),
+        new_text: format!("{}{}", separator, code),
+        old_text: None,
+    };
+    let plan = edits::prepare(vec![edit], &[], documents).await?;
+    edits::apply(client, &plan, document_changes).await?;
+    undo_journal.record(JournalEntry::new("write-tests", plan.snapshots()));
+    Ok(())
+}
+
+/// Whether the client applies `kind` operations in a workspace/applyEdit
+fn supports_resource_operation(
+    capabilities: &SharedClientCapabilities,
+    kind: ResourceOperationKind,
+) -> bool {
+    let workspace_edit = capabilities
+        .get()
+        .and_then(|caps| caps.workspace.as_ref())
+        .and_then(|workspace| workspace.workspace_edit.as_ref());
+    workspace_edit.is_some_and(|workspace_edit| {
+        workspace_edit.document_changes == Some(true)
+            && workspace_edit
+                .resource_operations
+                .as_ref()
+                .is_some_and(|kinds| kinds.contains(&kind))
+    })
+}
+
+/// Show a warning with Allow/Allow always/Deny buttons; errors and dismissing the
+/// message deny
+async fn request_permission(client: &Client, request: PermissionRequest) -> Decision {
+    let actions = Decision::ALL
+        .into_iter()
+        .map(|decision| MessageActionItem {
+            title: decision.title().to_string(),
+            properties: Default::default(),
+        })
+        .collect();
+
+    match client
+        .show_message_request(MessageType::WARNING, request.message, Some(actions))
+        .await
+    {
+        Ok(action) => Decision::from_title(action.as_ref().map(|action| action.title.as_str())),
+        Err(e) => {
+            warn!("window/showMessageRequest failed: {}", e);
+            Decision::Deny
+        }
+    }
+}
@@ -0,0 +1,3499 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
+use tower_lsp::lsp_types::{Diagnostic, Position, Range, Url};
+use tracing::{debug, info, warn};
+
+use crate::claude::ClaudeClient;
+use crate::commit_message;
+use crate::config::{Config, Features};
+use crate::context::{self, ContextBudget};
+use crate::diagnostics;
+use crate::diff;
+use crate::documents::OpenDocument;
+use crate::edits::{self, EditPlan, FileEdit, FileOperation};
+use crate::file_tree::{self, TreeOptions};
+use crate::git::{self, DiffOptions};
+use crate::grep::{self, GrepOptions};
+use crate::hashes;
+use crate::indexer;
+use crate::journal::JournalEntry;
+use crate::language;
+use crate::lsp::{CommandSender, LspCommand, OpenFileRange};
+use crate::navigation::{self, Search, SearchResults};
+use crate::notification_schemas;
+use crate::notifications::{NotificationFilter, NotificationSettings};
+use crate::paths;
+use crate::permissions::{Action, Decision, PermissionRequest, SharedPermissions};
+use crate::privacy;
+use crate::process::ClaudeProcessManager;
+use crate::proxy::SharedLanguageServerActivity;
+use crate::refactor::{self, RefactorPlan};
+use crate::resources::{self, Subscriptions};
+use crate::shell::{self, RecordedRun, RunStatus, SharedScrollback};
+use crate::shell_env::SharedShellEnvironments;
+use crate::state::SharedServerState;
+use crate::status::ServerStatus;
+use crate::symbols;
+use crate::tasks::{self, ZedTask};
+use crate::text;
+use crate::workspace::{self, SharedWorkspaceFolders};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MCPRequest {
+    pub jsonrpc: String,
+    pub id: Option<Value>,
+    pub method: String,
+    pub params: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MCPResponse {
+    pub jsonrpc: String,
+    pub id: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<MCPError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MCPError {
+    pub code: i32,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    pub tools: Option<ToolsCapability>,
+    pub prompts: Option<PromptsCapability>,
+    pub resources: Option<ResourcesCapability>,
+    pub logging: Option<LoggingCapability>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolsCapability {
+    #[serde(rename = "listChanged")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptsCapability {
+    #[serde(rename = "listChanged")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourcesCapability {
+    pub subscribe: Option<bool>,
+    #[serde(rename = "listChanged")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingCapability {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema")]
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(rename = "mimeType")]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextContent {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub text: String,
+}
+
+/// Diff tabs opened through openDiff and not yet closed, by tab name, with the
+/// file each one shows
+pub type DiffTabs = Arc<RwLock<HashMap<String, PathBuf>>>;
+
+/// Messages (e.g. notifications/progress) sent to the client while a request is
+/// still being handled
+pub type ProgressSender = mpsc::UnboundedSender<Value>;
+
+// searchSymbols results when the caller doesn't give a limit
+const DEFAULT_SYMBOL_LIMIT: usize = 50;
+// Trailing output lines per run returned by getLatestTerminalOutput by default
+const DEFAULT_TERMINAL_LINES: usize = 200;
+// getSelectionHistory results when the caller doesn't give a limit
+const DEFAULT_SELECTION_HISTORY: usize = 10;
+
+#[derive(Clone)]
+pub struct MCPServer {
+    capabilities: ServerCapabilities,
+    command_sender: Option<CommandSender>,
+    /// The editor state the LSP server (or the proxy) collects
+    state: SharedServerState,
+    claude_process: Option<ClaudeProcessManager>,
+    /// What the language server behind the proxy said, in proxy mode
+    language_server: Option<SharedLanguageServerActivity>,
+    permissions: SharedPermissions,
+    scrollback: SharedScrollback,
+    /// Users' shell environments for runCommand, per directory
+    shell_environments: SharedShellEnvironments,
+    subscriptions: Subscriptions,
+    notification_filter: NotificationFilter,
+}
+
+impl MCPServer {
+    pub fn new(command_sender: Option<CommandSender>) -> Self {
+        let capabilities = ServerCapabilities {
+            tools: Some(ToolsCapability {
+                list_changed: Some(true),
+            }),
+            prompts: Some(PromptsCapability {
+                list_changed: Some(false),
+            }),
+            resources: Some(ResourcesCapability {
+                subscribe: Some(true),
+                list_changed: Some(true),
+            }),
+            logging: Some(LoggingCapability {}),
+        };
+
+        Self {
+            capabilities,
+            command_sender,
+            state: SharedServerState::default(),
+            claude_process: None,
+            language_server: None,
+            permissions: SharedPermissions::default(),
+            scrollback: SharedScrollback::default(),
+            shell_environments: SharedShellEnvironments::default(),
+            subscriptions: Subscriptions::default(),
+            notification_filter: NotificationFilter::default(),
+        }
+    }
+
+    /// Handler for one client connection, with its own resource subscriptions
+    /// and notification filter
+    pub fn for_connection(mut self) -> Self {
+        self.subscriptions = Subscriptions::default();
+        self.notification_filter = NotificationFilter::default();
+        self
+    }
+
+    pub fn resource_subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    pub fn notification_filter(&self) -> &NotificationFilter {
+        &self.notification_filter
+    }
+
+    /// Receiver signalled whenever the cached diagnostics change
+    pub fn diagnostics_changes(&self) -> watch::Receiver<()> {
+        self.state.diagnostics.subscribe()
+    }
+
+    pub fn document_edits(&self) -> broadcast::Receiver<Url> {
+        self.state.documents.subscribe_edits()
+    }
+
+    /// How long updates to the resource `uri` are held back, merging bursts
+    pub async fn resource_debounce(&self, uri: &str) -> Duration {
+        let window = |config: &Config| {
+            if uri == resources::DIAGNOSTICS_URI {
+                config.diagnostics_debounce_ms
+            } else {
+                config.document_change_debounce_ms
+            }
+        };
+        let ms = window(&*self.state.config.read().await);
+        Duration::from_millis(ms)
+    }
+
+    /// Serve the editor state the LSP server collects
+    pub fn with_state(mut self, state: SharedServerState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn with_permissions(mut self, permissions: SharedPermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn with_language_server(mut self, activity: SharedLanguageServerActivity) -> Self {
+        self.language_server = Some(activity);
+        self
+    }
+
+    pub fn workspace_folders(&self) -> &SharedWorkspaceFolders {
+        &self.state.workspace_folders
+    }
+
+    pub fn with_claude_process(mut self, claude_process: ClaudeProcessManager) -> Self {
+        self.claude_process = Some(claude_process);
+        self
+    }
+
+    pub fn claude_process(&self) -> Option<&ClaudeProcessManager> {
+        self.claude_process.as_ref()
+    }
+
+    pub fn status(&self) -> &ServerStatus {
+        &self.state.status
+    }
+
+    /// Features enabled in the editor's configuration (all of them when unconfigured)
+    async fn features(&self) -> Features {
+        self.state.config.read().await.features.clone()
+    }
+
+    /// Whether metrics are served and exported (not when unconfigured)
+    pub async fn metrics_enabled(&self) -> bool {
+        self.state.config.read().await.metrics.enabled
+    }
+
+    /// How notifications are queued and sent (the defaults when unconfigured)
+    pub async fn notification_settings(&self) -> NotificationSettings {
+        self.state.config.read().await.notifications.clone()
+    }
+
+    /// Whether runCommand writes Zed tasks instead of running commands
+    async fn run_commands_as_tasks(&self) -> bool {
+        self.state.config.read().await.run_commands_as_tasks
+    }
+
+    /// JSON for getCurrentSelection/getLatestSelection from the cached selection
+    async fn selection_response(&self, missing_message: &str) -> Value {
+        let selection = if self.features().await.selection_tracking {
+            self.state.latest_selection.read().await.clone()
+        } else {
+            None
+        };
+
+        match selection {
+            Some(selection) => serde_json::json!({
+                "success": true,
+                "text": selection.text,
+                "filePath": selection.file_path,
+                "fileUrl": selection.file_url,
+                "languageId": selection.language_id,
+                "selection": selection.selection,
+                "truncated": selection.truncated,
+                "selections": selection.selections
+            }),
+            None => serde_json::json!({
+                "success": false,
+                "message": missing_message
+            }),
+        }
+    }
+
+    /// JSON for getSelectionHistory: up to `limit` past selections, newest first
+    async fn selection_history(&self, file_path: Option<&str>, limit: usize) -> Value {
+        if !self.features().await.selection_tracking {
+            return serde_json::json!({
+                "success": false,
+                "message": "Selection tracking is disabled"
+            });
+        }
+        let file = file_path.map(|path| workspace::resolve_path(&self.roots(), path));
+        let selections: Vec<Value> = self
+            .state
+            .selection_history
+            .recent(file.as_deref(), limit)
+            .into_iter()
+            .map(|record| {
+                let mut selection = serde_json::to_value(&record.selection).unwrap_or_default();
+                selection["selectedAt"] = record.selected_at.into();
+                selection
+            })
+            .collect();
+        serde_json::json!({
+            "success": true,
+            "selections": selections
+        })
+    }
+
+    pub async fn handle_request(
+        &self,
+        request: MCPRequest,
+        progress: Option<ProgressSender>,
+    ) -> Result<MCPResponse> {
+        info!("Handling MCP request: {}", request.method);
+        debug!("Request params: {:?}", request.params);
+        let started = Instant::now();
+        // Tool calls are timed per tool
+        let timed = match (request.method.as_str(), &request.params) {
+            ("tools/call", Some(params)) => match params.get("name").and_then(Value::as_str) {
+                Some(tool) => format!("tools/call {}", tool),
+                None => request.method.clone(),
+            },
+            _ => request.method.clone(),
+        };
+        let response = self.dispatch_request(request, progress).await;
+        self.state
+            .status
+            .metrics()
+            .mcp_request_finished(&timed, started.elapsed());
+        response
+    }
+
+    async fn dispatch_request(
+        &self,
+        request: MCPRequest,
+        progress: Option<ProgressSender>,
+    ) -> Result<MCPResponse> {
+        let result = match request.method.as_str() {
+            "initialize" => self.handle_initialize(request.params).await?,
+            "tools/list" => self.handle_tools_list().await?,
+            "tools/call" => self.handle_tools_call(request.params, progress).await?,
+            "logging/setLevel" => self.handle_logging_set_level(request.params).await?,
+            "prompts/list" => self.handle_prompts_list().await?,
+            "prompts/get" => self.handle_prompts_get(request.params).await?,
+            "resources/list" => self.handle_resources_list().await?,
+            "resources/templates/list" => self.handle_resource_templates_list()?,
+            "resources/read" => self.handle_resources_read(request.params).await?,
+            "resources/subscribe" => self.handle_resources_subscribe(request.params, true)?,
+            "resources/unsubscribe" => self.handle_resources_subscribe(request.params, false)?,
+            "ide/setNotificationFilter" => self.handle_set_notification_filter(request.params)?,
+            _ => {
+                return Ok(MCPResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: request.id,
+                    result: None,
+                    error: Some(MCPError {
+                        code: -32601,
+                        message: format!("Method not found: {}", request.method),
+                        data: None,
+                    }),
+                });
+            }
+        };
+
+        Ok(MCPResponse {
+            jsonrpc: "2.0".to_string(),
+            id: request.id,
+            result: Some(result),
+            error: None,
+        })
+    }
+
+    async fn handle_initialize(&self, params: Option<Value>) -> Result<Value> {
+        info!("Initializing MCP session");
+
+        if let Some(params) = &params {
+            debug!("Initialize params: {}", params);
+        }
+
+        // The notifications the client handles, and the newest version of each
+        let understood = params
+            .as_ref()
+            .and_then(|params| params.pointer("/capabilities/experimental/ideNotifications"))
+            .map(|versions| serde_json::from_value::<BTreeMap<String, u32>>(versions.clone()))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid ideNotifications: {}", e))?;
+        let versions = understood.map(|understood| notification_schemas::negotiate(&understood));
+        if let Some(versions) = &versions {
+            info!("Notification versions agreed: {:?}", versions);
+        }
+        self.notification_filter.set_versions(versions.clone());
+
+        let mut result = serde_json::json!({
+            "protocolVersion": "2025-03-26",
+            "capabilities": self.capabilities,
+            "serverInfo": ServerInfo {
+                name: "claude-code-server".to_string(),
+                version: "0.1.0".to_string()
+            }
+        });
+        // What the client will get: the agreed versions, or every one at its latest
+        result["capabilities"]["experimental"] = serde_json::json!({
+            "ideNotifications": versions.unwrap_or_else(notification_schemas::latest)
+        });
+        Ok(result)
+    }
+
+    async fn handle_tools_list(&self) -> Result<Value> {
+        info!("Listing available tools");
+
+        let tools: Vec<Tool> = vec![
+            Tool {
+                name: "getCurrentSelection".to_string(),
+                description: Some(
+                    "Get the current text selection in the active editor".to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "getLatestSelection".to_string(),
+                description: Some(
+                    "Get the most recent text selection, even if not in the active editor"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "getSelectionHistory".to_string(),
+                description: Some(
+                    "Get the selections recently sent from the editor, newest first, to see what the user was looking at before the current selection"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Only selections in this file (default: every file)"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Most selections to return (default: 10)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "readFileRange".to_string(),
+                description: Some(
+                    "Read lines of a file, as the editor has it. Selections in large files are sent as a reference (path, range and hash) to read this way."
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the file to read"
+                        },
+                        "startLine": {
+                            "type": "integer",
+                            "description": "First line to read (0-based)"
+                        },
+                        "endLine": {
+                            "type": "integer",
+                            "description": "Last line to read (0-based, inclusive)"
+                        },
+                        "hash": {
+                            "type": "string",
+                            "description": "Optional hash from a file reference, to tell whether the file changed since"
+                        }
+                    },
+                    "required": ["filePath", "startLine", "endLine"]
+                }),
+            },
+            Tool {
+                name: "readFile".to_string(),
+                description: Some(
+                    "Read a whole file. Returns the editor's buffer when the file has unsaved changes, and the file on disk otherwise, with its dirty state, version, languageId and size."
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the file to read"
+                        }
+                    },
+                    "required": ["filePath"]
+                }),
+            },
+            Tool {
+                name: "getWorkspaceFolders".to_string(),
+                description: Some("Get the workspace folders open in the editor".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "listWorkspaceFiles".to_string(),
+                description: Some(
+                    "List the files and directories of the workspace as a tree, down to a depth limit and respecting .gitignore, to get oriented in a project"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Optional directory to list (default: every workspace folder)"
+                        },
+                        "maxDepth": {
+                            "type": "number",
+                            "description": "Levels of directories to descend, 1 for only the directory's own entries (default 3)"
+                        },
+                        "maxEntries": {
+                            "type": "number",
+                            "description": "Maximum number of entries (default 500, at most 5000)"
+                        },
+                        "respectGitignore": {
+                            "type": "boolean",
+                            "description": "Leave out files .gitignore excludes (default true)"
+                        },
+                        "includeHidden": {
+                            "type": "boolean",
+                            "description": "Include dotfiles and dot-directories (default false)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "getOpenEditors".to_string(),
+                description: Some("Get the list of files currently open in the editor".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "getDiagnostics".to_string(),
+                description: Some(
+                    "Get language diagnostics (errors, warnings) from the editor".to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "uri": {
+                            "type": "string",
+                            "description": "Optional file URI to get diagnostics for. If not provided, gets diagnostics for all files."
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "getIdeContext".to_string(),
+                description: Some(
+                    "Get the editor's context in one call: the active file, the current selection, open editors, outstanding diagnostics and uncommitted git changes"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "checkDocumentDirty".to_string(),
+                description: Some(
+                    "Check whether a document open in the editor has unsaved changes".to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the file to check"
+                        }
+                    },
+                    "required": ["filePath"]
+                }),
+            },
+            Tool {
+                name: "saveDocument".to_string(),
+                description: Some(
+                    "Save a document with unsaved changes to disk, or every such document when no file path is given"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Optional path of the file to save"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "applyEdits".to_string(),
+                description: Some(
+                    "Apply a unified diff or a list of edits to the files open in the editor, all at once or not at all, after the user allows it"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "diff": {
+                            "type": "string",
+                            "description": "Unified diff; context and removed lines must match the editor's contents"
+                        },
+                        "edits": {
+                            "type": "array",
+                            "description": "Edits to apply when no diff is given",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "filePath": { "type": "string" },
+                                    "range": {
+                                        "type": "object",
+                                        "description": "0-based LSP range { start: { line, character }, end: { line, character } }"
+                                    },
+                                    "newText": { "type": "string" },
+                                    "oldText": {
+                                        "type": "string",
+                                        "description": "Optional text expected in the range"
+                                    }
+                                },
+                                "required": ["filePath", "range", "newText"]
+                            }
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "applyTextEdit".to_string(),
+                description: Some(
+                    "Replace one range of a workspace file, after the user allows it, if it still holds oldText. Goes through the editor when the file is open there, and is written to disk otherwise."
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the file to edit"
+                        },
+                        "range": {
+                            "type": "object",
+                            "description": "0-based LSP range { start: { line, character }, end: { line, character } }"
+                        },
+                        "oldText": {
+                            "type": "string",
+                            "description": "Text the range holds now; the edit is refused if it differs"
+                        },
+                        "newText": {
+                            "type": "string",
+                            "description": "Text to put in its place"
+                        }
+                    },
+                    "required": ["filePath", "range", "oldText", "newText"]
+                }),
+            },
+            Tool {
+                name: "proposeRefactor".to_string(),
+                description: Some(
+                    "Propose a change spanning several workspace files. The plan is shown to the user as one document; once they approve it, the files are edited one at a time, stopping at the first that fails."
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "title": {
+                            "type": "string",
+                            "description": "What the refactor does, in a few words"
+                        },
+                        "summary": {
+                            "type": "string",
+                            "description": "Why, and how the changes fit together"
+                        },
+                        "files": {
+                            "type": "array",
+                            "description": "Existing files to edit, in the order to edit them",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "filePath": { "type": "string" },
+                                    "description": {
+                                        "type": "string",
+                                        "description": "What changes in this file, and why"
+                                    },
+                                    "newContents": {
+                                        "type": "string",
+                                        "description": "The whole file after the change"
+                                    }
+                                },
+                                "required": ["filePath", "description", "newContents"]
+                            }
+                        }
+                    },
+                    "required": ["title", "files"]
+                }),
+            },
+            Tool {
+                name: "undoLastEdit".to_string(),
+                description: Some(
+                    "Revert the most recent edit applied for Claude (applyEdits or a code action), if its files haven't changed since"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+            Tool {
+                name: "createFile".to_string(),
+                description: Some(
+                    "Create a file in the workspace through the editor, after the user allows it"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the new file, absolute or relative to a workspace folder"
+                        },
+                        "contents": {
+                            "type": "string",
+                            "description": "Optional contents of the file (default empty)"
+                        },
+                        "overwrite": {
+                            "type": "boolean",
+                            "description": "Replace the file if it exists (default false)"
+                        }
+                    },
+                    "required": ["filePath"]
+                }),
+            },
+            Tool {
+                name: "deleteFile".to_string(),
+                description: Some(
+                    "Delete a file in the workspace through the editor, after the user allows it"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the file, absolute or relative to a workspace folder"
+                        }
+                    },
+                    "required": ["filePath"]
+                }),
+            },
+            Tool {
+                name: "renameFile".to_string(),
+                description: Some(
+                    "Rename or move a file in the workspace through the editor, after the user allows it"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "filePath": {
+                            "type": "string",
+                            "description": "Path of the file, absolute or relative to a workspace folder"
+                        },
+                        "newFilePath": {
+                            "type": "string",
+                            "description": "Its new path, absolute or relative to a workspace folder"
+                        },
+                        "overwrite": {
+                            "type": "boolean",
+                            "description": "Replace a file already at the new path (default false)"
+                        }
+                    },
+                    "required": ["filePath", "newFilePath"]
+                }),
+            },
+            Tool {
+                name: "runCommand".to_string(),
+                description: Some(
+                    "Run a shell command (build, test, lint) in the workspace after the user allows it, returning its exit code and output"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "description": "Shell command to run"
+                        },
+                        "cwd": {
+                            "type": "string",
+                            "description": "Optional directory to run in, relative to the first workspace folder. Must be inside the workspace."
+                        },
+                        "timeout": {
+                            "type": "number",
+                            "description": "Optional timeout in milliseconds (default 600000)"
+                        }
+                    },
+                    "required": ["command"]
+                }),
+            },
+            Tool {
+                name: "suggestTask".to_string(),
+                description: Some(
+                    "Suggest a shell command as a Zed task, written to the workspace's .zed/tasks.json, for the user to run from Zed's task picker. Its output appears in Zed's task terminal."
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "description": "Shell command to run"
+                        },
+                        "label": {
+                            "type": "string",
+                            "description": "Optional name for the task in the picker (default: the command). A task Claude suggested earlier with this label is replaced."
+                        },
+                        "cwd": {
+                            "type": "string",
+                            "description": "Optional directory to run in, relative to the first workspace folder. Must be inside the workspace."
+                        }
+                    },
+                    "required": ["command"]
+                }),
+            },
+            Tool {
+                name: "getLatestTerminalOutput".to_string(),
+                description: Some(
+                    "Get the output of the most recent runCommand runs (stdout and stderr interleaved as they arrived), with their exit status, without running them again"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "count": {
+                            "type": "number",
+                            "description": "Number of recent runs to return, oldest first (default 1)"
+                        },
+                        "maxLines": {
+                            "type": "number",
+                            "description": "Trailing output lines to return per run (default 200)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "getGitStatus".to_string(),
+                description: Some(
+                    "Get the current branch, its upstream and the staged, unstaged and untracked files of the workspace's git repository"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Optional file or directory in the repository (default: the first workspace folder)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "getGitDiff".to_string(),
+                description: Some(
+                    "Get uncommitted changes in the workspace's git repository as a unified diff"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Optional file or directory to restrict the diff to (default: the whole repository of the first workspace folder)"
+                        },
+                        "staged": {
+                            "type": "boolean",
+                            "description": "Show staged changes instead of unstaged ones (default false)"
+                        },
+                        "base": {
+                            "type": "string",
+                            "description": "Optional commit to compare against, e.g. \"HEAD\" for all uncommitted changes or \"main\""
+                        },
+                        "contextLines": {
+                            "type": "number",
+                            "description": "Lines of context around each change (default 3)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "getCurrentBranch".to_string(),
+                description: Some(
+                    "Get the checked-out branch (or detached commit) of the workspace's git repository"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Optional file or directory in the repository (default: the first workspace folder)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "generateCommitMessage".to_string(),
+                description: Some(
+                    "Write a commit message for the staged changes of the workspace's git repository, following the repository's conventions or Conventional Commits, and save it to .git/COMMIT_EDITMSG"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Optional file or directory in the repository (default: the first workspace folder)"
+                        },
+                        "write": {
+                            "type": "boolean",
+                            "description": "Save the message to COMMIT_EDITMSG (default true)"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "grepWorkspace".to_string(),
+                description: Some(
+                    "Search the text of every workspace file (respecting .gitignore) with a regular expression, returning matching lines with their line numbers"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "pattern": {
+                            "type": "string",
+                            "description": "Regular expression (Rust regex syntax) matched against each line"
+                        },
+                        "fixedStrings": {
+                            "type": "boolean",
+                            "description": "Treat the pattern as a literal string (default false)"
+                        },
+                        "caseInsensitive": {
+                            "type": "boolean",
+                            "description": "Ignore case when matching (default false)"
+                        },
+                        "glob": {
+                            "type": "string",
+                            "description": "Only search files matching this glob, e.g. \"**/*.rs\" or \"src/**\""
+                        },
+                        "maxResults": {
+                            "type": "number",
+                            "description": "Maximum number of matching lines (default 100, at most 2000)"
+                        }
+                    },
+                    "required": ["pattern"]
+                }),
+            },
+            Tool {
+                name: "searchSymbols".to_string(),
+                description: Some(
+                    "Search the workspace for functions, types, modules and other declarations by name"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "Name or part of a name; matching ignores case and also accepts the characters in order (e.g. \"hndreq\" for \"handle_request\")"
+                        },
+                        "limit": {
+                            "type": "number",
+                            "description": "Maximum number of results (default 50)"
+                        }
+                    },
+                    "required": ["query"]
+                }),
+            },
+            Tool {
+                name: "goToDefinition".to_string(),
+                description: Some(
+                    "Find where a symbol is declared in the workspace, returning file paths and ranges"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "symbol": {
+                            "type": "string",
+                            "description": "Name to look up. Alternatively give filePath, line and character to use the identifier at that position."
+                        },
+                        "filePath": {
+                            "type": "string",
+                            "description": "File containing the identifier"
+                        },
+                        "line": {
+                            "type": "number",
+                            "description": "0-based line of the identifier"
+                        },
+                        "character": {
+                            "type": "number",
+                            "description": "0-based character of the identifier within the line"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "findReferences".to_string(),
+                description: Some(
+                    "Find every whole-word occurrence of a symbol in the workspace, declarations included"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "symbol": {
+                            "type": "string",
+                            "description": "Name to look up. Alternatively give filePath, line and character to use the identifier at that position."
+                        },
+                        "filePath": {
+                            "type": "string",
+                            "description": "File containing the identifier"
+                        },
+                        "line": {
+                            "type": "number",
+                            "description": "0-based line of the identifier"
+                        },
+                        "character": {
+                            "type": "number",
+                            "description": "0-based character of the identifier within the line"
+                        }
+                    }
+                }),
+            },
+            Tool {
+                name: "getLanguageServerActivity".to_string(),
+                description: Some(
+                    "Get the hovers the project's language server recently showed the user and the edits it asked the editor to apply, newest first"
+                        .to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+        ];
+
+        // Only the proxy sees the language server's traffic
+        let tools: Vec<Tool> = tools
+            .into_iter()
+            .filter(|tool| {
+                tool.name != "getLanguageServerActivity" || self.language_server.is_some()
+            })
+            .collect();
+        let config = self.state.config.read().await;
+        let tools: Vec<Tool> = tools
+            .into_iter()
+            .filter(|tool| config.tool_enabled(&tool.name))
+            .filter(|tool| self.state.trust.tool_allowed(&tool.name))
+            .collect();
+
+        Ok(serde_json::json!({
+            "tools": tools
+        }))
+    }
+
+    async fn handle_tools_call(
+        &self,
+        params: Option<Value>,
+        progress: Option<ProgressSender>,
+    ) -> Result<Value> {
+        let params = params.ok_or_else(|| anyhow::anyhow!("Missing parameters for tools/call"))?;
+
+        let tool_name = params
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing tool name"))?;
+
+        let default_args = serde_json::json!({});
+        let arguments = params.get("arguments").unwrap_or(&default_args);
+
+        info!("Calling tool: {}", tool_name);
+        debug!("Tool arguments: {}", arguments);
+
+        if !self.state.config.read().await.tool_enabled(tool_name) {
+            return Err(anyhow::anyhow!(
+                "Tool {} is disabled in the settings",
+                tool_name
+            ));
+        }
+        if !self.state.trust.tool_allowed(tool_name) {
+            return Err(anyhow::anyhow!(
+                "Tool {} needs a trusted workspace; run claude-code.trust-workspace in the editor to trust it",
+                tool_name
+            ));
+        }
+
+        let content = match tool_name {
+            "echo" => {
+                let text = arguments
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No text provided");
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: format!("Echo: {}", text),
+                }]
+            }
+            "get_workspace_info" => {
+                let workspace_info = std::env::current_dir()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| "Unknown workspace".to_string());
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: format!("Current workspace: {}", workspace_info),
+                }]
+            }
+            "closeAllDiffTabs" => {
+                info!("Closing all diff tabs");
+
+                let file_paths: Vec<PathBuf> = self
+                    .state
+                    .diff_tabs
+                    .write()
+                    .await
+                    .drain()
+                    .map(|(_, file_path)| file_path)
+                    .collect();
+                let closed_count = file_paths.len();
+                if closed_count > 0 {
+                    self.send_command(LspCommand::CloseAllDiffTabs { file_paths })
+                        .await;
+                }
+
+                // Return the count of closed diff tabs according to protocol
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: format!("CLOSED_{}_DIFF_TABS", closed_count),
+                }]
+            }
+            "openFile" => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No file path provided");
+                // Zed gives language servers no preview tabs, so preview is accepted
+                // but opens an ordinary tab
+                let _preview = arguments
+                    .get("preview")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let _start_text = arguments.get("startText").and_then(|v| v.as_str());
+                let _end_text = arguments.get("endText").and_then(|v| v.as_str());
+                let make_frontmost = arguments
+                    .get("makeFrontmost")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(true);
+                // 1-based; line and column are the older names for the start
+                let position = |names: &[&str]| {
+                    names
+                        .iter()
+                        .find_map(|name| arguments.get(*name).and_then(|v| v.as_u64()))
+                        .map(|n| n as u32)
+                };
+                let range = position(&["startLine", "line"]).map(|start_line| OpenFileRange {
+                    start_line,
+                    start_column: position(&["startColumn", "column"]),
+                    end_line: position(&["endLine"]),
+                    end_column: position(&["endColumn"]),
+                });
+                let backwards = range.is_some_and(|range| {
+                    let start = (range.start_line, range.start_column.unwrap_or(1));
+                    let end = (
+                        range.end_line.unwrap_or(range.start_line),
+                        range.end_column.unwrap_or(u32::MAX),
+                    );
+                    end < start
+                });
+
+                info!(
+                    "Opening file: {} (range: {:?}, take_focus: {})",
+                    file_path, range, make_frontmost
+                );
+
+                // Send command to LSP to open file in Zed, and wait to hear how it went
+                let mut opened_in_ide = false;
+                let mut open_error = None;
+                if backwards {
+                    open_error = Some("The range ends before it starts".to_string());
+                } else if let Some(sender) = &self.command_sender {
+                    let (reply, opened) = oneshot::channel();
+                    let command = LspCommand::OpenFile {
+                        file_path: file_path.to_string(),
+                        range,
+                        take_focus: make_frontmost,
+                        reply,
+                    };
+
+                    match sender.send(command).await {
+                        Ok(()) => match opened.await {
+                            Ok(Ok(())) => opened_in_ide = true,
+                            Ok(Err(e)) => {
+                                open_error = Some(format!("Failed to open the file in Zed: {}", e))
+                            }
+                            Err(_) => warn!("The editor dropped the openFile command"),
+                        },
+                        Err(e) => {
+                            warn!("Failed to send openFile command: {}", e);
+                        }
+                    }
+                }
+
+                // Return response
+                let canonical_path = std::path::Path::new(file_path)
+                    .canonicalize()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| file_path.to_string());
+                let language_id = match Url::from_file_path(&canonical_path) {
+                    Ok(uri) => self.state.documents.language_id(&uri).await,
+                    Err(()) => language::from_path(Path::new(&canonical_path)).to_string(),
+                };
+
+                let response = serde_json::json!({
+                    "success": true,
+                    "filePath": canonical_path,
+                    "openedInIde": opened_in_ide,
+                    "languageId": language_id,
+                    "lineCount": 0
+                });
+
+                let text = match open_error {
+                    Some(e) => serde_json::json!({
+                        "success": false,
+                        "filePath": canonical_path,
+                        "openedInIde": false,
+                        "error": e
+                    })
+                    .to_string(),
+                    None if make_frontmost => format!("Opened file: {}", file_path),
+                    None => response.to_string(),
+                };
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text,
+                }]
+            }
+            "getCurrentSelection" => {
+                info!("Getting current selection");
+
+                // Return JSON-stringified response according to protocol
+                let response = self.selection_response("No active editor found").await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getOpenEditors" => {
+                info!("Getting open editors");
+
+                // Return JSON-stringified response according to protocol
+                let response = serde_json::json!({
+                    "tabs": self.open_editor_tabs().await
+                });
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "listWorkspaceFiles" => {
+                let path = arguments.get("path").and_then(|v| v.as_str());
+                let defaults = TreeOptions::default();
+                let options = TreeOptions {
+                    max_depth: arguments
+                        .get("maxDepth")
+                        .and_then(|v| v.as_u64())
+                        .map_or(defaults.max_depth, |depth| depth.max(1) as usize),
+                    max_entries: arguments
+                        .get("maxEntries")
+                        .and_then(|v| v.as_u64())
+                        .map_or(defaults.max_entries, |max| max as usize)
+                        .min(file_tree::MAX_ENTRIES),
+                    respect_gitignore: arguments
+                        .get("respectGitignore")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(defaults.respect_gitignore),
+                    include_hidden: arguments
+                        .get("includeHidden")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(defaults.include_hidden),
+                };
+
+                info!("Listing workspace files under {:?}", path);
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.list_workspace_files(path, options).await {
+                    Ok(trees) => serde_json::json!({
+                        "success": true,
+                        "trees": trees
+                    }),
+                    Err(e) => serde_json::json!({
+                        "success": false,
+                        "error": e.to_string()
+                    }),
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getWorkspaceFolders" => {
+                info!("Getting workspace folders");
+
+                let folders = self.state.workspace_folders.list();
+
+                // Fall back to the process working directory when no folders are known
+                let folders: Vec<Value> = if folders.is_empty() {
+                    let current_dir = std::env::current_dir().ok();
+                    let workspace_info = current_dir
+                        .as_ref()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .unwrap_or_else(|| "Unknown workspace".to_string());
+                    vec![serde_json::json!({
+                        "name": std::path::Path::new(&workspace_info)
+                            .file_name()
+                            .and_then(|n| n.to_str())
+                            .unwrap_or("workspace"),
+                        "uri": current_dir.as_deref().and_then(paths::to_url),
+                        "path": workspace_info
+                    })]
+                } else {
+                    folders
+                        .into_iter()
+                        .map(|folder| {
+                            let path = paths::path_string(&folder.uri);
+                            serde_json::json!({
+                                "name": folder.name,
+                                "uri": folder.uri,
+                                "path": path
+                            })
+                        })
+                        .collect()
+                };
+                let root_path = folders[0]["path"].clone();
+
+                // Return JSON-stringified response according to protocol
+                let response = serde_json::json!({
+                    "success": true,
+                    "folders": folders,
+                    "rootPath": root_path
+                });
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "openDiff" => {
+                let old_file_path = arguments
+                    .get("old_file_path")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No old file path provided");
+                let new_file_path = arguments
+                    .get("new_file_path")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No new file path provided");
+                let new_file_contents = arguments
+                    .get("new_file_contents")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No new file contents provided");
+                let tab_name = arguments
+                    .get("tab_name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("diff");
+
+                info!("Opening diff for {} vs {}", old_file_path, new_file_path);
+
+                // Remember the tab so close_tab/closeAllDiffTabs can dismiss it
+                let old_path = workspace::resolve_path(&self.roots(), old_file_path);
+                self.state
+                    .diff_tabs
+                    .write()
+                    .await
+                    .insert(tab_name.to_string(), old_path.clone());
+                self.preview_diff(tab_name, &old_path, new_file_contents)
+                    .await;
+
+                // Claude writes the file itself once the diff is accepted, so an
+                // untrusted workspace rejects it
+                if !self.state.trust.trusted() {
+                    vec![
+                        TextContent {
+                            type_: "text".to_string(),
+                            text: "DIFF_REJECTED".to_string(),
+                        },
+                        TextContent {
+                            type_: "text".to_string(),
+                            text: tab_name.to_string(),
+                        },
+                    ]
+                } else {
+                    // Respond with FILE_SAVED to simulate accepting the diff
+                    vec![
+                        TextContent {
+                            type_: "text".to_string(),
+                            text: "FILE_SAVED".to_string(),
+                        },
+                        TextContent {
+                            type_: "text".to_string(),
+                            text: new_file_contents.to_string(),
+                        },
+                    ]
+                }
+            }
+            "getLatestSelection" => {
+                info!("Getting latest selection");
+
+                // Return JSON-stringified response according to protocol
+                let response = self.selection_response("No selection available").await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getSelectionHistory" => {
+                let file_path = arguments.get("filePath").and_then(|v| v.as_str());
+                let limit = arguments
+                    .get("limit")
+                    .and_then(|v| v.as_u64())
+                    .map_or(DEFAULT_SELECTION_HISTORY, |limit| limit as usize);
+
+                info!("Getting selection history for: {:?}", file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = self.selection_history(file_path, limit).await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getLanguageServerActivity" => {
+                info!("Getting language server activity");
+
+                let activity = self
+                    .language_server
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("No language server is proxied"))?;
+                let (hovers, edits) = activity.snapshot();
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: serde_json::json!({ "hovers": hovers, "edits": edits }).to_string(),
+                }]
+            }
+            "readFileRange" => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing filePath"))?;
+                let line = |name: &str| {
+                    arguments
+                        .get(name)
+                        .and_then(|v| v.as_u64())
+                        .map(|line| line as u32)
+                        .ok_or_else(|| anyhow::anyhow!("Missing {}", name))
+                };
+                let (start_line, end_line) = (line("startLine")?, line("endLine")?);
+                let hash = arguments.get("hash").and_then(|v| v.as_str());
+
+                info!("Reading lines {}-{} of {}", start_line, end_line, file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = self
+                    .read_file_range(file_path, start_line, end_line, hash)
+                    .await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "readFile" => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing filePath"))?;
+
+                info!("Reading {}", file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = self.read_file(file_path).await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getDiagnostics" => {
+                let uri = arguments.get("uri").and_then(|v| v.as_str());
+
+                info!("Getting diagnostics for: {:?}", uri);
+
+                // Accept either a file:// URI or a plain path
+                let uri = uri.and_then(|uri| {
+                    Url::parse(uri)
+                        .ok()
+                        .or_else(|| Url::from_file_path(uri).ok())
+                });
+
+                // Return JSON-stringified array of diagnostics per file
+                let response = match self.diagnostics_snapshot(uri.as_ref()).await {
+                    Some(snapshot) => diagnostics::to_mcp_json(&snapshot),
+                    None => match uri {
+                        Some(uri) => serde_json::json!([{
+                            "uri": uri,
+                            "diagnostics": []
+                        }]),
+                        None => serde_json::json!([]),
+                    },
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getIdeContext" => {
+                info!("Getting IDE context");
+
+                // Return JSON-stringified response according to protocol
+                let response = self.ide_context().await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "checkDocumentDirty" => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No file path provided");
+
+                info!("Checking if document is dirty: {}", file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.open_document(file_path).await {
+                    Some(document) => serde_json::json!({
+                        "success": true,
+                        "filePath": file_path,
+                        "isDirty": document.is_dirty,
+                        "isUntitled": document.uri.scheme() == "untitled"
+                    }),
+                    None => serde_json::json!({
+                        "success": false,
+                        "message": format!("Document not open: {}", file_path)
+                    }),
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "saveDocument" => {
+                // Without a file path, every document with unsaved changes is saved
+                let file_path = arguments.get("filePath").and_then(|v| v.as_str());
+
+                info!("Saving document: {:?}", file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = match file_path {
+                    Some(file_path) => self.save_document(file_path).await,
+                    None => self.save_all_documents().await,
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "close_tab" => {
+                let tab_name = arguments
+                    .get("tab_name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No tab name provided");
+
+                info!("Closing tab: {}", tab_name);
+
+                let file_path = self.state.diff_tabs.write().await.remove(tab_name);
+                match file_path {
+                    Some(file_path) => {
+                        self.send_command(LspCommand::CloseTab {
+                            tab_name: tab_name.to_string(),
+                            file_path,
+                        })
+                        .await;
+                    }
+                    None => debug!("No diff tab named {}, nothing to close", tab_name),
+                }
+
+                // The protocol expects TAB_CLOSED whether or not the tab was known
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: "TAB_CLOSED".to_string(),
+                }]
+            }
+            "executeCode" => {
+                let code = arguments
+                    .get("code")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("No code provided");
+
+                info!(
+                    "Executing code: {}",
+                    code.chars().take(50).collect::<String>()
+                );
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: format!("Code executed successfully. Output: (simulated execution of {} characters)", code.len()),
+                }]
+            }
+            "applyEdits" => {
+                info!("Applying edits");
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.apply_edits(arguments).await {
+                    Ok((files, permission)) if permission.allowed() => serde_json::json!({
+                        "success": true,
+                        "files": files,
+                        "permission": permission
+                    }),
+                    Ok((_, permission)) => {
+                        info!("User denied the edits");
+                        serde_json::json!({
+                            "success": false,
+                            "error": "The user denied these edits",
+                            "permission": permission
+                        })
+                    }
+                    Err(e) => {
+                        warn!("Failed to apply edits: {}", e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "applyTextEdit" => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing filePath"))?;
+
+                info!("Applying a text edit to {}", file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.apply_text_edit(arguments).await {
+                    Ok((path, through, permission)) if permission.allowed() => {
+                        serde_json::json!({
+                            "success": true,
+                            "filePath": path,
+                            "appliedThrough": through,
+                            "permission": permission
+                        })
+                    }
+                    Ok((path, _, permission)) => {
+                        info!("User denied the edit to {}", path.display());
+                        serde_json::json!({
+                            "success": false,
+                            "filePath": path,
+                            "error": "The user denied this edit",
+                            "permission": permission
+                        })
+                    }
+                    Err(e) => {
+                        warn!("Failed to apply a text edit to {}: {}", file_path, e);
+                        serde_json::json!({
+                            "success": false,
+                            "filePath": file_path,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "proposeRefactor" => {
+                let response = match self.propose_refactor(arguments).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        warn!("Failed to propose a refactor: {}", e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "undoLastEdit" => {
+                info!("Undoing the last edit");
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.undo_last_edit().await {
+                    Ok(entry) => serde_json::json!({
+                        "success": true,
+                        "source": entry.source,
+                        "appliedAt": entry.applied_at,
+                        "files": entry
+                            .files
+                            .iter()
+                            .map(|file| paths::path_string(&file.uri))
+                            .collect::<Vec<_>>()
+                    }),
+                    Err(e) => {
+                        warn!("Failed to undo the last edit: {}", e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "createFile" | "deleteFile" | "renameFile" => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing filePath"))?;
+
+                info!("{} for {}", tool_name, file_path);
+
+                // Return JSON-stringified response according to protocol
+                let response = self.file_operation(tool_name, file_path, arguments).await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "runCommand" => {
+                let command = arguments
+                    .get("command")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing command"))?;
+                let cwd = arguments.get("cwd").and_then(|v| v.as_str());
+                let timeout = arguments
+                    .get("timeout")
+                    .and_then(|v| v.as_u64())
+                    .map(Duration::from_millis)
+                    .unwrap_or(shell::DEFAULT_TIMEOUT);
+
+                // Output is streamed only if the client asked for progress
+                let progress_token = params
+                    .get("_meta")
+                    .and_then(|meta| meta.get("progressToken"))
+                    .cloned();
+
+                // Return JSON-stringified response according to protocol
+                let response = self
+                    .run_command(command, cwd, timeout, progress_token.zip(progress))
+                    .await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "suggestTask" => {
+                let command = arguments
+                    .get("command")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing command"))?;
+                let label = arguments.get("label").and_then(|v| v.as_str());
+                let cwd = arguments.get("cwd").and_then(|v| v.as_str());
+
+                info!("Suggesting task `{}`", command);
+
+                // Return JSON-stringified response according to protocol
+                let response = self.suggest_task(command, label, cwd).await;
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getLatestTerminalOutput" => {
+                let count = arguments
+                    .get("count")
+                    .and_then(|v| v.as_u64())
+                    .map_or(1, |count| count as usize);
+                let max_lines = arguments
+                    .get("maxLines")
+                    .and_then(|v| v.as_u64())
+                    .map_or(DEFAULT_TERMINAL_LINES, |lines| lines as usize);
+
+                info!("Getting output of the last {} command runs", count);
+
+                let runs = self.scrollback.latest(count);
+                let response = if runs.is_empty() {
+                    serde_json::json!({
+                        "success": false,
+                        "message": "No commands have been run yet"
+                    })
+                } else {
+                    serde_json::json!({
+                        "success": true,
+                        "runs": runs
+                            .iter()
+                            .map(|run| terminal_output_json(run, max_lines))
+                            .collect::<Vec<_>>()
+                    })
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "getGitStatus" | "getGitDiff" | "getCurrentBranch" => {
+                let path = arguments.get("path").and_then(|v| v.as_str());
+
+                info!("{} for {:?}", tool_name, path);
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.git_query(tool_name, path, arguments).await {
+                    Ok(mut result) => {
+                        result["success"] = Value::Bool(true);
+                        result
+                    }
+                    Err(e) => {
+                        warn!("{} failed: {}", tool_name, e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "generateCommitMessage" => {
+                let path = arguments.get("path").and_then(|v| v.as_str());
+                let write = arguments
+                    .get("write")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(true);
+
+                info!("Generating a commit message for {:?}", path);
+
+                let response = match self.generate_commit_message(path, write).await {
+                    Ok(generated) => serde_json::json!({
+                        "success": true,
+                        "message": generated.message,
+                        "writtenTo": generated.written_to
+                    }),
+                    Err(e) => {
+                        warn!("generateCommitMessage failed: {}", e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "grepWorkspace" => {
+                let options = GrepOptions {
+                    pattern: arguments
+                        .get("pattern")
+                        .and_then(|v| v.as_str())
+                        .ok_or_else(|| anyhow::anyhow!("Missing pattern"))?
+                        .to_string(),
+                    fixed_strings: arguments
+                        .get("fixedStrings")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false),
+                    case_insensitive: arguments
+                        .get("caseInsensitive")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false),
+                    glob: arguments
+                        .get("glob")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                    max_results: arguments
+                        .get("maxResults")
+                        .and_then(|v| v.as_u64())
+                        .map_or(grep::DEFAULT_MAX_RESULTS, |max| max as usize)
+                        .min(grep::MAX_RESULTS),
+                };
+
+                info!("Searching workspace for {:?}", options.pattern);
+
+                let roots = self.roots();
+                let buffers = self.open_buffers().await;
+                let result =
+                    tokio::task::spawn_blocking(move || grep::grep(&roots, &buffers, &options))
+                        .await?;
+
+                // Return JSON-stringified response according to protocol
+                let response = match result {
+                    Ok(results) => serde_json::json!({
+                        "success": true,
+                        "matches": results.matches,
+                        "filesSearched": results.files_searched,
+                        "truncated": results.truncated
+                    }),
+                    Err(e) => {
+                        warn!("Workspace search failed: {}", e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "searchSymbols" => {
+                let query = arguments
+                    .get("query")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                let limit = arguments
+                    .get("limit")
+                    .and_then(|v| v.as_u64())
+                    .map_or(DEFAULT_SYMBOL_LIMIT, |limit| limit as usize)
+                    .min(indexer::MAX_RESULTS);
+
+                info!("Searching symbols for {:?}", query);
+
+                // Return JSON-stringified response according to protocol
+                let symbols = self.search_symbols(query, limit).await;
+                let response = serde_json::json!({
+                    "success": true,
+                    "symbols": symbols
+                });
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            "goToDefinition" | "findReferences" => {
+                let search = if tool_name == "goToDefinition" {
+                    Search::Definitions
+                } else {
+                    Search::References
+                };
+                let key = match search {
+                    Search::Definitions => "definitions",
+                    Search::References => "references",
+                };
+
+                // Return JSON-stringified response according to protocol
+                let response = match self.navigate(arguments, search).await {
+                    Ok((symbol, results)) => serde_json::json!({
+                        "success": true,
+                        "symbol": symbol,
+                        key: results.matches,
+                        "truncated": results.truncated
+                    }),
+                    Err(e) => {
+                        warn!("{} failed: {}", tool_name, e);
+                        serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        })
+                    }
+                };
+
+                vec![TextContent {
+                    type_: "text".to_string(),
+                    text: response.to_string(),
+                }]
+            }
+            _ => return Err(anyhow::anyhow!("Unknown tool: {}", tool_name)),
+        };
+        self.count_sent(tool_name, &content).await;
+
+        Ok(serde_json::json!({
+            "content": content,
+            "isError": false
+        }))
+    }
+
+    /// Count a tool result as sent to Claude, and warn the user when it is over
+    /// `contextBudget.warnTokens`
+    async fn count_sent(&self, tool_name: &str, content: &[TextContent]) {
+        let bytes = content.iter().map(|content| content.text.len()).sum();
+        let tokens = self.state.status.metrics().context_sent(tool_name, bytes);
+        let warning = self
+            .state
+            .config
+            .read()
+            .await
+            .context_budget
+            .size_warning(tool_name, tokens);
+        if let Some(message) = warning {
+            warn!("{}", message);
+            self.send_command(LspCommand::ShowMessage { message }).await;
+        }
+    }
+
+    /// Send a fire-and-forget command to the LSP side, if it is connected
+    async fn send_command(&self, command: LspCommand) {
+        let Some(sender) = &self.command_sender else {
+            debug!("No editor connection for {:?}", command);
+            return;
+        };
+        if let Err(e) = sender.send(command).await {
+            warn!("Failed to send command to LSP: {}", e);
+        }
+    }
+
+    /// Send the editor side the hunks turning what it shows for `path` into
+    /// `new_contents`. A file that doesn't exist yet is all one insertion.
+    async fn preview_diff(&self, tab_name: &str, path: &Path, new_contents: &str) {
+        let Ok(uri) = Url::from_file_path(path) else {
+            warn!("Cannot build file URI for {}", path.display());
+            return;
+        };
+        let documents = &self.state.documents;
+        let (old_contents, language_id) = match documents.get(&uri).await {
+            Some(document) => (document.text, document.language_id),
+            None => (
+                text::read_file(path).unwrap_or_default(),
+                language::from_path(path).to_string(),
+            ),
+        };
+        let encoding = documents.encoding();
+
+        let new_contents = new_contents.to_string();
+        let hunks = tokio::task::spawn_blocking(move || {
+            diff::hunks(&old_contents, &new_contents, &language_id, encoding)
+        })
+        .await
+        .unwrap_or_default();
+        debug!("openDiff for {} has {} hunks", path.display(), hunks.len());
+        self.send_command(LspCommand::PreviewDiff {
+            uri,
+            tab_name: tab_name.to_string(),
+            hunks,
+        })
+        .await;
+    }
+
+    /// The open document for a path (absolute or workspace-relative) or URI
+    async fn open_document(&self, file_path: &str) -> Option<OpenDocument> {
+        let uri = match Url::parse(file_path) {
+            Ok(uri) => uri,
+            Err(_) => {
+                Url::from_file_path(workspace::resolve_path(&self.roots(), file_path)).ok()?
+            }
+        };
+        self.state.documents.get(&uri).await
+    }
+
+    /// Declarations matching `query` from the workspace symbol index
+    async fn search_symbols(&self, query: &str, limit: usize) -> Vec<Value> {
+        let index = self.state.symbol_index.clone();
+        let roots = self.roots();
+        let query = query.to_string();
+        let hits = tokio::task::spawn_blocking(move || {
+            index.refresh_if_stale(&roots);
+            index.search(&query, limit)
+        })
+        .await
+        .unwrap_or_default();
+
+        hits.into_iter()
+            .map(|hit| {
+                serde_json::json!({
+                    "name": hit.symbol.name,
+                    "kind": hit.symbol.kind,
+                    "filePath": hit.path.to_string_lossy(),
+                    "line": hit.symbol.line,
+                    "endLine": hit.symbol.end_line
+                })
+            })
+            .collect()
+    }
+
+    /// getOpenEditors' tabs
+    async fn open_editor_tabs(&self) -> Vec<Value> {
+        let (documents, active) = self.state.documents.list().await;
+        documents
+            .into_iter()
+            .map(|document| {
+                let label = document
+                    .uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .unwrap_or_default()
+                    .to_string();
+                serde_json::json!({
+                    "uri": document.uri,
+                    "isActive": active.as_ref() == Some(&document.uri),
+                    "label": label,
+                    "languageId": document.language_id,
+                    "isDirty": document.is_dirty
+                })
+            })
+            .collect()
+    }
+
+    /// Diagnostics of `uri` (every file when none), unless they are turned off
+    async fn diagnostics_snapshot(&self, uri: Option<&Url>) -> Option<Vec<(Url, Vec<Diagnostic>)>> {
+        if !self.features().await.diagnostics {
+            return None;
+        }
+        Some(self.state.diagnostics.snapshot(uri).await)
+    }
+
+    /// JSON for getIdeContext: what getCurrentSelection, getOpenEditors,
+    /// getDiagnostics and getGitDiff (against HEAD) would each answer, with the
+    /// active file. Parts that aren't available are null; files without
+    /// diagnostics are left out.
+    async fn ide_context(&self) -> Value {
+        let (open_documents, active) = self.state.documents.list().await;
+        let roots = self.roots();
+        let active_file = open_documents
+            .into_iter()
+            .find(|document| active.as_ref() == Some(&document.uri))
+            .map(|document| {
+                let file_path = paths::file_path(&document.uri);
+                let relative_path = roots
+                    .iter()
+                    .find_map(|root| paths::relative(&file_path, root));
+                serde_json::json!({
+                    "filePath": file_path,
+                    "fileUrl": document.uri,
+                    "relativePath": relative_path,
+                    "languageId": document.language_id,
+                    "isDirty": document.is_dirty
+                })
+            });
+
+        let selection = self.selection_response("No selection available").await;
+        let selection = match selection["success"].as_bool() {
+            Some(true) => selection,
+            _ => Value::Null,
+        };
+
+        let diagnostics = match self.diagnostics_snapshot(None).await {
+            Some(mut snapshot) => {
+                snapshot.retain(|(_, diagnostics)| !diagnostics.is_empty());
+                diagnostics::to_mcp_json(&snapshot)
+            }
+            None => Value::Null,
+        };
+
+        let git_diff = match self
+            .git_query("getGitDiff", None, &serde_json::json!({ "base": "HEAD" }))
+            .await
+        {
+            Ok(diff) => diff,
+            Err(e) => {
+                debug!("No git diff for the IDE context: {}", e);
+                Value::Null
+            }
+        };
+
+        serde_json::json!({
+            "success": true,
+            "activeFile": active_file,
+            "selection": selection,
+            "openEditors": self.open_editor_tabs().await,
+            "diagnostics": diagnostics,
+            "gitDiff": git_diff
+        })
+    }
+
+    /// Run one of the git tools in the repository containing `path`
+    async fn git_query(
+        &self,
+        tool_name: &str,
+        path: Option<&str>,
+        arguments: &Value,
+    ) -> Result<Value> {
+        let target = path.map(|path| workspace::resolve_path(&self.roots(), path));
+        let dir = match &target {
+            Some(target) if !target.is_dir() => target.parent().map(Path::to_path_buf),
+            _ => target.clone(),
+        };
+        let dir = self.command_dir(dir.as_ref().map(|dir| dir.to_string_lossy()).as_deref())?;
+
+        let result = match tool_name {
+            "getGitStatus" => serde_json::to_value(git::status(&dir).await?)?,
+            "getCurrentBranch" => serde_json::to_value(git::current_branch(&dir).await?)?,
+            _ => {
+                let options = DiffOptions {
+                    staged: arguments
+                        .get("staged")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false),
+                    base: arguments
+                        .get("base")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                    path: target,
+                    context_lines: arguments
+                        .get("contextLines")
+                        .and_then(|v| v.as_u64())
+                        .map(|lines| lines as u32),
+                };
+                serde_json::to_value(git::diff(&dir, &options).await?)?
+            }
+        };
+        Ok(result)
+    }
+
+    /// The tree of the directory `path`, which must be in the workspace, or of every
+    /// workspace folder, sharing `options.max_entries` between them
+    async fn list_workspace_files(
+        &self,
+        path: Option<&str>,
+        mut options: TreeOptions,
+    ) -> Result<Vec<file_tree::Tree>> {
+        let dirs = match path {
+            Some(path) => vec![self.command_dir(Some(path))?],
+            None => {
+                let roots = self.roots();
+                if roots.is_empty() {
+                    vec![self.command_dir(None)?]
+                } else {
+                    roots
+                }
+            }
+        };
+        tokio::task::spawn_blocking(move || {
+            let mut trees = Vec::new();
+            for dir in dirs {
+                let tree = file_tree::list(&dir, &options);
+                options.max_entries -= tree.entries.len();
+                trees.push(tree);
+            }
+            trees
+        })
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Ask Claude for a message for what is staged in the repository containing
+    /// `path` (or the first workspace folder)
+    async fn generate_commit_message(
+        &self,
+        path: Option<&str>,
+        write: bool,
+    ) -> Result<commit_message::CommitMessage> {
+        let target = path.map(|path| workspace::resolve_path(&self.roots(), path));
+        let dir = match &target {
+            Some(target) if !target.is_dir() => target.parent().map(Path::to_path_buf),
+            _ => target,
+        };
+        let dir = self.command_dir(dir.as_ref().map(|dir| dir.to_string_lossy()).as_deref())?;
+        let claude = ClaudeClient::new(&*self.state.config.read().await);
+        commit_message::generate(&claude.with_working_dir(Some(dir.clone())), &dir, write).await
+    }
+
+    /// Local directories of the workspace folders
+    fn roots(&self) -> Vec<PathBuf> {
+        self.state.workspace_folders.roots()
+    }
+
+    /// Text of the documents open in the editor, by path
+    async fn open_buffers(&self) -> HashMap<PathBuf, String> {
+        let (open_documents, _) = self.state.documents.list().await;
+        open_documents
+            .into_iter()
+            .filter_map(|document| Some((document.uri.to_file_path().ok()?, document.text)))
+            .collect()
+    }
+
+    /// Look up the symbol named by `symbol`, or found at filePath/line/character,
+    /// across the workspace
+    async fn navigate(&self, arguments: &Value, search: Search) -> Result<(String, SearchResults)> {
+        let encoding = self.state.documents.encoding();
+        let roots = self.roots();
+
+        let symbol = match arguments.get("symbol").and_then(|v| v.as_str()) {
+            Some(symbol) => symbol.to_string(),
+            None => {
+                let file_path = arguments
+                    .get("filePath")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Provide either symbol or filePath"))?;
+                let position = Position::new(
+                    arguments.get("line").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+                    arguments
+                        .get("character")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(0) as u32,
+                );
+                let text = match self.open_document(file_path).await {
+                    Some(document) => document.text,
+                    None => text::read_file(workspace::resolve_path(&roots, file_path))?,
+                };
+                symbols::identifier_at(&text, position, encoding)
+                    .map(|(symbol, _)| symbol)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No identifier at {}:{}:{}",
+                            file_path,
+                            position.line,
+                            position.character
+                        )
+                    })?
+            }
+        };
+        if symbol.is_empty() {
+            anyhow::bail!("The symbol name is empty");
+        }
+
+        // Open documents are searched as the editor shows them
+        let buffers = self.open_buffers().await;
+        let name = symbol.clone();
+        let results = tokio::task::spawn_blocking(move || {
+            navigation::search(&roots, &buffers, &name, search, encoding)
+        })
+        .await?;
+        Ok((symbol, results))
+    }
+
+    /// Ask the LSP side to write unsaved buffers to disk: one document, or all
+    /// dirty ones when `uri` is None
+    async fn write_documents(&self, uri: Option<Url>) -> Result<Vec<(Url, Result<()>)>> {
+        let sender = self
+            .command_sender
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No editor connection to save documents"))?;
+        let (reply, saved) = oneshot::channel();
+        sender
+            .send(LspCommand::SaveDocuments { uri, reply })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send SaveDocuments command: {}", e))?;
+        saved
+            .await
+            .map_err(|_| anyhow::anyhow!("The editor connection closed"))
+    }
+
+    async fn save_document(&self, file_path: &str) -> Value {
+        let failure = |message: String| {
+            serde_json::json!({
+                "success": false,
+                "filePath": file_path,
+                "saved": false,
+                "message": message
+            })
+        };
+
+        let Some(document) = self.open_document(file_path).await else {
+            return failure(format!("Document not open: {}", file_path));
+        };
+        if !document.is_dirty {
+            return serde_json::json!({
+                "success": true,
+                "filePath": file_path,
+                "saved": false,
+                "message": "Document has no unsaved changes"
+            });
+        }
+
+        let results = match self.write_documents(Some(document.uri)).await {
+            Ok(results) => results,
+            Err(e) => return failure(e.to_string()),
+        };
+        match results.into_iter().next() {
+            Some((_, Err(e))) => failure(e.to_string()),
+            // Nothing written means it was saved in the meantime
+            _ => serde_json::json!({
+                "success": true,
+                "filePath": file_path,
+                "saved": true,
+                "message": "Document saved successfully"
+            }),
+        }
+    }
+
+    async fn save_all_documents(&self) -> Value {
+        let results = match self.write_documents(None).await {
+            Ok(results) => results,
+            Err(e) => {
+                return serde_json::json!({
+                    "success": false,
+                    "message": e.to_string()
+                })
+            }
+        };
+
+        let mut saved = Vec::new();
+        let mut failed = Vec::new();
+        for (uri, result) in results {
+            match result {
+                Ok(()) => saved.push(serde_json::json!(paths::path_string(&uri))),
+                Err(e) => failed.push(serde_json::json!({
+                    "filePath": paths::path_string(&uri),
+                    "message": e.to_string()
+                })),
+            }
+        }
+        serde_json::json!({
+            "success": failed.is_empty(),
+            "savedFiles": saved,
+            "failedFiles": failed
+        })
+    }
+
+    /// Validate the diff or edit list in `arguments` against the open buffers and,
+    /// if the user allows it, have the editor apply it. Returns the changed files
+    /// and their edit counts, and the user's decision.
+    async fn apply_edits(&self, arguments: &Value) -> Result<(Vec<Value>, Decision)> {
+        let edits: Vec<FileEdit> = match arguments.get("diff").and_then(|v| v.as_str()) {
+            Some(diff) => edits::parse_unified_diff(diff)?,
+            None => {
+                let edits = arguments
+                    .get("edits")
+                    .ok_or_else(|| anyhow::anyhow!("Provide either diff or edits"))?;
+                serde_json::from_value(edits.clone())?
+            }
+        };
+
+        let roots = self.roots();
+        let documents = &self.state.documents;
+        let plan = edits::prepare(edits, &roots, documents).await?;
+
+        let files = plan
+            .files
+            .iter()
+            .map(|file| {
+                serde_json::json!({
+                    "filePath": paths::path_string(&file.uri),
+                    "edits": file.edits.len()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let sender = self
+            .command_sender
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No editor connection to apply edits"))?;
+        let message = format!(
+            "Claude wants to edit {}",
+            plan.files
+                .iter()
+                .map(|file| paths::path_string(&file.uri))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let permission = self.edit_permission(&plan, message).await;
+        if !permission.allowed() {
+            return Ok((files, permission));
+        }
+        let (reply, applied) = oneshot::channel();
+        sender
+            .send(LspCommand::ApplyEdits { plan, reply })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send ApplyEdits command: {}", e))?;
+        applied
+            .await
+            .map_err(|_| anyhow::anyhow!("The editor connection closed"))??;
+        Ok((files, permission))
+    }
+
+    /// Ask the user about the edits in `plan`. Files that changed since Claude
+    /// was shown them are asked about again, even when the user always allows
+    /// edits.
+    async fn edit_permission(&self, plan: &EditPlan, message: String) -> Decision {
+        let drifted = plan
+            .files
+            .iter()
+            .filter(|file| {
+                self.state
+                    .content_hashes
+                    .drifted(&file.uri, file.original())
+            })
+            .map(|file| paths::path_string(&file.uri))
+            .collect::<Vec<_>>();
+        if drifted.is_empty() {
+            return self.ask_permission(Action::ApplyEdits, message).await;
+        }
+        info!(
+            "Edits to files changed since Claude saw them: {:?}",
+            drifted
+        );
+        let message = format!(
+            "{}, which changed since Claude last saw it: {}",
+            message,
+            drifted.join(", ")
+        );
+        self.request_permission(Action::ApplyEdits, message).await
+    }
+
+    /// Show the user Claude's refactor plan and, once they approve it, have the
+    /// editor side apply it file by file. Returns the tool's result.
+    async fn propose_refactor(&self, arguments: &Value) -> Result<Value> {
+        let plan: RefactorPlan = serde_json::from_value(arguments.clone())?;
+        info!(
+            "Claude proposes {} across {} files",
+            plan.title,
+            plan.files.len()
+        );
+        let roots = self.roots();
+        let documents = &self.state.documents;
+        let steps = refactor::prepare(&plan, &roots, documents).await?;
+        if steps.is_empty() {
+            return Ok(serde_json::json!({
+                "success": true,
+                "applied": [],
+                "notApplied": [],
+                "message": "The plan leaves every file as it is"
+            }));
+        }
+        let sender = self
+            .command_sender
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No editor connection to show the plan"))?;
+
+        // Shown while the user decides
+        let plan_file = match roots.first() {
+            Some(root) => {
+                let path = root.join(refactor::PLAN_FILE);
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                text::write_file(&path, &refactor::render(&plan, &steps, &roots)).await?;
+                let (reply, opened) = oneshot::channel();
+                let command = LspCommand::OpenFile {
+                    file_path: path.to_string_lossy().to_string(),
+                    range: None,
+                    take_focus: true,
+                    reply,
+                };
+                if sender.send(command).await.is_ok() {
+                    if let Ok(Err(e)) = opened.await {
+                        warn!("Failed to open the refactor plan: {}", e);
+                    }
+                }
+                Some(path)
+            }
+            None => None,
+        };
+
+        let files = steps
+            .iter()
+            .map(|step| step.path.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let mut message = format!(
+            "Claude wants to {}, editing {}",
+            plan.title,
+            files.join(", ")
+        );
+        if let Some(path) = &plan_file {
+            message.push_str(&format!(". The plan is in {}", path.display()));
+        }
+        // A plan is always reviewed, even when edits are always allowed
+        let permission = self.request_permission(Action::ApplyEdits, message).await;
+        if !permission.allowed() {
+            info!("User rejected the refactor plan");
+            return Ok(serde_json::json!({
+                "success": false,
+                "error": "The user rejected the plan",
+                "planFile": plan_file,
+                "permission": permission
+            }));
+        }
+
+        let (reply, applied) = oneshot::channel();
+        sender
+            .send(LspCommand::ApplyRefactor {
+                title: plan.title.clone(),
+                steps,
+                reply,
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send ApplyRefactor command: {}", e))?;
+        let outcome = applied
+            .await
+            .map_err(|_| anyhow::anyhow!("The editor connection closed"))?;
+        Ok(serde_json::json!({
+            "success": outcome.failed.is_none(),
+            "applied": outcome.applied,
+            "failed": outcome.failed,
+            "notApplied": outcome.not_applied,
+            "planFile": plan_file,
+            "permission": permission
+        }))
+    }
+
+    /// Replace one range of a workspace file that still holds `oldText`. The path
+    /// is resolved through symlinks before it is checked against the workspace
+    /// folders. An open file is edited through the editor, like applyEdits; any
+    /// other is written on disk. Returns the file, "editor" or "disk", and the
+    /// user's answer.
+    async fn apply_text_edit(
+        &self,
+        arguments: &Value,
+    ) -> Result<(PathBuf, &'static str, Decision)> {
+        let mut edit: FileEdit = serde_json::from_value(arguments.clone())?;
+        if edit.old_text.is_none() {
+            anyhow::bail!("Missing oldText");
+        }
+        let path = self.workspace_file(&edit.file_path)?;
+        edit.file_path = path.to_string_lossy().to_string();
+
+        let roots = self.roots();
+        let documents = &self.state.documents;
+        let plan = edits::prepare(vec![edit.clone()], &roots, documents).await?;
+
+        let message = format!("Claude wants to edit {}", path.display());
+        let permission = self.edit_permission(&plan, message).await;
+        if !permission.allowed() {
+            return Ok((path, "editor", permission));
+        }
+        // The file may have changed while the user decided, so oldText is
+        // checked again against what is there now
+        let plan = edits::prepare(vec![edit], &roots, documents).await?;
+        if !plan.files.iter().any(|file| file.is_open()) {
+            plan.write_to_disk().await?;
+            self.state
+                .undo_journal
+                .record(JournalEntry::new("applyTextEdit", plan.snapshots()));
+            return Ok((path, "disk", permission));
+        }
+
+        let sender = self
+            .command_sender
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No editor connection to apply edits"))?;
+        let (reply, applied) = oneshot::channel();
+        sender
+            .send(LspCommand::ApplyEdits { plan, reply })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send ApplyEdits command: {}", e))?;
+        applied
+            .await
+            .map_err(|_| anyhow::anyhow!("The editor connection closed"))??;
+        Ok((path, "editor", permission))
+    }
+
+    /// Have the editor revert the most recent edit in the undo journal
+    async fn undo_last_edit(&self) -> Result<JournalEntry> {
+        let sender = self
+            .command_sender
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No editor connection to undo edits"))?;
+        let (reply, undone) = oneshot::channel();
+        sender
+            .send(LspCommand::UndoLastEdit { reply })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send UndoLastEdit command: {}", e))?;
+        undone
+            .await
+            .map_err(|_| anyhow::anyhow!("The editor connection closed"))?
+    }
+
+    /// Lines `start_line..=end_line` of a file, from its open buffer when there is
+    /// one, cut down to the context budget. `stale` tells whether the file no
+    /// longer matches `hash`.
+    async fn read_file_range(
+        &self,
+        file_path: &str,
+        start_line: u32,
+        end_line: u32,
+        hash: Option<&str>,
+    ) -> Value {
+        let failure = |error: String| {
+            serde_json::json!({
+                "success": false,
+                "filePath": file_path,
+                "error": error
+            })
+        };
+
+        let (path, budget) = match self.readable_file(file_path).await {
+            Ok(readable) => readable,
+            Err(e) => return failure(e.to_string()),
+        };
+
+        let contents = match self.open_document(&path.to_string_lossy()).await {
+            Some(document) => document.text,
+            None => match text::read_file(&path) {
+                Ok(contents) => contents,
+                Err(e) => return failure(format!("Failed to read {}: {}", path.display(), e)),
+            },
+        };
+        let total_lines = contents.lines().count() as u32;
+        if start_line > end_line || start_line >= total_lines {
+            return failure(format!(
+                "Lines {}-{} are outside the file's {} lines",
+                start_line, end_line, total_lines
+            ));
+        }
+        let end_line = end_line.min(total_lines - 1);
+        let lines: String = contents
+            .split_inclusive('\n')
+            .skip(start_line as usize)
+            .take((end_line - start_line + 1) as usize)
+            .collect();
+
+        let encoding = self.state.documents.encoding();
+        let range = Range::new(Position::new(start_line, 0), Position::new(end_line + 1, 0));
+        let (lines, truncated) = context::fit_text(&lines, range, &budget, encoding);
+        let fingerprint = hashes::fingerprint(&contents);
+
+        let mut response = serde_json::json!({
+            "success": true,
+            "filePath": path,
+            "startLine": start_line,
+            "endLine": end_line,
+            "text": lines,
+            "totalLines": total_lines,
+            "hash": fingerprint,
+            "stale": hash.is_some_and(|hash| hash != fingerprint)
+        });
+        if let Some(truncated) = truncated {
+            response["truncated"] = serde_json::json!(truncated);
+        }
+        response
+    }
+
+    /// Path of a workspace file Claude may read, and the budget for what it gets
+    async fn readable_file(&self, file_path: &str) -> Result<(PathBuf, ContextBudget)> {
+        let path = self.workspace_file(file_path)?;
+        let root = self
+            .roots()
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .find(|root| path.starts_with(root));
+        let config = self.state.config.read().await;
+        if config.privacy.is_sensitive(&path, root.as_deref()) {
+            return Err(anyhow::anyhow!("{} is a sensitive file", path.display()));
+        }
+        Ok((path, config.context_budget.clone()))
+    }
+
+    /// A whole file: the editor's buffer when it has unsaved changes, so Claude
+    /// doesn't work from what's on disk, and the file on disk otherwise
+    async fn read_file(&self, file_path: &str) -> Value {
+        let failure = |error: String| {
+            serde_json::json!({
+                "success": false,
+                "filePath": file_path,
+                "error": error
+            })
+        };
+
+        let (path, budget) = match self.readable_file(file_path).await {
+            Ok(readable) => readable,
+            Err(e) => return failure(e.to_string()),
+        };
+
+        let document = self.open_document(&path.to_string_lossy()).await;
+        let (contents, source) = match &document {
+            Some(document) if document.is_dirty => (document.text.clone(), "buffer"),
+            _ => match text::read_file(&path) {
+                Ok(contents) => (contents, "disk"),
+                Err(e) => return failure(format!("Failed to read {}: {}", path.display(), e)),
+            },
+        };
+        let language_id = match &document {
+            Some(document) => document.language_id.clone(),
+            None => language::from_path(&path).to_string(),
+        };
+
+        let encoding = self.state.documents.encoding();
+        let total_lines = contents.lines().count() as u32;
+        let range = Range::new(Position::new(0, 0), Position::new(total_lines, 0));
+        let (text, truncated) = context::fit_text(&contents, range, &budget, encoding);
+
+        let mut response = serde_json::json!({
+            "success": true,
+            "filePath": path,
+            "text": text,
+            "source": source,
+            "isDirty": document.as_ref().is_some_and(|document| document.is_dirty),
+            "version": document.as_ref().map(|document| document.version),
+            "languageId": language_id,
+            "size": contents.len(),
+            "totalLines": total_lines,
+            "hash": hashes::fingerprint(&contents)
+        });
+        if let Some(truncated) = truncated {
+            response["truncated"] = serde_json::json!(truncated);
+        }
+        response
+    }
+
+    /// createFile, deleteFile or renameFile: check the paths, ask the user, then
+    /// have the editor carry it out (or do it on disk without an editor)
+    async fn file_operation(&self, tool_name: &str, file_path: &str, arguments: &Value) -> Value {
+        let failure = |error: String| {
+            serde_json::json!({
+                "success": false,
+                "filePath": file_path,
+                "error": error
+            })
+        };
+
+        let (operation, action, message, paths) = match self
+            .prepare_file_operation(tool_name, file_path, arguments)
+            .await
+        {
+            Ok(prepared) => prepared,
+            Err(e) => return failure(e.to_string()),
+        };
+        let permission = self.ask_permission(action, message).await;
+        if !permission.allowed() {
+            info!("User denied {} for {}", tool_name, file_path);
+            let mut response = failure("The user denied this change".to_string());
+            response["permission"] = serde_json::json!(permission);
+            return response;
+        }
+
+        let result = match &self.command_sender {
+            Some(sender) => {
+                let (reply, applied) = oneshot::channel();
+                match sender
+                    .send(LspCommand::ApplyFileOperation { operation, reply })
+                    .await
+                {
+                    Ok(()) => applied
+                        .await
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("The editor connection closed"))),
+                    Err(e) => Err(anyhow::anyhow!("Failed to send ApplyFileOperation: {}", e)),
+                }
+            }
+            None => operation.apply_to_disk().await,
+        };
+        let mut response = match result {
+            Ok(()) => {
+                let mut response = serde_json::json!({
+                    "success": true,
+                    "filePath": paths.0
+                });
+                if let Some(new_path) = paths.1 {
+                    response["newFilePath"] = serde_json::json!(new_path);
+                }
+                response
+            }
+            Err(e) => failure(format!("{:#}", e)),
+        };
+        response["permission"] = serde_json::json!(permission);
+        response
+    }
+
+    /// The operation a file tool asks for, its permission prompt and the paths it
+    /// touches. Buffers with unsaved changes are never deleted, renamed or
+    /// overwritten.
+    async fn prepare_file_operation(
+        &self,
+        tool_name: &str,
+        file_path: &str,
+        arguments: &Value,
+    ) -> Result<(FileOperation, Action, String, (PathBuf, Option<PathBuf>))> {
+        let overwrite = arguments
+            .get("overwrite")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let url = |path: &Path| {
+            paths::to_url(path)
+                .ok_or_else(|| anyhow::anyhow!("Cannot build file URI for {}", path.display()))
+        };
+
+        match tool_name {
+            "createFile" => {
+                let path = self.workspace_target(file_path)?;
+                if path.exists() && !overwrite {
+                    anyhow::bail!("{} already exists", path.display());
+                }
+                let uri = url(&path)?;
+                self.refuse_unsaved(&uri).await?;
+                let contents = arguments
+                    .get("contents")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                let message = format!("Claude wants to create {}", path.display());
+                Ok((
+                    FileOperation::Create {
+                        uri,
+                        contents,
+                        overwrite,
+                    },
+                    Action::CreateFile,
+                    message,
+                    (path, None),
+                ))
+            }
+            "deleteFile" => {
+                let path = self.workspace_file(file_path)?;
+                let uri = url(&path)?;
+                self.refuse_unsaved(&uri).await?;
+                let message = format!("Claude wants to delete {}", path.display());
+                Ok((
+                    FileOperation::Delete { uri },
+                    Action::DeleteFile,
+                    message,
+                    (path, None),
+                ))
+            }
+            _ => {
+                let new_file_path = arguments
+                    .get("newFilePath")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing newFilePath"))?;
+                let path = self.workspace_file(file_path)?;
+                let new_path = self.workspace_target(new_file_path)?;
+                if new_path.exists() && !overwrite {
+                    anyhow::bail!("{} already exists", new_path.display());
+                }
+                let (from, to) = (url(&path)?, url(&new_path)?);
+                self.refuse_unsaved(&from).await?;
+                self.refuse_unsaved(&to).await?;
+                let message = format!(
+                    "Claude wants to rename {} to {}",
+                    path.display(),
+                    new_path.display()
+                );
+                Ok((
+                    FileOperation::Rename {
+                        from,
+                        to,
+                        overwrite,
+                    },
+                    Action::RenameFile,
+                    message,
+                    (path, Some(new_path)),
+                ))
+            }
+        }
+    }
+
+    /// Fail when the editor has unsaved changes to `uri`
+    async fn refuse_unsaved(&self, uri: &Url) -> Result<()> {
+        match self.state.documents.get(uri).await {
+            Some(document) if document.is_dirty => anyhow::bail!(
+                "{} has unsaved changes in the editor; save it first",
+                paths::path_string(uri)
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// A path inside a workspace folder that need not exist yet, for a path
+    /// absolute or relative to one
+    fn workspace_target(&self, file_path: &str) -> Result<PathBuf> {
+        let roots = self.roots();
+        let path = workspace::resolve_path(&roots, file_path);
+        if path
+            .components()
+            .any(|component| component == std::path::Component::ParentDir)
+        {
+            anyhow::bail!("{} may not contain ..", path.display());
+        }
+        // The nearest existing directory decides where the file really lands. A
+        // symlink counts as existing even when its target doesn't, so a dangling
+        // one can't send the file somewhere unchecked.
+        let existing = path
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())
+            .ok_or_else(|| anyhow::anyhow!("Cannot find {}", path.display()))?;
+        let canonical = existing
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Cannot resolve {}: {}", existing.display(), e))?;
+        let resolved = canonical.join(
+            path.strip_prefix(existing)
+                .expect("an ancestor is a prefix"),
+        );
+        if resolved.is_dir() {
+            anyhow::bail!("{} is a directory", resolved.display());
+        }
+
+        let inside_workspace = roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| resolved.starts_with(root));
+        if !inside_workspace {
+            anyhow::bail!("{} is outside the workspace", resolved.display());
+        }
+        Ok(resolved)
+    }
+
+    /// An existing file inside a workspace folder, for a path absolute or relative
+    /// to one
+    fn workspace_file(&self, file_path: &str) -> Result<PathBuf> {
+        let roots = self.roots();
+        let path = workspace::resolve_path(&roots, file_path);
+        let path = path
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Cannot find {}: {}", path.display(), e))?;
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("{} is not a file", path.display()));
+        }
+
+        let inside_workspace = roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| path.starts_with(root));
+        if !inside_workspace {
+            return Err(anyhow::anyhow!(
+                "{} is outside the workspace",
+                path.display()
+            ));
+        }
+        Ok(path)
+    }
+
+    async fn run_command(
+        &self,
+        command: &str,
+        cwd: Option<&str>,
+        timeout: Duration,
+        progress: Option<(Value, ProgressSender)>,
+    ) -> Value {
+        let failure = |error: String| {
+            serde_json::json!({
+                "success": false,
+                "command": command,
+                "error": error
+            })
+        };
+
+        if self.run_commands_as_tasks().await {
+            return self.suggest_task(command, None, cwd).await;
+        }
+
+        let cwd = match self.command_dir(cwd) {
+            Ok(cwd) => cwd,
+            Err(e) => return failure(e.to_string()),
+        };
+        let allowed = self.state.config.read().await.command_allowed(command);
+        let message = format!("Claude wants to run `{}` in {}", command, cwd.display());
+        let permission = if allowed {
+            info!("`{}` is allowed by the settings", command);
+            Decision::Allow
+        } else {
+            self.ask_permission(Action::RunCommand, message).await
+        };
+        if !permission.allowed() {
+            info!("User denied running `{}`", command);
+            let mut response = failure("The user denied running this command".to_string());
+            response["permission"] = serde_json::json!(permission);
+            return response;
+        }
+
+        // Forward output lines as notifications/progress while the command runs
+        let (lines, forwarder) = match progress {
+            Some((token, progress)) => {
+                let (lines, mut line_receiver): (shell::OutputSender, _) =
+                    mpsc::unbounded_channel();
+                let forwarder = tokio::spawn(async move {
+                    let mut count = 0;
+                    while let Some((_, line)) = line_receiver.recv().await {
+                        count += 1;
+                        let _ = progress.send(serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "method": "notifications/progress",
+                            "params": {
+                                "progressToken": token,
+                                "progress": count,
+                                "message": line.trim_end()
+                            }
+                        }));
+                    }
+                });
+                (Some(lines), Some(forwarder))
+            }
+            None => (None, None),
+        };
+
+        let settings = self.state.config.read().await.shell_environment.clone();
+        let environment = self.shell_environments.load(&cwd, &settings).await;
+        let result = shell::run(
+            command,
+            &cwd,
+            environment.as_deref(),
+            timeout,
+            lines,
+            Some(&self.scrollback),
+        )
+        .await;
+        // The forwarder ends once run() drops its sender; let it flush first
+        if let Some(forwarder) = forwarder {
+            let _ = forwarder.await;
+        }
+
+        match result {
+            Ok(output) => serde_json::json!({
+                "success": output.success(),
+                "command": command,
+                "cwd": cwd,
+                "exitCode": output.exit_code,
+                "stdout": output.stdout,
+                "stderr": output.stderr,
+                "timedOut": output.timed_out,
+                "truncated": output.truncated,
+                "permission": permission
+            }),
+            Err(e) => failure(e.to_string()),
+        }
+    }
+
+    /// Write `command` to the tasks.json of the workspace folder it runs in, for
+    /// the user to run from Zed's task picker. Nothing is run, so the user isn't
+    /// asked first; they are told the task is there.
+    async fn suggest_task(&self, command: &str, label: Option<&str>, cwd: Option<&str>) -> Value {
+        let failure = |error: String| {
+            serde_json::json!({
+                "success": false,
+                "command": command,
+                "error": error
+            })
+        };
+
+        let cwd = match self.command_dir(cwd) {
+            Ok(cwd) => cwd,
+            Err(e) => return failure(e.to_string()),
+        };
+        let root = self
+            .roots()
+            .into_iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .filter(|root| cwd.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .unwrap_or_else(|| cwd.clone());
+        let task = ZedTask::new(command, label, &root, &cwd);
+        match tasks::write(&root, &task) {
+            Ok(tasks_file) => {
+                self.send_command(LspCommand::ShowMessage {
+                    message: format!(
+                        "Claude suggested the task \"{}\". Run it with task: spawn.",
+                        task.label
+                    ),
+                })
+                .await;
+                serde_json::json!({
+                    "success": true,
+                    "command": command,
+                    "cwd": cwd,
+                    "ran": false,
+                    "task": task,
+                    "tasksFile": tasks_file,
+                    "message": "Written as a Zed task for the user to run; its output appears in Zed's task terminal"
+                })
+            }
+            Err(e) => failure(format!("{:#}", e)),
+        }
+    }
+
+    /// Directory for runCommand: `cwd` resolved against the first workspace folder,
+    /// or that folder itself. Anything outside the workspace is refused.
+    fn command_dir(&self, cwd: Option<&str>) -> Result<PathBuf> {
+        let mut roots = self.roots();
+        if roots.is_empty() {
+            roots.push(std::env::current_dir()?);
+        }
+
+        let dir = match cwd {
+            Some(cwd) => roots[0].join(cwd),
+            None => roots[0].clone(),
+        };
+        let dir = dir.canonicalize().map_err(|e| {
+            anyhow::anyhow!("Cannot use {} as working directory: {}", dir.display(), e)
+        })?;
+        if !dir.is_dir() {
+            return Err(anyhow::anyhow!("{} is not a directory", dir.display()));
+        }
+
+        let inside_workspace = roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| dir.starts_with(root));
+        if !inside_workspace {
+            return Err(anyhow::anyhow!(
+                "{} is outside the workspace",
+                dir.display()
+            ));
+        }
+        Ok(dir)
+    }
+
+    /// Ask the user through the editor, unless they always allow `action`.
+    /// Without an LSP connection there is nobody to ask, so it is denied.
+    async fn ask_permission(&self, action: Action, message: String) -> Decision {
+        if self.permissions.always_allowed(action) {
+            return Decision::AllowAlways;
+        }
+        self.request_permission(action, message).await
+    }
+
+    /// Ask the user through the editor, whatever they answered before
+    async fn request_permission(&self, action: Action, message: String) -> Decision {
+        let Some(sender) = &self.command_sender else {
+            warn!("No editor connection to allow {:?}, denying it", action);
+            return Decision::Deny;
+        };
+
+        let (reply, decided) = oneshot::channel();
+        let request = LspCommand::RequestPermission {
+            request: PermissionRequest { action, message },
+            reply,
+        };
+        if let Err(e) = sender.send(request).await {
+            warn!("Failed to send RequestPermission command: {}", e);
+            return Decision::Deny;
+        }
+        let decision = decided.await.unwrap_or(Decision::Deny);
+        if decision == Decision::AllowAlways {
+            self.permissions.allow_always(action);
+        }
+        decision
+    }
+
+    async fn handle_logging_set_level(&self, params: Option<Value>) -> Result<Value> {
+        if let Some(params) = params {
+            let level = params
+                .get("level")
+                .and_then(|v| v.as_str())
+                .unwrap_or("info");
+            info!("Setting log level to: {}", level);
+        }
+
+        Ok(serde_json::json!({}))
+    }
+
+    async fn handle_prompts_list(&self) -> Result<Value> {
+        info!("Listing available prompts");
+
+        Ok(serde_json::json!({
+            "prompts": []
+        }))
+    }
+
+    async fn handle_prompts_get(&self, params: Option<Value>) -> Result<Value> {
+        let params = params.ok_or_else(|| anyhow::anyhow!("Missing parameters for prompts/get"))?;
+
+        let prompt_name = params
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing prompt name"))?;
+
+        info!("Getting prompt: {}", prompt_name);
+
+        Ok(serde_json::json!({
+            "description": format!("Prompt: {}", prompt_name),
+            "messages": []
+        }))
+    }
+
+    async fn handle_resources_list(&self) -> Result<Value> {
+        info!("Listing resources");
+
+        let features = self.features().await;
+        let mut resources = Vec::new();
+        // The proxy doesn't see selections
+        if self.language_server.is_none() && features.selection_tracking {
+            resources.push(Resource {
+                uri: resources::SELECTION_URI.to_string(),
+                name: "Current selection".to_string(),
+                description: Some("The latest selection in the editor, with its text".to_string()),
+                mime_type: Some("application/json".to_string()),
+            });
+        }
+        if features.diagnostics {
+            resources.push(Resource {
+                uri: resources::DIAGNOSTICS_URI.to_string(),
+                name: "Diagnostics".to_string(),
+                description: Some(
+                    "Errors and warnings reported in the editor, per file".to_string(),
+                ),
+                mime_type: Some("application/json".to_string()),
+            });
+        }
+        let (documents, _) = self.state.documents.list().await;
+        for document in documents {
+            let Ok(path) = document.uri.to_file_path() else {
+                continue;
+            };
+            let path = path.to_string_lossy();
+            resources.push(Resource {
+                uri: resources::open_file_uri(&path),
+                name: path.to_string(),
+                description: Some(format!("Editor buffer ({})", document.language_id)),
+                mime_type: Some("text/plain".to_string()),
+            });
+        }
+
+        Ok(serde_json::json!({
+            "resources": resources
+        }))
+    }
+
+    fn handle_resource_templates_list(&self) -> Result<Value> {
+        Ok(serde_json::json!({
+            "resourceTemplates": [{
+                "uriTemplate": resources::OPEN_FILES_TEMPLATE,
+                "name": "Open file",
+                "description": "Editor buffer of a file open in the editor, by absolute path",
+                "mimeType": "text/plain"
+            }]
+        }))
+    }
+
+    async fn handle_resources_read(&self, params: Option<Value>) -> Result<Value> {
+        let uri = resource_uri(params.as_ref())?;
+        info!("Reading resource: {}", uri);
+
+        let (mime_type, text) = match uri {
+            resources::SELECTION_URI => (
+                "application/json",
+                self.selection_response("No selection available")
+                    .await
+                    .to_string(),
+            ),
+            resources::DIAGNOSTICS_URI => {
+                let snapshot = self.diagnostics_snapshot(None).await.unwrap_or_default();
+                (
+                    "application/json",
+                    diagnostics::to_mcp_json(&snapshot).to_string(),
+                )
+            }
+            _ => {
+                let document = match resources::open_file_path(uri) {
+                    Some(path) => self.open_document(&path).await,
+                    None => None,
+                };
+                let document =
+                    document.ok_or_else(|| anyhow::anyhow!("Resource not found: {}", uri))?;
+                // Open buffers of sensitive files keep their contents to themselves,
+                // as with selections in them
+                let sensitive = match document.uri.to_file_path() {
+                    Ok(path) => {
+                        let root = self.state.workspace_folders.root_for(&path);
+                        let config = self.state.config.read().await;
+                        config.privacy.is_sensitive(&path, root.as_deref())
+                    }
+                    Err(_) => false,
+                };
+                if sensitive {
+                    info!("Redacting resource {}", uri);
+                    ("text/plain", privacy::REDACTED.to_string())
+                } else {
+                    ("text/plain", document.text)
+                }
+            }
+        };
+
+        Ok(serde_json::json!({
+            "contents": [{
+                "uri": uri,
+                "mimeType": mime_type,
+                "text": text
+            }]
+        }))
+    }
+
+    fn handle_resources_subscribe(&self, params: Option<Value>, subscribe: bool) -> Result<Value> {
+        let uri = resource_uri(params.as_ref())?;
+        if subscribe {
+            info!("Subscribing to resource: {}", uri);
+            self.subscriptions.subscribe(uri);
+        } else {
+            info!("Unsubscribing from resource: {}", uri);
+            self.subscriptions.unsubscribe(uri);
+        }
+
+        Ok(serde_json::json!({}))
+    }
+
+    /// Choose the notification methods this client gets; null or no `methods`
+    /// brings back all of them
+    fn handle_set_notification_filter(&self, params: Option<Value>) -> Result<Value> {
+        let methods = match params.as_ref().and_then(|params| params.get("methods")) {
+            None | Some(Value::Null) => None,
+            Some(methods) => Some(
+                serde_json::from_value::<HashSet<String>>(methods.clone())
+                    .map_err(|e| anyhow::anyhow!("Invalid methods: {}", e))?,
+            ),
+        };
+        info!("Notification filter set to {:?}", methods);
+        self.notification_filter.set(methods);
+
+        Ok(serde_json::json!({ "methods": self.notification_filter.methods() }))
+    }
+}
+
+/// getLatestTerminalOutput entry for a recorded run, with its last `max_lines` lines
+fn terminal_output_json(run: &RecordedRun, max_lines: usize) -> Value {
+    let skip = run.lines.len().saturating_sub(max_lines);
+    let output: String = run.lines.iter().skip(skip).map(String::as_str).collect();
+    let (status, exit_code) = match run.status {
+        RunStatus::Running => ("running", None),
+        RunStatus::Exited { exit_code } => ("exited", exit_code),
+        RunStatus::TimedOut => ("timedOut", None),
+        RunStatus::Interrupted => ("interrupted", None),
+    };
+
+    serde_json::json!({
+        "command": run.command,
+        "cwd": run.cwd,
+        "startedSecondsAgo": run.started.elapsed().as_secs(),
+        "status": status,
+        "exitCode": exit_code,
+        "output": output,
+        "truncated": run.truncated || skip > 0
+    })
+}
+
+/// The `uri` parameter of a resources/* request
+fn resource_uri(params: Option<&Value>) -> Result<&str> {
+    params
+        .and_then(|params| params.get("uri"))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing resource uri"))
+}
+
+impl Default for MCPServer {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tower_lsp::lsp_types::Url;
+
+// Mentions typed into a buffer rather than picked from completions:
+// "@path/to/file.rs", "@path:10" or "@path:10-20" (1-based lines, as editors
+// show them) and "@claude <instruction>". A path is finished by the whitespace
+// after it and an instruction by the end of its line; didChange sends a mention
+// when the edit it gets typed in the finishing character.
+
+const CLAUDE_PREFIX: &str = "claude ";
+/// Comment endings left off an instruction typed in a block comment
+const COMMENT_CLOSERS: [&str; 2] = ["*/", "-->"];
+/// Punctuation after a path that ends the sentence rather than the path
+const TRAILING_PUNCTUATION: [char; 9] = [',', ';', '.', ')', ']', '}', '"', '\'', '`'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypedMention {
+    /// A file, directory or glob, with the 1-based lines given after it
+    Path {
+        path: String,
+        lines: Option<(u32, u32)>,
+    },
+    /// What Claude should do with the code the instruction was typed in
+    Claude { instruction: String },
+}
+
+/// A finished mention on a line
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub mention: TypedMention,
+    /// The mention as typed, from its "@"
+    pub token: String,
+    /// Byte offset in the line where the mention ends
+    pub end: usize,
+    /// Byte offset in the line of the character that finished it: the whitespace
+    /// after a path, the line break after an instruction
+    pub terminator: usize,
+}
+
+/// The finished mentions on `line` (without its line break). `ended` says whether
+/// a line break follows it.
+pub fn find(line: &str, ended: bool) -> Vec<Found> {
+    let mut found = Vec::new();
+    let mut search = 0;
+    while let Some(offset) = line[search..].find('@') {
+        let at = search + offset;
+        search = at + 1;
+        // Not the "@" of an email address or "@@"
+        let starts_word = !matches!(
+            line[..at].chars().next_back(),
+            Some(ch) if ch.is_alphanumeric() || matches!(ch, '_' | '.' | '-' | '@')
+        );
+        if !starts_word {
+            continue;
+        }
+
+        let rest = &line[at + 1..];
+        if let Some(instruction) = rest.strip_prefix(CLAUDE_PREFIX) {
+            let instruction = without_comment_closer(instruction);
+            if ended && !instruction.is_empty() {
+                found.push(Found {
+                    mention: TypedMention::Claude {
+                        instruction: instruction.to_string(),
+                    },
+                    token: format!("@{}{}", CLAUDE_PREFIX, instruction),
+                    end: line.trim_end().len(),
+                    terminator: line.len(),
+                });
+            }
+            // The instruction runs to the end of the line
+            break;
+        }
+
+        let length = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        search = at + 1 + length;
+        if length == rest.len() && !ended {
+            continue;
+        }
+        let word = rest[..length].trim_end_matches(TRAILING_PUNCTUATION);
+        if let Some(mention) = parse_path(word) {
+            found.push(Found {
+                mention,
+                token: format!("@{}", word),
+                end: at + 1 + word.len(),
+                terminator: at + 1 + length,
+            });
+        }
+    }
+    found
+}
+
+/// "path", "path:10" or "path:10-20"; a range given backwards is turned around
+fn parse_path(word: &str) -> Option<TypedMention> {
+    if word.is_empty() || word == CLAUDE_PREFIX.trim_end() {
+        return None;
+    }
+    let lines = word.rsplit_once(':').and_then(|(path, lines)| {
+        let (start, end) = match lines.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => {
+                let line: u32 = lines.parse().ok()?;
+                (line, line)
+            }
+        };
+        Some((path, (start.min(end), start.max(end))))
+    });
+    let (path, lines) = match lines {
+        Some((path, lines)) => (path, Some(lines)),
+        None => (word, None),
+    };
+    (!path.is_empty()).then(|| TypedMention::Path {
+        path: path.to_string(),
+        lines,
+    })
+}
+
+fn without_comment_closer(instruction: &str) -> &str {
+    let instruction = instruction.trim();
+    COMMENT_CLOSERS
+        .iter()
+        .find_map(|closer| instruction.strip_suffix(closer))
+        .unwrap_or(instruction)
+        .trim_end()
+}
+
+/// Mentions sent from each open document, annotated with inlay hints while they
+/// stay in the buffer
+#[derive(Debug, Default)]
+pub struct SentMentions {
+    sent: Mutex<HashMap<Url, HashSet<String>>>,
+}
+
+impl SentMentions {
+    pub fn insert(&self, uri: &Url, token: String) {
+        self.sent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(uri.clone())
+            .or_default()
+            .insert(token);
+    }
+
+    pub fn contains(&self, uri: &Url, token: &str) -> bool {
+        self.sent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(uri)
+            .is_some_and(|tokens| tokens.contains(token))
+    }
+
+    pub fn remove(&self, uri: &Url) {
+        self.sent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(uri);
+    }
+}
@@ -0,0 +1,431 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::config::SharedConfig;
+use crate::context;
+use crate::shutdown::Shutdown;
+use crate::status::ServerStatus;
+
+// Counters and timings for debugging a sluggish integration: notifications sent
+// to Claude by method, events the debouncer dropped for newer ones, restarts of
+// the debounce task after a panic, how long
+// editor commands take, how long MCP requests take to answer, and how much text
+// went to Claude in notifications and tool results, in bytes and estimated tokens.
+// They are always
+// kept, being a few map updates; `metrics.enabled` decides whether they are
+// served at GET /metrics (Prometheus text format) and pushed to an OTLP/HTTP
+// collector as JSON.
+
+const DEFAULT_EXPORT_INTERVAL_MS: u64 = 10_000;
+// Longest an OTLP export may take before it is given up on
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MetricsSettings {
+    /// Serve GET /metrics and export to `otlpEndpoint`
+    pub enabled: bool,
+    /// OTLP/HTTP collector, e.g. http://localhost:4318; metrics go to its
+    /// /v1/metrics. Not exported when unset.
+    pub otlp_endpoint: Option<String>,
+    pub export_interval_ms: u64,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            otlp_endpoint: None,
+            export_interval_ms: DEFAULT_EXPORT_INTERVAL_MS,
+        }
+    }
+}
+
+/// Text sent to Claude from one notification method or tool
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sent {
+    pub count: u64,
+    pub bytes: u64,
+    /// Estimated, at about four bytes per token
+    pub tokens: u64,
+}
+
+/// Count, total and longest of a set of durations
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Timing {
+    fn record(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// By the method of the notification dropped
+    debounce_dropped: Mutex<BTreeMap<String, u64>>,
+    /// Times the debounce task panicked and was started again
+    debounce_restarts: AtomicU64,
+    /// workspace/executeCommand, by command
+    commands: Mutex<BTreeMap<String, Timing>>,
+    /// MCP requests, by method (tools/call by tool)
+    mcp_requests: Mutex<BTreeMap<String, Timing>>,
+    /// By notification method, or tool for tool results
+    context_sent: Mutex<BTreeMap<String, Sent>>,
+}
+
+pub type SharedMetrics = Arc<Metrics>;
+
+impl Metrics {
+    /// The debouncer replaced a held `method` notification with a newer one
+    pub fn debounce_dropped(&self, method: &str) {
+        *lock(&self.debounce_dropped)
+            .entry(method.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn debounce_restarted(&self) {
+        self.debounce_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn debounce_restarts(&self) -> u64 {
+        self.debounce_restarts.load(Ordering::Relaxed)
+    }
+
+    pub fn command_finished(&self, command: &str, elapsed: Duration) {
+        lock(&self.commands)
+            .entry(command.to_string())
+            .or_default()
+            .record(elapsed);
+    }
+
+    pub fn mcp_request_finished(&self, method: &str, elapsed: Duration) {
+        lock(&self.mcp_requests)
+            .entry(method.to_string())
+            .or_default()
+            .record(elapsed);
+    }
+
+    /// Count `bytes` of text sent to Claude from `source`, returning the
+    /// estimated tokens in them
+    pub fn context_sent(&self, source: &str, bytes: usize) -> usize {
+        let tokens = context::estimate_tokens(bytes);
+        let mut sent = lock(&self.context_sent);
+        let sent = sent.entry(source.to_string()).or_default();
+        sent.count += 1;
+        sent.bytes += bytes as u64;
+        sent.tokens += tokens as u64;
+        tokens
+    }
+
+    pub fn context_sent_by_source(&self) -> BTreeMap<String, Sent> {
+        lock(&self.context_sent).clone()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            notifications_sent: BTreeMap::new(),
+            notifications_dropped: 0,
+            debounce_dropped: lock(&self.debounce_dropped).clone(),
+            debounce_restarts: self.debounce_restarts(),
+            commands: lock(&self.commands).clone(),
+            mcp_requests: lock(&self.mcp_requests).clone(),
+            context_sent: self.context_sent_by_source(),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Everything measured so far, including the notification bus's counts
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub notifications_sent: BTreeMap<String, u64>,
+    /// Dropped from the queue of a client that fell behind
+    pub notifications_dropped: u64,
+    pub debounce_dropped: BTreeMap<String, u64>,
+    pub debounce_restarts: u64,
+    pub commands: BTreeMap<String, Timing>,
+    pub mcp_requests: BTreeMap<String, Timing>,
+    pub context_sent: BTreeMap<String, Sent>,
+}
+
+impl MetricsSnapshot {
+    fn context_bytes(&self) -> BTreeMap<String, u64> {
+        self.context_sent
+            .iter()
+            .map(|(source, sent)| (source.clone(), sent.bytes))
+            .collect()
+    }
+
+    fn context_tokens(&self) -> BTreeMap<String, u64> {
+        self.context_sent
+            .iter()
+            .map(|(source, sent)| (source.clone(), sent.tokens))
+            .collect()
+    }
+
+    /// The Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        counter(
+            &mut text,
+            "claude_code_notifications_sent_total",
+            "Notifications sent to Claude",
+            "method",
+            &self.notifications_sent,
+        );
+        let _ = writeln!(
+            text,
+            "# HELP claude_code_notifications_dropped_total Notifications dropped for clients that fell behind\n\
+             # TYPE claude_code_notifications_dropped_total counter\n\
+             claude_code_notifications_dropped_total {}",
+            self.notifications_dropped
+        );
+        counter(
+            &mut text,
+            "claude_code_debounce_dropped_total",
+            "Notifications replaced by newer ones while debounced",
+            "method",
+            &self.debounce_dropped,
+        );
+        let _ = writeln!(
+            text,
+            "# HELP claude_code_debounce_restarts_total Restarts of the debounce task after a panic\n\
+             # TYPE claude_code_debounce_restarts_total counter\n\
+             claude_code_debounce_restarts_total {}",
+            self.debounce_restarts
+        );
+        summary(
+            &mut text,
+            "claude_code_command_duration_seconds",
+            "Time taken by workspace/executeCommand",
+            "command",
+            &self.commands,
+        );
+        summary(
+            &mut text,
+            "claude_code_mcp_request_duration_seconds",
+            "Time taken to answer MCP requests",
+            "method",
+            &self.mcp_requests,
+        );
+        counter(
+            &mut text,
+            "claude_code_context_bytes_total",
+            "Bytes of notifications and tool results sent to Claude",
+            "source",
+            &self.context_bytes(),
+        );
+        counter(
+            &mut text,
+            "claude_code_context_tokens_total",
+            "Estimated tokens of notifications and tool results sent to Claude",
+            "source",
+            &self.context_tokens(),
+        );
+        text
+    }
+
+    /// An OTLP ExportMetricsServiceRequest in its JSON encoding. `start` is when
+    /// counting began, both as Unix nanoseconds.
+    pub fn otlp(&self, start: u64, now: u64) -> Value {
+        let sum = |name: &str, label: &str, counts: &BTreeMap<String, u64>| {
+            let points: Vec<Value> = counts
+                .iter()
+                .map(|(key, count)| {
+                    serde_json::json!({
+                        "attributes": [attribute(label, key)],
+                        "startTimeUnixNano": start.to_string(),
+                        "timeUnixNano": now.to_string(),
+                        "asInt": count.to_string()
+                    })
+                })
+                .collect();
+            serde_json::json!({
+                "name": name,
+                "sum": {
+                    "dataPoints": points,
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true
+                }
+            })
+        };
+        let summary = |name: &str, label: &str, timings: &BTreeMap<String, Timing>| {
+            let points: Vec<Value> = timings
+                .iter()
+                .map(|(key, timing)| {
+                    serde_json::json!({
+                        "attributes": [attribute(label, key)],
+                        "startTimeUnixNano": start.to_string(),
+                        "timeUnixNano": now.to_string(),
+                        "count": timing.count.to_string(),
+                        "sum": timing.total.as_secs_f64(),
+                        "quantileValues": [{ "quantile": 1.0, "value": timing.max.as_secs_f64() }]
+                    })
+                })
+                .collect();
+            serde_json::json!({
+                "name": name,
+                "unit": "s",
+                "summary": { "dataPoints": points }
+            })
+        };
+
+        serde_json::json!({
+            "resourceMetrics": [{
+                "resource": {
+                    "attributes": [attribute("service.name", "claude-code-server")]
+                },
+                "scopeMetrics": [{
+                    "scope": {
+                        "name": "claude-code-server",
+                        "version": env!("CARGO_PKG_VERSION")
+                    },
+                    "metrics": [
+                        sum("claude_code.notifications.sent", "method", &self.notifications_sent),
+                        sum("claude_code.debounce.dropped", "method", &self.debounce_dropped),
+                        summary("claude_code.command.duration", "command", &self.commands),
+                        summary("claude_code.mcp_request.duration", "method", &self.mcp_requests),
+                        sum("claude_code.context.bytes", "source", &self.context_bytes()),
+                        sum("claude_code.context.tokens", "source", &self.context_tokens())
+                    ]
+                }]
+            }]
+        })
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn counter(text: &mut String, name: &str, help: &str, label: &str, counts: &BTreeMap<String, u64>) {
+    let _ = writeln!(text, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (key, count) in counts {
+        let _ = writeln!(text, "{}{{{}=\"{}\"}} {}", name, label, escape(key), count);
+    }
+}
+
+fn summary(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    timings: &BTreeMap<String, Timing>,
+) {
+    let _ = writeln!(text, "# HELP {} {}\n# TYPE {} summary", name, help, name);
+    for (key, timing) in timings {
+        let labels = format!("{{{}=\"{}\"}}", label, escape(key));
+        let _ = writeln!(text, "{}_count{} {}", name, labels, timing.count);
+        let _ = writeln!(
+            text,
+            "{}_sum{} {}",
+            name,
+            labels,
+            timing.total.as_secs_f64()
+        );
+    }
+    // The longest seen, as its own gauge
+    let _ = writeln!(text, "# TYPE {}_max gauge", name);
+    for (key, timing) in timings {
+        let _ = writeln!(
+            text,
+            "{}_max{{{}=\"{}\"}} {}",
+            name,
+            label,
+            escape(key),
+            timing.max.as_secs_f64()
+        );
+    }
+}
+
+/// Escape a label value for the text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Push the metrics to the configured OTLP collector every exportIntervalMs while
+/// they are enabled, until shutdown. Settings are read again before each export.
+pub async fn export(status: ServerStatus, config: SharedConfig, shutdown: Shutdown) {
+    let start = unix_nanos();
+    loop {
+        let settings = config.read().await.metrics.clone();
+        let interval = Duration::from_millis(settings.export_interval_ms.max(1000));
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.triggered() => return,
+        }
+
+        let endpoint = match (&settings.otlp_endpoint, settings.enabled) {
+            (Some(endpoint), true) => endpoint,
+            _ => continue,
+        };
+        let body = status.metrics_snapshot().otlp(start, unix_nanos());
+        match tokio::time::timeout(EXPORT_TIMEOUT, post(endpoint, &body)).await {
+            Ok(Ok(())) => debug!("Exported metrics to {}", endpoint),
+            Ok(Err(e)) => warn!("Failed to export metrics to {}: {}", endpoint, e),
+            Err(_) => warn!("Exporting metrics to {} timed out", endpoint),
+        }
+    }
+}
+
+/// POST `body` to the collector's /v1/metrics. Plain HTTP only, as collectors
+/// next to the editor serve it.
+async fn post(endpoint: &str, body: &Value) -> anyhow::Result<()> {
+    let url = Url::parse(endpoint)?.join("/v1/metrics")?;
+    if url.scheme() != "http" {
+        anyhow::bail!("only http:// collectors are supported");
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("no host in {}", endpoint))?;
+    let port = url.port_or_known_default().unwrap_or(4318);
+    let body = body.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path(),
+        host,
+        port,
+        body.len(),
+        body
+    );
+
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => anyhow::bail!("collector answered {:?}", status_line),
+    }
+}
@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{Position, Range, SymbolKind};
+
+use crate::language;
+use crate::symbols;
+use crate::text::{LineIndex, PositionEncoding};
+use crate::workspace;
+
+// Code navigation for the goToDefinition/findReferences MCP tools. Definitions are
+// the declarations found by the keyword scanner in symbols.rs and references are
+// whole-word occurrences of the name, searched across the workspace folders
+// (respecting .gitignore). Open documents are searched as they are in the editor.
+
+// Files searched per workspace folder
+const MAX_FILES: usize = 5000;
+pub const MAX_RESULTS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    Definitions,
+    References,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NavigationMatch {
+    pub file_path: String,
+    /// Range of the name itself, 0-based like LSP ranges
+    pub range: Range,
+    /// The whole line, trimmed
+    pub text: String,
+    /// Whether the occurrence is a declaration of the name
+    pub is_definition: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<SymbolKind>,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchResults {
+    pub matches: Vec<NavigationMatch>,
+    /// Set when the search stopped at MAX_RESULTS
+    pub truncated: bool,
+}
+
+/// Search every file under `roots` for `name`. `buffers` holds the text of open
+/// documents, used instead of the file on disk.
+pub fn search(
+    roots: &[PathBuf],
+    buffers: &HashMap<PathBuf, String>,
+    name: &str,
+    search: Search,
+    encoding: PositionEncoding,
+) -> SearchResults {
+    let mut results = SearchResults::default();
+    for root in roots {
+        for relative in workspace::list_files(root, MAX_FILES) {
+            let path = root.join(relative);
+            let Some(text) = workspace::searchable_text(&path, buffers) else {
+                continue;
+            };
+            // Cheap check before scanning the file line by line
+            if !text.contains(name) {
+                continue;
+            }
+
+            for found in search_text(&path, &text, name, search, encoding) {
+                if results.matches.len() == MAX_RESULTS {
+                    results.truncated = true;
+                    return results;
+                }
+                results.matches.push(found);
+            }
+        }
+    }
+    results
+}
+
+fn search_text(
+    path: &Path,
+    text: &str,
+    name: &str,
+    search: Search,
+    encoding: PositionEncoding,
+) -> Vec<NavigationMatch> {
+    let index = LineIndex::new(text);
+    let declarations: HashMap<u32, SymbolKind> =
+        symbols::extract_symbols(text, language::from_path(path))
+            .into_iter()
+            .filter(|symbol| symbol.name == name)
+            .map(|symbol| (symbol.line, symbol.kind))
+            .collect();
+
+    let mut matches = Vec::new();
+    let mut line_number = 0;
+    while let Some(line) = index.line(line_number) {
+        let kind = declarations.get(&line_number).copied();
+        for (occurrence, start) in symbols::word_occurrences(line, name).enumerate() {
+            // On a declaration's line, the first occurrence is the declared name
+            let is_definition = kind.is_some() && occurrence == 0;
+            if search == Search::Definitions && !is_definition {
+                break;
+            }
+            matches.push(NavigationMatch {
+                file_path: path.to_string_lossy().to_string(),
+                range: Range::new(
+                    Position::new(line_number, encoding.measure(&line[..start])),
+                    Position::new(line_number, encoding.measure(&line[..start + name.len()])),
+                ),
+                text: line.trim().to_string(),
+                is_definition,
+                kind: kind.filter(|_| is_definition),
+            });
+        }
+        line_number += 1;
+    }
+    matches
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use crate::lsp::JsonRpcNotification;
+
+// Versions of the notifications the editor sends Claude. Each version of a
+// method's params adds fields to the one before; version 1 is the payload the
+// first Claude CLIs were written against. A client lists the methods it handles
+// in `initialize`, under `capabilities.experimental.ideNotifications`, with the
+// newest version of each it understands. From then on it only gets those methods,
+// cut down to the fields of the agreed versions. A client that lists nothing
+// gets every method at its latest version. A new field in a notification means a
+// new version here.
+
+/// The fields each version of a method's params added, starting with version 1
+struct Schema {
+    method: &'static str,
+    versions: &'static [&'static [&'static str]],
+}
+
+const SCHEMAS: &[Schema] = &[
+    Schema {
+        method: "selection_changed",
+        versions: &[
+            &["text", "filePath", "fileUrl", "selection"],
+            &["languageId", "workspaceRoot", "relativePath", "truncated"],
+            &["reference"],
+            &["surroundingLines"],
+            &["selections"],
+        ],
+    },
+    Schema {
+        method: "at_mentioned",
+        versions: &[
+            &["filePath", "lineStart", "lineEnd"],
+            &[
+                "languageId",
+                "workspaceRoot",
+                "relativePath",
+                "files",
+                "truncated",
+            ],
+            &["instruction"],
+            &["symbol"],
+            &["text", "totalLines"],
+        ],
+    },
+    Schema {
+        method: "opened_file",
+        versions: &[
+            &["filePath", "fileUrl", "languageId"],
+            &["workspaceRoot", "relativePath"],
+        ],
+    },
+    Schema {
+        method: "closed_file",
+        versions: &[&["filePath", "fileUrl"], &["workspaceRoot", "relativePath"]],
+    },
+    Schema {
+        method: "file_renamed",
+        versions: &[
+            &["oldFilePath", "filePath", "fileUrl", "isDirectory"],
+            &["oldRelativePath", "workspaceRoot", "relativePath"],
+        ],
+    },
+    Schema {
+        method: "file_deleted",
+        versions: &[&["filePath", "fileUrl"], &["workspaceRoot", "relativePath"]],
+    },
+];
+
+fn schema(method: &str) -> Option<&'static Schema> {
+    SCHEMAS.iter().find(|schema| schema.method == method)
+}
+
+/// The latest version of every versioned method
+pub fn latest() -> BTreeMap<String, u32> {
+    SCHEMAS
+        .iter()
+        .map(|schema| (schema.method.to_string(), schema.versions.len() as u32))
+        .collect()
+}
+
+/// What a client that understands `understood` gets: the methods both sides
+/// know, each at the older of the two versions
+pub fn negotiate(understood: &BTreeMap<String, u32>) -> BTreeMap<String, u32> {
+    understood
+        .iter()
+        .filter_map(|(method, &version)| {
+            let latest = schema(method)?.versions.len() as u32;
+            Some((method.clone(), version.clamp(1, latest)))
+        })
+        .collect()
+}
+
+/// Whether a client that agreed on `agreed` gets `method` at all. Methods
+/// without versions, like MCP's own notifications, always go through.
+pub fn is_agreed(method: &str, agreed: &BTreeMap<String, u32>) -> bool {
+    schema(method).is_none() || agreed.contains_key(method)
+}
+
+/// `notification` without the fields added after `version` of its method
+pub fn downgrade(notification: &JsonRpcNotification, version: u32) -> JsonRpcNotification {
+    let mut notification = notification.clone();
+    let Some(schema) = schema(&notification.method) else {
+        return notification;
+    };
+    if let Some(params) = notification.params.as_object_mut() {
+        let known: Vec<&str> = schema
+            .versions
+            .iter()
+            .take(version as usize)
+            .flat_map(|fields| fields.iter().copied())
+            .collect();
+        params.retain(|field, _| known.contains(&field.as_str()));
+    }
+    notification
+}
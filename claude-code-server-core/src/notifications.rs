@@ -0,0 +1,850 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Instant;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::Client;
+use tracing::{debug, warn};
+
+use crate::config::SharedConfig;
+use crate::lsp::JsonRpcNotification;
+use crate::mcp::MCPResponse;
+use crate::metrics::SharedMetrics;
+use crate::notification_schemas;
+use crate::rpc::{self, JsonRpcRequest, PendingRequests, RequestError};
+use crate::sinks::{NotificationSinks, SinkSettings};
+
+// Notifications from the LSP side to Claude. Each connected client has its own
+// bounded queue, and only gets the methods its filter accepts (all of them unless
+// it set one with ide/setNotificationFilter), at the versions it agreed on (see
+// notification_schemas.rs). A client that falls behind either
+// loses its oldest notifications or holds up the sender for a while, as
+// `notifications.overflow` says. Nothing would
+// reach a client that isn't connected yet, so the bus also keeps a bounded history
+// and replays the editor's state from it to each new client. Notifications no
+// client got (at-mentions, say) wait in a separate queue instead, along with tool
+// results whose client disconnected before they were ready, so a flood of
+// selections can't push them out. Queued messages go to the next client that
+// connects, unless they expire first. The editor side can also
+// ask the most recent client a question and wait for its answer; such requests go
+// straight to that client and are never replayed. Every notification sent is
+// also copied to the notification sinks (see sinks.rs).
+
+// Longest a send waits for a full queue under the block policy before dropping
+// the oldest notification after all
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(2);
+// A payload identical to the last one sent for its method within this long is dropped
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(1);
+const RATE_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub notification: JsonRpcNotification,
+    /// Whether a connected client received it when it was sent
+    pub delivered: bool,
+}
+
+/// What a client whose queue is full does to the sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    /// Drop the client's oldest queued notification
+    #[default]
+    DropOldest,
+    /// Make the sender wait for room, up to BLOCK_TIMEOUT
+    Block,
+}
+
+/// Notification methods one client wants, and the versions of them it agreed on
+/// in `initialize`; until it says, every method at its latest version
+#[derive(Debug, Clone, Default)]
+pub struct NotificationFilter {
+    methods: Arc<Mutex<Option<HashSet<String>>>>,
+    versions: Arc<Mutex<Option<BTreeMap<String, u32>>>>,
+}
+
+impl NotificationFilter {
+    /// Only let `methods` through from now on, or everything again for None
+    pub fn set(&self, methods: Option<HashSet<String>>) {
+        *self.lock() = methods;
+    }
+
+    /// Send the versioned methods at `versions` only, or all of them at their
+    /// latest again for None
+    pub fn set_versions(&self, versions: Option<BTreeMap<String, u32>>) {
+        *self.lock_versions() = versions;
+    }
+
+    /// The methods let through, sorted, or None for all of them
+    pub fn methods(&self) -> Option<Vec<String>> {
+        self.lock().as_ref().map(|methods| {
+            let mut methods: Vec<String> = methods.iter().cloned().collect();
+            methods.sort();
+            methods
+        })
+    }
+
+    pub fn accepts(&self, method: &str) -> bool {
+        let wanted = self
+            .lock()
+            .as_ref()
+            .is_none_or(|methods| methods.contains(method));
+        wanted
+            && self
+                .lock_versions()
+                .as_ref()
+                .is_none_or(|versions| notification_schemas::is_agreed(method, versions))
+    }
+
+    /// `notification` as the client agreed to get it
+    pub fn adapt(&self, notification: &JsonRpcNotification) -> JsonRpcNotification {
+        let version = self
+            .lock_versions()
+            .as_ref()
+            .and_then(|versions| versions.get(&notification.method).copied());
+        match version {
+            Some(version) => notification_schemas::downgrade(notification, version),
+            None => notification.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<HashSet<String>>> {
+        self.methods.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_versions(&self) -> std::sync::MutexGuard<'_, Option<BTreeMap<String, u32>>> {
+        self.versions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A message kept for the next client to connect
+#[derive(Debug)]
+enum Queued {
+    Notification(JsonRpcNotification),
+    Result(MCPResponse),
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    notifications: VecDeque<JsonRpcNotification>,
+    /// Dropped since the client last received
+    lagged: u64,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    queue: Mutex<Queue>,
+    /// Signalled when a notification is queued
+    queued: Notify,
+    /// Signalled when the client takes a notification off the queue
+    taken: Notify,
+    filter: NotificationFilter,
+    /// Requests for the client, which don't wait behind queued notifications
+    requests: mpsc::UnboundedSender<JsonRpcRequest>,
+    /// Requests sent to the client, until it responds. Only the client's receiver
+    /// keeps its subscriber alive, so these fail as soon as it disconnects.
+    pending: PendingRequests,
+}
+
+impl Subscriber {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Notifications dropped because the client fell behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged(pub u64);
+
+/// A client's queue on the bus; dropping it unsubscribes
+#[derive(Debug)]
+pub struct NotificationReceiver {
+    subscriber: Arc<Subscriber>,
+}
+
+impl NotificationReceiver {
+    /// The next notification, or how many were dropped since the last one received
+    pub async fn recv(&mut self) -> Result<JsonRpcNotification, Lagged> {
+        loop {
+            let queued = self.subscriber.queued.notified();
+            tokio::pin!(queued);
+            // Register before checking, so a notification queued in between
+            // isn't missed
+            queued.as_mut().enable();
+            {
+                let mut queue = self.subscriber.lock();
+                if queue.lagged > 0 {
+                    return Err(Lagged(std::mem::take(&mut queue.lagged)));
+                }
+                if let Some(notification) = queue.notifications.pop_front() {
+                    self.subscriber.taken.notify_waiters();
+                    return Ok(notification);
+                }
+            }
+            queued.await;
+        }
+    }
+
+    /// Hand a response from the client to the request waiting for it. Returns
+    /// false when none is, e.g. because it timed out.
+    pub fn respond(&self, response: MCPResponse) -> bool {
+        self.subscriber.pending.resolve(response)
+    }
+}
+
+/// Counters for how well clients keep up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationStats {
+    pub sent: u64,
+    /// Dropped from the queue of a client that fell behind
+    pub dropped: u64,
+    /// Sends that had to wait for a client under the block policy
+    pub blocked: u64,
+    /// Notifications and tool results waiting for a client to connect
+    pub queued: usize,
+    /// Queued messages that expired before a client connected
+    pub expired: u64,
+    /// Queued messages pushed out by newer ones because the queue was full
+    pub evicted: u64,
+}
+
+#[derive(Debug)]
+pub struct NotificationBus {
+    subscribers: Mutex<Vec<Weak<Subscriber>>>,
+    history: Mutex<VecDeque<Recorded>>,
+    capacity: usize,
+    /// Messages for the next client, with when they expire
+    queued: Mutex<VecDeque<(Instant, Queued)>>,
+    /// Bumped whenever the history changes
+    revision: AtomicU64,
+    sent: AtomicU64,
+    sent_by_method: Mutex<BTreeMap<String, u64>>,
+    dropped: AtomicU64,
+    blocked: AtomicU64,
+    expired: AtomicU64,
+    evicted: AtomicU64,
+    sinks: NotificationSinks,
+}
+
+pub type SharedNotificationBus = Arc<NotificationBus>;
+
+/// A new client's view of the bus: what to replay first, then live notifications
+/// and requests
+pub struct Subscription {
+    pub replay: Vec<JsonRpcNotification>,
+    /// Tool results another client didn't stay to get, sent after the replay as
+    /// `tool_result` notifications. A response would reuse an id the new client
+    /// may have given one of its own requests.
+    pub results: Vec<JsonRpcNotification>,
+    pub receiver: NotificationReceiver,
+    pub requests: mpsc::UnboundedReceiver<JsonRpcRequest>,
+}
+
+impl NotificationBus {
+    /// Keep up to `capacity` recent notifications for replay
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            queued: Mutex::default(),
+            revision: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            sent_by_method: Mutex::default(),
+            dropped: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+            sinks: NotificationSinks::default(),
+        }
+    }
+
+    /// Copy every notification sent to `sinks` as well
+    pub fn with_sinks(mut self, sinks: NotificationSinks) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// Record a notification and queue it for every client that wants it,
+    /// returning whether any client got it. A client whose queue holds `settings.channel_capacity`
+    /// notifications is handled as `settings.overflow` says. One no client got is
+    /// kept for the next client, unless it is part of the editor's state.
+    pub async fn send(
+        &self,
+        notification: JsonRpcNotification,
+        settings: &NotificationSettings,
+    ) -> bool {
+        let capacity = settings.channel_capacity.max(1);
+        let deadline = Instant::now() + BLOCK_TIMEOUT;
+        let mut waited = false;
+        loop {
+            // Holding the history lock while queueing keeps send and subscribe
+            // ordered, so a new client neither misses nor duplicates a notification
+            let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+            let subscribers: Vec<_> = self
+                .live_subscribers()
+                .into_iter()
+                .filter(|subscriber| subscriber.filter.accepts(&notification.method))
+                .collect();
+
+            let full = subscribers
+                .iter()
+                .find(|subscriber| subscriber.lock().notifications.len() >= capacity);
+            if let Some(full) = full {
+                if settings.overflow == OverflowPolicy::Block && Instant::now() < deadline {
+                    let taken = full.taken.notified();
+                    tokio::pin!(taken);
+                    taken.as_mut().enable();
+                    // The client may have caught up before the wait was registered
+                    let still_full = full.lock().notifications.len() >= capacity;
+                    drop(history);
+                    if !still_full {
+                        continue;
+                    }
+                    if !waited {
+                        waited = true;
+                        self.blocked.fetch_add(1, Ordering::Relaxed);
+                        debug!("Waiting for a client to catch up");
+                    }
+                    let _ = tokio::time::timeout_at(deadline, taken).await;
+                    continue;
+                }
+            }
+
+            if waited && full.is_some() {
+                warn!(
+                    "A client didn't catch up within {:?}, dropping its oldest notification",
+                    BLOCK_TIMEOUT
+                );
+            }
+            for subscriber in &subscribers {
+                let mut queue = subscriber.lock();
+                while queue.notifications.len() >= capacity {
+                    queue.notifications.pop_front();
+                    queue.lagged += 1;
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                queue
+                    .notifications
+                    .push_back(subscriber.filter.adapt(&notification));
+                drop(queue);
+                subscriber.queued.notify_waiters();
+            }
+            let delivered = !subscribers.is_empty();
+            self.sent.fetch_add(1, Ordering::Relaxed);
+            *self
+                .sent_by_method
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(notification.method.clone())
+                .or_default() += 1;
+            self.sinks.send(&notification, &settings.sinks);
+            if !delivered && !is_state(&notification.method) {
+                self.enqueue(Queued::Notification(notification.clone()), settings);
+            }
+
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(Recorded {
+                notification,
+                delivered,
+            });
+            self.revision.fetch_add(1, Ordering::Relaxed);
+            return delivered;
+        }
+    }
+
+    /// Ask the most recently connected client and wait up to `timeout` for its
+    /// result
+    pub async fn request(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<Value, RequestError> {
+        let Some(subscriber) = self.live_subscribers().pop() else {
+            return Err(RequestError::NotConnected);
+        };
+        let (request, response) = subscriber.pending.register(method, params);
+        let id = request.id.clone();
+        debug!("Sending {} request {} to Claude", method, id);
+        if subscriber.requests.send(request).is_err() {
+            return Err(RequestError::Disconnected);
+        }
+
+        // Holding on to the subscriber would keep its pending requests alive
+        let subscriber = {
+            let weak = Arc::downgrade(&subscriber);
+            drop(subscriber);
+            weak
+        };
+        let result = rpc::wait(response, timeout).await;
+        if result.is_err() {
+            if let Some(subscriber) = subscriber.upgrade() {
+                subscriber.pending.forget(&id);
+            }
+        }
+        result?.map_err(RequestError::Failed)
+    }
+
+    /// Keep a tool result for the next client, as its own client disconnected
+    /// before it was ready
+    pub fn queue_result(&self, response: MCPResponse, settings: &NotificationSettings) {
+        debug!(
+            "Queueing the result of {:?} for the next client",
+            response.id
+        );
+        self.enqueue(Queued::Result(response), settings);
+    }
+
+    fn enqueue(&self, message: Queued, settings: &NotificationSettings) {
+        let mut queued = self.lock_queued();
+        while queued.len() >= settings.queue_capacity.max(1) {
+            queued.pop_front();
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+        }
+        let expires = Instant::now() + Duration::from_millis(settings.queue_ttl_ms);
+        queued.push_back((expires, message));
+    }
+
+    /// The queued messages, without the expired ones
+    fn lock_queued(&self) -> std::sync::MutexGuard<'_, VecDeque<(Instant, Queued)>> {
+        let mut queued = self.queued.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let before = queued.len();
+        queued.retain(|(expires, _)| *expires > now);
+        let expired = before - queued.len();
+        if expired > 0 {
+            debug!(
+                "{} queued messages expired before a client connected",
+                expired
+            );
+            self.expired.fetch_add(expired as u64, Ordering::Relaxed);
+        }
+        queued
+    }
+
+    pub fn stats(&self) -> NotificationStats {
+        NotificationStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            queued: self.lock_queued().len(),
+            expired: self.expired.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// How many notifications of each method were sent
+    pub fn sent_by_method(&self) -> BTreeMap<String, u64> {
+        self.sent_by_method
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Subscribers whose receiver is still around, forgetting the others
+    fn live_subscribers(&self) -> Vec<Arc<Subscriber>> {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        subscribers.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Put notifications from an earlier run back into the history, e.g. from a
+    /// saved session, so they are replayed like any other. Those no client got
+    /// are queued again, for as long as the default settings keep them.
+    pub fn restore(&self, notifications: Vec<Recorded>) {
+        let settings = NotificationSettings::default();
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        for recorded in notifications {
+            if !recorded.delivered && !is_state(&recorded.notification.method) {
+                self.enqueue(
+                    Queued::Notification(recorded.notification.clone()),
+                    &settings,
+                );
+            }
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(recorded);
+        }
+        self.revision.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The history as it is now, with its revision
+    pub fn snapshot(&self) -> (u64, Vec<Recorded>) {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        let revision = self.revision.load(Ordering::Relaxed);
+        (revision, history.iter().cloned().collect())
+    }
+
+    /// Connect a client, which gets what `filter` accepts
+    pub fn subscribe(&self, filter: NotificationFilter) -> Subscription {
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        let (requests, requested) = mpsc::unbounded_channel();
+        let subscriber = Arc::new(Subscriber {
+            queue: Mutex::default(),
+            queued: Notify::new(),
+            taken: Notify::new(),
+            filter: filter.clone(),
+            requests,
+            pending: PendingRequests::default(),
+        });
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::downgrade(&subscriber));
+        let receiver = NotificationReceiver { subscriber };
+        let mut replay = replay_set(&history);
+        let mut results = Vec::new();
+        for (_, message) in self.lock_queued().drain(..) {
+            match message {
+                Queued::Notification(notification) => replay.push(notification),
+                Queued::Result(response) => results.push(result_notification(response)),
+            }
+        }
+        let replay = replay
+            .iter()
+            .filter(|notification| filter.accepts(&notification.method))
+            .map(|notification| filter.adapt(notification))
+            .collect();
+        for recorded in history.iter_mut() {
+            recorded.delivered = true;
+        }
+        self.revision.fetch_add(1, Ordering::Relaxed);
+        Subscription {
+            replay,
+            results,
+            receiver,
+            requests: requested,
+        }
+    }
+}
+
+/// A queued tool result, for a client that didn't send the call: the id of the
+/// original request along with its result or error
+fn result_notification(response: MCPResponse) -> JsonRpcNotification {
+    let mut params = serde_json::json!({ "requestId": response.id });
+    if let Some(result) = response.result {
+        params["result"] = result;
+    }
+    if let Some(error) = response.error {
+        params["error"] = serde_json::to_value(error).unwrap_or_default();
+    }
+    JsonRpcNotification {
+        jsonrpc: "2.0".to_string(),
+        method: "tool_result".to_string(),
+        params,
+    }
+}
+
+/// Whether `method` describes the editor's state, which the history replays,
+/// rather than something that happened, which waits in the queue
+fn is_state(method: &str) -> bool {
+    matches!(method, "selection_changed" | "opened_file" | "closed_file")
+}
+
+/// What a new client needs to catch up with the editor's state: the latest
+/// selection and files that are still open
+fn replay_set(history: &VecDeque<Recorded>) -> Vec<JsonRpcNotification> {
+    let latest_selection = history
+        .iter()
+        .rposition(|recorded| recorded.notification.method == "selection_changed");
+
+    // Walk backwards so a later closed_file hides the earlier opened_file, and a
+    // file opened again (e.g. after a restored session) is replayed once
+    let mut settled_files = HashSet::new();
+    let mut replay = Vec::new();
+    for (index, recorded) in history.iter().enumerate().rev() {
+        let notification = &recorded.notification;
+        let file_path = notification.params["filePath"].as_str().unwrap_or_default();
+        let keep = match notification.method.as_str() {
+            "selection_changed" => Some(index) == latest_selection,
+            "closed_file" => {
+                settled_files.insert(file_path.to_string());
+                false
+            }
+            "opened_file" => settled_files.insert(file_path.to_string()),
+            _ => false,
+        };
+        if keep {
+            replay.push(notification.clone());
+        }
+    }
+
+    replay.reverse();
+    replay
+}
+
+/// Coalescing applied by NotificationCoalescer and client queueing on the bus,
+/// under `notifications` in the settings
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationSettings {
+    /// Notifications arriving this soon after one of the same method was sent are
+    /// held, merged and sent together when the window ends; 0 disables merging
+    pub coalesce_window_ms: u64,
+    /// Notifications of one method sent per second at most; 0 for no limit
+    pub max_per_second: usize,
+    /// Notifications queued per client before `overflow` applies
+    pub channel_capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// How long a notification no client got, or a tool result whose client
+    /// disconnected, waits for the next client
+    pub queue_ttl_ms: u64,
+    /// Messages kept for the next client at most; the oldest go first
+    pub queue_capacity: usize,
+    /// Where every notification is copied to as well
+    pub sinks: Vec<SinkSettings>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            coalesce_window_ms: 100,
+            max_per_second: 20,
+            channel_capacity: 100,
+            overflow: OverflowPolicy::default(),
+            queue_ttl_ms: 10 * 60 * 1000,
+            queue_capacity: 100,
+            sinks: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct StreamState {
+    /// When notifications were sent within the last RATE_PERIOD, oldest first
+    sent: VecDeque<Instant>,
+    /// Method and params of the last notification sent
+    last: Option<(String, Value)>,
+    /// Held notifications by coalescing key, in arrival order
+    pending: Vec<(String, JsonRpcNotification)>,
+    flush_scheduled: bool,
+}
+
+impl StreamState {
+    fn is_duplicate(&self, notification: &JsonRpcNotification, now: Instant) -> bool {
+        let recent = self
+            .sent
+            .back()
+            .is_some_and(|&sent| now.duration_since(sent) < DUPLICATE_WINDOW);
+        recent
+            && self.last.as_ref().is_some_and(|(method, params)| {
+                *method == notification.method && *params == notification.params
+            })
+    }
+
+    /// Earliest time the rate limit allows another send
+    fn rate_ready_at(&mut self, now: Instant, settings: &NotificationSettings) -> Instant {
+        while self
+            .sent
+            .front()
+            .is_some_and(|&sent| now.duration_since(sent) >= RATE_PERIOD)
+        {
+            self.sent.pop_front();
+        }
+        match self.sent.front() {
+            Some(&oldest)
+                if settings.max_per_second > 0 && self.sent.len() >= settings.max_per_second =>
+            {
+                oldest + RATE_PERIOD
+            }
+            _ => now,
+        }
+    }
+
+    /// Earliest time a newly arrived notification may go out
+    fn ready_at(&mut self, now: Instant, settings: &NotificationSettings) -> Instant {
+        let window_end = match self.sent.back() {
+            Some(&sent) => sent + Duration::from_millis(settings.coalesce_window_ms),
+            None => now,
+        };
+        self.rate_ready_at(now, settings).max(window_end)
+    }
+
+    fn record(&mut self, now: Instant, notification: &JsonRpcNotification) {
+        self.sent.push_back(now);
+        self.last = Some((notification.method.clone(), notification.params.clone()));
+    }
+}
+
+/// Sits in front of the bus so bursts from adjacent editor events (a code action
+/// and a selection range for the same selection, say) reach Claude as one
+/// notification. Per method, it drops repeats of the last payload sent, holds
+/// whatever arrives within the coalescing window and sends it when the window
+/// ends, merged by coalescing key, and stays under `maxPerSecond`. opened_file and
+/// closed_file are handled as one method so they can't overtake each other. What
+/// it sends is counted in `metrics`, and the editor warned of payloads over
+/// `contextBudget.warnTokens`.
+#[derive(Debug)]
+pub struct NotificationCoalescer {
+    bus: SharedNotificationBus,
+    config: SharedConfig,
+    metrics: SharedMetrics,
+    client: Client,
+    /// Held across sends, so a client that blocks the bus also holds up the
+    /// notifications queued behind it
+    streams: tokio::sync::Mutex<HashMap<&'static str, StreamState>>,
+}
+
+pub type SharedNotificationCoalescer = Arc<NotificationCoalescer>;
+
+impl NotificationCoalescer {
+    /// Settings are read from `config` on every send so changes apply live
+    pub fn new(
+        bus: SharedNotificationBus,
+        config: SharedConfig,
+        metrics: SharedMetrics,
+        client: Client,
+    ) -> Self {
+        Self {
+            bus,
+            config,
+            metrics,
+            client,
+            streams: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn send(self: &Arc<Self>, notification: JsonRpcNotification) {
+        let settings = self.config.read().await.notifications.clone();
+        let now = Instant::now();
+
+        let stream = stream(&notification.method);
+        let flush_at = {
+            let mut streams = self.streams.lock().await;
+            let state = streams.entry(stream).or_default();
+            if state.is_duplicate(&notification, now) {
+                debug!("Dropping duplicate {} notification", notification.method);
+                return;
+            }
+
+            if state.pending.is_empty() && state.ready_at(now, &settings) <= now {
+                state.record(now, &notification);
+                self.deliver(notification, &settings).await;
+                return;
+            }
+
+            // Held until the window ends; a later notification with the same key
+            // replaces the held one
+            let key = coalescing_key(&notification);
+            match state.pending.iter_mut().find(|(held, _)| *held == key) {
+                Some((_, held)) => *held = notification,
+                None => state.pending.push((key, notification)),
+            }
+            if state.flush_scheduled {
+                None
+            } else {
+                state.flush_scheduled = true;
+                Some(state.ready_at(now, &settings))
+            }
+        };
+
+        if let Some(at) = flush_at {
+            self.schedule_flush(stream, at);
+        }
+    }
+
+    fn schedule_flush(self: &Arc<Self>, stream: &'static str, at: Instant) {
+        let coalescer = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(at).await;
+            coalescer.flush(stream).await;
+        });
+    }
+
+    /// Send the notifications held for `stream`, as many as the rate limit allows
+    async fn flush(self: &Arc<Self>, stream: &'static str) {
+        let settings = self.config.read().await.notifications.clone();
+        let now = Instant::now();
+
+        let retry_at = {
+            let mut streams = self.streams.lock().await;
+            let Some(state) = streams.get_mut(stream) else {
+                return;
+            };
+            let mut held = std::mem::take(&mut state.pending).into_iter();
+            while state.rate_ready_at(now, &settings) <= now {
+                let Some((_, notification)) = held.next() else {
+                    break;
+                };
+                if state.is_duplicate(&notification, now) {
+                    debug!("Dropping duplicate {} notification", notification.method);
+                    continue;
+                }
+                state.record(now, &notification);
+                self.deliver(notification, &settings).await;
+            }
+
+            state.pending = held.collect();
+            if state.pending.is_empty() {
+                state.flush_scheduled = false;
+                None
+            } else {
+                debug!(
+                    "Rate limiting {}, {} notifications held",
+                    stream,
+                    state.pending.len()
+                );
+                Some(state.rate_ready_at(now, &settings))
+            }
+        };
+
+        if let Some(at) = retry_at {
+            self.schedule_flush(stream, at);
+        }
+    }
+
+    async fn deliver(&self, notification: JsonRpcNotification, settings: &NotificationSettings) {
+        let method = notification.method.clone();
+        let bytes = notification.params.to_string().len();
+        let tokens = self.metrics.context_sent(&method, bytes);
+        let warning = self
+            .config
+            .read()
+            .await
+            .context_budget
+            .size_warning(&method, tokens);
+        if let Some(warning) = warning {
+            warn!("{}", warning);
+            // Not awaited under the stream's lock
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                client
+                    .show_message(MessageType::WARNING, format!("Claude Code: {}", warning))
+                    .await;
+            });
+        }
+        if !self.bus.send(notification, settings).await {
+            debug!("No client connected, {} kept for the next one", method);
+        }
+    }
+}
+
+/// Notifications sharing a stream are coalesced and rate limited together
+fn stream(method: &str) -> &'static str {
+    match method {
+        "selection_changed" => "selection",
+        "opened_file" | "closed_file" => "files",
+        "at_mentioned" => "mentions",
+        _ => "other",
+    }
+}
+
+/// Held notifications with the same key are merged, keeping the latest: the
+/// latest selection, the latest open/close of each file, and otherwise only
+/// identical payloads.
+fn coalescing_key(notification: &JsonRpcNotification) -> String {
+    match notification.method.as_str() {
+        "selection_changed" => String::new(),
+        "opened_file" | "closed_file" => notification.params["filePath"].to_string(),
+        _ => format!("{} {}", notification.method, notification.params),
+    }
+}
@@ -0,0 +1,61 @@
+use percent_encoding::percent_decode_str;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::Url;
+
+use crate::workspace;
+
+// File paths as they are sent to Claude. URIs are decoded with
+// Url::to_file_path, so spaces and non-ASCII names come out as the file system
+// has them rather than percent-encoded. Windows drive letters keep their place
+// on any host: file:///C:/src/main.rs is C:/src/main.rs (C:\src\main.rs on
+// Windows), and `to_url` turns that back into the same URI.
+
+/// The file `uri` points at. URIs that aren't file paths (untitled buffers)
+/// give their decoded path.
+pub fn file_path(uri: &Url) -> PathBuf {
+    match uri.to_file_path() {
+        Ok(path) if cfg!(windows) => path,
+        Ok(path) => match drive_path(&path.to_string_lossy()) {
+            Some(path) => PathBuf::from(path),
+            None => path,
+        },
+        Err(()) => PathBuf::from(percent_decode_str(uri.path()).decode_utf8_lossy().as_ref()),
+    }
+}
+
+/// `file_path` as a string, as notifications carry it
+pub fn path_string(uri: &Url) -> String {
+    file_path(uri).to_string_lossy().to_string()
+}
+
+/// The file URI of `path`, absolute or with a drive letter
+pub fn to_url(path: &Path) -> Option<Url> {
+    if cfg!(windows) {
+        return Url::from_file_path(path).ok();
+    }
+    let text = path.to_string_lossy();
+    if has_drive_letter(&text) {
+        return Url::from_file_path(format!("/{}", text.replace('\\', "/"))).ok();
+    }
+    Url::from_file_path(path).ok()
+}
+
+/// `path` relative to the workspace folder `root`, with forward slashes
+pub fn relative(path: &Path, root: &Path) -> Option<String> {
+    path.strip_prefix(root)
+        .ok()
+        .map(workspace::display_relative)
+}
+
+/// "C:/src" for the "/C:/src" that drive-letter URIs decode to off Windows
+fn drive_path(path: &str) -> Option<&str> {
+    path.strip_prefix('/').filter(|rest| has_drive_letter(rest))
+}
+
+fn has_drive_letter(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes.get(2), None | Some(b'/' | b'\\'))
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+// The user's say over tools that are hard to undo: running a shell command,
+// applying edits, and creating, deleting or renaming a file. The editor asks with
+// window/showMessageRequest; "Allow always" is remembered per tool in
+// <workspace>/.claude/ide-permissions.json, and later calls go ahead unasked.
+
+const PERMISSIONS_FILE: &str = ".claude/ide-permissions.json";
+const FORMAT_VERSION: u32 = 1;
+
+/// Tools that only run with the user's permission, named as over MCP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    RunCommand,
+    ApplyEdits,
+    CreateFile,
+    DeleteFile,
+    RenameFile,
+}
+
+/// The user's answer, reported to Claude as `permission` in the tool result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Decision {
+    Allow,
+    /// Also the answer when an earlier "Allow always" applies
+    AllowAlways,
+    Deny,
+}
+
+impl Decision {
+    /// The prompt's buttons, in order
+    pub const ALL: [Decision; 3] = [Decision::Allow, Decision::AllowAlways, Decision::Deny];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Decision::Allow => "Allow",
+            Decision::AllowAlways => "Allow always",
+            Decision::Deny => "Deny",
+        }
+    }
+
+    /// The decision behind a chosen button; anything else, or dismissing the
+    /// prompt, denies
+    pub fn from_title(title: Option<&str>) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|decision| Some(decision.title()) == title)
+            .unwrap_or(Decision::Deny)
+    }
+
+    pub fn allowed(self) -> bool {
+        self != Decision::Deny
+    }
+}
+
+/// What Claude asks to do, as shown to the user
+#[derive(Debug, Clone)]
+pub struct PermissionRequest {
+    pub action: Action,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PermissionsFile {
+    version: u32,
+    always_allow: BTreeSet<Action>,
+}
+
+/// Tools the user always allows. Without a workspace they are only remembered
+/// until the server stops.
+#[derive(Debug, Default)]
+pub struct Permissions {
+    path: Option<PathBuf>,
+    always_allow: Mutex<BTreeSet<Action>>,
+}
+
+pub type SharedPermissions = Arc<Permissions>;
+
+impl Permissions {
+    /// The decisions saved in `workspace`, if any, kept there from now on
+    pub fn load(workspace: &Path) -> Self {
+        let path = workspace.join(PERMISSIONS_FILE);
+        let always_allow = match fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<PermissionsFile>(&text) {
+                Ok(file) if file.version == FORMAT_VERSION => file.always_allow,
+                Ok(file) => {
+                    warn!(
+                        "Ignoring permissions {} with unsupported version {}",
+                        path.display(),
+                        file.version
+                    );
+                    BTreeSet::new()
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid permissions {}: {}", path.display(), e);
+                    BTreeSet::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => {
+                warn!("Failed to read permissions {}: {}", path.display(), e);
+                BTreeSet::new()
+            }
+        };
+        Self {
+            path: Some(path),
+            always_allow: Mutex::new(always_allow),
+        }
+    }
+
+    pub fn always_allowed(&self, action: Action) -> bool {
+        self.lock().contains(&action)
+    }
+
+    /// Remember "Allow always" for `action` and save it
+    pub fn allow_always(&self, action: Action) {
+        let file = {
+            let mut always_allow = self.lock();
+            if !always_allow.insert(action) {
+                return;
+            }
+            PermissionsFile {
+                version: FORMAT_VERSION,
+                always_allow: always_allow.clone(),
+            }
+        };
+        let Some(path) = &self.path else {
+            return;
+        };
+        info!("Always allowing {:?}, saved to {}", action, path.display());
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let json = serde_json::to_string_pretty(&file).map_err(std::io::Error::other)?;
+                fs::write(path, json)
+            });
+        if let Err(e) = result {
+            warn!("Failed to save permissions {}: {}", path.display(), e);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<Action>> {
+        self.always_allow.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,76 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+// Keeps the contents of sensitive files out of the selections sent to Claude.
+// Selections in a matching file still report where they are, but their text is
+// replaced with a marker. Patterns are gitignore-style globs matched against the
+// path relative to its workspace folder.
+
+/// Sent instead of the text of a selection in a sensitive file
+pub const REDACTED: &str = "[redacted]";
+/// Environment files and secrets directories, redacted unless turned off
+const DEFAULT_PATTERNS: [&str; 3] = [".env", ".env.*", "secrets/"];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrivacySettings {
+    /// Redact .env files and secrets/ directories
+    pub default_patterns: bool,
+    /// Further globs of files whose contents are never sent
+    pub redact: Vec<String>,
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        Self {
+            default_patterns: true,
+            redact: Vec::new(),
+        }
+    }
+}
+
+impl PrivacySettings {
+    /// Whether selections in `path` must not carry their text. `root` is the
+    /// workspace folder containing it, if any.
+    pub fn is_sensitive(&self, path: &Path, root: Option<&Path>) -> bool {
+        let defaults: &[&str] = if self.default_patterns {
+            &DEFAULT_PATTERNS
+        } else {
+            &[]
+        };
+        let patterns = defaults
+            .iter()
+            .copied()
+            .chain(self.redact.iter().map(String::as_str));
+        let matcher = matcher(patterns);
+        if matcher.is_empty() {
+            return false;
+        }
+
+        // Outside a workspace folder the path is matched from the filesystem root
+        let relative: PathBuf = root
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        matcher
+            .matched_path_or_any_parents(relative, false)
+            .is_ignore()
+    }
+}
+
+fn matcher<'a>(patterns: impl Iterator<Item = &'a str>) -> Gitignore {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        if let Err(e) = builder.add_line(None, pattern) {
+            warn!("Ignoring invalid redaction pattern {:?}: {}", pattern, e);
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        warn!("Ignoring redaction patterns: {}", e);
+        Gitignore::empty()
+    })
+}
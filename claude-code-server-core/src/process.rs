@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{watch, Mutex};
+use tracing::{debug, error, info, warn};
+
+use crate::config::SharedConfig;
+
+// Supervises a `claude` CLI connected to this server's WebSocket: spawns it with the
+// IDE integration environment, polls it for liveness and restarts it with
+// exponential backoff until stopped
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// A process that stayed up this long is considered healthy again, resetting backoff
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Supervisor {
+    child: Mutex<Option<Child>>,
+    stop: watch::Sender<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClaudeProcessManager {
+    config: SharedConfig,
+    working_dir: Option<PathBuf>,
+    supervisor: Arc<Supervisor>,
+}
+
+impl ClaudeProcessManager {
+    pub fn new(working_dir: Option<PathBuf>, config: SharedConfig) -> Self {
+        Self {
+            config,
+            working_dir,
+            supervisor: Arc::new(Supervisor {
+                child: Mutex::new(None),
+                stop: watch::Sender::new(false),
+            }),
+        }
+    }
+
+    /// Start supervising in the background a CLI connecting to the server on
+    /// `port`
+    pub fn start(&self, port: u16) {
+        let manager = self.clone();
+        tokio::spawn(async move { manager.supervise(port).await });
+    }
+
+    /// Stop supervising and kill the running process, if any
+    pub async fn stop(&self) {
+        self.supervisor.stop.send_replace(true);
+        self.kill_child().await;
+    }
+
+    async fn kill_child(&self) {
+        if let Some(mut child) = self.supervisor.child.lock().await.take() {
+            info!("Stopping claude (pid {:?})", child.id());
+            if let Err(e) = child.kill().await {
+                warn!("Failed to kill claude: {}", e);
+            }
+        }
+    }
+
+    /// Whether a claude process is up right now, rather than between restarts
+    pub async fn is_running(&self) -> bool {
+        self.supervisor.child.lock().await.is_some()
+    }
+
+    fn stopping(&self) -> bool {
+        *self.supervisor.stop.borrow()
+    }
+
+    /// Command line for the CLI, using the binary and model configured right now
+    async fn command(&self, port: u16) -> Command {
+        let config = self.config.read().await;
+        let mut command = Command::new(&config.claude_binary);
+        if let Some(model) = &config.model {
+            command.args(["--model", model]);
+        }
+        command
+            .arg("--ide")
+            .env("CLAUDE_CODE_SSE_PORT", port.to_string())
+            .env("ENABLE_IDE_INTEGRATION", "true")
+            // stdout belongs to the LSP transport, so the CLI must never inherit it
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    async fn supervise(&self, port: u16) {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut stop = self.supervisor.stop.subscribe();
+
+        while !self.stopping() {
+            match self.command(port).await.spawn() {
+                Ok(mut child) => {
+                    info!("Started claude (pid {:?}) for port {}", child.id(), port);
+                    if let Some(stderr) = child.stderr.take() {
+                        tokio::spawn(async move {
+                            let mut lines = BufReader::new(stderr).lines();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                debug!("claude: {}", line);
+                            }
+                        });
+                    }
+
+                    let started = Instant::now();
+                    *self.supervisor.child.lock().await = Some(child);
+
+                    match self.wait_for_exit().await {
+                        Some(status) => warn!("claude exited with {}", status),
+                        None => {
+                            // Covers a stop() that raced with the spawn above
+                            self.kill_child().await;
+                            break;
+                        }
+                    }
+                    if started.elapsed() >= STABLE_AFTER {
+                        backoff = INITIAL_BACKOFF;
+                    }
+                }
+                Err(e) => error!("Failed to start claude: {}", e),
+            }
+
+            if self.stopping() {
+                break;
+            }
+            info!("Restarting claude in {:?}", backoff);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = stop.changed() => break,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        info!("Stopped supervising claude");
+    }
+
+    /// Health check loop: poll the child until it exits (returning its status) or
+    /// supervision is stopped (returning None)
+    async fn wait_for_exit(&self) -> Option<ExitStatus> {
+        let mut stop = self.supervisor.stop.subscribe();
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(HEALTH_CHECK_INTERVAL) => {}
+                _ = stop.changed() => return None,
+            }
+
+            let mut child = self.supervisor.child.lock().await;
+            let status = match child.as_mut()?.try_wait() {
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("Health check for claude failed: {}", e);
+                    continue;
+                }
+            };
+            if let Some(status) = status {
+                child.take();
+                return Some(status);
+            }
+        }
+    }
+}
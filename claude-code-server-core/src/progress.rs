@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tower_lsp::lsp_types::notification::Progress;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::{
+    NumberOrString, ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress,
+    WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkDoneProgressReport,
+};
+use tower_lsp::Client;
+use tracing::debug;
+use uuid::Uuid;
+
+// Longest tail of streamed text shown in a single progress report
+const MAX_REPORT_CHARS: usize = 120;
+
+/// A $/progress (work done) session. Inactive when the client supports neither
+/// client-initiated tokens nor window/workDoneProgress/create, in which case every
+/// call is a no-op.
+pub struct WorkDone {
+    client: Client,
+    token: Option<ProgressToken>,
+    /// Where the token is registered when the client may cancel through
+    /// window/workDoneProgress/cancel
+    cancellable: Option<SharedCancellableWork>,
+}
+
+impl WorkDone {
+    /// Start reporting progress, using the token the client sent with the request
+    /// if any, or creating a server-initiated one when `can_create` is set. With
+    /// `cancellable`, the client can cancel it until it ends.
+    pub async fn begin(
+        client: &Client,
+        token: Option<ProgressToken>,
+        can_create: bool,
+        title: &str,
+        cancellable: Option<(&SharedCancellableWork, &Cancellation)>,
+    ) -> Self {
+        let token = match token {
+            Some(token) => Some(token),
+            None if can_create => {
+                let token = NumberOrString::String(Uuid::new_v4().to_string());
+                let params = WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                };
+                match client.send_request::<WorkDoneProgressCreate>(params).await {
+                    Ok(()) => Some(token),
+                    Err(e) => {
+                        debug!("Client refused a progress token: {}", e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let cancellable = match (&token, cancellable) {
+            (Some(token), Some((work, cancellation))) => {
+                work.register(token.clone(), cancellation.clone());
+                Some(work.clone())
+            }
+            _ => None,
+        };
+        let work_done = Self {
+            client: client.clone(),
+            token,
+            cancellable,
+        };
+        work_done
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                cancellable: Some(work_done.cancellable.is_some()),
+                message: None,
+                percentage: None,
+            }))
+            .await;
+        work_done
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Report the text received so far, showing only the end of its last line
+    pub async fn report_text(&self, text: &str) {
+        let last_line = text.trim_end().lines().last().unwrap_or_default();
+        let skip = last_line.chars().count().saturating_sub(MAX_REPORT_CHARS);
+        let message: String = last_line.chars().skip(skip).collect();
+
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(self.cancellable.is_some()),
+            message: Some(message),
+            percentage: None,
+        }))
+        .await;
+    }
+
+    /// Report `message`, with how far along the work is when that is known
+    pub async fn report(&self, message: String, percentage: Option<u32>) {
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(self.cancellable.is_some()),
+            message: Some(message),
+            percentage,
+        }))
+        .await;
+    }
+
+    pub async fn end(self, message: Option<String>) {
+        if let (Some(work), Some(token)) = (&self.cancellable, &self.token) {
+            work.finish(token);
+        }
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd { message }))
+            .await;
+    }
+
+    async fn send(&self, progress: WorkDoneProgress) {
+        if let Some(token) = &self.token {
+            self.client
+                .send_notification::<Progress>(ProgressParams {
+                    token: token.clone(),
+                    value: ProgressParamsValue::WorkDone(progress),
+                })
+                .await;
+        }
+    }
+}
+
+/// Cancellation of a Claude request: by the client, through $/cancelRequest (which
+/// drops the request handler) or window/workDoneProgress/cancel
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Cancellation {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.sender
+            .send_if_modified(|cancelled| !std::mem::replace(cancelled, true));
+    }
+
+    /// Resolves once cancelled
+    pub async fn cancelled(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as self, so this can't fail
+        let _ = receiver.wait_for(|&cancelled| cancelled).await;
+    }
+
+    /// Guard that cancels when dropped, e.g. along with a request handler
+    /// tower-lsp drops on $/cancelRequest
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(self.clone())
+    }
+}
+
+pub struct CancelOnDrop(Cancellation);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// Running work the client can cancel, by progress token
+#[derive(Debug, Default)]
+pub struct CancellableWork {
+    by_token: Mutex<HashMap<ProgressToken, Cancellation>>,
+}
+
+pub type SharedCancellableWork = Arc<CancellableWork>;
+
+impl CancellableWork {
+    /// Make `cancellation` reachable through `token` until `finish`
+    fn register(&self, token: ProgressToken, cancellation: Cancellation) {
+        self.lock().insert(token, cancellation);
+    }
+
+    fn finish(&self, token: &ProgressToken) {
+        self.lock().remove(token);
+    }
+
+    /// Cancel the work behind `token`, returning whether there was any
+    pub fn cancel(&self, token: &ProgressToken) -> bool {
+        match self.lock().remove(token) {
+            Some(cancellation) => {
+                cancellation.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ProgressToken, Cancellation>> {
+        self.by_token.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tower_lsp::lsp_types::{CodeActionKind, Diagnostic, NumberOrString};
+use tracing::{debug, info, warn};
+
+use crate::commands;
+
+// Prompt templates behind the Claude code actions and their executeCommand
+// entries. explain, improve, fix and write-tests are built in; a worktree can override them,
+// or add its own, with Markdown files in .claude/commands (the directory the
+// Claude CLI reads custom slash commands from). The file name is the template
+// name, and optional front matter sets how it shows up in the editor:
+//
+//     ---
+//     title: Document with Claude
+//     edits: true
+//     kind: refactor.rewrite
+//     ---
+//     Add doc comments to the following code from {{file}}.
+//
+//     ```
+//     {{code}}
+//     ```
+
+const COMMANDS_DIR: &str = ".claude/commands";
+pub const COMMAND_PREFIX: &str = "claude-code.";
+
+const EXPLAIN: &str = "Explain what the following code from {{file}} does. Be concise.\n\n\
+                       ```\n{{code}}\n```";
+const IMPROVE: &str = "Improve the following code from {{file}} (readability, performance, \
+                       idiomatic style) without changing its behavior. Reply with only the \
+                       replacement code in a single fenced code block.\n\n```\n{{code}}\n```";
+const WRITE_TESTS: &str = "Write unit tests for the following code from {{file}}, following the \
+                           testing conventions of its language and project. Reply with only the \
+                           test code in a single fenced code block.\n\n```\n{{code}}\n```";
+const FIX: &str = "Find and fix any bugs in the following code from {{file}}. Reply with only \
+                   the corrected code in a single fenced code block.\n\n```\n{{code}}\n```";
+
+/// Declarations surrounding the code a template runs on
+#[derive(Debug, Clone)]
+pub struct Enclosing {
+    /// Enclosing symbols, outermost first, e.g. "`Server` > `run`"
+    pub path: String,
+    /// Text of the innermost one, unless the code already is the whole declaration
+    pub text: Option<String>,
+}
+
+/// A prompt Claude can be asked to run on a range of code
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    pub name: String,
+    /// Code action and progress title
+    pub title: String,
+    pub kind: CodeActionKind,
+    /// Whether Claude's answer replaces the selected code (as opposed to being shown)
+    pub edits_code: bool,
+    /// Prompt text, with {{file}} and {{code}} placeholders
+    body: String,
+}
+
+impl PromptTemplate {
+    fn builtin(
+        name: &str,
+        title: &str,
+        kind: CodeActionKind,
+        edits_code: bool,
+        body: &str,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            title: title.to_string(),
+            kind,
+            edits_code,
+            body: body.to_string(),
+        }
+    }
+
+    /// Whether Claude's answer goes into a test file (see test_files.rs) rather
+    /// than being shown
+    pub fn writes_tests(&self) -> bool {
+        self.name == "write-tests" && !self.edits_code
+    }
+
+    pub fn command(&self) -> String {
+        format!("{}{}", COMMAND_PREFIX, self.name)
+    }
+
+    /// Prompt for this template on `code`, with the declaration around it (if any)
+    /// appended as reference. A template without {{code}} gets the code appended.
+    pub fn prompt(&self, file_path: &str, code: &str, enclosing: Option<&Enclosing>) -> String {
+        let mut prompt = render(&self.body, file_path, code);
+        if !self.body.contains("{{code}}") {
+            prompt.push_str(&format!("\n\n```\n{}\n```", code));
+        }
+
+        if let Some(enclosing) = enclosing {
+            prompt.push_str(&format!("\n\nThe code is inside {}.", enclosing.path));
+            if let Some(text) = &enclosing.text {
+                prompt.push_str(&format!(
+                    " For reference only (don't include it in your reply), here is the whole \
+                     declaration:\n\n```\n{}\n```",
+                    text
+                ));
+            }
+        }
+        prompt
+    }
+
+    /// `prompt`, told which problem to fix: the diagnostic a "Fix with Claude"
+    /// quick fix was offered for
+    pub fn prompt_for_diagnostic(
+        &self,
+        file_path: &str,
+        code: &str,
+        enclosing: Option<&Enclosing>,
+        diagnostic: &Diagnostic,
+    ) -> String {
+        let mut prompt = self.prompt(file_path, code, enclosing);
+        let source = diagnostic.source.as_deref().unwrap_or("the editor");
+        prompt.push_str(&format!(
+            "\n\nFix this problem {} reports on line {}: {}",
+            source,
+            diagnostic.range.start.line + 1,
+            diagnostic.message
+        ));
+        match &diagnostic.code {
+            Some(NumberOrString::String(code)) => prompt.push_str(&format!(" ({})", code)),
+            Some(NumberOrString::Number(code)) => prompt.push_str(&format!(" ({})", code)),
+            None => {}
+        }
+        prompt
+    }
+
+    /// Parse a template file, on top of the built-in template it overrides (if any)
+    fn parse(name: &str, text: &str, base: Option<&PromptTemplate>) -> Self {
+        let (front_matter, body) = split_front_matter(text);
+        let mut template = match base {
+            Some(base) => base.clone(),
+            None => Self {
+                name: name.to_string(),
+                title: default_title(name),
+                kind: CodeActionKind::REFACTOR,
+                edits_code: false,
+                body: String::new(),
+            },
+        };
+        template.body = body.trim().to_string();
+
+        let mut kind = None;
+        for (key, value) in front_matter {
+            match key {
+                // `description` is what Claude CLI slash commands use
+                "title" | "description" => template.title = value.to_string(),
+                "edits" => match value {
+                    "true" => template.edits_code = true,
+                    "false" => template.edits_code = false,
+                    _ => warn!("Prompt template {}: edits must be true or false", name),
+                },
+                "kind" => kind = Some(value),
+                _ => debug!("Prompt template {}: ignoring {}", name, key),
+            }
+        }
+
+        template.kind = match kind {
+            Some("quickfix") => CodeActionKind::QUICKFIX,
+            Some("refactor") => CodeActionKind::REFACTOR,
+            Some("refactor.rewrite") => CodeActionKind::REFACTOR_REWRITE,
+            Some(other) => {
+                warn!("Prompt template {}: unknown kind {}", name, other);
+                template.kind
+            }
+            None if base.is_none() && template.edits_code => CodeActionKind::REFACTOR_REWRITE,
+            None => template.kind,
+        };
+        template
+    }
+}
+
+/// The templates available in one worktree: the built-in ones, as overridden,
+/// followed by the worktree's own in name order
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplates {
+    templates: Vec<PromptTemplate>,
+}
+
+impl Default for PromptTemplates {
+    fn default() -> Self {
+        Self {
+            templates: vec![
+                PromptTemplate::builtin(
+                    "explain",
+                    "Explain with Claude",
+                    CodeActionKind::REFACTOR,
+                    false,
+                    EXPLAIN,
+                ),
+                PromptTemplate::builtin(
+                    "improve",
+                    "Improve with Claude",
+                    CodeActionKind::REFACTOR_REWRITE,
+                    true,
+                    IMPROVE,
+                ),
+                PromptTemplate::builtin(
+                    "fix",
+                    "Fix with Claude",
+                    CodeActionKind::QUICKFIX,
+                    true,
+                    FIX,
+                ),
+                PromptTemplate::builtin(
+                    "write-tests",
+                    "Write tests with Claude",
+                    CodeActionKind::REFACTOR,
+                    false,
+                    WRITE_TESTS,
+                ),
+            ],
+        }
+    }
+}
+
+impl PromptTemplates {
+    /// Built-in templates plus overrides from `<root>/.claude/commands/*.md`
+    pub fn load(root: &Path) -> Self {
+        let mut templates = Self::default();
+        let dir = root.join(COMMANDS_DIR);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return templates,
+            Err(e) => {
+                warn!(
+                    "Failed to read prompt templates in {}: {}",
+                    dir.display(),
+                    e
+                );
+                return templates;
+            }
+        };
+
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "md"))
+            .collect();
+        files.sort();
+
+        for path in files {
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if !is_valid_name(name) {
+                warn!("Skipping prompt template {}: invalid name", path.display());
+                continue;
+            }
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    warn!("Failed to read prompt template {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            match templates.templates.iter_mut().find(|t| t.name == name) {
+                Some(existing) => {
+                    info!("Prompt template {} overridden by {}", name, path.display());
+                    *existing = PromptTemplate::parse(name, &text, Some(existing));
+                }
+                None => {
+                    info!("Prompt template {} loaded from {}", name, path.display());
+                    let template = PromptTemplate::parse(name, &text, None);
+                    templates.templates.push(template);
+                }
+            }
+        }
+        templates
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PromptTemplate> {
+        self.templates.iter().find(|template| template.name == name)
+    }
+
+    /// Template behind an executeCommand command, e.g. `claude-code.explain`
+    pub fn for_command(&self, command: &str) -> Option<&PromptTemplate> {
+        self.get(command.strip_prefix(COMMAND_PREFIX)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PromptTemplate> {
+        self.templates.iter()
+    }
+}
+
+/// Templates per worktree, loaded on first use and reloaded after their
+/// directory changes
+#[derive(Debug, Default)]
+pub struct PromptLibrary {
+    by_root: Mutex<HashMap<PathBuf, Arc<PromptTemplates>>>,
+}
+
+impl PromptLibrary {
+    /// Templates for files under `root`, or the built-in ones outside any worktree
+    pub fn for_root(&self, root: Option<&Path>) -> Arc<PromptTemplates> {
+        let Some(root) = root else {
+            return Arc::new(PromptTemplates::default());
+        };
+        if let Some(templates) = self.lock().get(root) {
+            return templates.clone();
+        }
+
+        let templates = Arc::new(PromptTemplates::load(root));
+        self.lock().insert(root.to_path_buf(), templates.clone());
+        templates
+    }
+
+    /// Forget the templates of any worktree whose template directory `path` is
+    /// in (or contains), so they are loaded again on next use
+    pub fn changed(&self, path: &Path) {
+        self.lock().retain(|root, _| {
+            let affected = affects_templates(root, path);
+            if affected {
+                debug!(
+                    "Prompt templates in {} changed",
+                    root.join(COMMANDS_DIR).display()
+                );
+            }
+            !affected
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Arc<PromptTemplates>>> {
+        self.by_root.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether a change to `path` can change the templates of the worktree at `root`:
+/// `path` is in its template directory, or contains it
+pub fn affects_templates(root: &Path, path: &Path) -> bool {
+    let dir = root.join(COMMANDS_DIR);
+    path.starts_with(&dir) || dir.starts_with(path)
+}
+
+/// Template names become command names, so they stick to what those allow
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        // The server's own commands can't be taken over
+        && !commands::is_builtin(&format!("{}{}", COMMAND_PREFIX, name))
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// "add-docs" -> "Add docs with Claude"
+fn default_title(name: &str) -> String {
+    let words = name.replace(['-', '_'], " ");
+    let mut chars = words.chars();
+    let capitalized = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    format!("{} with Claude", capitalized)
+}
+
+/// `key: value` pairs between leading `---` lines, and the text after them
+fn split_front_matter(text: &str) -> (Vec<(&str, &str)>, &str) {
+    let mut lines = text.split_inclusive('\n');
+    if lines.next().map(str::trim_end) != Some("---") {
+        return (Vec::new(), text);
+    }
+
+    let mut offset = text.find('\n').map_or(text.len(), |index| index + 1);
+    let mut pairs = Vec::new();
+    for line in lines {
+        offset += line.len();
+        if line.trim_end() == "---" {
+            return (pairs, &text[offset..]);
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            let unquoted = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            pairs.push((key.trim(), unquoted));
+        }
+    }
+    // No closing line, so it wasn't front matter after all
+    (Vec::new(), text)
+}
+
+/// Fill in {{file}} and {{code}} in one pass, so placeholders in the code itself
+/// are left alone
+fn render(body: &str, file_path: &str, code: &str) -> String {
+    let mut rendered = String::with_capacity(body.len() + code.len());
+    let mut rest = body;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        if let Some(after) = placeholder.strip_prefix("{{file}}") {
+            rendered.push_str(file_path);
+            rest = after;
+        } else if let Some(after) = placeholder.strip_prefix("{{code}}") {
+            rendered.push_str(code);
+            rest = after;
+        } else {
+            rendered.push_str("{{");
+            rest = &placeholder[2..];
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
@@ -0,0 +1,378 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tower_lsp::lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, Hover, HoverContents, HoverParams, MarkedString, Position,
+    PositionEncodingKind, PublishDiagnosticsParams, Url,
+};
+use tracing::{debug, info, warn};
+
+use crate::diagnostics::SharedDiagnostics;
+use crate::documents::SharedDocuments;
+use crate::lsp::LspTransport;
+use crate::shutdown::Shutdown;
+use crate::text::PositionEncoding;
+
+// Proxy mode: the server sits between the editor and the project's own language
+// server (rust-analyzer, tsserver, ...), passing every message through unchanged
+// in both directions. Along the way it watches the traffic for what Claude can
+// use: the documents the editor opens and edits, the diagnostics the language
+// server publishes, the hovers it answers and the edits it asks the editor to
+// apply. Documents and diagnostics land where the companion LSP server would put
+// them, so getOpenEditors and getDiagnostics work as usual; hovers and edits are
+// kept here for getLanguageServerActivity.
+
+// Hovers and edits kept; the oldest are forgotten first
+const MAX_HOVERS: usize = 20;
+const MAX_EDITS: usize = 20;
+
+/// A hover the language server answered
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HoverRecord {
+    pub uri: Url,
+    pub position: Position,
+    /// The hover's contents as text, markdown where the server sent markdown
+    pub contents: String,
+    /// Milliseconds since the Unix epoch
+    pub answered_at: u64,
+}
+
+/// An edit the language server asked the editor to apply with workspace/applyEdit
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The WorkspaceEdit as sent
+    pub edit: Value,
+    /// Milliseconds since the Unix epoch
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    hovers: VecDeque<HoverRecord>,
+    edits: VecDeque<EditRecord>,
+    /// Hover requests from the editor waiting for an answer, by request id
+    pending_hovers: HashMap<String, (Url, Position)>,
+}
+
+/// What the language server behind the proxy told the editor recently
+#[derive(Debug, Default)]
+pub struct LanguageServerActivity {
+    activity: Mutex<Activity>,
+}
+
+pub type SharedLanguageServerActivity = Arc<LanguageServerActivity>;
+
+impl LanguageServerActivity {
+    /// Recent hovers and edits, newest first
+    pub fn snapshot(&self) -> (Vec<HoverRecord>, Vec<EditRecord>) {
+        let activity = self.lock();
+        (
+            activity.hovers.iter().rev().cloned().collect(),
+            activity.edits.iter().rev().cloned().collect(),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn hover_requested(&self, id: &Value, params: HoverParams) {
+        let position = params.text_document_position_params;
+        self.lock().pending_hovers.insert(
+            id.to_string(),
+            (position.text_document.uri, position.position),
+        );
+    }
+
+    fn hover_answered(&self, id: &Value, result: Option<&Value>) {
+        let mut activity = self.lock();
+        let Some((uri, position)) = activity.pending_hovers.remove(&id.to_string()) else {
+            return;
+        };
+        let Some(hover) =
+            result.and_then(|result| serde_json::from_value::<Hover>(result.clone()).ok())
+        else {
+            return;
+        };
+        let contents = hover_text(hover.contents);
+        if contents.trim().is_empty() {
+            return;
+        }
+        activity.hovers.push_back(HoverRecord {
+            uri,
+            position,
+            contents,
+            answered_at: now_millis(),
+        });
+        if activity.hovers.len() > MAX_HOVERS {
+            activity.hovers.pop_front();
+        }
+    }
+
+    fn edit_requested(&self, params: &Value) {
+        let mut activity = self.lock();
+        activity.edits.push_back(EditRecord {
+            label: params
+                .get("label")
+                .and_then(|label| label.as_str())
+                .map(str::to_string),
+            edit: params.get("edit").cloned().unwrap_or_default(),
+            requested_at: now_millis(),
+        });
+        if activity.edits.len() > MAX_EDITS {
+            activity.edits.pop_front();
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Hover contents as one text, code blocks fenced with their language
+fn hover_text(contents: HoverContents) -> String {
+    let marked = |string: MarkedString| match string {
+        MarkedString::String(text) => text,
+        MarkedString::LanguageString(code) => {
+            format!("```{}\n{}\n```", code.language, code.value)
+        }
+    };
+    match contents {
+        HoverContents::Scalar(string) => marked(string),
+        HoverContents::Array(strings) => strings
+            .into_iter()
+            .map(marked)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        HoverContents::Markup(markup) => markup.value,
+    }
+}
+
+/// Where the language server behind the proxy comes from
+pub enum LanguageServer {
+    /// A program started with these arguments, speaking LSP over its stdio
+    Command(Vec<String>),
+    /// Streams to a language server run by the embedder
+    Transport(LspTransport),
+}
+
+/// Editor state the proxy keeps up to date from the traffic it passes
+#[derive(Clone)]
+pub struct Observers {
+    pub diagnostics: SharedDiagnostics,
+    pub documents: SharedDocuments,
+    pub activity: SharedLanguageServerActivity,
+}
+
+/// Pass messages between the editor on `client` and the language server until
+/// either side closes or `shutdown` is triggered
+pub async fn run_proxy(
+    client: LspTransport,
+    language_server: LanguageServer,
+    observers: Observers,
+    shutdown: Shutdown,
+) -> Result<()> {
+    // The child is killed when the proxy stops, taking the transport with it
+    let (server, _child) = match language_server {
+        LanguageServer::Command(command) => {
+            let (program, args) = command
+                .split_first()
+                .ok_or_else(|| anyhow!("No language server command given"))?;
+            info!("Starting language server: {}", command.join(" "));
+            let mut child = tokio::process::Command::new(program)
+                .args(args)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("Failed to start language server {}", program))?;
+            let stdin = child.stdin.take().expect("stdin is piped");
+            let stdout = child.stdout.take().expect("stdout is piped");
+            (LspTransport::new(stdout, stdin), Some(child))
+        }
+        LanguageServer::Transport(transport) => (transport, None),
+    };
+
+    let (client_input, client_output) = client.into_parts();
+    let (server_input, server_output) = server.into_parts();
+    let to_server = pump(client_input, server_output, |message| {
+        observe_client(message, &observers)
+    });
+    let to_client = pump(server_input, client_output, |message| {
+        observe_server(message, &observers)
+    });
+
+    tokio::select! {
+        result = to_server => match result {
+            Ok(()) => info!("The editor closed the connection to the language server"),
+            Err(e) => warn!("Failed to pass messages to the language server: {}", e),
+        },
+        result = to_client => match result {
+            Ok(()) => info!("The language server exited"),
+            Err(e) => warn!("Failed to pass messages to the editor: {}", e),
+        },
+        _ = shutdown.triggered() => info!("Stopping the language server proxy"),
+    }
+    Ok(())
+}
+
+/// Copy messages from `input` to `output` until `input` ends, showing each to
+/// `observe` first
+async fn pump<F>(
+    input: impl tokio::io::AsyncRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+    observe: impl Fn(Value) -> F,
+) -> Result<()>
+where
+    F: std::future::Future<Output = ()>,
+{
+    let mut input = BufReader::new(input);
+    while let Some(body) = read_message(&mut input).await? {
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(message) => observe(message).await,
+            Err(e) => debug!("Passing on a message that isn't JSON: {}", e),
+        }
+        write_message(&mut output, &body).await?;
+    }
+    Ok(())
+}
+
+/// The body of the next LSP message, or None at the end of the stream
+async fn read_message(input: &mut (impl AsyncBufReadExt + Unpin)) -> Result<Option<Vec<u8>>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line).await? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(anyhow!("The stream ended inside a message header")),
+            };
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| anyhow!("A message header has no Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+async fn write_message(output: &mut (impl AsyncWrite + Unpin), body: &[u8]) -> Result<()> {
+    output
+        .write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+        .await?;
+    output.write_all(body).await?;
+    output.flush().await?;
+    Ok(())
+}
+
+fn params_of<T: DeserializeOwned>(message: &Value) -> Option<T> {
+    serde_json::from_value(message.get("params")?.clone()).ok()
+}
+
+/// Follow documents and hover requests on their way to the language server
+async fn observe_client(message: Value, observers: &Observers) {
+    let method = message.get("method").and_then(|method| method.as_str());
+    match (method, message.get("id")) {
+        (Some("textDocument/didOpen"), None) => {
+            if let Some(params) = params_of::<DidOpenTextDocumentParams>(&message) {
+                let document = params.text_document;
+                observers
+                    .documents
+                    .open(
+                        document.uri,
+                        document.language_id,
+                        document.version,
+                        document.text,
+                    )
+                    .await;
+            }
+        }
+        (Some("textDocument/didChange"), None) => {
+            if let Some(params) = params_of::<DidChangeTextDocumentParams>(&message) {
+                observers
+                    .documents
+                    .changed(
+                        &params.text_document.uri,
+                        params.text_document.version,
+                        params.content_changes,
+                    )
+                    .await;
+            }
+        }
+        (Some("textDocument/didSave"), None) => {
+            if let Some(params) = params_of::<DidSaveTextDocumentParams>(&message) {
+                observers.documents.saved(&params.text_document.uri).await;
+            }
+        }
+        (Some("textDocument/didClose"), None) => {
+            if let Some(params) = params_of::<DidCloseTextDocumentParams>(&message) {
+                observers.documents.close(&params.text_document.uri).await;
+            }
+        }
+        (Some("textDocument/hover"), Some(id)) => {
+            if let Some(params) = params_of::<HoverParams>(&message) {
+                observers.activity.hover_requested(id, params);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Pick up diagnostics, hovers, edits and the position encoding on their way to
+/// the editor
+async fn observe_server(message: Value, observers: &Observers) {
+    let method = message.get("method").and_then(|method| method.as_str());
+    match (method, message.get("id")) {
+        (Some("textDocument/publishDiagnostics"), None) => {
+            if let Some(params) = params_of::<PublishDiagnosticsParams>(&message) {
+                observers
+                    .diagnostics
+                    .publish(params.uri, params.diagnostics)
+                    .await;
+            }
+        }
+        (Some("workspace/applyEdit"), Some(_)) => {
+            if let Some(params) = message.get("params") {
+                observers.activity.edit_requested(params);
+            }
+        }
+        (None, Some(id)) => {
+            let result = message.get("result");
+            // The answer to initialize names the encoding every position uses
+            if let Some(kind) = result
+                .and_then(|result| result.pointer("/capabilities/positionEncoding"))
+                .and_then(|kind| kind.as_str())
+            {
+                let encoding = PositionEncoding::negotiate(Some(&[PositionEncodingKind::from(
+                    kind.to_string(),
+                )]));
+                info!("The language server uses {:?} positions", encoding);
+                observers.documents.set_encoding(encoding);
+            }
+            observers.activity.hover_answered(id, result);
+        }
+        _ => {}
+    }
+}
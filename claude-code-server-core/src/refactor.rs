@@ -0,0 +1,248 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tower_lsp::Client;
+use tracing::{info, warn};
+
+use crate::diff;
+use crate::documents::OpenDocuments;
+use crate::edits::{self, EditPlan};
+use crate::journal::{JournalEntry, UndoJournal};
+use crate::language;
+use crate::paths;
+use crate::progress::WorkDone;
+use crate::text::PositionEncoding;
+use crate::workspace;
+
+// Changes Claude plans across several files at once, through the proposeRefactor
+// tool. The plan says what changes in each file and why, with the file's new
+// contents. It is written out as one Markdown document, .claude/refactor-plan.md,
+// with the changed lines of every file, and opened in the editor while the user is
+// asked to approve it. Once approved, the files are edited one at a time through
+// workspace/applyEdit, reported with $/progress. The first file that fails
+// (because it changed since the plan was made, say) stops the rest; the files
+// edited by then stay edited, and are undone together like any other edit.
+
+pub const PLAN_FILE: &str = ".claude/refactor-plan.md";
+/// Source of the undo journal entry
+const SOURCE: &str = "proposeRefactor";
+
+/// Argument of the proposeRefactor tool
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefactorPlan {
+    pub title: String,
+    #[serde(default)]
+    pub summary: Option<String>,
+    pub files: Vec<PlannedFile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedFile {
+    pub file_path: String,
+    /// What changes in the file, and why
+    pub description: String,
+    pub new_contents: String,
+}
+
+/// One file of the plan, validated against what the editor shows for it
+#[derive(Debug)]
+pub struct Step {
+    pub path: PathBuf,
+    pub description: String,
+    pub edit: EditPlan,
+}
+
+/// What came of an approved plan
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefactorOutcome {
+    pub applied: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed: Option<FailedStep>,
+    /// Files after the one that failed, left alone
+    pub not_applied: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedStep {
+    pub file_path: String,
+    pub error: String,
+}
+
+impl RefactorOutcome {
+    /// One line for the end of the progress report
+    pub fn summary(&self) -> String {
+        let total = self.applied.len() + self.not_applied.len() + self.failed.iter().count();
+        match &self.failed {
+            Some(failed) => format!(
+                "Stopped at {} after {} of {} files: {}",
+                failed.file_path,
+                self.applied.len(),
+                total,
+                failed.error
+            ),
+            None => format!("Edited {} files", total),
+        }
+    }
+}
+
+/// The plan's files with their edits, in order, leaving out those it doesn't
+/// change. Fails when a file can't be read or comes up twice.
+pub async fn prepare(
+    plan: &RefactorPlan,
+    roots: &[PathBuf],
+    documents: &OpenDocuments,
+) -> Result<Vec<Step>> {
+    if plan.files.is_empty() {
+        bail!("The plan has no files");
+    }
+    let mut steps: Vec<Step> = Vec::new();
+    for file in &plan.files {
+        let path = workspace::resolve_path(roots, &file.file_path);
+        if steps.iter().any(|step| step.path == path) {
+            bail!("{} is in the plan more than once", path.display());
+        }
+        let edit = edits::replace(&path, &file.new_contents, documents).await?;
+        if edit
+            .files
+            .iter()
+            .all(|file| file.original() == file.updated())
+        {
+            info!("The plan leaves {} as it is", path.display());
+            continue;
+        }
+        steps.push(Step {
+            path,
+            description: file.description.clone(),
+            edit,
+        });
+    }
+    Ok(steps)
+}
+
+/// The plan as a Markdown document: its summary, then every file with what
+/// changes in it and the lines changed
+pub fn render(plan: &RefactorPlan, steps: &[Step], roots: &[PathBuf]) -> String {
+    let mut document = format!("# {}\n\n", plan.title.trim());
+    if let Some(summary) = plan
+        .summary
+        .as_deref()
+        .filter(|summary| !summary.trim().is_empty())
+    {
+        document.push_str(summary.trim());
+        document.push_str("\n\n");
+    }
+    document.push_str(&format!(
+        "Claude plans to edit {} files, in this order. Answer the editor's prompt to apply the plan.\n",
+        steps.len()
+    ));
+
+    for (number, step) in steps.iter().enumerate() {
+        document.push_str(&format!(
+            "\n## {}. {}\n\n{}\n",
+            number + 1,
+            display_path(&step.path, roots),
+            step.description.trim()
+        ));
+        for file in &step.edit.files {
+            document.push_str(&format!(
+                "\n```diff\n{}```\n",
+                changed_lines(file.original(), file.updated(), &step.path)
+            ));
+        }
+    }
+    document
+}
+
+/// The path relative to the workspace folder holding it
+fn display_path(path: &Path, roots: &[PathBuf]) -> String {
+    roots
+        .iter()
+        .find_map(|root| paths::relative(path, root))
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+/// Each hunk from `original` to `updated` as removed and added lines, under the
+/// line it starts at and the declaration it falls in
+fn changed_lines(original: &str, updated: &str, path: &Path) -> String {
+    let old_lines: Vec<&str> = original.lines().collect();
+    let hunks = diff::hunks(
+        original,
+        updated,
+        language::from_path(path),
+        PositionEncoding::default(),
+    );
+    let mut text = String::new();
+    for hunk in hunks {
+        let start = hunk.range.start.line as usize;
+        let end = hunk.range.end.line as usize;
+        match &hunk.symbol {
+            Some(symbol) => text.push_str(&format!("@@ line {} in {} @@\n", start + 1, symbol)),
+            None => text.push_str(&format!("@@ line {} @@\n", start + 1)),
+        }
+        for line in old_lines
+            .get(start..end.min(old_lines.len()))
+            .unwrap_or_default()
+        {
+            text.push_str(&format!("-{}\n", line));
+        }
+        for line in hunk.new_text.lines() {
+            text.push_str(&format!("+{}\n", line));
+        }
+    }
+    text
+}
+
+/// Edit the files in order, reporting each to `progress`, until one fails. The
+/// files edited by then go into the undo journal as one entry.
+pub async fn apply(
+    client: &Client,
+    steps: Vec<Step>,
+    documents: &OpenDocuments,
+    document_changes: bool,
+    journal: &UndoJournal,
+    progress: &WorkDone,
+) -> RefactorOutcome {
+    let total = steps.len();
+    let mut outcome = RefactorOutcome::default();
+    let mut snapshots = Vec::new();
+    let mut steps = steps.into_iter().enumerate();
+    for (index, step) in steps.by_ref() {
+        let file_path = step.path.to_string_lossy().to_string();
+        progress
+            .report(
+                format!("{}/{}: {}", index + 1, total, file_path),
+                Some((index * 100 / total.max(1)) as u32),
+            )
+            .await;
+        let applied = match step.edit.check_current(documents).await {
+            Ok(()) => edits::apply(client, &step.edit, document_changes).await,
+            Err(e) => Err(e),
+        };
+        match applied {
+            Ok(()) => {
+                snapshots.extend(step.edit.snapshots());
+                outcome.applied.push(file_path);
+            }
+            Err(e) => {
+                warn!("Refactor plan stopped at {}: {}", file_path, e);
+                outcome.failed = Some(FailedStep {
+                    file_path,
+                    error: e.to_string(),
+                });
+                break;
+            }
+        }
+    }
+    outcome.not_applied = steps
+        .map(|(_, step)| step.path.to_string_lossy().to_string())
+        .collect();
+
+    if !snapshots.is_empty() {
+        journal.record(JournalEntry::new(SOURCE, snapshots));
+    }
+    outcome
+}
@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::lsp::JsonRpcNotification;
+
+// MCP resources: the editor state Claude otherwise only hears about through
+// notifications, readable on demand. ide://selection is the latest selection,
+// ide://diagnostics every cached diagnostic, and ide://open-files/<path> the
+// buffer of each open file. A client subscribed to one of them gets
+// notifications/resources/updated when the notification bus reports a change to
+// it (diagnostics, which never go through the bus, are tracked by their cache),
+// and every client gets notifications/resources/list_changed when files open,
+// close or move.
+
+pub const SELECTION_URI: &str = "ide://selection";
+pub const DIAGNOSTICS_URI: &str = "ide://diagnostics";
+pub const OPEN_FILES_TEMPLATE: &str = "ide://open-files/{path}";
+const OPEN_FILES_PREFIX: &str = "ide://open-files/";
+
+/// Resource URI for the open file at the absolute `path`
+pub fn open_file_uri(path: &str) -> String {
+    format!("{}{}", OPEN_FILES_PREFIX, path.trim_start_matches('/'))
+}
+
+/// Absolute path behind an ide://open-files resource URI
+pub fn open_file_path(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix(OPEN_FILES_PREFIX)?;
+    Some(format!("/{}", path))
+}
+
+/// notifications/resources/* messages for a client with `subscriptions`, following
+/// a notification on the bus
+pub fn notifications(
+    notification: &JsonRpcNotification,
+    subscriptions: &Subscriptions,
+) -> Vec<JsonRpcNotification> {
+    let file_path = notification.params["filePath"].as_str();
+    let (updated, list_changed) = match notification.method.as_str() {
+        "selection_changed" => (Some(SELECTION_URI.to_string()), false),
+        // A reopened file may have changed on disk in between
+        "opened_file" => (file_path.map(open_file_uri), true),
+        "closed_file" | "file_renamed" | "file_deleted" => (None, true),
+        _ => (None, false),
+    };
+
+    let mut notifications = Vec::new();
+    if list_changed {
+        notifications.push(JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/resources/list_changed".to_string(),
+            params: serde_json::json!({}),
+        });
+    }
+    if let Some(uri) = updated.filter(|uri| subscriptions.contains(uri)) {
+        notifications.push(updated_notification(&uri));
+    }
+    notifications
+}
+
+/// notifications/resources/updated for `uri`
+pub fn updated_notification(uri: &str) -> JsonRpcNotification {
+    JsonRpcNotification {
+        jsonrpc: "2.0".to_string(),
+        method: "notifications/resources/updated".to_string(),
+        params: serde_json::json!({ "uri": uri }),
+    }
+}
+
+/// Resources one client subscribed to through resources/subscribe
+#[derive(Debug, Clone, Default)]
+pub struct Subscriptions {
+    uris: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&self, uri: &str) {
+        self.lock().insert(uri.to_string());
+    }
+
+    pub fn unsubscribe(&self, uri: &str) {
+        self.lock().remove(uri);
+    }
+
+    pub fn contains(&self, uri: &str) -> bool {
+        self.lock().contains(uri)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.uris.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, Diagnostic, DiagnosticSeverity, Position, Range, TextEdit, Url,
+    WorkspaceEdit,
+};
+
+use crate::claude;
+use crate::text::{LineIndex, PositionEncoding};
+
+// Code review by Claude, surfaced as diagnostics. claude-code.review sends a file,
+// or its uncommitted changes, to Claude asking for findings as JSON. Each finding
+// becomes a diagnostic, and one that comes with replacement code also becomes a
+// quick fix. The replacement travels in the diagnostic's data, so the fix needs
+// no state on the server.
+
+pub const REVIEW_SOURCE: &str = "Claude review";
+// Longest finding message quoted in a quick fix title
+const MAX_FIX_TITLE_CHARS: usize = 60;
+
+/// What to review
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewScope {
+    /// The whole file
+    #[default]
+    File,
+    /// Only lines changed since HEAD
+    Diff,
+}
+
+/// Argument of claude-code.review
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReviewTarget {
+    pub uri: Url,
+    #[serde(default)]
+    pub scope: ReviewScope,
+}
+
+/// A problem Claude found, with 1-based lines
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Finding {
+    line: u32,
+    #[serde(default)]
+    end_line: Option<u32>,
+    #[serde(default)]
+    severity: Option<String>,
+    message: String,
+    /// New text for lines `line` to `end_line`
+    #[serde(default)]
+    replacement: Option<String>,
+}
+
+/// Prompt asking for findings on `text`, or only on the lines `diff` changes.
+/// Lines are numbered so Claude can point at them reliably.
+pub fn prompt(file_path: &str, text: &str, diff: Option<&str>) -> String {
+    let numbered: String = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>5}| {}\n", index + 1, line))
+        .collect();
+
+    let mut prompt = format!(
+        "Review the following code from {} for bugs, security problems, performance \
+         issues and unclear code.",
+        file_path
+    );
+    if let Some(diff) = diff {
+        prompt.push_str(&format!(
+            " Only report problems in lines changed by this diff against the last \
+             commit:\n\n```diff\n{}\n```\n\nThe current file, with line numbers:",
+            diff.trim_end()
+        ));
+    } else {
+        prompt.push_str(" Lines are numbered:");
+    }
+    prompt.push_str(&format!(
+        "\n\n```\n{}```\n\n\
+         Reply with only a JSON array of findings, [] if there are none. Each finding is \
+         an object with \"line\" and optionally \"endLine\" (1-based, inclusive), \
+         \"severity\" (\"error\", \"warning\", \"info\" or \"hint\"), \"message\", and \
+         optionally \"replacement\": the corrected text of those whole lines, without \
+         line numbers.",
+        numbered
+    ));
+    prompt
+}
+
+/// Diagnostics for the findings in Claude's response, with ranges in `text`
+pub fn diagnostics(
+    response: &str,
+    text: &str,
+    encoding: PositionEncoding,
+) -> Result<Vec<Diagnostic>> {
+    let findings = parse_findings(response)?;
+    let index = LineIndex::new(text);
+    let last_line = text.lines().count().saturating_sub(1) as u32;
+
+    Ok(findings
+        .into_iter()
+        .filter(|finding| finding.line > 0)
+        .map(|finding| {
+            let start = (finding.line - 1).min(last_line);
+            let end = finding
+                .end_line
+                .map_or(start, |end| end.saturating_sub(1).clamp(start, last_line));
+            let end_character = index.line(end).map_or(0, |line| encoding.measure(line));
+
+            let replacement = finding.replacement.map(|replacement| {
+                let replacement = replacement.strip_suffix('\n').unwrap_or(&replacement);
+                serde_json::json!({ "replacement": replacement })
+            });
+            Diagnostic {
+                range: Range::new(Position::new(start, 0), Position::new(end, end_character)),
+                severity: Some(severity(finding.severity.as_deref())),
+                source: Some(REVIEW_SOURCE.to_string()),
+                message: finding.message,
+                data: replacement,
+                ..Default::default()
+            }
+        })
+        .collect())
+}
+
+/// Quick fixes applying the replacements carried by review diagnostics
+pub fn fixes(uri: &Url, diagnostics: &[Diagnostic]) -> Vec<CodeAction> {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.source.as_deref() == Some(REVIEW_SOURCE))
+        .filter_map(|diagnostic| {
+            let replacement = diagnostic.data.as_ref()?.get("replacement")?.as_str()?;
+            let edit = TextEdit {
+                range: diagnostic.range,
+                new_text: replacement.to_string(),
+            };
+            Some(CodeAction {
+                title: fix_title(&diagnostic.message),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some([(uri.clone(), vec![edit])].into_iter().collect()),
+                    ..Default::default()
+                }),
+                is_preferred: Some(true),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Findings from a JSON array, fenced or not, possibly surrounded by prose
+fn parse_findings(response: &str) -> Result<Vec<Finding>> {
+    let code = claude::extract_code(response);
+    if let Ok(findings) = serde_json::from_str(code.trim()) {
+        return Ok(findings);
+    }
+    let start = response.find('[');
+    let end = response.rfind(']');
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str(&response[start..=end])
+            .map_err(|e| anyhow!("Claude's review findings are not valid JSON: {}", e)),
+        _ => Err(anyhow!("Claude's review contained no findings list")),
+    }
+}
+
+fn severity(severity: Option<&str>) -> DiagnosticSeverity {
+    match severity.map(str::to_ascii_lowercase).as_deref() {
+        Some("error") => DiagnosticSeverity::ERROR,
+        Some("info" | "information") => DiagnosticSeverity::INFORMATION,
+        Some("hint") => DiagnosticSeverity::HINT,
+        _ => DiagnosticSeverity::WARNING,
+    }
+}
+
+/// "Apply Claude's fix: <first line of the message>", shortened
+fn fix_title(message: &str) -> String {
+    format!("Apply Claude's fix: {}", short_message(message))
+}
+
+/// The first line of a diagnostic message, shortened to fit a quick fix title
+pub fn short_message(message: &str) -> String {
+    let first_line = message.lines().next().unwrap_or_default();
+    let mut title: String = first_line.chars().take(MAX_FIX_TITLE_CHARS).collect();
+    if title.len() < first_line.len() {
+        title.push('…');
+    }
+    title
+}
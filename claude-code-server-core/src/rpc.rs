@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::mcp::{MCPError, MCPResponse};
+
+// Requests from the editor side to the connected Claude client, sent over its
+// notification channel. Claude's own requests already get MCP responses; these are
+// the other direction, for questions like accepting a diff or granting a
+// permission. Each waits for its response up to a timeout and fails with a
+// RequestError rather than hanging or being dropped.
+
+/// Longest a request waits for its response unless the caller says otherwise
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request for the Claude client, sent alongside the notifications
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub id: Value,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+/// Why a request got no result
+#[derive(Debug)]
+pub enum RequestError {
+    /// Nobody is connected on the other side to ask
+    NotConnected,
+    /// No response within the timeout
+    Timeout(Duration),
+    /// The connection went away before responding
+    Disconnected,
+    /// The other side answered with an error object
+    Failed(MCPError),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConnected => write!(f, "not connected"),
+            Self::Timeout(timeout) => write!(f, "no response within {:?}", timeout),
+            Self::Disconnected => write!(f, "the connection closed before responding"),
+            Self::Failed(error) => write!(f, "{} ({})", error.message, error.code),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+type Reply = oneshot::Sender<Result<Value, MCPError>>;
+
+/// Requests sent on a connection and waiting for their responses, by id
+#[derive(Debug, Default)]
+pub struct PendingRequests {
+    next_id: AtomicU64,
+    waiting: Mutex<HashMap<String, Reply>>,
+}
+
+impl PendingRequests {
+    /// A request with a fresh id, and where its response will arrive
+    pub fn register(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> (JsonRpcRequest, oneshot::Receiver<Result<Value, MCPError>>) {
+        // Prefixed so they can't be mistaken for the client's own request ids
+        let id = format!("ide-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let (reply, response) = oneshot::channel();
+        self.lock().insert(id.clone(), reply);
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Value::String(id),
+            method: method.to_string(),
+            params,
+        };
+        (request, response)
+    }
+
+    /// Hand a response to the request waiting for it. Returns false when none is,
+    /// e.g. because it timed out.
+    pub fn resolve(&self, response: MCPResponse) -> bool {
+        let Some(id) = response.id.as_ref().and_then(Value::as_str) else {
+            return false;
+        };
+        let Some(reply) = self.lock().remove(id) else {
+            return false;
+        };
+        let result = match response.error {
+            Some(error) => Err(error),
+            None => Ok(response.result.unwrap_or(Value::Null)),
+        };
+        reply.send(result).is_ok()
+    }
+
+    /// Stop waiting for a request
+    pub fn forget(&self, id: &Value) {
+        if let Some(id) = id.as_str() {
+            self.lock().remove(id);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Reply>> {
+        self.waiting.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Wait up to `timeout` for a reply; a dropped sender means the other side went
+/// away
+pub async fn wait<T>(reply: oneshot::Receiver<T>, timeout: Duration) -> Result<T, RequestError> {
+    match tokio::time::timeout(timeout, reply).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(_)) => Err(RequestError::Disconnected),
+        Err(_) => Err(RequestError::Timeout(timeout)),
+    }
+}
+
+/// The response in a message from the client, if it is one rather than a request
+/// or notification
+pub fn response(text: &str) -> Option<MCPResponse> {
+    let message: Value = serde_json::from_str(text).ok()?;
+    if message.get("method").is_some() || message.get("id").is_none() {
+        return None;
+    }
+    serde_json::from_value(message).ok()
+}
@@ -0,0 +1,99 @@
+use lru::LruCache;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lsp::SelectionChangedNotification;
+
+// The selections sent to Claude recently, per file, so it can ask what the user
+// was looking at before the current selection (getSelectionHistory) and the user
+// can send one of them again (claude-code.resend-selection). Selections are kept
+// as they were sent, so files the privacy settings hide have no text here either.
+
+// Selections kept per file; the oldest are forgotten first
+const MAX_PER_FILE: usize = 20;
+// Files with a history; the least recently selected in are forgotten first
+const MAX_FILES: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct SelectionRecord {
+    pub selection: SelectionChangedNotification,
+    /// Milliseconds since the Unix epoch
+    pub selected_at: u64,
+    /// Orders selections across files
+    sequence: u64,
+}
+
+#[derive(Debug)]
+struct History {
+    files: LruCache<String, VecDeque<SelectionRecord>>,
+    sequence: u64,
+}
+
+#[derive(Debug)]
+pub struct SelectionHistory {
+    history: Mutex<History>,
+}
+
+pub type SharedSelectionHistory = Arc<SelectionHistory>;
+
+impl Default for SelectionHistory {
+    fn default() -> Self {
+        Self {
+            history: Mutex::new(History {
+                files: LruCache::new(NonZeroUsize::new(MAX_FILES).expect("capacity is non-zero")),
+                sequence: 0,
+            }),
+        }
+    }
+}
+
+impl SelectionHistory {
+    /// Remember `selection`, unless it is the range last selected in its file
+    pub fn record(&self, selection: &SelectionChangedNotification) {
+        let mut history = self.lock();
+        history.sequence += 1;
+        let sequence = history.sequence;
+        let records = history
+            .files
+            .get_or_insert_mut(selection.file_path.clone(), VecDeque::new);
+        let repeated = records.back().is_some_and(|last| {
+            last.selection.selection.start == selection.selection.start
+                && last.selection.selection.end == selection.selection.end
+        });
+        if repeated {
+            records.pop_back();
+        }
+        if records.len() == MAX_PER_FILE {
+            records.pop_front();
+        }
+        records.push_back(SelectionRecord {
+            selection: selection.clone(),
+            selected_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64),
+            sequence,
+        });
+    }
+
+    /// Up to `limit` selections, newest first: those in `file` or in any file
+    pub fn recent(&self, file: Option<&Path>, limit: usize) -> Vec<SelectionRecord> {
+        let history = self.lock();
+        let mut records: Vec<SelectionRecord> = history
+            .files
+            .iter()
+            .filter(|(path, _)| file.is_none_or(|file| Path::new(path) == file))
+            .flat_map(|(_, records)| records.iter().cloned())
+            .collect();
+        records.sort_by_key(|record| Reverse(record.sequence));
+        records.truncate(limit);
+        records
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,454 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::config::SharedConfig;
+use crate::diagnostics::SharedDiagnostics;
+use crate::documents::SharedDocuments;
+use crate::journal::UndoJournal;
+use crate::lsp::{
+    run_lsp_server_with_notifications, DebounceHook, JsonRpcNotification, LspTransport,
+    SharedSelection,
+};
+use crate::mcp::MCPServer;
+use crate::metrics;
+use crate::notifications::NotificationBus;
+use crate::permissions::Permissions;
+use crate::process::ClaudeProcessManager;
+use crate::proxy::{self, LanguageServer, LanguageServerActivity, Observers};
+use crate::session;
+use crate::shutdown::{Shutdown, SHUTDOWN_TIMEOUT};
+use crate::sinks::{NotificationSink, NotificationSinks};
+use crate::state::ServerState;
+use crate::status::ServerStatus;
+use crate::trust::{Trust, WorkspaceTrust};
+use crate::websocket::{run_websocket_server_with_notifications, McpTransport};
+use crate::workspace::WorkspaceFolders;
+
+// Entry point for running the server in-process. The claude-code-server binary is
+// a thin CLI over ServerBuilder; other editors' bridges and integration tests can
+// use it the same way, handing the LSP server any byte stream instead of stdio
+// and stopping everything through their own Shutdown.
+
+pub struct ServerBuilder {
+    worktree: Option<PathBuf>,
+    mcp_port: Option<u16>,
+    mcp_transport: McpTransport,
+    mcp_socket: bool,
+    launch_claude: bool,
+    lsp_transport: Option<LspTransport>,
+    shutdown: Shutdown,
+    workspace_trust: Option<bool>,
+    notification_sinks: Vec<Box<dyn NotificationSink>>,
+    language_server: Option<LanguageServer>,
+    debounce_hook: Option<DebounceHook>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            worktree: None,
+            mcp_port: None,
+            mcp_transport: McpTransport::default(),
+            mcp_socket: false,
+            launch_claude: false,
+            lsp_transport: None,
+            shutdown: Shutdown::default(),
+            workspace_trust: None,
+            notification_sinks: Vec::new(),
+            language_server: None,
+            debounce_hook: None,
+        }
+    }
+
+    /// Root of the project; more folders arrive through the editor's initialize
+    pub fn with_worktree(mut self, worktree: impl Into<PathBuf>) -> Self {
+        self.worktree = Some(worktree.into());
+        self
+    }
+
+    /// Port for the MCP WebSocket server, DEFAULT_PORT otherwise
+    pub fn with_mcp_port(mut self, port: u16) -> Self {
+        self.mcp_port = Some(port);
+        self
+    }
+
+    /// Transport the lock file tells the Claude CLI to use; the port serves both
+    pub fn with_mcp_transport(mut self, transport: McpTransport) -> Self {
+        self.mcp_transport = transport;
+        self
+    }
+
+    /// Serve MCP on a Unix socket next to the lock file rather than on the port,
+    /// which then only names the lock file. Unix only.
+    pub fn with_mcp_socket(mut self, mcp_socket: bool) -> Self {
+        self.mcp_socket = mcp_socket;
+        self
+    }
+
+    /// Start a Claude CLI connected to the MCP server and restart it if it exits
+    pub fn with_launch_claude(mut self, launch_claude: bool) -> Self {
+        self.launch_claude = launch_claude;
+        self
+    }
+
+    /// Speak LSP over these streams rather than stdin/stdout
+    pub fn with_lsp_transport(mut self, transport: LspTransport) -> Self {
+        self.lsp_transport = Some(transport);
+        self
+    }
+
+    /// Stop the servers when `shutdown` is triggered, as well as on their own
+    /// accord (the LSP shutdown request, the transport closing)
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Whether Claude may write files and run commands in the workspace, decided
+    /// by the embedder. Unset, the user is asked on the first run in a workspace
+    /// and their answer remembered.
+    pub fn with_workspace_trust(mut self, trusted: Option<bool>) -> Self {
+        self.workspace_trust = trusted;
+        self
+    }
+
+    /// Copy every notification sent to Claude to `sink` too, in hybrid mode,
+    /// alongside the sinks in the settings
+    pub fn with_notification_sink(mut self, sink: impl NotificationSink) -> Self {
+        self.notification_sinks.push(Box::new(sink));
+        self
+    }
+
+    /// The project's own language server, for run_proxy to stand in front of
+    pub fn with_language_server(mut self, language_server: LanguageServer) -> Self {
+        self.language_server = Some(language_server);
+        self
+    }
+
+    /// Call `hook` with each notification the debouncer lets through, before it is
+    /// sent. For tests: a hook that panics shows the debounce task is restarted.
+    pub fn with_debounce_hook(
+        mut self,
+        hook: impl Fn(&JsonRpcNotification) + Send + Sync + 'static,
+    ) -> Self {
+        self.debounce_hook = Some(DebounceHook::new(hook));
+        self
+    }
+
+    fn workspace_trust(&self) -> WorkspaceTrust {
+        match (self.workspace_trust, &self.worktree) {
+            (Some(true), _) => WorkspaceTrust::fixed(Trust::Trusted),
+            (Some(false), _) => WorkspaceTrust::fixed(Trust::Untrusted),
+            (None, Some(worktree)) => WorkspaceTrust::load(worktree),
+            (None, None) => WorkspaceTrust::default(),
+        }
+    }
+
+    /// Run only the LSP server, without a bridge to Claude
+    pub async fn run_lsp(self) -> Result<()> {
+        if let Some(path) = &self.worktree {
+            info!("Worktree path: {}", path.display());
+        }
+        let state = ServerState {
+            workspace_folders: Arc::new(WorkspaceFolders::new(self.worktree.as_deref())),
+            undo_journal: Arc::new(
+                self.worktree
+                    .as_deref()
+                    .map(UndoJournal::load)
+                    .unwrap_or_default(),
+            ),
+            trust: Arc::new(self.workspace_trust()),
+            shutdown: self.shutdown,
+            ..Default::default()
+        };
+
+        run_lsp_server_with_notifications(
+            None,
+            None,
+            Arc::new(state),
+            self.lsp_transport.unwrap_or_else(LspTransport::stdio),
+        )
+        .await
+    }
+
+    /// Run only the MCP WebSocket server, without editor state behind it
+    pub async fn run_mcp(self) -> Result<()> {
+        run_websocket_server_with_notifications(
+            self.mcp_port,
+            self.mcp_transport,
+            self.mcp_socket,
+            self.worktree,
+            None,
+            MCPServer::default(),
+            self.shutdown,
+        )
+        .await
+    }
+
+    /// Run the LSP server and the MCP WebSocket server sharing the editor's
+    /// state. Returns once both have stopped, or SHUTDOWN_TIMEOUT after either
+    /// stops or shutdown is triggered.
+    pub async fn run_hybrid(self) -> Result<()> {
+        let trust = Arc::new(self.workspace_trust());
+        let Self {
+            worktree,
+            mcp_port: port,
+            mcp_transport,
+            mcp_socket,
+            launch_claude,
+            lsp_transport,
+            shutdown,
+            workspace_trust: _,
+            notification_sinks,
+            language_server: _,
+            debounce_hook,
+        } = self;
+
+        info!("Starting hybrid server (LSP + WebSocket)");
+        if let Some(path) = &worktree {
+            info!("Worktree path: {}", path.display());
+        }
+
+        // Create notification bus for LSP -> WebSocket communication, keeping recent
+        // notifications for clients that connect later. Without a transport the LSP
+        // server speaks over stdout, so notifications can't be copied there.
+        let sinks = NotificationSinks::new(lsp_transport.is_none(), notification_sinks);
+        let notifications = Arc::new(NotificationBus::new(100).with_sinks(sinks));
+
+        // Create command channel for WebSocket -> LSP communication (bidirectional!)
+        let (command_sender, command_receiver) = tokio::sync::mpsc::channel(100);
+
+        // Editor state collected by the LSP server and served to Claude over MCP
+        let latest_selection = SharedSelection::default();
+        let config = SharedConfig::default();
+
+        // Bring back the context of the previous run before anything new is sent, and
+        // keep saving it
+        let mut session_handle = None;
+        if let Some(path) = worktree.as_deref().map(session::session_path) {
+            session::restore(&path, &notifications, &latest_selection).await;
+            session_handle = Some(tokio::spawn(session::persist(
+                path,
+                notifications.clone(),
+                shutdown.clone(),
+            )));
+        }
+
+        // "Allow always" answers and Claude's edits from earlier runs in this
+        // workspace
+        let permissions = worktree
+            .as_deref()
+            .map(Permissions::load)
+            .unwrap_or_default();
+        let undo_journal = worktree
+            .as_deref()
+            .map(UndoJournal::load)
+            .unwrap_or_default();
+
+        let mut status = ServerStatus::default().with_notifications(notifications.clone());
+        let claude_process = launch_claude.then(|| {
+            // Started by the WebSocket server once it knows its port
+            let claude_process = ClaudeProcessManager::new(worktree.clone(), config.clone());
+            status = status.clone().with_claude_process(claude_process.clone());
+            claude_process
+        });
+        let state = Arc::new(ServerState {
+            latest_selection,
+            workspace_folders: Arc::new(WorkspaceFolders::new(worktree.as_deref())),
+            config: config.clone(),
+            undo_journal: Arc::new(undo_journal),
+            trust,
+            status: status.clone(),
+            shutdown: shutdown.clone(),
+            debounce_hook,
+            ..Default::default()
+        });
+
+        let mut mcp_server = MCPServer::new(Some(command_sender))
+            .with_state(state.clone())
+            .with_permissions(Arc::new(permissions));
+        if let Some(claude_process) = claude_process {
+            mcp_server = mcp_server.with_claude_process(claude_process);
+        }
+        tokio::spawn(metrics::export(status, config, shutdown.clone()));
+
+        // In hybrid mode, we run both servers with notification bridge
+        let mut websocket_handle = tokio::spawn(run_websocket_server_with_notifications(
+            port,
+            mcp_transport,
+            mcp_socket,
+            worktree,
+            Some(notifications.clone()),
+            mcp_server,
+            shutdown.clone(),
+        ));
+        let mut lsp_handle = tokio::spawn(run_lsp_server_with_notifications(
+            Some(notifications),
+            Some(command_receiver),
+            state,
+            lsp_transport.unwrap_or_else(LspTransport::stdio),
+        ));
+
+        // Wait for either to complete (or fail), or for a shutdown
+        tokio::select! {
+            result = &mut websocket_handle => {
+                match result {
+                    Ok(Ok(())) => info!("WebSocket server completed"),
+                    Ok(Err(e)) => error!("WebSocket server error: {}", e),
+                    Err(e) => error!("WebSocket server task panicked: {}", e),
+                }
+            }
+            result = &mut lsp_handle => {
+                match result {
+                    Ok(Ok(())) => info!("LSP server completed"),
+                    Ok(Err(e)) => error!("LSP server error: {}", e),
+                    Err(e) => error!("LSP server task panicked: {}", e),
+                }
+            }
+            _ = shutdown.triggered() => info!("Shutting down"),
+        }
+
+        // Let the WebSocket server clean up (lock file, Claude CLI) and the LSP server
+        // answer the shutdown request and take the exit notification, but never wait
+        // longer than SHUTDOWN_TIMEOUT
+        shutdown.trigger();
+        let cleanup = async {
+            if !websocket_handle.is_finished() {
+                let _ = (&mut websocket_handle).await;
+            }
+            if !lsp_handle.is_finished() {
+                let _ = (&mut lsp_handle).await;
+            }
+            if let Some(session_handle) = session_handle {
+                let _ = session_handle.await;
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, cleanup)
+            .await
+            .is_err()
+        {
+            warn!("Shutdown timed out after {:?}", SHUTDOWN_TIMEOUT);
+        }
+
+        info!("Claude Code Server stopped");
+        Ok(())
+    }
+
+    /// Run the MCP server alongside a proxy between the editor and the language
+    /// server given with with_language_server, which serves the editor while the
+    /// proxy shows Claude what passes between them. Tools that drive the editor
+    /// are unavailable, as there is no LSP server of ours for them to go through.
+    pub async fn run_proxy(self) -> Result<()> {
+        let trust = Arc::new(self.workspace_trust());
+        let Self {
+            worktree,
+            mcp_port: port,
+            mcp_transport,
+            mcp_socket,
+            launch_claude,
+            lsp_transport,
+            shutdown,
+            workspace_trust: _,
+            notification_sinks: _,
+            language_server,
+            debounce_hook: _,
+        } = self;
+        let language_server =
+            language_server.ok_or_else(|| anyhow::anyhow!("Proxy mode needs a language server"))?;
+
+        info!("Starting proxy server (language server proxy + WebSocket)");
+        if let Some(path) = &worktree {
+            info!("Worktree path: {}", path.display());
+        }
+
+        let observers = Observers {
+            diagnostics: SharedDiagnostics::default(),
+            documents: SharedDocuments::default(),
+            activity: Arc::new(LanguageServerActivity::default()),
+        };
+        let config = SharedConfig::default();
+        let mut status = ServerStatus::default();
+        let claude_process = launch_claude.then(|| {
+            let claude_process = ClaudeProcessManager::new(worktree.clone(), config.clone());
+            status = status.clone().with_claude_process(claude_process.clone());
+            claude_process
+        });
+        let state = Arc::new(ServerState {
+            diagnostics: observers.diagnostics.clone(),
+            documents: observers.documents.clone(),
+            workspace_folders: Arc::new(WorkspaceFolders::new(worktree.as_deref())),
+            config,
+            trust,
+            status,
+            shutdown: shutdown.clone(),
+            ..Default::default()
+        });
+        let mut mcp_server = MCPServer::new(None)
+            .with_state(state)
+            .with_language_server(observers.activity.clone());
+        if let Some(claude_process) = claude_process {
+            mcp_server = mcp_server.with_claude_process(claude_process);
+        }
+
+        let mut websocket_handle = tokio::spawn(run_websocket_server_with_notifications(
+            port,
+            mcp_transport,
+            mcp_socket,
+            worktree,
+            None,
+            mcp_server,
+            shutdown.clone(),
+        ));
+        let mut proxy_handle = tokio::spawn(proxy::run_proxy(
+            lsp_transport.unwrap_or_else(LspTransport::stdio),
+            language_server,
+            observers,
+            shutdown.clone(),
+        ));
+
+        tokio::select! {
+            result = &mut websocket_handle => {
+                match result {
+                    Ok(Ok(())) => info!("WebSocket server completed"),
+                    Ok(Err(e)) => error!("WebSocket server error: {}", e),
+                    Err(e) => error!("WebSocket server task panicked: {}", e),
+                }
+            }
+            result = &mut proxy_handle => {
+                match result {
+                    Ok(Ok(())) => info!("Language server proxy completed"),
+                    Ok(Err(e)) => error!("Language server proxy error: {}", e),
+                    Err(e) => error!("Language server proxy task panicked: {}", e),
+                }
+            }
+            _ = shutdown.triggered() => info!("Shutting down"),
+        }
+
+        shutdown.trigger();
+        let cleanup = async {
+            if !websocket_handle.is_finished() {
+                let _ = (&mut websocket_handle).await;
+            }
+            if !proxy_handle.is_finished() {
+                let _ = (&mut proxy_handle).await;
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, cleanup)
+            .await
+            .is_err()
+        {
+            warn!("Shutdown timed out after {:?}", SHUTDOWN_TIMEOUT);
+        }
+
+        info!("Claude Code Server stopped");
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::lsp::SharedSelection;
+use crate::notifications::{NotificationBus, Recorded, SharedNotificationBus};
+use crate::shutdown::Shutdown;
+
+// Keeps IDE context across restarts. The part of the notification history worth
+// keeping (recent selections and at-mentions, and the files open in the editor)
+// is saved to <workspace>/.claude/ide-session.json and put back into the history
+// on startup, so the Claude CLI gets it replayed when it connects again.
+
+const SESSION_FILE: &str = ".claude/ide-session.json";
+const FORMAT_VERSION: u32 = 1;
+const MAX_SELECTIONS: usize = 10;
+const MAX_MENTIONS: usize = 20;
+// How often the history is checked for changes worth saving
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionFile {
+    version: u32,
+    notifications: Vec<Recorded>,
+}
+
+pub fn session_path(workspace: &Path) -> PathBuf {
+    workspace.join(SESSION_FILE)
+}
+
+/// Load a saved session into `bus`, and its latest selection into
+/// `latest_selection`. Files that no longer exist are left out.
+pub async fn restore(path: &Path, bus: &NotificationBus, latest_selection: &SharedSelection) {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("Failed to read session {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let session = match serde_json::from_str::<SessionFile>(&text) {
+        Ok(session) if session.version == FORMAT_VERSION => session,
+        Ok(session) => {
+            warn!(
+                "Ignoring session {} with unsupported version {}",
+                path.display(),
+                session.version
+            );
+            return;
+        }
+        Err(e) => {
+            warn!("Ignoring invalid session {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let notifications: Vec<Recorded> = session
+        .notifications
+        .into_iter()
+        .filter(|recorded| {
+            let notification = &recorded.notification;
+            notification.method != "opened_file"
+                || notification.params["filePath"]
+                    .as_str()
+                    .is_some_and(|file_path| Path::new(file_path).exists())
+        })
+        .collect();
+
+    let selection = notifications
+        .iter()
+        .rev()
+        .find(|recorded| recorded.notification.method == "selection_changed")
+        .and_then(|recorded| serde_json::from_value(recorded.notification.params.clone()).ok());
+    if let Some(selection) = selection {
+        *latest_selection.write().await = Some(selection);
+    }
+
+    info!(
+        "Restored {} notifications from {}",
+        notifications.len(),
+        path.display()
+    );
+    bus.restore(notifications);
+}
+
+/// Save the session whenever the history changed, checking every SAVE_INTERVAL,
+/// and a last time once shutdown is triggered
+pub async fn persist(path: PathBuf, bus: SharedNotificationBus, shutdown: Shutdown) {
+    let (mut saved, _) = bus.snapshot();
+    let mut check = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        let stopping = tokio::select! {
+            _ = check.tick() => false,
+            _ = shutdown.triggered() => true,
+        };
+
+        let (revision, history) = bus.snapshot();
+        if revision != saved {
+            match save(&path, retained(history)) {
+                Ok(()) => {
+                    debug!("Saved session to {}", path.display());
+                    saved = revision;
+                }
+                Err(e) => warn!("Failed to save session {}: {}", path.display(), e),
+            }
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+/// What is worth keeping from the history, in order: the last MAX_SELECTIONS
+/// selections, the last MAX_MENTIONS at-mentions and the files still open
+fn retained(history: Vec<Recorded>) -> Vec<Recorded> {
+    let mut selections = 0;
+    let mut mentions = 0;
+    // Walk backwards so a later closed_file drops the file, and a file opened
+    // more than once is kept once
+    let mut settled_files = HashSet::new();
+    let mut kept = Vec::new();
+    for recorded in history.into_iter().rev() {
+        let notification = &recorded.notification;
+        let file_path = notification.params["filePath"].as_str().unwrap_or_default();
+        let keep = match notification.method.as_str() {
+            "selection_changed" => {
+                selections += 1;
+                selections <= MAX_SELECTIONS
+            }
+            "at_mentioned" => {
+                mentions += 1;
+                mentions <= MAX_MENTIONS
+            }
+            "opened_file" => settled_files.insert(file_path.to_string()),
+            "closed_file" => {
+                settled_files.insert(file_path.to_string());
+                false
+            }
+            _ => false,
+        };
+        if keep {
+            kept.push(recorded);
+        }
+    }
+
+    kept.reverse();
+    kept
+}
+
+fn save(path: &Path, notifications: Vec<Recorded>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(&SessionFile {
+        version: FORMAT_VERSION,
+        notifications,
+    })?;
+
+    // Write to a temporary file and rename, so a crash never leaves a partial session
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
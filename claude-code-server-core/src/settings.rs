@@ -0,0 +1,167 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::shutdown::Shutdown;
+use crate::workspace::SharedWorkspaceFolders;
+
+// Settings come from three places, each overriding the keys it sets in the ones
+// before it:
+//
+// 1. the `claudeCode` section of ~/.claude/settings.json, for every project
+// 2. the editor: initialization options and workspace/didChangeConfiguration
+// 3. the `claudeCode` section of <folder>/.claude/settings.json, for one project
+//    (with several workspace folders, the first folder's file wins)
+//
+// Objects are merged key by key and anything else is replaced, so a project file
+// can change one debounce window and leave the rest alone. The files are checked
+// for changes every CHECK_INTERVAL and the settings merged again when one did,
+// or right away when the editor reports the change through
+// workspace/didChangeWatchedFiles.
+
+/// Relative to the home directory and to each workspace folder
+pub const SETTINGS_FILE: &str = ".claude/settings.json";
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The settings the editor sent, kept to merge the files with again
+#[derive(Debug, Default)]
+pub struct SettingsSources {
+    editor: Mutex<Option<Value>>,
+}
+
+pub type SharedSettingsSources = Arc<SettingsSources>;
+
+impl SettingsSources {
+    /// Replace the editor's `claudeCode` section
+    pub fn set_editor(&self, section: Value) {
+        *self.editor.lock().unwrap_or_else(|e| e.into_inner()) = Some(section);
+    }
+
+    /// The settings of every source merged, for workspace folders `roots`. None
+    /// when they don't make a valid configuration.
+    pub fn config(&self, roots: &[PathBuf]) -> Option<Config> {
+        let mut merged = Value::Object(Default::default());
+        let editor = self
+            .editor
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let sections = global_file()
+            .and_then(|path| read_section(&path))
+            .into_iter()
+            .chain(editor)
+            .chain(project_files(roots).filter_map(|path| read_section(&path)));
+        for section in sections {
+            merge(&mut merged, section);
+        }
+        Config::from_section(merged)
+    }
+}
+
+fn global_file() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(SETTINGS_FILE))
+}
+
+/// Whether a change to `path` can change the settings of workspace folders
+/// `roots`: `path` is one of their settings files, or a directory holding one
+pub fn is_settings_path(path: &Path, roots: &[PathBuf]) -> bool {
+    global_file()
+        .into_iter()
+        .chain(project_files(roots))
+        .any(|file| file.starts_with(path))
+}
+
+/// The settings files of workspace folders `roots`, lowest precedence first
+fn project_files(roots: &[PathBuf]) -> impl Iterator<Item = PathBuf> + '_ {
+    roots.iter().rev().map(|root| root.join(SETTINGS_FILE))
+}
+
+/// The `claudeCode` section of the settings file at `path`, if it has one
+fn read_section(path: &Path) -> Option<Value> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match serde_json::from_str::<Value>(&text) {
+        Ok(mut settings) => match settings.get_mut("claudeCode").map(Value::take) {
+            Some(section) if section.is_object() => Some(section),
+            Some(_) => {
+                warn!("Ignoring {}: claudeCode isn't an object", path.display());
+                None
+            }
+            None => None,
+        },
+        Err(e) => {
+            warn!("Ignoring invalid settings file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Merge `overlay` into `base`: objects key by key, anything else replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Call `changed` whenever a settings file of the workspace `folders` is created,
+/// modified or removed, until shutdown
+pub async fn watch<F, Fut>(folders: SharedWorkspaceFolders, shutdown: Shutdown, mut changed: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut seen = modified_times(&folders.roots());
+    let mut check =
+        tokio::time::interval_at(tokio::time::Instant::now() + CHECK_INTERVAL, CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = check.tick() => {}
+            _ = shutdown.triggered() => return,
+        }
+
+        // The files of folders added since were read as they were added
+        let current = modified_times(&folders.roots());
+        let differs = current
+            .iter()
+            .any(|(path, modified)| seen.get(path).is_some_and(|seen| seen != modified));
+        if differs {
+            info!("Settings files changed, reloading settings");
+            changed().await;
+        }
+        seen = current;
+    }
+}
+
+/// When each settings file was last modified, None for those missing
+fn modified_times(roots: &[PathBuf]) -> HashMap<PathBuf, Option<SystemTime>> {
+    global_file()
+        .into_iter()
+        .chain(project_files(roots))
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
@@ -0,0 +1,330 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::shell_env::Environment;
+
+// Runs shell commands requested over MCP (builds, tests, linters) inside a
+// workspace folder, capturing their output and forwarding it line by line as it
+// arrives. The output of recent runs also stays in a scrollback, so Claude can
+// look at a failure again (getLatestTerminalOutput) without running it twice.
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+// Output kept per stream; anything beyond is dropped and flagged as truncated
+const MAX_CAPTURED_BYTES: usize = 256 * 1024;
+// Runs kept in the scrollback, oldest dropped first
+const MAX_RECORDED_RUNS: usize = 20;
+// Output kept in the scrollback across all runs, oldest lines dropped first
+const MAX_SCROLLBACK_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+pub type OutputSender = mpsc::UnboundedSender<(OutputStream, String)>;
+
+#[derive(Debug, Default)]
+pub struct CommandOutput {
+    /// None when the command was killed by a signal or timed out
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    pub truncated: bool,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    fn capture(&mut self, stream: OutputStream, line: &str) {
+        let buffer = match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        };
+        if buffer.len() + line.len() > MAX_CAPTURED_BYTES {
+            self.truncated = true;
+            return;
+        }
+        buffer.push_str(line);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Running,
+    /// exit_code is None when the command was killed by a signal
+    Exited {
+        exit_code: Option<i32>,
+    },
+    TimedOut,
+    /// Stopped before it finished, e.g. because the request was cancelled
+    Interrupted,
+}
+
+/// A command in the scrollback
+#[derive(Debug, Clone)]
+pub struct RecordedRun {
+    pub command: String,
+    pub cwd: PathBuf,
+    pub started: Instant,
+    pub status: RunStatus,
+    /// Lines from both streams, in the order they were read
+    pub lines: VecDeque<String>,
+    /// Whether earlier lines were dropped to keep the scrollback bounded
+    pub truncated: bool,
+}
+
+#[derive(Debug, Default)]
+struct Runs {
+    runs: VecDeque<(u64, RecordedRun)>,
+    bytes: usize,
+    next_id: u64,
+}
+
+impl Runs {
+    fn get(&mut self, id: u64) -> Option<&mut RecordedRun> {
+        self.runs
+            .iter_mut()
+            .find(|(run_id, _)| *run_id == id)
+            .map(|(_, run)| run)
+    }
+
+    /// Drop the oldest runs, then the oldest lines, until within bounds
+    fn trim(&mut self) {
+        while self.runs.len() > MAX_RECORDED_RUNS {
+            if let Some((_, run)) = self.runs.pop_front() {
+                self.bytes -= run.lines.iter().map(String::len).sum::<usize>();
+            }
+        }
+        for (_, run) in self.runs.iter_mut() {
+            while self.bytes > MAX_SCROLLBACK_BYTES {
+                let Some(line) = run.lines.pop_front() else {
+                    break;
+                };
+                self.bytes -= line.len();
+                run.truncated = true;
+            }
+        }
+    }
+}
+
+/// Output of the commands run recently, bounded in runs and bytes
+#[derive(Debug, Default)]
+pub struct Scrollback {
+    runs: Mutex<Runs>,
+}
+
+pub type SharedScrollback = Arc<Scrollback>;
+
+impl Scrollback {
+    /// Up to `count` of the most recent runs, oldest first
+    pub fn latest(&self, count: usize) -> Vec<RecordedRun> {
+        let runs = self.lock();
+        let skip = runs.runs.len().saturating_sub(count);
+        runs.runs
+            .iter()
+            .skip(skip)
+            .map(|(_, run)| run.clone())
+            .collect()
+    }
+
+    fn start(self: &Arc<Self>, command: &str, cwd: &Path) -> Recording {
+        let mut runs = self.lock();
+        let id = runs.next_id;
+        runs.next_id += 1;
+        runs.runs.push_back((
+            id,
+            RecordedRun {
+                command: command.to_string(),
+                cwd: cwd.to_path_buf(),
+                started: Instant::now(),
+                status: RunStatus::Running,
+                lines: VecDeque::new(),
+                truncated: false,
+            },
+        ));
+        runs.trim();
+        Recording {
+            scrollback: self.clone(),
+            id,
+            finished: false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Runs> {
+        self.runs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A run being recorded; dropping it before finish() marks it interrupted
+struct Recording {
+    scrollback: SharedScrollback,
+    id: u64,
+    finished: bool,
+}
+
+impl Recording {
+    fn push(&self, line: &str) {
+        let mut runs = self.scrollback.lock();
+        let Some(run) = runs.get(self.id) else {
+            return;
+        };
+        run.lines.push_back(line.to_string());
+        runs.bytes += line.len();
+        runs.trim();
+    }
+
+    fn finish(mut self, output: &CommandOutput) {
+        self.finished = true;
+        let status = if output.timed_out {
+            RunStatus::TimedOut
+        } else {
+            RunStatus::Exited {
+                exit_code: output.exit_code,
+            }
+        };
+        self.set_status(status);
+    }
+
+    fn set_status(&self, status: RunStatus) {
+        if let Some(run) = self.scrollback.lock().get(self.id) {
+            run.status = status;
+        }
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.set_status(RunStatus::Interrupted);
+        }
+    }
+}
+
+/// Run `command` through the platform shell in `cwd`, killing it after `timeout`.
+/// It gets `environment` instead of the server's environment, when given. Each
+/// output line (with its newline) is also sent to `lines` as it is read, and
+/// recorded in `scrollback`.
+pub async fn run(
+    command: &str,
+    cwd: &Path,
+    environment: Option<&Environment>,
+    timeout: Duration,
+    lines: Option<OutputSender>,
+    scrollback: Option<&SharedScrollback>,
+) -> Result<CommandOutput> {
+    info!("Running `{}` in {}", command, cwd.display());
+
+    let mut shell = shell_command(command);
+    if let Some(environment) = environment {
+        shell.env_clear().envs(environment);
+    }
+    let mut child = shell
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start `{}`", command))?;
+    let recording = scrollback.map(|scrollback| scrollback.start(command, cwd));
+
+    let mut stdout = child
+        .stdout
+        .take()
+        .map(|stdout| BufReader::new(stdout).split(b'\n'));
+    let mut stderr = child
+        .stderr
+        .take()
+        .map(|stderr| BufReader::new(stderr).split(b'\n'));
+
+    let deadline = Instant::now() + timeout;
+    let mut output = CommandOutput::default();
+
+    // Read both pipes until they close, which normally happens when the command exits
+    while stdout.is_some() || stderr.is_some() {
+        let (stream, segment) = tokio::select! {
+            segment = next_segment(&mut stdout) => (OutputStream::Stdout, segment),
+            segment = next_segment(&mut stderr) => (OutputStream::Stderr, segment),
+            _ = tokio::time::sleep_until(deadline) => {
+                output.timed_out = true;
+                break;
+            }
+        };
+
+        match segment {
+            Some(mut bytes) => {
+                bytes.push(b'\n');
+                let line = String::from_utf8_lossy(&bytes);
+                output.capture(stream, &line);
+                if let Some(recording) = &recording {
+                    recording.push(&line);
+                }
+                if let Some(lines) = &lines {
+                    let _ = lines.send((stream, line.into_owned()));
+                }
+            }
+            None => match stream {
+                OutputStream::Stdout => stdout = None,
+                OutputStream::Stderr => stderr = None,
+            },
+        }
+    }
+
+    if !output.timed_out {
+        match tokio::time::timeout_at(deadline, child.wait()).await {
+            Ok(status) => output.exit_code = status?.code(),
+            Err(_) => output.timed_out = true,
+        }
+    }
+    if output.timed_out {
+        warn!("`{}` timed out after {:?}, killing it", command, timeout);
+        if let Err(e) = child.kill().await {
+            warn!("Failed to kill `{}`: {}", command, e);
+        }
+    }
+
+    debug!("`{}` finished with {:?}", command, output.exit_code);
+    if let Some(recording) = recording {
+        recording.finish(&output);
+    }
+    Ok(output)
+}
+
+/// Next line from a pipe, or None once it is closed (or was never opened).
+/// Read errors are treated as end of output.
+async fn next_segment<R>(pipe: &mut Option<tokio::io::Split<R>>) -> Option<Vec<u8>>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    match pipe {
+        Some(pipe) => pipe.next_segment().await.ok().flatten(),
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+// The environment commands run in for Claude, as the user's terminal has it. The
+// user's login shell is started interactively in the command's directory, so the
+// PATH and variables its startup files set up (nvm, pyenv, cargo and the like)
+// are there, and its environment is read back. When the directory has an .envrc,
+// direnv's exports are applied on top. Environments are cached per directory and
+// loaded again when the .envrc changes. When loading fails or times out, commands
+// get the server's own environment, as they do on Windows.
+
+// Printed before the environment, so whatever the startup files print is skipped
+const MARKER: &str = "__CLAUDE_CODE_SHELL_ENV__";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShellEnvironmentSettings {
+    /// Load the environment from the user's shell, rather than running commands
+    /// in the server's
+    pub enabled: bool,
+    /// Shell to load it from; $SHELL when unset
+    pub shell: Option<String>,
+    /// Apply direnv's exports in directories with an .envrc
+    pub direnv: bool,
+    /// Longest loading may take
+    pub timeout_ms: u64,
+}
+
+impl Default for ShellEnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            shell: None,
+            direnv: true,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+pub type Environment = HashMap<String, String>;
+
+/// A directory's environment, with what it was loaded with
+#[derive(Debug)]
+struct Cached {
+    settings: ShellEnvironmentSettings,
+    envrc_modified: Option<SystemTime>,
+    /// None when loading failed
+    environment: Option<Arc<Environment>>,
+}
+
+/// Environments loaded so far, per directory
+#[derive(Debug, Default)]
+pub struct ShellEnvironments {
+    cache: Mutex<HashMap<PathBuf, Cached>>,
+}
+
+pub type SharedShellEnvironments = Arc<ShellEnvironments>;
+
+impl ShellEnvironments {
+    /// The environment for commands run in `dir`, or None for the server's own
+    pub async fn load(
+        &self,
+        dir: &Path,
+        settings: &ShellEnvironmentSettings,
+    ) -> Option<Arc<Environment>> {
+        if !settings.enabled || cfg!(windows) {
+            return None;
+        }
+        let envrc = settings.direnv.then(|| envrc(dir)).flatten();
+        let envrc_modified = envrc
+            .as_ref()
+            .and_then(|envrc| envrc.metadata().ok()?.modified().ok());
+        if let Some(cached) = self.lock().get(dir) {
+            if cached.settings == *settings && cached.envrc_modified == envrc_modified {
+                return cached.environment.clone();
+            }
+        }
+
+        let timeout = Duration::from_millis(settings.timeout_ms);
+        let loaded = tokio::time::timeout(timeout, async {
+            let mut environment = login_shell(dir, settings).await?;
+            if let Some(envrc) = &envrc {
+                apply_direnv(dir, envrc, &mut environment).await;
+            }
+            Ok::<_, anyhow::Error>(environment)
+        })
+        .await;
+        let environment = match loaded {
+            Ok(Ok(environment)) => {
+                info!(
+                    "Loaded the shell environment for {} ({} variables)",
+                    dir.display(),
+                    environment.len()
+                );
+                Some(Arc::new(environment))
+            }
+            Ok(Err(e)) => {
+                warn!(
+                    "Failed to load the shell environment for {}: {}",
+                    dir.display(),
+                    e
+                );
+                None
+            }
+            Err(_) => {
+                warn!(
+                    "Loading the shell environment for {} took over {} ms, giving up",
+                    dir.display(),
+                    settings.timeout_ms
+                );
+                None
+            }
+        };
+        self.lock().insert(
+            dir.to_path_buf(),
+            Cached {
+                settings: settings.clone(),
+                envrc_modified,
+                environment: environment.clone(),
+            },
+        );
+        environment
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Cached>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The .envrc direnv would load in `dir`, the nearest in it or above it
+fn envrc(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(".envrc"))
+        .find(|envrc| envrc.is_file())
+}
+
+/// The environment of an interactive login shell started in `dir`
+async fn login_shell(dir: &Path, settings: &ShellEnvironmentSettings) -> Result<Environment> {
+    let shell = settings
+        .shell
+        .clone()
+        .or_else(|| env::var("SHELL").ok())
+        .unwrap_or_else(|| "sh".to_string());
+    debug!("Loading the environment of {} in {}", shell, dir.display());
+    let output = Command::new(&shell)
+        .args(["-l", "-i", "-c", &format!("printf '%s' {}; env", MARKER)])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| anyhow!("Failed to start {}: {}", shell, e))?;
+    if !output.status.success() {
+        return Err(anyhow!("{} exited with {}", shell, output.status));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, listing) = stdout
+        .split_once(MARKER)
+        .ok_or_else(|| anyhow!("{} printed no environment", shell))?;
+    Ok(parse_env(listing))
+}
+
+/// Variables from `env` output. Lines that don't start a variable continue the
+/// value before them, which had a newline in it.
+fn parse_env(listing: &str) -> Environment {
+    let mut environment = Environment::new();
+    let mut last: Option<String> = None;
+    for line in listing.lines() {
+        match line.split_once('=').filter(|(name, _)| is_name(name)) {
+            Some((name, value)) => {
+                environment.insert(name.to_string(), value.to_string());
+                last = Some(name.to_string());
+            }
+            None => {
+                if let Some(value) = last.as_ref().and_then(|name| environment.get_mut(name)) {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    environment
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Apply what direnv exports for `dir` to `environment`. Nothing changes when
+/// direnv isn't installed or the .envrc isn't allowed.
+async fn apply_direnv(dir: &Path, envrc: &Path, environment: &mut Environment) {
+    let output = Command::new("direnv")
+        .args(["export", "json"])
+        .current_dir(dir)
+        .env_clear()
+        .envs(environment.iter())
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await;
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            debug!("direnv isn't installed, ignoring {}", envrc.display());
+            return;
+        }
+        Err(e) => {
+            warn!("Failed to run direnv for {}: {}", envrc.display(), e);
+            return;
+        }
+    };
+    if !output.status.success() {
+        warn!(
+            "direnv failed for {}: {}",
+            envrc.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return;
+    }
+    // Empty when there is nothing to change
+    let Ok(Value::Object(exports)) = serde_json::from_slice::<Value>(&output.stdout) else {
+        return;
+    };
+    for (name, value) in exports {
+        match value {
+            Value::String(value) => {
+                environment.insert(name, value);
+            }
+            _ => {
+                environment.remove(&name);
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// Coordinated shutdown. The LSP `shutdown` request, the LSP transport closing and
+// SIGTERM/Ctrl+C all trigger it; long-running tasks (the selection debouncer, the
+// LSP command handler, the WebSocket listener and its connections) watch for it and
+// wind down, and the hybrid server gives them SHUTDOWN_TIMEOUT before it exits
+// regardless, so it never keeps Zed's extension host waiting.
+
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+// How long the LSP transport stays up after shutdown was triggered, waiting for
+// the exit notification
+pub const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Start shutting down; repeated calls have no further effect
+    pub fn trigger(&self) {
+        self.sender
+            .send_if_modified(|triggered| !std::mem::replace(triggered, true));
+    }
+
+    /// Resolves once shutdown has been triggered
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as self, so this can't fail
+        let _ = receiver.wait_for(|&triggered| triggered).await;
+    }
+
+    /// Trigger shutdown on Ctrl+C, or SIGTERM on Unix (what editors send when
+    /// stopping servers)
+    pub fn trigger_on_signal(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = signal() => {
+                    info!("Shutdown signal received");
+                    shutdown.trigger();
+                }
+                _ = shutdown.triggered() => {}
+            }
+        });
+    }
+}
+
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!("Failed to install SIGTERM handler: {}", e);
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.ok();
+    }
+}
@@ -0,0 +1,240 @@
+use serde::Deserialize;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::lsp::JsonRpcNotification;
+
+// Copies of every notification sent to Claude, for debugging and external tools.
+// The built-in sinks write each one as a JSON line to a file, to stdout or to a
+// Unix domain socket, and are set up from `notifications.sinks` in the settings,
+// following changes to them. Embedders can add sinks of their own with
+// ServerBuilder::with_notification_sink. Each sink has a task and a bounded queue
+// of its own, so one that is slow or failing loses lines rather than holding up
+// Claude's notifications.
+
+// Notifications waiting for a sink before further ones are dropped
+const QUEUE_CAPACITY: usize = 1000;
+
+/// A built-in sink, as configured under `notifications.sinks`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SinkSettings {
+    /// Append to a file, created if missing
+    File { path: PathBuf },
+    /// Write to stdout, unless the LSP server speaks over it
+    Stdout,
+    /// Connect to a socket listening at `path`, reconnecting after failures
+    UnixSocket { path: PathBuf },
+}
+
+/// Somewhere notifications sent to Claude are copied to
+#[tower_lsp::async_trait]
+pub trait NotificationSink: Send + 'static {
+    /// What the sink writes to, for logs
+    fn name(&self) -> String;
+
+    async fn send(&mut self, notification: &JsonRpcNotification) -> io::Result<()>;
+}
+
+/// `notification` as one line of JSON
+fn json_line(notification: &JsonRpcNotification) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(notification)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Appends to a file, opening it again after a failed write
+struct FileSink {
+    path: PathBuf,
+    file: Option<tokio::fs::File>,
+}
+
+#[tower_lsp::async_trait]
+impl NotificationSink for FileSink {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn send(&mut self, notification: &JsonRpcNotification) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?;
+                self.file.insert(file)
+            }
+        };
+        let result = file.write_all(&json_line(notification)?).await;
+        if result.is_err() {
+            self.file = None;
+        }
+        result
+    }
+}
+
+struct StdoutSink(tokio::io::Stdout);
+
+#[tower_lsp::async_trait]
+impl NotificationSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    async fn send(&mut self, notification: &JsonRpcNotification) -> io::Result<()> {
+        self.0.write_all(&json_line(notification)?).await?;
+        self.0.flush().await
+    }
+}
+
+/// Writes to whatever listens at `path`, connecting when there is something to
+/// send and it isn't connected
+#[cfg(unix)]
+struct UnixSocketSink {
+    path: PathBuf,
+    stream: Option<tokio::net::UnixStream>,
+}
+
+#[cfg(unix)]
+#[tower_lsp::async_trait]
+impl NotificationSink for UnixSocketSink {
+    fn name(&self) -> String {
+        format!("unix:{}", self.path.display())
+    }
+
+    async fn send(&mut self, notification: &JsonRpcNotification) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self
+                .stream
+                .insert(tokio::net::UnixStream::connect(&self.path).await?),
+        };
+        let result = stream.write_all(&json_line(notification)?).await;
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+/// The built-in sink for `settings`, if it can be used here
+fn built_in(settings: &SinkSettings, stdout_in_use: bool) -> Option<Box<dyn NotificationSink>> {
+    match settings {
+        SinkSettings::File { path } => Some(Box::new(FileSink {
+            path: path.clone(),
+            file: None,
+        })),
+        SinkSettings::Stdout if stdout_in_use => {
+            warn!("Not copying notifications to stdout, which carries LSP messages");
+            None
+        }
+        SinkSettings::Stdout => Some(Box::new(StdoutSink(tokio::io::stdout()))),
+        #[cfg(unix)]
+        SinkSettings::UnixSocket { path } => Some(Box::new(UnixSocketSink {
+            path: path.clone(),
+            stream: None,
+        })),
+        #[cfg(not(unix))]
+        SinkSettings::UnixSocket { path } => {
+            warn!(
+                "Unix sockets aren't supported here, not copying notifications to {}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Start a task feeding `sink`, returning its queue
+fn spawn(mut sink: Box<dyn NotificationSink>) -> mpsc::Sender<JsonRpcNotification> {
+    let (sender, mut receiver) = mpsc::channel::<JsonRpcNotification>(QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        let name = sink.name();
+        debug!("Copying notifications to {}", name);
+        // Only the first failure in a row is worth a warning
+        let mut failing = false;
+        while let Some(notification) = receiver.recv().await {
+            match sink.send(&notification).await {
+                Ok(()) if failing => {
+                    info!("Copying notifications to {} again", name);
+                    failing = false;
+                }
+                Ok(()) => {}
+                Err(e) if !failing => {
+                    warn!("Failed to copy notifications to {}: {}", name, e);
+                    failing = true;
+                }
+                Err(e) => debug!("Failed to copy {} to {}: {}", notification.method, name, e),
+            }
+        }
+    });
+    sender
+}
+
+#[derive(Debug, Default)]
+struct Queues {
+    /// The settings the configured sinks were set up from
+    settings: Vec<SinkSettings>,
+    configured: Vec<mpsc::Sender<JsonRpcNotification>>,
+    added: Vec<mpsc::Sender<JsonRpcNotification>>,
+}
+
+/// The sinks notifications are copied to
+#[derive(Debug, Default)]
+pub struct NotificationSinks {
+    queues: Mutex<Queues>,
+    /// Whether the LSP server speaks over stdout, ruling out the stdout sink
+    stdout_in_use: bool,
+}
+
+impl NotificationSinks {
+    /// Sinks set up from the settings, plus `added` ones that are always there.
+    /// Needs a tokio runtime.
+    pub fn new(stdout_in_use: bool, added: Vec<Box<dyn NotificationSink>>) -> Self {
+        Self {
+            queues: Mutex::new(Queues {
+                added: added.into_iter().map(spawn).collect(),
+                ..Queues::default()
+            }),
+            stdout_in_use,
+        }
+    }
+
+    /// Queue `notification` for every sink, first setting up the configured ones
+    /// again if `settings` changed
+    pub fn send(&self, notification: &JsonRpcNotification, settings: &[SinkSettings]) {
+        let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+        if queues.settings != settings {
+            // Dropping the old queues ends their tasks once they are written out
+            queues.configured = settings
+                .iter()
+                .filter_map(|sink| built_in(sink, self.stdout_in_use))
+                .map(spawn)
+                .collect();
+            queues.settings = settings.to_vec();
+        }
+
+        let Queues {
+            configured, added, ..
+        } = &*queues;
+        for queue in configured.iter().chain(added) {
+            if let Err(mpsc::error::TrySendError::Full(notification)) =
+                queue.try_send(notification.clone())
+            {
+                debug!(
+                    "A notification sink fell behind, dropping {}",
+                    notification.method
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use futures_util::{sink, stream};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::mcp::MCPServer;
+use crate::notifications::SharedNotificationBus;
+use crate::shutdown::Shutdown;
+use crate::websocket::{
+    handle_mcp_connection, http_response, read_head, tokens_match, Peer, RequestHead, AUTH_HEADER,
+};
+
+// The HTTP+SSE transport of MCP, served next to the WebSocket endpoint: the client
+// opens an event stream with GET /sse, is told where to POST its messages, and
+// gets every response and notification as an event. The session then runs the
+// same loop as a WebSocket connection, fed with the POSTed messages.
+
+/// Path of the event stream
+pub const STREAM_PATH: &str = "/sse";
+/// Path messages are POSTed to, with the session id in the query
+pub const MESSAGES_PATH: &str = "/messages";
+// Same limit as tungstenite puts on a WebSocket message
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// Where the POSTed messages of each open event stream go, by session id
+pub type SseSessions = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>>;
+
+/// Forgets a session when its event stream ends, so later POSTs get a 404
+struct SessionGuard {
+    sessions: SseSessions,
+    id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// GET /sse: answer with an event stream, announce the endpoint for POSTs, then
+/// run an MCP session over it until the client hangs up or the server stops
+pub async fn serve_stream(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    auth_token: &str,
+    sessions: SseSessions,
+    notifications: Option<SharedNotificationBus>,
+    mcp_handler: MCPServer,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let head = read_head(&mut reader).await?;
+    if !authorized(&head, auth_token, peer_addr, &mut writer).await? {
+        return Ok(());
+    }
+
+    let id = Uuid::new_v4().to_string();
+    writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await?;
+    let endpoint = format!("{}?sessionId={}", MESSAGES_PATH, id);
+    writer
+        .write_all(event("endpoint", &endpoint).as_bytes())
+        .await?;
+    info!("SSE session {} opened by {}", id, peer_addr);
+
+    let (posts, posted) = mpsc::unbounded_channel();
+    sessions
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(id.clone(), posts.clone());
+    let _session = SessionGuard { sessions, id };
+
+    let sender = sink::unfold(
+        (writer, posts),
+        |(mut writer, posts), message: Message| async move {
+            match message {
+                Message::Text(text) => writer.write_all(event("message", &text).as_bytes()).await,
+                // Clients can't answer pings over SSE; a comment that could be
+                // written is all the sign of life there is, so it counts as the pong
+                Message::Ping(_) => {
+                    let written = writer.write_all(b": ping\n\n").await;
+                    if written.is_ok() {
+                        let _ = posts.send(Message::Pong(Vec::new()));
+                    }
+                    written
+                }
+                Message::Close(_) => writer.shutdown().await,
+                _ => Ok(()),
+            }
+            .map_err(tungstenite::Error::Io)?;
+            Ok::<_, tungstenite::Error>((writer, posts))
+        },
+    );
+    let receiver = stream::unfold((posted, reader), |(mut posted, mut reader)| async move {
+        let mut byte = [0; 1];
+        loop {
+            tokio::select! {
+                message = posted.recv() => {
+                    return message.map(|message| (Ok(message), (posted, reader)));
+                }
+                // The client ends the session by closing the stream
+                read = reader.read(&mut byte) => match read {
+                    Ok(0) | Err(_) => return None,
+                    Ok(_) => {}
+                },
+            }
+        }
+    });
+
+    handle_mcp_connection(
+        Box::pin(sender),
+        Box::pin(receiver),
+        Peer::Tcp(peer_addr),
+        notifications,
+        mcp_handler,
+        shutdown,
+    )
+    .await
+}
+
+/// POST /messages?sessionId=: hand the message to its session, which sends the
+/// response over the event stream
+pub async fn accept_message(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    auth_token: &str,
+    sessions: &SseSessions,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let head = read_head(&mut reader).await?;
+    if !authorized(&head, auth_token, peer_addr, &mut writer).await? {
+        return Ok(());
+    }
+
+    let session = session_id(&head.target).and_then(|id| {
+        sessions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(id)
+            .cloned()
+    });
+    let length = head
+        .header("content-length")
+        .and_then(|length| length.parse::<usize>().ok());
+    let status = match (session, length) {
+        (None, _) => "404 Not Found",
+        (Some(_), None) => "411 Length Required",
+        (Some(_), Some(length)) if length > MAX_MESSAGE_SIZE => "413 Payload Too Large",
+        (Some(session), Some(length)) => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await?;
+            match String::from_utf8(body) {
+                Ok(text) => match session.send(Message::Text(text)) {
+                    Ok(()) => "202 Accepted",
+                    // The event stream closed since the lookup
+                    Err(_) => "404 Not Found",
+                },
+                Err(_) => "400 Bad Request",
+            }
+        }
+    };
+    if status != "202 Accepted" {
+        warn!(
+            "Refusing message from {} for {}: {}",
+            peer_addr, head.target, status
+        );
+    }
+
+    writer
+        .write_all(http_response(status, "text/plain", status).as_bytes())
+        .await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Check the auth token advertised in the lock file, answering 401 without it
+async fn authorized(
+    head: &RequestHead,
+    auth_token: &str,
+    peer_addr: SocketAddr,
+    writer: &mut OwnedWriteHalf,
+) -> Result<bool> {
+    if head
+        .header(AUTH_HEADER)
+        .is_some_and(|token| tokens_match(token, auth_token))
+    {
+        return Ok(true);
+    }
+
+    warn!(
+        "Rejecting SSE request from {}: missing or invalid auth token",
+        peer_addr
+    );
+    let status = "401 Unauthorized";
+    writer
+        .write_all(http_response(status, "text/plain", status).as_bytes())
+        .await?;
+    writer.shutdown().await?;
+    Ok(false)
+}
+
+/// The sessionId query parameter of a request target
+fn session_id(target: &str) -> Option<&str> {
+    let (_, query) = target.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("sessionId="))
+}
+
+/// An SSE event, with every line of `data` in a data field
+fn event(name: &str, data: &str) -> String {
+    let mut event = format!("event: {}\n", name);
+    for line in data.lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    event
+}
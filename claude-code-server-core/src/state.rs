@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use crate::config::SharedConfig;
+use crate::diagnostics::SharedDiagnostics;
+use crate::documents::SharedDocuments;
+use crate::hashes::SharedContentHashes;
+use crate::indexer::SharedSymbolIndex;
+use crate::journal::SharedUndoJournal;
+use crate::lsp::{DebounceHook, SharedSelection};
+use crate::mcp::DiffTabs;
+use crate::selections::SharedSelectionHistory;
+use crate::shutdown::Shutdown;
+use crate::status::ServerStatus;
+use crate::trust::SharedWorkspaceTrust;
+use crate::workspace::SharedWorkspaceFolders;
+
+// The editor state the LSP server collects and the MCP server serves to Claude.
+// ServerBuilder makes one and hands the same SharedServerState to both, and the
+// LSP server passes it on to its command handler and background tasks. Each part
+// does its own locking, so LSP handlers, tool calls and command tasks read and
+// update it concurrently, and none of them holds a lock on the whole.
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerState {
+    /// Diagnostics the editor published
+    pub diagnostics: SharedDiagnostics,
+    /// Documents open in the editor
+    pub documents: SharedDocuments,
+    /// Last selection that made it through the debouncer
+    pub latest_selection: SharedSelection,
+    /// Recent selections per file
+    pub selection_history: SharedSelectionHistory,
+    pub workspace_folders: SharedWorkspaceFolders,
+    /// Settings files merged with the editor's settings
+    pub config: SharedConfig,
+    /// Declarations across the workspace
+    pub symbol_index: SharedSymbolIndex,
+    /// Diff tabs opened through openDiff, updated when their files move
+    pub diff_tabs: DiffTabs,
+    /// Edits applied for Claude, for undoing them
+    pub undo_journal: SharedUndoJournal,
+    /// Files as Claude was last shown them
+    pub content_hashes: SharedContentHashes,
+    /// Whether the user trusts the workspace
+    pub trust: SharedWorkspaceTrust,
+    pub status: ServerStatus,
+    /// Triggered by the shutdown request, stops the background tasks
+    pub shutdown: Shutdown,
+    /// See ServerBuilder::with_debounce_hook
+    pub debounce_hook: Option<DebounceHook>,
+}
+
+pub type SharedServerState = Arc<ServerState>;
@@ -0,0 +1,166 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use crate::logging;
+use crate::metrics::{MetricsSnapshot, Sent, SharedMetrics};
+use crate::notifications::{NotificationStats, SharedNotificationBus};
+use crate::process::ClaudeProcessManager;
+
+// What an editor status indicator needs at a glance: whether Claude is connected
+// over MCP, how many notifications went out and how much text they and tool
+// results sent Claude, whether a supervised Claude CLI is
+// up, whether the debounce task has had to be restarted, the last error logged,
+// and the port (or Unix socket) the MCP server ended up listening on. Reported by the claude-code.status command and by GET /status on the
+// WebSocket port.
+
+#[derive(Debug, Clone)]
+pub struct ServerStatus {
+    started: Instant,
+    /// Open MCP connections
+    connections: Arc<AtomicUsize>,
+    notifications: Option<SharedNotificationBus>,
+    claude_process: Option<ClaudeProcessManager>,
+    metrics: SharedMetrics,
+    listening: Arc<OnceLock<Listening>>,
+}
+
+/// Where the MCP server listens, once it does
+#[derive(Debug, Clone)]
+struct Listening {
+    port: Option<u16>,
+    socket_path: Option<PathBuf>,
+}
+
+impl Default for ServerStatus {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: Arc::default(),
+            notifications: None,
+            claude_process: None,
+            metrics: SharedMetrics::default(),
+            listening: Arc::default(),
+        }
+    }
+}
+
+/// Counts an MCP connection as open until dropped
+pub struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub connected: bool,
+    pub connections: usize,
+    pub notifications: NotificationStats,
+    /// Whether the supervised Claude CLI is running; None when it isn't supervised
+    pub claude_running: Option<bool>,
+    /// Times the debounce task panicked and was started again; selections and
+    /// debounced notifications held at the time were lost
+    pub debounce_restarts: u64,
+    /// Text sent to Claude since the server started, by notification method or
+    /// tool
+    pub context_sent: BTreeMap<String, Sent>,
+    /// Estimated tokens of all of it
+    pub context_tokens: u64,
+    /// Port the MCP server listens on, which differs from the one asked for when
+    /// that was busy; None before it listens, or when it listens on a socket
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+    pub last_error: Option<LastError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastError {
+    pub message: String,
+    pub seconds_ago: u64,
+}
+
+impl ServerStatus {
+    pub fn with_notifications(mut self, notifications: SharedNotificationBus) -> Self {
+        self.notifications = Some(notifications);
+        self
+    }
+
+    pub fn with_claude_process(mut self, claude_process: ClaudeProcessManager) -> Self {
+        self.claude_process = Some(claude_process);
+        self
+    }
+
+    /// Record where the MCP server listens: `port`, or the Unix socket at
+    /// `socket_path`
+    pub fn listening(&self, port: Option<u16>, socket_path: Option<PathBuf>) {
+        let _ = self.listening.set(Listening { port, socket_path });
+    }
+
+    pub fn metrics(&self) -> &SharedMetrics {
+        &self.metrics
+    }
+
+    /// The metrics measured so far, with the notification bus's counts
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let mut snapshot = self.metrics.snapshot();
+        if let Some(bus) = &self.notifications {
+            snapshot.notifications_sent = bus.sent_by_method();
+            snapshot.notifications_dropped = bus.stats().dropped;
+        }
+        snapshot
+    }
+
+    /// Count an MCP connection as open for the lifetime of the guard
+    pub fn connected(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            connections: self.connections.clone(),
+        }
+    }
+
+    pub async fn report(&self) -> StatusReport {
+        let connections = self.connections.load(Ordering::Relaxed);
+        let claude_running = match &self.claude_process {
+            Some(claude_process) => Some(claude_process.is_running().await),
+            None => None,
+        };
+        let context_sent = self.metrics.context_sent_by_source();
+        StatusReport {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            connected: connections > 0,
+            connections,
+            notifications: self
+                .notifications
+                .as_ref()
+                .map(|bus| bus.stats())
+                .unwrap_or_default(),
+            claude_running,
+            debounce_restarts: self.metrics.debounce_restarts(),
+            context_tokens: context_sent.values().map(|sent| sent.tokens).sum(),
+            context_sent,
+            port: self.listening.get().and_then(|listening| listening.port),
+            socket_path: self
+                .listening
+                .get()
+                .and_then(|listening| listening.socket_path.clone()),
+            last_error: logging::last_error().map(|error| LastError {
+                message: error.message,
+                seconds_ago: error.at.elapsed().as_secs(),
+            }),
+        }
+    }
+}
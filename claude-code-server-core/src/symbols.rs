@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tower_lsp::lsp_types::{DocumentSymbol, Position, Range, SymbolKind, Url};
+
+use crate::syntax;
+use crate::text::{LineIndex, PositionEncoding};
+
+// Symbol extraction: a tree-sitter parse for the languages syntax.rs has a
+// grammar for, and otherwise a lightweight, language-agnostic scan for
+// declaration keywords. The scan is good enough to offer symbol mentions without
+// a real parser for every language.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolMatch {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// 0-based line of the declaration
+    pub line: u32,
+    /// 0-based last line of the declaration's body (best effort)
+    pub end_line: u32,
+}
+
+// Modifiers that may precede a declaration keyword
+const MODIFIERS: &[&str] = &[
+    "pub",
+    "pub(crate)",
+    "pub(super)",
+    "export",
+    "default",
+    "async",
+    "unsafe",
+    "static",
+    "public",
+    "private",
+    "protected",
+    "internal",
+    "abstract",
+    "final",
+    "override",
+    "open",
+    "data",
+    "sealed",
+];
+
+fn keyword_kind(keyword: &str) -> Option<SymbolKind> {
+    match keyword {
+        "fn" | "def" | "defp" | "function" | "fun" | "func" => Some(SymbolKind::FUNCTION),
+        "struct" => Some(SymbolKind::STRUCT),
+        "class" | "object" => Some(SymbolKind::CLASS),
+        "enum" => Some(SymbolKind::ENUM),
+        "trait" | "interface" | "protocol" => Some(SymbolKind::INTERFACE),
+        "type" => Some(SymbolKind::TYPE_PARAMETER),
+        "mod" | "module" | "defmodule" | "namespace" => Some(SymbolKind::MODULE),
+        "const" => Some(SymbolKind::CONSTANT),
+        _ => None,
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Parse a single line as a declaration, returning its name and kind
+fn parse_declaration(line: &str) -> Option<(String, SymbolKind)> {
+    let mut words = line
+        .split_whitespace()
+        .skip_while(|word| MODIFIERS.contains(word));
+    let kind = keyword_kind(words.next()?)?;
+
+    let name: String = words
+        .next()?
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '$'))
+        .collect();
+    if name.is_empty() || name.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((name, kind))
+}
+
+/// Find where a declaration starting at `start` ends: the first later non-blank line
+/// indented no deeper than the declaration closes it (and is included when it is a
+/// closing token such as `}` or `end` at the declaration's own indentation; a
+/// shallower one closes an outer block)
+fn block_end(lines: &[&str], start: usize) -> usize {
+    let base = indentation(lines[start]);
+    let mut end = start;
+
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indentation(line) <= base {
+            let trimmed = line.trim_start();
+            let closes =
+                trimmed.starts_with('}') || trimmed.starts_with(')') || trimmed.starts_with("end");
+            if closes && indentation(line) == base {
+                end = index;
+            }
+            break;
+        }
+        end = index;
+    }
+
+    end
+}
+
+/// Extract declarations from a document's text, in document order
+pub fn extract_symbols(text: &str, language_id: &str) -> Vec<SymbolMatch> {
+    syntax::extract_symbols(text, language_id).unwrap_or_else(|| scan_symbols(text))
+}
+
+/// Declarations found by their keywords
+fn scan_symbols(text: &str) -> Vec<SymbolMatch> {
+    let lines: Vec<&str> = text.lines().collect();
+
+    lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let (name, kind) = parse_declaration(line)?;
+            Some(SymbolMatch {
+                name,
+                kind,
+                line: index as u32,
+                end_line: block_end(&lines, index) as u32,
+            })
+        })
+        .collect()
+}
+
+/// Extracted symbols, shared between the cache and its readers
+pub type Symbols = Arc<Vec<SymbolMatch>>;
+
+/// Characters that can make up an identifier in most languages
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The identifier under `position` and its range
+pub fn identifier_at(
+    text: &str,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<(String, Range)> {
+    let index = LineIndex::new(text);
+    let line = index.line(position.line)?;
+    let line_start = index.offset(Position::new(position.line, 0), encoding)?;
+    let cursor = index.offset(position, encoding)? - line_start;
+
+    let start = line[..cursor]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier_char(*c))
+        .last()
+        .map_or(cursor, |(index, _)| index);
+    let end = line[cursor..]
+        .char_indices()
+        .find(|(_, c)| !is_identifier_char(*c))
+        .map_or(line.len(), |(index, _)| cursor + index);
+    if start == end {
+        return None;
+    }
+
+    let range = Range::new(
+        Position::new(position.line, encoding.measure(&line[..start])),
+        Position::new(position.line, encoding.measure(&line[..end])),
+    );
+    Some((line[start..end].to_string(), range))
+}
+
+/// Byte offsets of whole-word occurrences of `word` in `line`
+pub fn word_occurrences<'a>(line: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a {
+    line.match_indices(word)
+        .map(|(start, _)| start)
+        .filter(move |&start| {
+            let before = line[..start].chars().next_back();
+            let after = line[start + word.len()..].chars().next();
+            !before.is_some_and(is_identifier_char) && !after.is_some_and(is_identifier_char)
+        })
+}
+
+/// Symbols of open documents, extracted again only when a document's version changes
+#[derive(Debug, Default)]
+pub struct SymbolCache {
+    entries: Mutex<HashMap<Url, (i32, Symbols)>>,
+}
+
+impl SymbolCache {
+    /// Symbols of `text`, which is version `version` of the document at `uri`
+    pub fn get(&self, uri: &Url, version: i32, text: &str, language_id: &str) -> Symbols {
+        if let Some((cached_version, symbols)) = self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(uri)
+        {
+            if *cached_version == version {
+                return symbols.clone();
+            }
+        }
+
+        let symbols = Arc::new(extract_symbols(text, language_id));
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(uri.clone(), (version, symbols.clone()));
+        symbols
+    }
+
+    pub fn remove(&self, uri: &Url) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(uri);
+    }
+}
+
+/// Symbols whose declaration spans every line of `range`, outermost first
+pub fn enclosing_symbols(symbols: &[SymbolMatch], range: Range) -> Vec<&SymbolMatch> {
+    let mut enclosing: Vec<&SymbolMatch> = symbols
+        .iter()
+        .filter(|symbol| symbol.line <= range.start.line && symbol.end_line >= range.end.line)
+        .collect();
+    enclosing.sort_by_key(|symbol| (symbol.line, std::cmp::Reverse(symbol.end_line)));
+    enclosing
+}
+
+/// `range` grown to the whole declaration of the nearest enclosing symbol, or
+/// unchanged when no symbol encloses it
+pub fn expand_to_symbol(symbols: &[SymbolMatch], range: Range) -> Range {
+    match enclosing_symbols(symbols, range).last() {
+        // The end character is clamped to the end of the line
+        Some(symbol) => Range::new(
+            Position::new(symbol.line, 0),
+            Position::new(symbol.end_line, u32::MAX),
+        ),
+        None => range,
+    }
+}
+
+/// Symbols as an outline for textDocument/documentSymbol, each nested in the
+/// nearest declaration spanning its lines
+pub fn document_symbols(
+    symbols: &[SymbolMatch],
+    text: &str,
+    encoding: PositionEncoding,
+) -> Vec<DocumentSymbol> {
+    let index = LineIndex::new(text);
+    let mut ordered: Vec<&SymbolMatch> = symbols.iter().collect();
+    ordered.sort_by_key(|symbol| (symbol.line, std::cmp::Reverse(symbol.end_line)));
+
+    // Declarations still open at the current one, outermost first
+    let mut open: Vec<(&SymbolMatch, DocumentSymbol)> = Vec::new();
+    let mut outline = Vec::new();
+    for symbol in ordered {
+        while let Some((parent, _)) = open.last() {
+            if contains(parent, symbol) {
+                break;
+            }
+            close(&mut open, &mut outline);
+        }
+        open.push((symbol, document_symbol(symbol, &index, encoding)));
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut outline);
+    }
+    outline
+}
+
+/// Whether `child` is declared inside `parent`, and not merely on the same lines
+fn contains(parent: &SymbolMatch, child: &SymbolMatch) -> bool {
+    parent.line <= child.line
+        && child.end_line <= parent.end_line
+        && (parent.line < child.line || child.end_line < parent.end_line)
+}
+
+/// Move the innermost open declaration into its parent, or the outline
+fn close(open: &mut Vec<(&SymbolMatch, DocumentSymbol)>, outline: &mut Vec<DocumentSymbol>) {
+    let Some((_, symbol)) = open.pop() else {
+        return;
+    };
+    match open.last_mut() {
+        Some((_, parent)) => parent.children.get_or_insert_with(Vec::new).push(symbol),
+        None => outline.push(symbol),
+    }
+}
+
+fn document_symbol(
+    symbol: &SymbolMatch,
+    index: &LineIndex,
+    encoding: PositionEncoding,
+) -> DocumentSymbol {
+    let end_character = index
+        .line(symbol.end_line)
+        .map_or(0, |line| encoding.measure(line));
+    let range = Range::new(
+        Position::new(symbol.line, 0),
+        Position::new(symbol.end_line, end_character),
+    );
+    // The name as written on the declaration line, or the whole first line
+    let declaration = index.line(symbol.line).unwrap_or_default();
+    let selection_range = match word_occurrences(declaration, &symbol.name).next() {
+        Some(start) => Range::new(
+            Position::new(symbol.line, encoding.measure(&declaration[..start])),
+            Position::new(
+                symbol.line,
+                encoding.measure(&declaration[..start + symbol.name.len()]),
+            ),
+        ),
+        None => Range::new(
+            range.start,
+            Position::new(symbol.line, encoding.measure(declaration)),
+        ),
+    };
+
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: None,
+        kind: symbol.kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: None,
+    }
+}
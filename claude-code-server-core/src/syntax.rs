@@ -0,0 +1,323 @@
+use std::ops::Range;
+use tower_lsp::lsp_types::SymbolKind;
+use tracing::debug;
+use tree_sitter::{Language, Node, Parser, Tree};
+
+use crate::symbols::SymbolMatch;
+
+// Declarations from a real parse, for languages with a bundled tree-sitter
+// grammar. Unlike the keyword scanner in symbols.rs, the parse gets multi-line
+// signatures, arrow functions and exact block ends right, and tells methods from
+// functions. symbols.rs falls back to the scanner for every other language.
+// The same parse gives textDocument/selectionRange the syntax nodes around the
+// cursor, so expanding a selection goes token, expression, statement, function.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Grammar {
+    C,
+    Cpp,
+    Elixir,
+    Go,
+    Java,
+    JavaScript,
+    Python,
+    Ruby,
+    Rust,
+    Tsx,
+    TypeScript,
+}
+
+impl Grammar {
+    fn for_language(language_id: &str) -> Option<Self> {
+        match language_id {
+            "c" => Some(Self::C),
+            "cpp" => Some(Self::Cpp),
+            "elixir" => Some(Self::Elixir),
+            "go" => Some(Self::Go),
+            "java" => Some(Self::Java),
+            "javascript" | "javascriptreact" => Some(Self::JavaScript),
+            "python" => Some(Self::Python),
+            "ruby" => Some(Self::Ruby),
+            "rust" => Some(Self::Rust),
+            "tsx" | "typescriptreact" => Some(Self::Tsx),
+            "typescript" => Some(Self::TypeScript),
+            _ => None,
+        }
+    }
+
+    fn language(self) -> Language {
+        match self {
+            Self::C => tree_sitter_c::LANGUAGE.into(),
+            Self::Cpp => tree_sitter_cpp::LANGUAGE.into(),
+            Self::Elixir => tree_sitter_elixir::LANGUAGE.into(),
+            Self::Go => tree_sitter_go::LANGUAGE.into(),
+            Self::Java => tree_sitter_java::LANGUAGE.into(),
+            Self::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+            Self::Ruby => tree_sitter_ruby::LANGUAGE.into(),
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Self::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+        }
+    }
+
+    /// The declaration `node` makes, if any
+    fn declaration(self, node: Node, text: &str) -> Option<(SymbolKind, String)> {
+        let kind = match self {
+            Self::C | Self::Cpp => return c_declaration(node, text),
+            Self::Elixir => return elixir_declaration(node, text),
+            Self::Go => go_kind(node)?,
+            Self::Java => java_kind(node.kind())?,
+            Self::JavaScript | Self::Tsx | Self::TypeScript => {
+                return script_declaration(node, text)
+            }
+            Self::Python => python_kind(node.kind())?,
+            Self::Ruby => ruby_kind(node.kind())?,
+            Self::Rust => rust_kind(node.kind())?,
+        };
+        Some((kind, field_text(node, "name", text)?))
+    }
+}
+
+fn parse(text: &str, language_id: &str) -> Option<(Grammar, Tree)> {
+    let grammar = Grammar::for_language(language_id)?;
+    let mut parser = Parser::new();
+    if let Err(e) = parser.set_language(&grammar.language()) {
+        debug!("No {} parser: {}", language_id, e);
+        return None;
+    }
+    Some((grammar, parser.parse(text, None)?))
+}
+
+/// Declarations in `text`, or None when `language_id` has no bundled grammar
+pub fn extract_symbols(text: &str, language_id: &str) -> Option<Vec<SymbolMatch>> {
+    let (grammar, tree) = parse(text, language_id)?;
+
+    // Depth first, in document order; the flag says whether functions at this
+    // level are methods of a type
+    let mut symbols = Vec::new();
+    let mut pending = vec![(tree.root_node(), false)];
+    while let Some((node, in_type)) = pending.pop() {
+        let mut children_in_type = in_type || is_type_body(node.kind());
+        if let Some((kind, name)) = grammar.declaration(node, text) {
+            let kind = if kind == SymbolKind::FUNCTION && in_type {
+                SymbolKind::METHOD
+            } else {
+                kind
+            };
+            children_in_type = matches!(
+                kind,
+                SymbolKind::CLASS | SymbolKind::STRUCT | SymbolKind::INTERFACE | SymbolKind::ENUM
+            );
+            symbols.push(SymbolMatch {
+                name,
+                kind,
+                line: node.start_position().row as u32,
+                end_line: end_line(node),
+            });
+        }
+
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.named_children(&mut cursor).collect();
+        pending.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|child| (child, children_in_type)),
+        );
+    }
+    Some(symbols)
+}
+
+/// For each byte offset in `offsets`, the byte ranges of the syntax nodes around
+/// it, innermost first, each larger than the one before and ending with the
+/// whole text. None when `language_id` has no bundled grammar.
+pub fn enclosing_ranges(
+    text: &str,
+    language_id: &str,
+    offsets: &[usize],
+) -> Option<Vec<Vec<Range<usize>>>> {
+    let (_, tree) = parse(text, language_id)?;
+    let root = tree.root_node();
+    let around = |offset: usize| {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut node = root.descendant_for_byte_range(offset, offset);
+        while let Some(current) = node {
+            let range = current.byte_range();
+            // A node spanning just what its only child does adds nothing
+            let grows = ranges
+                .last()
+                .is_none_or(|last| range.start < last.start || range.end > last.end);
+            if grows {
+                ranges.push(range);
+            }
+            node = current.parent();
+        }
+        if ranges.last() != Some(&(0..text.len())) {
+            ranges.push(0..text.len());
+        }
+        ranges
+    };
+    Some(offsets.iter().map(|&offset| around(offset)).collect())
+}
+
+/// Blocks whose functions are methods without the block being a symbol itself
+fn is_type_body(kind: &str) -> bool {
+    matches!(kind, "impl_item" | "trait_item")
+}
+
+/// Last line of `node`, not counting a trailing newline it swallowed
+fn end_line(node: Node) -> u32 {
+    let end = node.end_position();
+    if end.column == 0 && end.row > node.start_position().row {
+        end.row as u32 - 1
+    } else {
+        end.row as u32
+    }
+}
+
+fn field_text(node: Node, field: &str, text: &str) -> Option<String> {
+    let name = node
+        .child_by_field_name(field)?
+        .utf8_text(text.as_bytes())
+        .ok()?;
+    Some(name.to_string())
+}
+
+fn rust_kind(kind: &str) -> Option<SymbolKind> {
+    match kind {
+        "function_item" | "function_signature_item" => Some(SymbolKind::FUNCTION),
+        "struct_item" | "union_item" => Some(SymbolKind::STRUCT),
+        "enum_item" => Some(SymbolKind::ENUM),
+        "trait_item" => Some(SymbolKind::INTERFACE),
+        "mod_item" => Some(SymbolKind::MODULE),
+        "const_item" | "static_item" => Some(SymbolKind::CONSTANT),
+        "type_item" => Some(SymbolKind::TYPE_PARAMETER),
+        _ => None,
+    }
+}
+
+fn python_kind(kind: &str) -> Option<SymbolKind> {
+    match kind {
+        "function_definition" => Some(SymbolKind::FUNCTION),
+        "class_definition" => Some(SymbolKind::CLASS),
+        _ => None,
+    }
+}
+
+fn ruby_kind(kind: &str) -> Option<SymbolKind> {
+    match kind {
+        "method" => Some(SymbolKind::FUNCTION),
+        "singleton_method" => Some(SymbolKind::METHOD),
+        "class" => Some(SymbolKind::CLASS),
+        "module" => Some(SymbolKind::MODULE),
+        _ => None,
+    }
+}
+
+fn java_kind(kind: &str) -> Option<SymbolKind> {
+    match kind {
+        "class_declaration" => Some(SymbolKind::CLASS),
+        "interface_declaration" | "annotation_type_declaration" => Some(SymbolKind::INTERFACE),
+        "enum_declaration" => Some(SymbolKind::ENUM),
+        "record_declaration" => Some(SymbolKind::STRUCT),
+        "method_declaration" => Some(SymbolKind::METHOD),
+        "constructor_declaration" => Some(SymbolKind::CONSTRUCTOR),
+        _ => None,
+    }
+}
+
+fn go_kind(node: Node) -> Option<SymbolKind> {
+    match node.kind() {
+        "function_declaration" => Some(SymbolKind::FUNCTION),
+        "method_declaration" => Some(SymbolKind::METHOD),
+        "type_spec" => Some(match node.child_by_field_name("type")?.kind() {
+            "struct_type" => SymbolKind::STRUCT,
+            "interface_type" => SymbolKind::INTERFACE,
+            _ => SymbolKind::TYPE_PARAMETER,
+        }),
+        _ => None,
+    }
+}
+
+/// JavaScript and TypeScript, including functions assigned to variables
+fn script_declaration(node: Node, text: &str) -> Option<(SymbolKind, String)> {
+    let kind = match node.kind() {
+        "function_declaration" | "generator_function_declaration" => SymbolKind::FUNCTION,
+        "class_declaration" | "abstract_class_declaration" => SymbolKind::CLASS,
+        "method_definition" | "method_signature" | "abstract_method_signature" => {
+            SymbolKind::METHOD
+        }
+        "interface_declaration" => SymbolKind::INTERFACE,
+        "type_alias_declaration" => SymbolKind::TYPE_PARAMETER,
+        "enum_declaration" => SymbolKind::ENUM,
+        "internal_module" | "module" => SymbolKind::MODULE,
+        "variable_declarator" => {
+            let value = node.child_by_field_name("value")?;
+            match value.kind() {
+                "arrow_function" | "function_expression" | "function" | "generator_function" => {
+                    SymbolKind::FUNCTION
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some((kind, field_text(node, "name", text)?))
+}
+
+/// C and C++, where a function's name is buried in its declarator
+fn c_declaration(node: Node, text: &str) -> Option<(SymbolKind, String)> {
+    let kind = match node.kind() {
+        "function_definition" => {
+            let mut declarator = node.child_by_field_name("declarator")?;
+            while matches!(
+                declarator.kind(),
+                "function_declarator"
+                    | "pointer_declarator"
+                    | "reference_declarator"
+                    | "parenthesized_declarator"
+                    | "attributed_declarator"
+            ) {
+                declarator = declarator.child_by_field_name("declarator")?;
+            }
+            let name = declarator.utf8_text(text.as_bytes()).ok()?;
+            return Some((SymbolKind::FUNCTION, name.to_string()));
+        }
+        // Only definitions, not every use of `struct foo`
+        _ if node.child_by_field_name("body").is_none() => return None,
+        "struct_specifier" | "union_specifier" => SymbolKind::STRUCT,
+        "class_specifier" => SymbolKind::CLASS,
+        "enum_specifier" => SymbolKind::ENUM,
+        "namespace_definition" => SymbolKind::NAMESPACE,
+        _ => return None,
+    };
+    Some((kind, field_text(node, "name", text)?))
+}
+
+/// Elixir, where definitions are calls to defmodule, def and friends
+fn elixir_declaration(node: Node, text: &str) -> Option<(SymbolKind, String)> {
+    if node.kind() != "call" {
+        return None;
+    }
+    let kind = match field_text(node, "target", text)?.as_str() {
+        "defmodule" => SymbolKind::MODULE,
+        "def" | "defp" | "defmacro" | "defmacrop" => SymbolKind::FUNCTION,
+        _ => return None,
+    };
+
+    let mut cursor = node.walk();
+    let arguments = node
+        .named_children(&mut cursor)
+        .find(|child| child.kind() == "arguments")?;
+    let mut head = arguments.named_child(0)?;
+    // `def name(args) when guard`
+    if head.kind() == "binary_operator" {
+        head = head.child_by_field_name("left")?;
+    }
+    if head.kind() == "call" {
+        head = head.child_by_field_name("target")?;
+    }
+    let name = head.utf8_text(text.as_bytes()).ok()?;
+    Some((kind, name.to_string()))
+}
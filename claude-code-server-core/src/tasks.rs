@@ -0,0 +1,219 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+// Commands Claude suggests, written as Zed tasks to <worktree>/.zed/tasks.json
+// instead of being run. They show up in Zed's task picker (task: spawn) and run
+// in its task terminal if and when the user picks them. Claude's tasks are tagged
+// "claude", and suggesting one again under the same label replaces it. The file
+// is edited in place, so the user's own tasks and comments stay as they are.
+
+const TASKS_FILE: &str = ".zed/tasks.json";
+pub const TASK_TAG: &str = "claude";
+// Longest label made up from a command
+const MAX_LABEL_CHARS: usize = 60;
+
+/// A task as Zed reads it from tasks.json
+#[derive(Debug, Clone, Serialize)]
+pub struct ZedTask {
+    pub label: String,
+    pub command: String,
+    /// Relative to the worktree, as $ZED_WORKTREE_ROOT/<dir>
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    pub reveal: &'static str,
+    pub tags: Vec<&'static str>,
+}
+
+impl ZedTask {
+    /// A task running `command` in `cwd`, a directory of the worktree at `root`.
+    /// Without a label, the command is the label.
+    pub fn new(command: &str, label: Option<&str>, root: &Path, cwd: &Path) -> Self {
+        let label = match label {
+            Some(label) => label.to_string(),
+            None => {
+                let mut label: String = command.chars().take(MAX_LABEL_CHARS).collect();
+                if label.len() < command.len() {
+                    label.push('…');
+                }
+                label
+            }
+        };
+        let cwd = match cwd.strip_prefix(root) {
+            Ok(relative) if relative.as_os_str().is_empty() => None,
+            Ok(relative) => Some(format!(
+                "$ZED_WORKTREE_ROOT/{}",
+                relative.to_string_lossy().replace('\\', "/")
+            )),
+            Err(_) => Some(cwd.to_string_lossy().to_string()),
+        };
+        Self {
+            label,
+            command: command.to_string(),
+            cwd,
+            reveal: "always",
+            tags: vec![TASK_TAG],
+        }
+    }
+}
+
+/// Add `task` to the tasks.json of the worktree at `root`, replacing Claude's
+/// task of the same label. Returns the file written.
+pub fn write(root: &Path, task: &ZedTask) -> Result<PathBuf> {
+    let path = root.join(TASKS_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let text = with_task(&text, task)
+        .with_context(|| format!("Cannot add a task to {}", path.display()))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Added task {:?} to {}", task.label, path.display());
+    Ok(path)
+}
+
+/// `text`, the contents of a tasks.json, with `task` added or replacing Claude's
+/// task of the same label
+fn with_task(text: &str, task: &ZedTask) -> Result<String> {
+    let json = indent(&serde_json::to_string_pretty(task)?);
+    let code = blank_comments(text);
+    let Some(open) = code.find(|c: char| !c.is_whitespace()) else {
+        return Ok(format!("[\n{}\n]\n", json));
+    };
+    if code.as_bytes()[open] != b'[' {
+        bail!("the file doesn't hold a list of tasks");
+    }
+
+    let elements = elements(&code, open)?;
+    let existing = elements.iter().find(|span| {
+        serde_json::from_str::<Value>(&code[(*span).clone()]).is_ok_and(|element| {
+            element["label"] == task.label.as_str()
+                && element["tags"]
+                    .as_array()
+                    .is_some_and(|tags| tags.iter().any(|tag| tag == TASK_TAG))
+        })
+    });
+
+    let mut text = text.to_string();
+    match (existing, elements.last()) {
+        (Some(span), _) => text.replace_range(span.clone(), json.trim_start()),
+        (None, Some(last)) => text.insert_str(last.end, &format!(",\n{}", json)),
+        (None, None) => text.insert_str(open + 1, &format!("\n{}", json)),
+    }
+    Ok(text)
+}
+
+/// Byte ranges of the objects in the array opening at `open`
+fn elements(code: &str, open: usize) -> Result<Vec<Range<usize>>> {
+    let mut spans = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in code.char_indices().skip_while(|(i, _)| *i < open) {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => {
+                if depth == 1 && c == '{' {
+                    start = i;
+                }
+                depth += 1;
+            }
+            ']' | '}' => {
+                depth -= 1;
+                if depth == 1 && c == '}' {
+                    spans.push(start..i + 1);
+                }
+                if depth == 0 {
+                    return Ok(spans);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(anyhow!("the list of tasks isn't closed"))
+}
+
+/// `text` with its // and /* */ comments replaced by spaces, so offsets into it
+/// are offsets into `text`
+fn blank_comments(text: &str) -> String {
+    let mut code = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    let mut escaped = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            code.push(c);
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                code.push(c);
+            }
+            ('/', Some('/')) => {
+                code.push(' ');
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    code.push_str(&" ".repeat(next.len_utf8()));
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                code.push_str("  ");
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    code.push_str(&blank(next));
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            _ => code.push(c),
+        }
+    }
+    code
+}
+
+/// Spaces as wide (in bytes) as `c`, keeping line breaks
+fn blank(c: char) -> String {
+    match c {
+        '\n' => "\n".to_string(),
+        _ => " ".repeat(c.len_utf8()),
+    }
+}
+
+fn indent(json: &str) -> String {
+    json.lines()
+        .map(|line| format!("  {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
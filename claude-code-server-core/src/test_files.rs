@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use crate::text;
+use crate::workspace;
+
+// Where claude-code.write-tests puts the tests it asks Claude for, following the
+// layout the worktree already uses: Rust integration tests in the crate's tests/
+// directory (or a tests module in the file itself without one), Go's
+// <name>_test.go, Python's test_<name>.py (under a top-level tests/ when there is
+// one), and JavaScript/TypeScript <name>.test.ts or .spec.ts (inside __tests__
+// when the directory has one). An existing test file from the worktree goes along
+// with the prompt, so Claude follows the project's own conventions.
+
+// Most of an example or existing test file shown to Claude
+const MAX_SHOWN_BYTES: usize = 4000;
+// Files looked through for an example
+const MAX_SCANNED_FILES: usize = 5000;
+
+const SCRIPT_EXTENSIONS: [&str; 6] = ["js", "jsx", "ts", "tsx", "mjs", "cjs"];
+
+/// The file tests for a source file belong in
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestFile {
+    pub path: PathBuf,
+    /// Another of the project's test files, showing its conventions
+    pub example: Option<PathBuf>,
+}
+
+/// Where tests for `source` go, for languages with a known layout. `root` is the
+/// workspace folder containing it.
+pub fn locate(source: &Path, root: &Path) -> Option<TestFile> {
+    let dir = source.parent()?;
+    let stem = source.file_stem()?.to_str()?;
+    let extension = source.extension()?.to_str()?;
+
+    // The test file, and where to look for an example of the project's tests
+    let (path, examples): (PathBuf, Option<(&Path, &str)>) = match extension {
+        "rs" => {
+            let crate_dir = dir
+                .ancestors()
+                .take_while(|ancestor| ancestor.starts_with(root))
+                .find(|ancestor| ancestor.join("Cargo.toml").is_file())
+                .unwrap_or(root);
+            let tests = crate_dir.join("tests");
+            if tests.is_dir() && !source.starts_with(&tests) {
+                (
+                    tests.join(format!("{}.rs", stem)),
+                    Some((crate_dir, "tests/**/*.rs")),
+                )
+            } else {
+                // Tests go in a module at the end of the file itself
+                (source.to_path_buf(), None)
+            }
+        }
+        "go" => (
+            dir.join(format!("{}_test.go", stem)),
+            Some((root, "**/*_test.go")),
+        ),
+        "py" => {
+            let tests = root.join("tests");
+            let dir = if tests.is_dir() { &tests } else { dir };
+            (
+                dir.join(format!("test_{}.py", stem)),
+                Some((root, "**/test_*.py")),
+            )
+        }
+        extension if SCRIPT_EXTENSIONS.contains(&extension) => {
+            let (suffix, glob) = if uses_spec_files(root) {
+                ("spec", "**/*.spec.*")
+            } else {
+                ("test", "**/*.test.*")
+            };
+            let tests = dir.join("__tests__");
+            let dir = if tests.is_dir() { &tests } else { dir };
+            (
+                dir.join(format!("{}.{}.{}", stem, suffix, extension)),
+                Some((root, glob)),
+            )
+        }
+        _ => return None,
+    };
+
+    // A test file that exists already shows the conventions itself
+    let example = examples.filter(|_| !path.exists()).and_then(|(dir, glob)| {
+        let files = workspace::list_matching(dir, glob, MAX_SCANNED_FILES).ok()?;
+        files.into_iter().next().map(|file| dir.join(file))
+    });
+    Some(TestFile { path, example })
+}
+
+impl TestFile {
+    /// What to tell Claude, after the prompt, about where its tests go: the start
+    /// of the file when it has `existing` contents, or else the `example` text
+    pub fn instructions(&self, existing: Option<&str>, example: Option<&str>) -> String {
+        let path = self.path.display();
+        match existing {
+            Some(existing) => {
+                let (shown, cut) = shown_part(existing);
+                format!(
+                    "\n\nThe tests will be added to the end of {}, which {}:\n\n```\n{}\n```\n\n\
+                     Reply with only the code to add, in a single fenced code block, without \
+                     repeating what the file already has.",
+                    path,
+                    if cut { "starts" } else { "contains" },
+                    shown.trim_end()
+                )
+            }
+            None => {
+                let mut instructions = format!(
+                    "\n\nThe tests will go in a new file, {}. Reply with its whole contents in \
+                     a single fenced code block.",
+                    path
+                );
+                if let (Some(example_path), Some(example)) = (&self.example, example) {
+                    instructions.push_str(&format!(
+                        " Follow the conventions of this test file from the project, {}:\n\n```\n{}\n```",
+                        example_path.display(),
+                        example.trim_end()
+                    ));
+                }
+                instructions
+            }
+        }
+    }
+}
+
+/// Whether the project's script tests are named *.spec.* rather than *.test.*
+fn uses_spec_files(root: &Path) -> bool {
+    let count = |glob| {
+        workspace::list_matching(root, glob, MAX_SCANNED_FILES)
+            .map(|files| files.len())
+            .unwrap_or_default()
+    };
+    count("**/*.spec.*") > count("**/*.test.*")
+}
+
+/// The start of `text`, cut at a line break to at most MAX_SHOWN_BYTES, and
+/// whether anything was left out
+pub fn shown_part(text: &str) -> (&str, bool) {
+    if text.len() <= MAX_SHOWN_BYTES {
+        return (text, false);
+    }
+    let mut end = MAX_SHOWN_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let end = text[..end].rfind('\n').map_or(end, |newline| newline + 1);
+    (&text[..end], true)
+}
+
+/// The example's text, as much as is shown
+pub fn read_example(path: &Path) -> Option<String> {
+    let text = text::read_file(path).ok()?;
+    Some(shown_part(&text).0.to_string())
+}
@@ -0,0 +1,215 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
+// Conversion between LSP positions and byte offsets in a document. Characters are
+// counted in the position encoding negotiated at initialize (UTF-16 unless the
+// client offers another), and lines end at \n, \r\n or \r as the LSP spec defines.
+// Files are read the way the editor shows them, without a byte order mark, and
+// text going into a file takes on its line breaks, so edits never turn into
+// whole-file diffs on CRLF projects.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Pick from the encodings the client offers, preferring UTF-8 since it
+    /// matches Rust strings. Clients that offer nothing get the UTF-16 default.
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        let offered = offered.unwrap_or_default();
+        [Self::Utf8, Self::Utf32, Self::Utf16]
+            .into_iter()
+            .find(|encoding| offered.contains(&encoding.kind()))
+            .unwrap_or_default()
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// Code units `ch` takes up in this encoding
+    fn len(self, ch: char) -> usize {
+        match self {
+            Self::Utf8 => ch.len_utf8(),
+            Self::Utf16 => ch.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Code units `text` takes up in this encoding
+    pub fn measure(self, text: &str) -> u32 {
+        text.chars().map(|ch| self.len(ch)).sum::<usize>() as u32
+    }
+}
+
+/// Line start offsets of a text, for converting positions in either direction
+#[derive(Debug)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// Byte offset where each line starts; a trailing line break starts an empty line
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        let bytes = text.as_bytes();
+        for (index, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'\n' => line_starts.push(index + 1),
+                // A lone \r ends a line; in \r\n the \n does
+                b'\r' if bytes.get(index + 1) != Some(&b'\n') => line_starts.push(index + 1),
+                _ => {}
+            }
+        }
+        Self { text, line_starts }
+    }
+
+    /// Contents of a 0-based line, without its line break
+    pub fn line(&self, line: u32) -> Option<&'a str> {
+        let start = *self.line_starts.get(line as usize)?;
+        let end = self
+            .line_starts
+            .get(line as usize + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        let line = &self.text[start..end];
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        Some(line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    /// Byte offset of `position`. A character past the end of its line is clamped
+    /// to the line end (as the spec requires), one inside a multi-unit character
+    /// snaps to that character's start, and a line past the end is None.
+    pub fn offset(&self, position: Position, encoding: PositionEncoding) -> Option<usize> {
+        let line = self.line(position.line)?;
+        let start = self.line_starts[position.line as usize];
+
+        let mut units = 0;
+        for (index, ch) in line.char_indices() {
+            let len = encoding.len(ch);
+            if units + len > position.character as usize {
+                return Some(start + index);
+            }
+            units += len;
+        }
+        Some(start + line.len())
+    }
+
+    /// Position of a byte offset, which must lie on a character boundary
+    pub fn position(&self, offset: usize, encoding: PositionEncoding) -> Position {
+        let line = self
+            .line_starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1);
+        let start = self.line_starts[line];
+        Position {
+            line: line as u32,
+            character: encoding.measure(&self.text[start..offset]),
+        }
+    }
+
+    /// The text in `range`, with its original line breaks
+    pub fn slice(&self, range: Range, encoding: PositionEncoding) -> Option<&'a str> {
+        let start = self.offset(range.start, encoding)?;
+        let end = self.offset(range.end, encoding)?;
+        self.text.get(start..end.max(start))
+    }
+}
+
+/// Byte order mark some Windows tools start UTF-8 files with. Editors keep it out
+/// of the buffer, so positions count from after it.
+const BOM: char = '\u{feff}';
+
+/// A file's contents as the editor shows them
+pub fn read_file(path: impl AsRef<Path>) -> io::Result<String> {
+    fs::read_to_string(path).map(strip_bom)
+}
+
+pub fn strip_bom(text: String) -> String {
+    match text.strip_prefix(BOM) {
+        Some(stripped) => stripped.to_string(),
+        None => text,
+    }
+}
+
+/// Write a buffer to disk, keeping the byte order mark the file had
+pub async fn write_file(path: &Path, text: &str) -> io::Result<()> {
+    let had_bom = tokio::fs::read(path)
+        .await
+        .is_ok_and(|bytes| bytes.starts_with("\u{feff}".as_bytes()));
+    if had_bom && !text.starts_with(BOM) {
+        tokio::fs::write(path, format!("{}{}", BOM, text)).await
+    } else {
+        tokio::fs::write(path, text).await
+    }
+}
+
+/// Line break style of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// The style of a text's first line break; LF when it has none
+    pub fn detect(text: &str) -> Self {
+        match text.find('\n') {
+            Some(index) if text[..index].ends_with('\r') => Self::CrLf,
+            _ => Self::Lf,
+        }
+    }
+
+    /// `text` with every line break (\n, \r\n or a lone \r) in this style
+    pub fn apply(self, text: &str) -> String {
+        let line_break = match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        };
+        let mut converted = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\r' => {
+                    chars.next_if_eq(&'\n');
+                    converted.push_str(line_break);
+                }
+                '\n' => converted.push_str(line_break),
+                ch => converted.push(ch),
+            }
+        }
+        converted
+    }
+}
+
+/// Whether two texts only differ in their line breaks
+pub fn same_ignoring_line_endings(a: &str, b: &str) -> bool {
+    a == b || LineEnding::Lf.apply(a) == LineEnding::Lf.apply(b)
+}
+
+/// `replacement` for `replaced`, ending in a line break exactly when `replaced`
+/// did, so replacing whole lines neither joins nor splits the ones around them
+pub fn keep_trailing_newline(replacement: &str, replaced: &str) -> String {
+    let without_break = |text: &'_ str| {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        text.strip_suffix('\r').unwrap_or(text).len()
+    };
+    let replacement_ends = without_break(replacement) < replacement.len();
+    let replaced_ends = without_break(replaced) < replaced.len();
+    match (replaced_ends, replacement_ends) {
+        (true, false) => format!("{}\n", replacement),
+        (false, true) => replacement[..without_break(replacement)].to_string(),
+        _ => replacement.to_string(),
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+// Whether the user trusts the files in a workspace with Claude. On the first run
+// in a workspace the editor asks, with window/showMessageRequest; the answer is
+// kept per workspace in ~/.claude/ide-trusted-workspaces.json, outside the
+// workspace so its files can't vouch for themselves. Until the user trusts it, a
+// workspace is read-only to Claude: tools that write files or run commands are
+// withheld, and the workspace's own .claude/settings.json (which could point
+// claudeBinary, zedBinary or shellEnvironment.shell at a script in the
+// repository) is ignored.
+
+const TRUST_FILE: &str = ".claude/ide-trusted-workspaces.json";
+const FORMAT_VERSION: u32 = 1;
+
+/// Buttons of the prompt; dismissing it leaves the workspace undecided
+pub const TRUST: &str = "Trust";
+pub const DISTRUST: &str = "Don't trust";
+
+/// MCP tools that write files or run commands, withheld from untrusted workspaces
+const WRITE_TOOLS: &[&str] = &[
+    "applyEdits",
+    "applyTextEdit",
+    "proposeRefactor",
+    "undoLastEdit",
+    "createFile",
+    "deleteFile",
+    "renameFile",
+    "saveDocument",
+    "runCommand",
+    "suggestTask",
+    "executeCode",
+    "generateCommitMessage",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Trust {
+    Trusted,
+    Untrusted,
+    /// Not answered yet; treated as untrusted
+    Undecided,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrustFile {
+    version: u32,
+    /// Trusted or not, by workspace path
+    workspaces: BTreeMap<PathBuf, bool>,
+}
+
+/// The user's trust in the workspace. Without a workspace, or when the embedder
+/// decides it, nothing is saved.
+#[derive(Debug)]
+pub struct WorkspaceTrust {
+    workspace: Option<PathBuf>,
+    /// Where answers are saved, if anywhere
+    path: Option<PathBuf>,
+    trust: Mutex<Trust>,
+}
+
+pub type SharedWorkspaceTrust = Arc<WorkspaceTrust>;
+
+impl Default for WorkspaceTrust {
+    fn default() -> Self {
+        Self::fixed(Trust::Undecided)
+    }
+}
+
+// Saves from several servers in one process (as in tests) mustn't interleave
+static SAVING: Mutex<()> = Mutex::new(());
+
+impl WorkspaceTrust {
+    /// The user's earlier answer for `workspace`, if any, kept from now on
+    pub fn load(workspace: &Path) -> Self {
+        let workspace = workspace
+            .canonicalize()
+            .unwrap_or_else(|_| workspace.to_path_buf());
+        let path = dirs::home_dir().map(|home| home.join(TRUST_FILE));
+        let trust = match path.as_deref().map(read_file) {
+            Some(file) => match file.workspaces.get(&workspace) {
+                Some(true) => Trust::Trusted,
+                Some(false) => Trust::Untrusted,
+                None => Trust::Undecided,
+            },
+            None => Trust::Undecided,
+        };
+        info!("Workspace {} is {:?}", workspace.display(), trust);
+        Self {
+            workspace: Some(workspace),
+            path,
+            trust: Mutex::new(trust),
+        }
+    }
+
+    /// Trust decided by whoever runs the server, without asking or saving
+    pub fn fixed(trust: Trust) -> Self {
+        Self {
+            workspace: None,
+            path: None,
+            trust: Mutex::new(trust),
+        }
+    }
+
+    pub fn get(&self) -> Trust {
+        *self.trust.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn trusted(&self) -> bool {
+        self.get() == Trust::Trusted
+    }
+
+    /// Whether the MCP tool `name` is offered to Claude
+    pub fn tool_allowed(&self, name: &str) -> bool {
+        self.trusted() || !WRITE_TOOLS.contains(&name)
+    }
+
+    /// The workspace the answer is saved for, if it is saved
+    pub fn workspace(&self) -> Option<&Path> {
+        self.path.as_ref().and(self.workspace.as_deref())
+    }
+
+    /// Record the user's answer, saving it for the workspace
+    pub fn decide(&self, trusted: bool) {
+        let trust = if trusted {
+            Trust::Trusted
+        } else {
+            Trust::Untrusted
+        };
+        *self.trust.lock().unwrap_or_else(|e| e.into_inner()) = trust;
+        let (Some(path), Some(workspace)) = (&self.path, &self.workspace) else {
+            return;
+        };
+        info!("Workspace {} is now {:?}", workspace.display(), trust);
+
+        let _saving = SAVING.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = read_file(path);
+        file.version = FORMAT_VERSION;
+        file.workspaces.insert(workspace.clone(), trusted);
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let json = serde_json::to_string_pretty(&file).map_err(std::io::Error::other)?;
+                fs::write(path, json)
+            });
+        if let Err(e) = result {
+            warn!("Failed to save workspace trust {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// The saved answers; none when the file is missing or unreadable
+fn read_file(path: &Path) -> TrustFile {
+    match fs::read_to_string(path) {
+        Ok(text) => match serde_json::from_str::<TrustFile>(&text) {
+            Ok(file) if file.version == FORMAT_VERSION => file,
+            Ok(file) => {
+                warn!(
+                    "Ignoring workspace trust {} with unsupported version {}",
+                    path.display(),
+                    file.version
+                );
+                TrustFile::default()
+            }
+            Err(e) => {
+                warn!("Ignoring invalid workspace trust {}: {}", path.display(), e);
+                TrustFile::default()
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => TrustFile::default(),
+        Err(e) => {
+            warn!("Failed to read workspace trust {}: {}", path.display(), e);
+            TrustFile::default()
+        }
+    }
+}
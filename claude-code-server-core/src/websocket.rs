@@ -0,0 +1,1148 @@
+use anyhow::{anyhow, Result};
+use dirs::home_dir;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::handshake::server::{ErrorResponse, Request, Response},
+    tungstenite::http::StatusCode,
+    tungstenite::Message,
+};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::debounce::Debouncer;
+use crate::lock_file::{
+    self, cleanup_existing_lock_file, lock_file_folders, register, LockFileGuard, Registration,
+};
+use crate::mcp::{MCPRequest, MCPResponse, MCPServer};
+use crate::notifications::{Lagged, NotificationReceiver, SharedNotificationBus};
+use crate::resources;
+use crate::rpc;
+use crate::shutdown::Shutdown;
+use crate::sse::{self, SseSessions};
+use crate::status::ServerStatus;
+
+/// The halves of an MCP connection, speaking WebSocket messages; the SSE transport
+/// adapts its event stream and POSTed messages to them
+pub type McpSender =
+    Pin<Box<dyn Sink<Message, Error = tokio_tungstenite::tungstenite::Error> + Send>>;
+pub type McpReceiver =
+    Pin<Box<dyn Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Send>>;
+/// Messages read while a request was running, handled once it is done
+type Backlog = VecDeque<Result<Message, tokio_tungstenite::tungstenite::Error>>;
+
+pub const DEFAULT_PORT: u16 = 59792;
+
+/// Header the Claude CLI uses to present the auth token from the lock file
+pub const AUTH_HEADER: &str = "x-claude-code-ide-authorization";
+/// Plain HTTP path serving the status report, next to the WebSocket endpoint
+const STATUS_PATH: &str = "/status";
+/// Plain HTTP path serving metrics in the Prometheus text format, when enabled
+const METRICS_PATH: &str = "/metrics";
+
+// Heartbeat: ping every PING_INTERVAL and drop peers silent for PEER_TIMEOUT, so a
+// CLI that vanished without closing frees its connection and can reconnect cleanly
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const PEER_TIMEOUT: Duration = Duration::from_secs(90);
+// How often to check that the lock file still exists
+const LOCK_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// Socket names tried after the one for the port before giving up
+const MAX_SOCKET_CANDIDATES: usize = 100;
+
+/// How the Claude CLI should talk to the MCP server, as advertised in the lock
+/// file. Both transports are served on the port either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum McpTransport {
+    #[default]
+    WebSocket,
+    /// HTTP+SSE, which some Claude CLI versions use
+    Sse,
+}
+
+impl McpTransport {
+    /// The name in the lock file's transport field
+    pub(crate) fn lock_file_name(self) -> &'static str {
+        match self {
+            Self::WebSocket => "ws",
+            Self::Sse => "sse",
+        }
+    }
+}
+
+impl FromStr for McpTransport {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "ws" | "websocket" => Ok(Self::WebSocket),
+            "sse" => Ok(Self::Sse),
+            _ => Err(anyhow!(
+                "Unknown MCP transport {:?}, expected ws or sse",
+                name
+            )),
+        }
+    }
+}
+
+/// Who is at the other end of an MCP connection, for logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// Connections over the Unix socket, numbered in the order they came
+    Unix(u64),
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(number) => write!(f, "unix socket client {}", number),
+        }
+    }
+}
+
+fn warn_already_registered(other: &Path, folder: &str) {
+    warn!(
+        "{} is already advertised by the instance in {}, not advertising this server until it stops",
+        folder,
+        other.display()
+    );
+}
+
+/// Register the lock file off the runtime, as registering probes other instances
+async fn register_blocking(
+    registration: &Registration,
+    folders: Vec<String>,
+) -> Result<Option<(PathBuf, String)>> {
+    let registration = registration.clone();
+    tokio::task::spawn_blocking(move || register(&registration, folders)).await?
+}
+
+/// Where the MCP server takes connections
+enum Listener {
+    Tcp(TcpListener),
+    /// With the user id of the socket's owner, the only user let in
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, u32),
+}
+
+enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl Listener {
+    async fn accept(&self) -> std::io::Result<Accepted> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok(Accepted::Tcp(stream, peer_addr))
+            }
+            // Other users are refused, on top of the socket's permissions
+            #[cfg(unix)]
+            Self::Unix(listener, owner) => loop {
+                let (stream, _) = listener.accept().await?;
+                match stream.peer_cred() {
+                    Ok(credentials) if credentials.uid() == *owner => {
+                        return Ok(Accepted::Unix(stream))
+                    }
+                    Ok(credentials) => {
+                        warn!("Refusing a connection from user {}", credentials.uid())
+                    }
+                    Err(e) => warn!("Refusing a connection of unknown origin: {}", e),
+                }
+            },
+        }
+    }
+}
+
+/// The socket the server listens on in place of the port: `<port>.sock` next to
+/// the lock file
+pub fn socket_path(port: u16) -> Result<PathBuf> {
+    let home = home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home
+        .join(".claude")
+        .join("ide")
+        .join(format!("{}.sock", port)))
+}
+
+/// Where to listen in place of `port`: its socket, unless another server listens
+/// there or a running instance holds its lock file, in which case the first
+/// number after it that is free. Returns the socket and the number naming it and
+/// the lock file.
+fn free_socket(port: u16) -> Result<(PathBuf, u16)> {
+    for number in (port..=u16::MAX).take(MAX_SOCKET_CANDIDATES) {
+        let path = socket_path(number)?;
+        if is_listening(&path) {
+            info!("Another server listens on {}", path.display());
+        } else if lock_file::held_elsewhere(number) {
+            info!("Another instance holds the lock file for {}", number);
+        } else {
+            return Ok((path, number));
+        }
+    }
+    Err(anyhow!(
+        "No free socket among the {} after {}",
+        MAX_SOCKET_CANDIDATES,
+        port
+    ))
+}
+
+/// Whether a server accepts connections on the socket at `path`
+#[cfg(unix)]
+fn is_listening(path: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+fn is_listening(_path: &Path) -> bool {
+    false
+}
+
+/// Listen on the Unix socket at `path`, replacing one left behind by an earlier
+/// run. Only the current user may connect to it.
+#[cfg(unix)]
+fn bind_socket(path: &Path) -> Result<Listener> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(path) {
+        Ok(()) => info!("Removed stale socket {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", path.display(), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    let owner = fs::metadata(path)?.uid();
+    info!("WebSocket server listening on {}", path.display());
+    Ok(Listener::Unix(listener, owner))
+}
+
+#[cfg(not(unix))]
+fn bind_socket(_path: &Path) -> Result<Listener> {
+    Err(anyhow!("Unix sockets aren't supported on this platform"))
+}
+
+/// Listen on 127.0.0.1:`port`, or on a port the OS picks when it stays busy,
+/// returning the port listened on
+async fn bind_port(port: u16) -> Result<(Listener, u16)> {
+    let addr = format!("127.0.0.1:{}", port);
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            // An earlier instance may still be letting go of it
+            warn!("Failed to bind to port {}: {}, retrying", port, e);
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            match TcpListener::bind(&addr).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!(
+                        "Port {} is unavailable ({}), letting the OS pick one",
+                        port, e
+                    );
+                    TcpListener::bind("127.0.0.1:0")
+                        .await
+                        .map_err(|e| anyhow!("Failed to bind to any port: {}", e))?
+                }
+            }
+        }
+    };
+    let port = listener.local_addr()?.port();
+    info!("WebSocket server listening on 127.0.0.1:{}", port);
+    Ok((Listener::Tcp(listener), port))
+}
+
+/// Run the WebSocket server. `mcp_server` is the prototype handler that every
+/// connection clones, so it carries the shared LSP state (commands, diagnostics).
+/// The same port serves the MCP HTTP+SSE transport, while the lock file advertises
+/// `transport` to the Claude CLI. With `unix_socket`, the server listens on
+/// `socket_path(port)` instead, serving only the WebSocket transport there.
+/// Returns once `shutdown` is triggered, after closing every connection, stopping
+/// a supervised Claude CLI and removing the lock file.
+pub async fn run_websocket_server_with_notifications(
+    port: Option<u16>,
+    transport: McpTransport,
+    unix_socket: bool,
+    worktree: Option<PathBuf>,
+    notifications: Option<SharedNotificationBus>,
+    mcp_server: MCPServer,
+    shutdown: Shutdown,
+) -> Result<()> {
+    info!("Starting WebSocket server...");
+
+    // Use fixed port or provided port
+    let port = port.unwrap_or(DEFAULT_PORT);
+
+    // With a socket the port only names the socket and the lock file, and nothing
+    // listens on it
+    let (listener, port, socket_path) = if unix_socket {
+        let (path, port) = tokio::task::spawn_blocking(move || free_socket(port)).await??;
+        (bind_socket(&path)?, port, Some(path))
+    } else {
+        let (listener, port) = bind_port(port).await?;
+        // Nothing else listens on the port now, so a lock file for it is stale
+        cleanup_existing_lock_file(port).await?;
+        (listener, port, None)
+    };
+    // As are those of instances that crashed
+    tokio::task::spawn_blocking(lock_file::remove_stale_lock_files).await?;
+    mcp_server.status().listening(
+        Some(port).filter(|_| socket_path.is_none()),
+        socket_path.clone(),
+    );
+
+    // Only advertise the server once the port is actually bound
+    let auth_token = Uuid::new_v4().to_string();
+    let workspace_folders = mcp_server.workspace_folders().clone();
+    let registration = Registration::new(port, transport, socket_path.clone(), auth_token.clone());
+    let _lock_file_guard = LockFileGuard {
+        path: registration.path()?,
+        socket_path: socket_path.clone(),
+    };
+    let folders = lock_file_folders(worktree.as_deref(), Some(&workspace_folders))?;
+    let mut blocked_by = register_blocking(&registration, folders).await?;
+    if let Some((other, folder)) = &blocked_by {
+        warn_already_registered(other, folder);
+    }
+    // A Claude CLI launched now finds the lock file for the port it is given
+    if let Some(claude_process) = mcp_server.claude_process() {
+        claude_process.start(port);
+    }
+
+    // Keep the lock file accurate while the server runs: its workspaceFolders follow
+    // the editor, so the CLI matches this server from any of the open projects, and
+    // it is recreated if removed (e.g. by another instance cleaning up this port),
+    // so the CLI can always find its way back after a disconnect. While another
+    // instance advertises the same workspace, this one waits for it to stop.
+    let lock_file_path = _lock_file_guard.path.clone();
+    // None once the folders can no longer change
+    let mut changes = Some(workspace_folders.subscribe());
+    let lock_file_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let mut check = tokio::time::interval(LOCK_FILE_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                // Stop before the guard removes the lock file, so it isn't recreated
+                _ = lock_file_shutdown.triggered() => break,
+                changed = async {
+                    match changes.as_mut() {
+                        Some(changes) => changes.changed().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if changed.is_err() {
+                        changes = None;
+                        continue;
+                    }
+                    info!("Workspace folders changed, updating lock file");
+                }
+                _ = check.tick() => {
+                    if blocked_by.is_some() {
+                        // Try again, in case the other instance stopped
+                    } else if lock_file_path.exists() {
+                        continue;
+                    } else {
+                        warn!("Lock file {} disappeared, recreating it", lock_file_path.display());
+                    }
+                }
+            }
+
+            let folders = lock_file_folders(worktree.as_deref(), Some(&workspace_folders));
+            let folders = match folders {
+                Ok(folders) => folders,
+                Err(e) => {
+                    warn!("Failed to resolve workspace folders: {}", e);
+                    continue;
+                }
+            };
+            match register_blocking(&registration, folders).await {
+                Ok(conflict) => {
+                    match (&blocked_by, &conflict) {
+                        (Some(_), None) => {
+                            info!("The other instance is gone, advertising this server")
+                        }
+                        (None, Some((other, folder))) => {
+                            // The folders now overlap another instance's
+                            warn_already_registered(other, folder);
+                            let _ = fs::remove_file(&lock_file_path);
+                        }
+                        _ => {}
+                    }
+                    blocked_by = conflict;
+                }
+                Err(e) => error!("Failed to update lock file: {}", e),
+            }
+        }
+    });
+
+    let sse_sessions = SseSessions::default();
+    let mut unix_clients = 0;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept a connection: {}", e);
+                    break;
+                }
+            },
+            _ = shutdown.triggered() => break,
+        };
+        let auth_token_clone = auth_token.clone();
+        match accepted {
+            Accepted::Tcp(stream, peer_addr) => {
+                info!("New connection from {}", peer_addr);
+                tokio::spawn(handle_connection(
+                    stream,
+                    peer_addr,
+                    auth_token_clone,
+                    sse_sessions.clone(),
+                    notifications.clone(),
+                    mcp_server.clone(),
+                    shutdown.clone(),
+                ));
+            }
+            #[cfg(unix)]
+            Accepted::Unix(stream) => {
+                unix_clients += 1;
+                let peer = Peer::Unix(unix_clients);
+                info!("New connection from {}", peer);
+                tokio::spawn(handle_unix_connection(
+                    stream,
+                    peer,
+                    auth_token_clone,
+                    notifications.clone(),
+                    mcp_server.clone(),
+                    shutdown.clone(),
+                ));
+            }
+        }
+    }
+
+    match &socket_path {
+        Some(path) => info!("Stopping WebSocket server on {}", path.display()),
+        None => info!("Stopping WebSocket server on port {}", port),
+    }
+    drop(listener);
+    if let Some(claude_process) = mcp_server.claude_process() {
+        claude_process.stop().await;
+    }
+    // The lock file guard removes the lock file on the way out
+    Ok(())
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    auth_token: String,
+    sse_sessions: SseSessions,
+    notifications: Option<SharedNotificationBus>,
+    mcp_handler: MCPServer,
+    shutdown: Shutdown,
+) -> Result<()> {
+    info!("Handling connection from {}", peer_addr);
+
+    match route(&stream).await {
+        Route::Status => return serve_status(stream, peer_addr, mcp_handler.status()).await,
+        Route::Metrics => return serve_metrics(stream, peer_addr, &mcp_handler).await,
+        Route::SseStream => {
+            return sse::serve_stream(
+                stream,
+                peer_addr,
+                &auth_token,
+                sse_sessions,
+                notifications,
+                mcp_handler,
+                shutdown,
+            )
+            .await
+        }
+        Route::SseMessage => {
+            return sse::accept_message(stream, peer_addr, &auth_token, &sse_sessions).await
+        }
+        Route::WebSocket => {}
+    }
+
+    let ws_stream = accept_websocket(stream, Peer::Tcp(peer_addr), &auth_token).await?;
+    let (ws_sender, ws_receiver) = ws_stream.split();
+    handle_mcp_connection(
+        Box::pin(ws_sender),
+        Box::pin(ws_receiver),
+        Peer::Tcp(peer_addr),
+        notifications,
+        mcp_handler,
+        shutdown,
+    )
+    .await
+}
+
+/// Serve a WebSocket client of the Unix socket
+#[cfg(unix)]
+async fn handle_unix_connection(
+    stream: tokio::net::UnixStream,
+    peer: Peer,
+    auth_token: String,
+    notifications: Option<SharedNotificationBus>,
+    mcp_handler: MCPServer,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let ws_stream = accept_websocket(stream, peer, &auth_token).await?;
+    let (ws_sender, ws_receiver) = ws_stream.split();
+    handle_mcp_connection(
+        Box::pin(ws_sender),
+        Box::pin(ws_receiver),
+        peer,
+        notifications,
+        mcp_handler,
+        shutdown,
+    )
+    .await
+}
+
+/// Complete the WebSocket handshake, rejecting clients that don't present the
+/// token advertised in the lock file
+// The handshake callback's error type is dictated by tungstenite
+#[allow(clippy::result_large_err)]
+async fn accept_websocket<S>(
+    stream: S,
+    peer_addr: Peer,
+    auth_token: &str,
+) -> Result<tokio_tungstenite::WebSocketStream<S>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let ws_stream = match accept_hdr_async(stream, |req: &Request, mut response: Response| {
+        let presented = req
+            .headers()
+            .get(AUTH_HEADER)
+            .and_then(|value| value.to_str().ok());
+        if !presented.is_some_and(|token| tokens_match(token, auth_token)) {
+            warn!(
+                "Rejecting connection from {}: missing or invalid auth token",
+                peer_addr
+            );
+            return Err(unauthorized_response());
+        }
+
+        // Check if client requested MCP protocol
+        if let Some(protocols) = req.headers().get("Sec-WebSocket-Protocol") {
+            if let Ok(protocols_str) = protocols.to_str() {
+                if protocols_str.contains("mcp") {
+                    // Add MCP protocol to response
+                    response
+                        .headers_mut()
+                        .insert("Sec-WebSocket-Protocol", "mcp".parse().unwrap());
+                    info!("MCP protocol negotiated for {}", peer_addr);
+                }
+            }
+        }
+        Ok(response)
+    })
+    .await
+    {
+        Ok(ws) => {
+            info!("WebSocket handshake completed for {}", peer_addr);
+            ws
+        }
+        Err(e) => {
+            error!("WebSocket handshake failed for {}: {}", peer_addr, e);
+            return Err(e.into());
+        }
+    };
+    Ok(ws_stream)
+}
+
+/// What a connection asks for besides a WebSocket handshake
+enum Route {
+    Status,
+    Metrics,
+    SseStream,
+    SseMessage,
+    WebSocket,
+}
+
+/// Route a connection by its request line, judged from its first bytes without
+/// consuming them
+async fn route(stream: &TcpStream) -> Route {
+    let mut start = [0; 32];
+    let read = stream.peek(&mut start).await.unwrap_or(0);
+    let opens_with = |request: String| start[..read].starts_with(request.as_bytes());
+
+    if opens_with(format!("GET {} ", STATUS_PATH)) {
+        Route::Status
+    } else if opens_with(format!("GET {} ", METRICS_PATH)) {
+        Route::Metrics
+    } else if opens_with(format!("GET {} ", sse::STREAM_PATH))
+        || opens_with(format!("GET {}?", sse::STREAM_PATH))
+    {
+        Route::SseStream
+    } else if opens_with(format!("POST {}?", sse::MESSAGES_PATH)) {
+        Route::SseMessage
+    } else {
+        Route::WebSocket
+    }
+}
+
+/// Request target and headers of an HTTP request
+pub struct RequestHead {
+    pub target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Value of the header `name`, matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read an HTTP request up to the blank line ending its headers
+pub async fn read_head(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<RequestHead> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let target = line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    Ok(RequestHead { target, headers })
+}
+
+/// A complete HTTP/1.1 response, after which the connection is closed
+pub fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Answer GET /status with the status report as JSON. Unlike the WebSocket it
+/// needs no auth token, so the editor can poll it, but only requests addressed to
+/// this machine get an answer, which keeps web pages from reading it through DNS
+/// rebinding.
+async fn serve_status(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    status: &ServerStatus,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let head = read_head(&mut reader).await?;
+    let host = head.header("host");
+
+    let (status_line, body) = if addressed_locally(&head) {
+        ("200 OK", serde_json::to_string(&status.report().await)?)
+    } else {
+        warn!(
+            "Refusing status request from {} for host {:?}",
+            peer_addr, host
+        );
+        ("403 Forbidden", "{}".to_string())
+    };
+    let response = http_response(status_line, "application/json", &body);
+
+    let mut stream = reader.into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Answer GET /metrics in the Prometheus text format, under the same rules as
+/// GET /status. Not found unless `metrics.enabled` is set.
+async fn serve_metrics(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    mcp_handler: &MCPServer,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let head = read_head(&mut reader).await?;
+
+    let response = if !addressed_locally(&head) {
+        warn!(
+            "Refusing metrics request from {} for host {:?}",
+            peer_addr,
+            head.header("host")
+        );
+        http_response("403 Forbidden", "text/plain", "")
+    } else if !mcp_handler.metrics_enabled().await {
+        http_response("404 Not Found", "text/plain", "metrics are disabled\n")
+    } else {
+        let body = mcp_handler.status().metrics_snapshot().prometheus();
+        http_response("200 OK", "text/plain; version=0.0.4", &body)
+    };
+
+    let mut stream = reader.into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Whether a request names this machine as its host
+fn addressed_locally(head: &RequestHead) -> bool {
+    head.header("host").is_some_and(|host| {
+        let name = host.split_once(':').map_or(host, |(name, _)| name);
+        matches!(name, "127.0.0.1" | "localhost")
+    })
+}
+
+fn unauthorized_response() -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some("Unauthorized".to_string()));
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response
+}
+
+/// Compare tokens without short-circuiting on the first differing byte
+pub fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Serve one MCP client until it disconnects or the server stops: its requests,
+/// the editor's notifications and updates to the resources it subscribed to
+pub async fn handle_mcp_connection(
+    mut ws_sender: McpSender,
+    mut ws_receiver: McpReceiver,
+    peer_addr: Peer,
+    notifications: Option<SharedNotificationBus>,
+    mcp_handler: MCPServer,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let mcp_handler = mcp_handler.for_connection();
+    let _connected = mcp_handler.status().connected();
+    // None once the LSP side stops sending them
+    let mut diagnostics_changes = Some(mcp_handler.diagnostics_changes());
+    let mut document_edits = Some(mcp_handler.document_edits());
+    // resources/updated for subscribed resources, held back to merge bursts
+    let mut resource_updates: Debouncer<String, ()> = Debouncer::default();
+
+    info!("WebSocket connection established with {}", peer_addr);
+
+    // Catch the client up on what happened before it connected
+    let mut notification_receiver = None;
+    let mut requests = None;
+    let filter = mcp_handler.notification_filter().clone();
+    if let Some(subscription) = notifications.as_ref().map(|bus| bus.subscribe(filter)) {
+        info!(
+            "Replaying {} notifications to {}",
+            subscription.replay.len(),
+            peer_addr
+        );
+        for notification in subscription.replay {
+            ws_sender
+                .send(Message::Text(serde_json::to_string(&notification)?))
+                .await?;
+        }
+        if !subscription.results.is_empty() {
+            info!(
+                "Sending {} tool results queued for {}",
+                subscription.results.len(),
+                peer_addr
+            );
+        }
+        for result in subscription.results {
+            ws_sender
+                .send(Message::Text(serde_json::to_string(&result)?))
+                .await?;
+        }
+        notification_receiver = Some(subscription.receiver);
+        requests = Some(subscription.requests);
+    }
+
+    let mut heartbeat = tokio::time::interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut last_seen = Instant::now();
+    let mut backlog = Backlog::new();
+
+    // Main message loop handling both WebSocket messages and IDE notifications
+    loop {
+        tokio::select! {
+            // Handle incoming WebSocket messages
+            msg = async {
+                match backlog.pop_front() {
+                    Some(msg) => Some(msg),
+                    None => ws_receiver.next().await,
+                }
+            } => {
+                match msg {
+                    Some(msg) => {
+                        // Any frame, including the pong to our ping, shows the peer is alive
+                        last_seen = Instant::now();
+                        let connection = Connection {
+                            sender: &mut ws_sender,
+                            receiver: &mut ws_receiver,
+                            backlog: &mut backlog,
+                            notifications: notification_receiver.as_ref(),
+                            bus: notifications.as_ref(),
+                        };
+                        if let Err(e) = handle_websocket_message(msg, &mcp_handler, connection, peer_addr).await {
+                            error!("Error handling WebSocket message: {}", e);
+                            break;
+                        }
+                    }
+                    None => {
+                        info!("WebSocket connection with {} ended", peer_addr);
+                        break;
+                    }
+                }
+            },
+            // Handle IDE notifications
+            notification = async {
+                if let Some(ref mut receiver) = notification_receiver {
+                    receiver.recv().await
+                } else {
+                    std::future::pending().await
+                }
+            } => {
+                match notification {
+                    Ok(notification) => {
+                        debug!("Received IDE notification: {:?}", notification);
+
+                        // Forward the notification to the MCP client, along with
+                        // the resource changes it implies
+                        let subscriptions = mcp_handler.resource_subscriptions();
+                        let messages = std::iter::once(notification.clone())
+                            .chain(resources::notifications(&notification, subscriptions));
+                        let mut failed = false;
+                        for message in messages {
+                            let message_json = serde_json::to_string(&message)?;
+                            if let Err(e) = ws_sender.send(Message::Text(message_json)).await {
+                                error!("Failed to send IDE notification to {}: {}", peer_addr, e);
+                                failed = true;
+                                break;
+                            }
+                        }
+                        if failed {
+                            break;
+                        }
+                    }
+                    Err(Lagged(skipped)) => {
+                        let stats = notifications.as_ref().map(|bus| bus.stats()).unwrap_or_default();
+                        warn!(
+                            "{} fell behind, skipped {} notifications ({} of {} dropped across clients)",
+                            peer_addr, skipped, stats.dropped, stats.sent
+                        );
+                    }
+                }
+            }
+            // Questions from the editor side, answered by the client
+            Some(request) = async {
+                match requests.as_mut() {
+                    Some(requests) => requests.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                debug!("Sending request to {}: {:?}", peer_addr, request);
+                if let Err(e) = ws_sender.send(Message::Text(serde_json::to_string(&request)?)).await {
+                    error!("Failed to send request to {}: {}", peer_addr, e);
+                    break;
+                }
+            }
+            // Diagnostics never go through the notification bus
+            Ok(()) = async {
+                match diagnostics_changes.as_mut() {
+                    Some(changes) => changes.changed().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let uri = resources::DIAGNOSTICS_URI;
+                if mcp_handler.resource_subscriptions().contains(uri) {
+                    let window = mcp_handler.resource_debounce(uri).await;
+                    resource_updates.push(uri.to_string(), (), window);
+                }
+            }
+            edited = async {
+                match document_edits.as_mut() {
+                    Some(edits) => edits.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match edited {
+                    Ok(edited) => {
+                        let uri = resources::open_file_uri(edited.path());
+                        if mcp_handler.resource_subscriptions().contains(&uri) {
+                            let window = mcp_handler.resource_debounce(&uri).await;
+                            resource_updates.push(uri, (), window);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("{} missed {} document edits", peer_addr, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => document_edits = None,
+                }
+            }
+            (uri, ()) = resource_updates.ready() => {
+                let updated = resources::updated_notification(&uri);
+                if let Err(e) = ws_sender.send(Message::Text(serde_json::to_string(&updated)?)).await {
+                    error!("Failed to send resource update to {}: {}", peer_addr, e);
+                    break;
+                }
+            }
+            _ = shutdown.triggered() => {
+                info!("Server shutting down, closing the connection with {}", peer_addr);
+                break;
+            }
+            // Detect dead peers
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() >= PEER_TIMEOUT {
+                    warn!(
+                        "No response from {} for {:?}, dropping the connection",
+                        peer_addr, PEER_TIMEOUT
+                    );
+                    break;
+                }
+                if let Err(e) = ws_sender.send(Message::Ping(Vec::new())).await {
+                    warn!("Failed to ping {}: {}", peer_addr, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Best effort: the peer may already be gone
+    if let Err(e) = ws_sender.close().await {
+        debug!("Closing the connection with {} failed: {}", peer_addr, e);
+    }
+    info!("Cleaned up connection with {}", peer_addr);
+
+    Ok(())
+}
+
+/// The connection's halves, lent to a message handler
+struct Connection<'a> {
+    sender: &'a mut McpSender,
+    /// Read while a request runs, to notice it being cancelled
+    receiver: &'a mut McpReceiver,
+    backlog: &'a mut Backlog,
+    /// Where responses to requests sent to the client go
+    notifications: Option<&'a NotificationReceiver>,
+    /// Where tool results go when the client leaves before they are ready
+    bus: Option<&'a SharedNotificationBus>,
+}
+
+async fn handle_websocket_message(
+    msg: Result<Message, tokio_tungstenite::tungstenite::Error>,
+    mcp_handler: &MCPServer,
+    connection: Connection<'_>,
+    peer_addr: Peer,
+) -> Result<()> {
+    let Connection {
+        sender: ws_sender,
+        receiver: ws_receiver,
+        backlog,
+        notifications,
+        bus,
+    } = connection;
+    match msg {
+        Ok(msg) => {
+            if msg.is_text() {
+                let text = msg.to_text().unwrap();
+                debug!("Received message from {}: {}", peer_addr, text);
+
+                if let Some(response) = rpc::response(text) {
+                    respond(notifications, response, peer_addr);
+                    return Ok(());
+                }
+
+                // Try to parse as MCP request
+                match serde_json::from_str::<MCPRequest>(text) {
+                    Ok(mcp_request) => {
+                        info!("Processing MCP request: {}", mcp_request.method);
+
+                        // Handle notifications (requests without ID) separately
+                        if mcp_request.id.is_none()
+                            && mcp_request.method.starts_with("notifications/")
+                        {
+                            info!("Processing notification: {}", mcp_request.method);
+                            // Notifications don't get responses, just return
+                            return Ok(());
+                        }
+
+                        // Run the request while forwarding anything it streams
+                        // (e.g. runCommand output) ahead of the response, and
+                        // drop it (stopping e.g. the command) if the client
+                        // cancels it with notifications/cancelled
+                        let id = mcp_request.id.clone();
+                        let is_tool_call = mcp_request.method == "tools/call";
+                        let mut closed = false;
+                        let (progress_sender, mut progress_receiver) = mpsc::unbounded_channel();
+                        let request =
+                            mcp_handler.handle_request(mcp_request, Some(progress_sender));
+                        tokio::pin!(request);
+                        let result = loop {
+                            tokio::select! {
+                                result = &mut request => break result,
+                                Some(message) = progress_receiver.recv(), if !closed => {
+                                    if ws_sender.send(Message::Text(message.to_string())).await.is_err() {
+                                        closed = true;
+                                    }
+                                }
+                                incoming = ws_receiver.next(), if !closed => match incoming {
+                                    Some(Ok(Message::Text(text))) if cancels(&text, id.as_ref()) => {
+                                        info!("Request {:?} cancelled by {}", id, peer_addr);
+                                        // Cancelled requests get no response
+                                        return Ok(());
+                                    }
+                                    // The request may be waiting on the editor
+                                    // side, which may be waiting on this response
+                                    Some(Ok(Message::Text(text))) if rpc::response(&text).is_some() => {
+                                        if let Some(response) = rpc::response(&text) {
+                                            respond(notifications, response, peer_addr);
+                                        }
+                                    }
+                                    // A tool call runs to the end anyway, so its
+                                    // result can go to the next client
+                                    Some(Err(_)) | None if is_tool_call => closed = true,
+                                    Some(incoming) => backlog.push_back(incoming),
+                                    None => return Err(anyhow!("Connection closed during a request")),
+                                },
+                            }
+                        };
+                        if closed {
+                            if let (Some(bus), Ok(response)) = (bus, result) {
+                                bus.queue_result(
+                                    response,
+                                    &mcp_handler.notification_settings().await,
+                                );
+                            }
+                            return Err(anyhow!("Connection closed during a request"));
+                        }
+                        while let Ok(message) = progress_receiver.try_recv() {
+                            ws_sender.send(Message::Text(message.to_string())).await?;
+                        }
+
+                        match result {
+                            Ok(response) => {
+                                let response_json = serde_json::to_string(&response)?;
+                                debug!("Sending MCP response: {}", response_json);
+
+                                if let Err(e) = ws_sender.send(Message::Text(response_json)).await {
+                                    error!("Failed to send MCP response to {}: {}", peer_addr, e);
+                                    if let Some(bus) = bus.filter(|_| is_tool_call) {
+                                        bus.queue_result(
+                                            response,
+                                            &mcp_handler.notification_settings().await,
+                                        );
+                                    }
+                                    return Err(e.into());
+                                }
+                            }
+                            Err(e) => {
+                                error!("Error handling MCP request: {}", e);
+                                let error_response = MCPResponse {
+                                    jsonrpc: "2.0".to_string(),
+                                    id: None,
+                                    result: None,
+                                    error: Some(crate::mcp::MCPError {
+                                        code: -32603,
+                                        message: "Internal error".to_string(),
+                                        data: Some(serde_json::json!({"details": e.to_string()})),
+                                    }),
+                                };
+
+                                let error_json = serde_json::to_string(&error_response)?;
+                                if let Err(e) = ws_sender.send(Message::Text(error_json)).await {
+                                    error!("Failed to send error response to {}: {}", peer_addr, e);
+                                    return Err(e.into());
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse MCP request from {}: {}", peer_addr, e);
+                        debug!("Invalid message content: {}", text);
+
+                        // Send back a JSON-RPC error response
+                        let error_response = MCPResponse {
+                            jsonrpc: "2.0".to_string(),
+                            id: None,
+                            result: None,
+                            error: Some(crate::mcp::MCPError {
+                                code: -32700,
+                                message: "Parse error".to_string(),
+                                data: None,
+                            }),
+                        };
+
+                        let error_json = serde_json::to_string(&error_response)?;
+                        if let Err(e) = ws_sender.send(Message::Text(error_json)).await {
+                            error!(
+                                "Failed to send parse error response to {}: {}",
+                                peer_addr, e
+                            );
+                            return Err(e.into());
+                        }
+                    }
+                }
+            } else if msg.is_close() {
+                info!("Connection closed by {}", peer_addr);
+                return Ok(());
+            }
+        }
+        Err(e) => {
+            error!("WebSocket error for {}: {}", peer_addr, e);
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Hand a response from the client to the editor-side request waiting for it
+fn respond(notifications: Option<&NotificationReceiver>, response: MCPResponse, peer_addr: Peer) {
+    let id = response.id.clone();
+    if !notifications.is_some_and(|receiver| receiver.respond(response)) {
+        warn!(
+            "Ignoring response from {} to unknown request {:?}",
+            peer_addr, id
+        );
+    }
+}
+
+/// Whether `text` is a notifications/cancelled for the request `id`
+fn cancels(text: &str, id: Option<&serde_json::Value>) -> bool {
+    let Ok(notification) = serde_json::from_str::<MCPRequest>(text) else {
+        return false;
+    };
+    notification.method == "notifications/cancelled"
+        && id.is_some()
+        && notification
+            .params
+            .as_ref()
+            .and_then(|params| params.get("requestId"))
+            == id
+}
@@ -0,0 +1,215 @@
+use anyhow::Result;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tower_lsp::lsp_types::{Url, WorkspaceFolder};
+
+use crate::text;
+
+const MAX_SEARCH_BYTES: u64 = 1024 * 1024;
+
+// Workspace folders known to the server: the worktree passed on the command line,
+// the folders from initialize, and any added/removed via didChangeWorkspaceFolders.
+// Kept in a watch channel so the lock file can follow changes.
+#[derive(Debug, Default)]
+pub struct WorkspaceFolders {
+    folders: watch::Sender<Vec<WorkspaceFolder>>,
+}
+
+pub type SharedWorkspaceFolders = Arc<WorkspaceFolders>;
+
+impl WorkspaceFolders {
+    pub fn new(worktree: Option<&Path>) -> Self {
+        let folders = worktree.and_then(folder_from_path).into_iter().collect();
+        Self {
+            folders: watch::Sender::new(folders),
+        }
+    }
+
+    /// Add a folder unless the same directory is already known
+    pub fn add(&self, folder: WorkspaceFolder) {
+        self.folders.send_if_modified(|folders| {
+            if folders
+                .iter()
+                .any(|existing| same_location(&existing.uri, &folder.uri))
+            {
+                return false;
+            }
+            folders.push(folder);
+            true
+        });
+    }
+
+    pub fn remove(&self, uri: &Url) {
+        self.folders.send_if_modified(|folders| {
+            let before = folders.len();
+            folders.retain(|folder| !same_location(&folder.uri, uri));
+            folders.len() != before
+        });
+    }
+
+    pub fn list(&self) -> Vec<WorkspaceFolder> {
+        self.folders.borrow().clone()
+    }
+
+    /// Local directories of all folders, in the order they were added
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.folders
+            .borrow()
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect()
+    }
+
+    /// The innermost root containing `path`, so nested folders resolve to the
+    /// most specific project
+    pub fn root_for(&self, path: &Path) -> Option<PathBuf> {
+        self.roots()
+            .into_iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+    }
+
+    /// Receiver that is notified whenever folders are added or removed
+    pub fn subscribe(&self) -> watch::Receiver<Vec<WorkspaceFolder>> {
+        self.folders.subscribe()
+    }
+}
+
+/// Compare folder URIs by the directory they point at, so a trailing slash
+/// (as produced by `Url::from_directory_path`) doesn't create duplicates
+fn same_location(a: &Url, b: &Url) -> bool {
+    match (a.to_file_path(), b.to_file_path()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Build a workspace folder for a local directory, named after its last component
+pub fn folder_from_path(path: &Path) -> Option<WorkspaceFolder> {
+    let uri = Url::from_directory_path(path).ok()?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string());
+    Some(WorkspaceFolder { uri, name })
+}
+
+/// Resolve a relative path against the first root that contains it
+pub fn resolve_path(roots: &[PathBuf], file_path: &str) -> PathBuf {
+    if Path::new(file_path).is_absolute() {
+        return PathBuf::from(file_path);
+    }
+    roots
+        .iter()
+        .map(|root| root.join(file_path))
+        .find(|candidate| candidate.exists())
+        .or_else(|| roots.first().map(|root| root.join(file_path)))
+        .unwrap_or_else(|| PathBuf::from(file_path))
+}
+
+/// List files under `root` (respecting .gitignore and hidden-file rules),
+/// returned as paths relative to `root`, sorted, and capped at `limit` entries
+pub fn list_files(root: &Path, limit: usize) -> Vec<PathBuf> {
+    walk_files(WalkBuilder::new(root), root, limit)
+}
+
+/// Whether `list_files(root, ..)` would list `path`, a file under `root`. Only
+/// the directories on the way to it are walked.
+pub fn is_listed(root: &Path, path: &Path) -> bool {
+    let target = path.to_path_buf();
+    let mut walk = WalkBuilder::new(root);
+    walk.filter_entry(move |entry| target.starts_with(entry.path()));
+    walk.build()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.path() == path && entry.file_type().is_some_and(|t| t.is_file()))
+}
+
+/// Like `list_files`, keeping only files that match a gitignore-style glob
+/// relative to `root` (e.g. `**/*.test.ts` or `src/*.rs`)
+pub fn list_matching(root: &Path, glob: &str, limit: usize) -> Result<Vec<PathBuf>> {
+    let overrides = OverrideBuilder::new(root).add(glob)?.build()?;
+    let mut walk = WalkBuilder::new(root);
+    walk.overrides(overrides);
+    Ok(walk_files(walk, root, limit))
+}
+
+fn walk_files(walk: WalkBuilder, root: &Path, limit: usize) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walk
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .take(limit)
+        .collect();
+    files.sort();
+    files
+}
+
+/// Whether a mention is a glob rather than a plain path
+pub fn is_glob(mention: &str) -> bool {
+    mention.contains(['*', '?', '[', '{'])
+}
+
+/// Files a directory or glob mention refers to, as absolute paths. A directory
+/// is resolved like any other path; a glob is matched in every root. At most
+/// `limit` files are returned.
+pub fn expand_mention(roots: &[PathBuf], mention: &str, limit: usize) -> Result<Vec<PathBuf>> {
+    if !is_glob(mention) {
+        let dir = resolve_path(roots, mention);
+        return Ok(list_files(&dir, limit)
+            .into_iter()
+            .map(|relative| dir.join(relative))
+            .collect());
+    }
+
+    let mut files = Vec::new();
+    for root in roots {
+        let remaining = limit - files.len();
+        if remaining == 0 {
+            break;
+        }
+        files.extend(
+            list_matching(root, mention, remaining)?
+                .into_iter()
+                .map(|relative| root.join(relative)),
+        );
+    }
+    Ok(files)
+}
+
+/// Directories containing the given root-relative files (at any depth), sorted
+pub fn parent_dirs(files: &[PathBuf]) -> Vec<PathBuf> {
+    let dirs: BTreeSet<PathBuf> = files
+        .iter()
+        .flat_map(|file| file.ancestors().skip(1))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect();
+    dirs.into_iter().collect()
+}
+
+/// Text of a file for searching: the editor's buffer when `buffers` has one,
+/// otherwise the file on disk. Files over MAX_SEARCH_BYTES (typically generated
+/// or minified) and binary or non-UTF-8 files are skipped.
+pub fn searchable_text(path: &Path, buffers: &HashMap<PathBuf, String>) -> Option<String> {
+    if let Some(text) = buffers.get(path) {
+        return Some(text.clone());
+    }
+    if fs::metadata(path).ok()?.len() > MAX_SEARCH_BYTES {
+        return None;
+    }
+    text::read_file(path).ok()
+}
+
+/// Render a root-relative path with forward slashes, as used in mentions
+pub fn display_relative(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Result};
+use std::env;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::config::Config;
+
+// The zed CLI, for opening files when the editor can't be asked through
+// window/showDocument. Each run is waited for, up to `zedCliTimeoutMs`, and one
+// that fails in a way that may pass (it exited with an error, or couldn't be
+// started for a reason other than a missing or forbidden binary) is tried once
+// more. A run that times out isn't: Zed may still open the file, and a second run
+// would open it twice. On Windows the CLI is zed.exe, which Zed's installer puts
+// in its per-user install directory; it is found there when it isn't on PATH.
+
+// Pause before the second try
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+// Most of the CLI's error output kept in error messages
+const MAX_STDERR_CHARS: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct ZedCli {
+    binary: String,
+    timeout: Duration,
+}
+
+/// Why a run failed, and whether running again might help
+enum Failure {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+impl ZedCli {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            binary: resolve_binary(&config.zed_binary),
+            timeout: Duration::from_millis(config.zed_cli_timeout_ms),
+        }
+    }
+
+    /// Open `target`, a path with an optional :line:column, in Zed
+    pub async fn open(&self, target: &str) -> Result<()> {
+        let error = match self.run(target).await {
+            Ok(()) => return Ok(()),
+            Err(Failure::Permanent(error)) => return Err(error),
+            Err(Failure::Transient(error)) => error,
+        };
+        warn!("{} {} failed, trying again: {}", self.binary, target, error);
+        tokio::time::sleep(RETRY_DELAY).await;
+        match self.run(target).await {
+            Ok(()) => Ok(()),
+            Err(Failure::Permanent(error) | Failure::Transient(error)) => Err(error),
+        }
+    }
+
+    async fn run(&self, target: &str) -> Result<(), Failure> {
+        let child = Command::new(&self.binary)
+            .arg(target)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                let error = anyhow!("Failed to start {}: {}", self.binary, e);
+                match e.kind() {
+                    ErrorKind::NotFound | ErrorKind::PermissionDenied => Failure::Permanent(error),
+                    _ => Failure::Transient(error),
+                }
+            })?;
+
+        let output = match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                return Err(Failure::Transient(anyhow!(
+                    "Failed to wait for {}: {}",
+                    self.binary,
+                    e
+                )))
+            }
+            Err(_) => {
+                return Err(Failure::Permanent(anyhow!(
+                    "{} didn't exit within {} ms",
+                    self.binary,
+                    self.timeout.as_millis()
+                )))
+            }
+        };
+        if output.status.success() {
+            info!("Opened {} via the zed CLI", target);
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr: String = stderr.trim().chars().take(MAX_STDERR_CHARS).collect();
+        let error = if stderr.is_empty() {
+            anyhow!("{} exited with {}", self.binary, output.status)
+        } else {
+            anyhow!("{} exited with {}: {}", self.binary, output.status, stderr)
+        };
+        Err(Failure::Transient(error))
+    }
+}
+
+/// The binary to run for `binary`. On Windows, the default `zed` that isn't on
+/// PATH becomes the zed.exe in Zed's install directory, when there is one.
+fn resolve_binary(binary: &str) -> String {
+    if !cfg!(windows) || binary != "zed" || on_path("zed.exe") {
+        return binary.to_string();
+    }
+    let installed = env::var_os("LOCALAPPDATA").map(|dir| {
+        PathBuf::from(dir)
+            .join("Programs")
+            .join("Zed")
+            .join("bin")
+            .join("zed.exe")
+    });
+    match installed {
+        Some(path) if path.is_file() => path.to_string_lossy().to_string(),
+        _ => binary.to_string(),
+    }
+}
+
+fn on_path(file_name: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(file_name).is_file()))
+}
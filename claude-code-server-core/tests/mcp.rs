@@ -0,0 +1,1549 @@
+//! Claude's side: MCP requests answered from the editor's state
+
+mod support;
+
+use std::path::Path;
+use std::time::Duration;
+
+use claude_code_server_core::McpTransport;
+use serde_json::json;
+use support::TestServer;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_editors_follow_the_editor() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", "fn main() {}\n");
+    server.write_file("notes.md", "# Notes\n");
+    let main = server.open("src/main.rs", "rust").await;
+    server.open("notes.md", "markdown").await;
+
+    let editors = claude.call_tool("getOpenEditors", json!({})).await;
+    let tabs = editors["tabs"].as_array().unwrap();
+    assert_eq!(tabs.len(), 2, "unexpected tabs: {}", editors);
+    let tab = tabs.iter().find(|tab| tab["uri"] == main.as_str()).unwrap();
+    assert_eq!(tab["label"], "main.rs");
+    assert_eq!(tab["languageId"], "rust");
+
+    server
+        .lsp
+        .notify(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": main } }),
+        )
+        .await;
+    // Give the notification time to be handled before asking again
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let editors = claude.call_tool("getOpenEditors", json!({})).await;
+    assert_eq!(editors["tabs"].as_array().unwrap().len(), 1);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn workspace_files_are_listed_as_a_tree() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file(".gitignore", "target/\n");
+    server.write_file("Cargo.toml", "[package]\n");
+    server.write_file("src/main.rs", "fn main() {}\n");
+    server.write_file("src/deep/er/mod.rs", "");
+    server.write_file("target/debug/app", "");
+
+    let listed = claude
+        .call_tool("listWorkspaceFiles", json!({ "maxDepth": 2 }))
+        .await;
+    assert_eq!(listed["success"], true, "{}", listed);
+    let tree = &listed["trees"][0];
+    assert_eq!(tree["truncated"], false);
+    assert_eq!(
+        tree["entries"],
+        json!([
+            { "path": "Cargo.toml", "type": "file", "size": 10 },
+            { "path": "src", "type": "directory" },
+            { "path": "src/deep", "type": "directory", "unexpanded": true },
+            { "path": "src/main.rs", "type": "file", "size": 13 },
+        ])
+    );
+
+    // Ignored and hidden files on request, within the entry limit
+    let listed = claude
+        .call_tool(
+            "listWorkspaceFiles",
+            json!({
+                "path": "target",
+                "respectGitignore": false,
+                "includeHidden": true,
+                "maxEntries": 1
+            }),
+        )
+        .await;
+    let tree = &listed["trees"][0];
+    assert_eq!(
+        tree["entries"],
+        json!([{ "path": "debug", "type": "directory" }])
+    );
+    assert_eq!(tree["truncated"], true);
+
+    let outside = claude
+        .call_tool("listWorkspaceFiles", json!({ "path": ".." }))
+        .await;
+    assert_eq!(outside["success"], false, "{}", outside);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn files_are_read_from_unsaved_buffers_first() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", "fn main() {}\n");
+
+    let read = claude
+        .call_tool("readFile", json!({ "filePath": "src/main.rs" }))
+        .await;
+    assert_eq!(read["success"], true, "{}", read);
+    assert_eq!(read["text"], "fn main() {}\n");
+    assert_eq!(read["source"], "disk");
+    assert_eq!(read["isDirty"], false);
+    assert_eq!(read["version"], json!(null));
+    assert_eq!(read["languageId"], "rust");
+    assert_eq!(read["size"], 13);
+
+    let uri = server.open("src/main.rs", "rust").await;
+    server
+        .lsp
+        .notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "text": "fn main() { run() }\n" }]
+            }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let read = claude
+        .call_tool("readFile", json!({ "filePath": "src/main.rs" }))
+        .await;
+    assert_eq!(read["text"], "fn main() { run() }\n");
+    assert_eq!(read["source"], "buffer");
+    assert_eq!(read["isDirty"], true);
+    assert_eq!(read["version"], 2);
+    assert_eq!(read["size"], 20);
+
+    let missing = claude
+        .call_tool("readFile", json!({ "filePath": "src/missing.rs" }))
+        .await;
+    assert_eq!(missing["success"], false, "{}", missing);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn edits_to_a_subscribed_file_are_debounced() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/main.rs", "fn main() {}\n");
+    let uri = server.open("src/main.rs", "rust").await;
+    let resource = format!(
+        "ide://open-files/{}",
+        path.to_string_lossy().trim_start_matches('/')
+    );
+    claude
+        .request("resources/subscribe", json!({ "uri": resource }))
+        .await;
+
+    for version in 2..=4 {
+        server
+            .lsp
+            .notify(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": [{ "text": format!("fn main() {{ {} }}\n", version) }],
+                }),
+            )
+            .await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let updates = claude
+        .notifications_within("notifications/resources/updated", Duration::from_secs(1))
+        .await;
+    assert_eq!(updates.len(), 1, "expected one update: {:?}", updates);
+    assert_eq!(updates[0].params["uri"], resource);
+
+    let contents = claude
+        .request("resources/read", json!({ "uri": resource }))
+        .await;
+    assert_eq!(contents["contents"][0]["text"], "fn main() { 4 }\n");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tools_allowed_always_stop_asking() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let old = server.write_file("old.txt", "");
+
+    // Dismissing the prompt denies
+    let denied = claude
+        .call_tool("deleteFile", json!({ "filePath": "old.txt" }))
+        .await;
+    assert_eq!(denied["success"], false);
+    assert_eq!(denied["permission"], "deny");
+    assert!(old.exists());
+
+    server.lsp.pick_message_action("Allow always");
+    let run = claude
+        .call_tool("runCommand", json!({ "command": "echo hi" }))
+        .await;
+    assert_eq!(run["permission"], "allowAlways");
+    assert_eq!(run["stdout"], "hi\n");
+    let saved: serde_json::Value = serde_json::from_slice(
+        &std::fs::read(server.worktree().join(".claude/ide-permissions.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(saved["alwaysAllow"], json!(["runCommand"]));
+
+    // Only the tool allowed always goes ahead without asking
+    server.lsp.pick_message_action("Deny");
+    let run = claude
+        .call_tool("runCommand", json!({ "command": "echo again" }))
+        .await;
+    assert_eq!(run["success"], true, "unexpected result: {}", run);
+    let denied = claude
+        .call_tool("deleteFile", json!({ "filePath": "old.txt" }))
+        .await;
+    assert_eq!(denied["permission"], "deny");
+
+    server.lsp.pick_message_action("Allow");
+    let deleted = claude
+        .call_tool("deleteFile", json!({ "filePath": "old.txt" }))
+        .await;
+    assert_eq!(deleted["permission"], "allow");
+    assert!(!old.exists());
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn applied_edits_can_be_undone() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/lib.rs", "fn a() {}\n");
+    server.lsp.pick_message_action("Allow");
+
+    let applied = claude
+        .call_tool(
+            "applyEdits",
+            json!({ "edits": [{
+                "filePath": "src/lib.rs",
+                "range": {
+                    "start": { "line": 0, "character": 3 },
+                    "end": { "line": 0, "character": 4 },
+                },
+                "newText": "b",
+            }] }),
+        )
+        .await;
+    assert_eq!(applied["success"], true, "unexpected result: {}", applied);
+    server.lsp.notification("workspace/applyEdit").await;
+    // The editor doesn't touch the file, so do what it would have
+    server.write_file("src/lib.rs", "fn b() {}\n");
+
+    let undone = claude.call_tool("undoLastEdit", json!({})).await;
+    assert_eq!(undone["success"], true, "unexpected result: {}", undone);
+    assert_eq!(undone["source"], "applyEdits");
+    let revert = server.lsp.notification("workspace/applyEdit").await;
+    let uri = url::Url::from_file_path(&path).unwrap();
+    assert_eq!(
+        revert["edit"]["changes"][uri.as_str()][0]["newText"],
+        "fn a() {}\n"
+    );
+
+    let nothing_left = claude.call_tool("undoLastEdit", json!({})).await;
+    assert_eq!(nothing_left["success"], false);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refactor_plans_are_shown_then_applied_until_a_file_fails() {
+    let server = TestServer::start_with_capabilities(json!({
+        "window": { "showDocument": { "support": true }, "workDoneProgress": true }
+    }))
+    .await;
+    let mut claude = server.mcp().await;
+    let first = server.write_file("src/a.rs", "fn old() {}\n");
+    let second = server.write_file("src/b.rs", "use crate::a::old;\n");
+    server.write_file("src/c.rs", "fn c() { old() }\n");
+    server.write_file("src/d.rs", "fn d() {}\n");
+    server.lsp.pick_message_action("Allow");
+    server.lsp.refuse_edits_to(&second);
+
+    let proposed = claude
+        .call_tool(
+            "proposeRefactor",
+            json!({
+                "title": "rename old to new",
+                "files": [
+                    { "filePath": "src/a.rs", "description": "Rename the function", "newContents": "fn new() {}\n" },
+                    { "filePath": "src/b.rs", "description": "Import it by its new name", "newContents": "use crate::a::new;\n" },
+                    { "filePath": "src/c.rs", "description": "Call it by its new name", "newContents": "fn c() { new() }\n" },
+                    { "filePath": "src/d.rs", "description": "Nothing to do", "newContents": "fn d() {}\n" },
+                ],
+            }),
+        )
+        .await;
+
+    // The plan is written out and shown before the user is asked
+    let plan_file = server.worktree().join(".claude/refactor-plan.md");
+    let shown = server.lsp.notification("window/showDocument").await;
+    assert_eq!(
+        shown["uri"],
+        url::Url::from_file_path(&plan_file).unwrap().as_str()
+    );
+    let plan = std::fs::read_to_string(&plan_file).unwrap();
+    assert!(plan.starts_with("# rename old to new\n"), "{}", plan);
+    assert!(
+        plan.contains("## 1. src/a.rs\n\nRename the function\n"),
+        "{}",
+        plan
+    );
+    assert!(plan.contains("-fn old() {}\n+fn new() {}\n"), "{}", plan);
+    assert!(!plan.contains("src/d.rs"), "{}", plan);
+
+    // b.rs fails, so c.rs is never edited
+    assert_eq!(
+        proposed["success"], false,
+        "unexpected result: {}",
+        proposed
+    );
+    assert_eq!(proposed["applied"], json!([first.to_string_lossy()]));
+    assert_eq!(
+        proposed["failed"]["filePath"],
+        second.to_string_lossy().as_ref()
+    );
+    assert_eq!(proposed["notApplied"].as_array().unwrap().len(), 1);
+    loop {
+        let progress = server.lsp.notification("$/progress").await;
+        if progress["value"]["kind"] == "end" {
+            let message = progress["value"]["message"].as_str().unwrap();
+            assert!(message.starts_with("Stopped at"), "{}", message);
+            break;
+        }
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn text_edits_go_through_the_editor_or_to_disk() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/lib.rs", "fn a() {}\n");
+    server.write_file("src/open.rs", "fn c() {}\n");
+    server.lsp.pick_message_action("Allow");
+    let edit = |file: &str, old: &str| {
+        json!({
+            "filePath": file,
+            "range": {
+                "start": { "line": 0, "character": 3 },
+                "end": { "line": 0, "character": 4 },
+            },
+            "oldText": old,
+            "newText": "b",
+        })
+    };
+
+    // Files that aren't open are written on disk
+    let applied = claude
+        .call_tool("applyTextEdit", edit("src/lib.rs", "a"))
+        .await;
+    assert_eq!(applied["success"], true, "unexpected result: {}", applied);
+    assert_eq!(applied["appliedThrough"], "disk");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "fn b() {}\n");
+
+    let stale = claude
+        .call_tool("applyTextEdit", edit("src/lib.rs", "a"))
+        .await;
+    assert_eq!(stale["success"], false, "unexpected result: {}", stale);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "fn b() {}\n");
+
+    // Disk edits can be undone like editor ones
+    let undone = claude.call_tool("undoLastEdit", json!({})).await;
+    assert_eq!(undone["success"], true, "unexpected result: {}", undone);
+    assert_eq!(undone["source"], "applyTextEdit");
+    let revert = server.lsp.notification("workspace/applyEdit").await;
+    let uri = url::Url::from_file_path(&path).unwrap();
+    assert_eq!(
+        revert["edit"]["changes"][uri.as_str()][0]["newText"],
+        "fn a() {}\n"
+    );
+
+    // Open files are edited by the editor
+    server.open("src/open.rs", "rust").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let applied = claude
+        .call_tool("applyTextEdit", edit("src/open.rs", "c"))
+        .await;
+    assert_eq!(applied["success"], true, "unexpected result: {}", applied);
+    assert_eq!(applied["appliedThrough"], "editor");
+    let request = server.lsp.notification("workspace/applyEdit").await;
+    assert!(
+        request["edit"].to_string().contains("\"newText\":\"b\""),
+        "{}",
+        request
+    );
+
+    let outside = claude
+        .call_tool("applyTextEdit", edit("../outside.rs", "a"))
+        .await;
+    assert_eq!(outside["success"], false, "unexpected result: {}", outside);
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn text_edits_refuse_symlinks_out_of_the_workspace() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let outside = tempfile::tempdir().unwrap();
+    let target = outside.path().join("secret.rs");
+    std::fs::write(&target, "fn a() {}\n").unwrap();
+    std::os::unix::fs::symlink(&target, server.worktree().join("link.rs")).unwrap();
+    server.lsp.pick_message_action("Allow");
+
+    let refused = claude
+        .call_tool(
+            "applyTextEdit",
+            json!({
+                "filePath": "link.rs",
+                "range": {
+                    "start": { "line": 0, "character": 3 },
+                    "end": { "line": 0, "character": 4 },
+                },
+                "oldText": "a",
+                "newText": "b",
+            }),
+        )
+        .await;
+    assert_eq!(refused["success"], false, "unexpected result: {}", refused);
+    assert!(refused["error"]
+        .as_str()
+        .unwrap()
+        .contains("outside the workspace"));
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "fn a() {}\n");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn undo_leaves_files_changed_since_alone() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("notes.md", "one\n");
+    server.lsp.pick_message_action("Allow");
+
+    let diff = "--- a/notes.md\n+++ b/notes.md\n@@ -1 +1 @@\n-one\n+two\n";
+    let applied = claude
+        .call_tool("applyEdits", json!({ "diff": diff }))
+        .await;
+    assert_eq!(applied["success"], true, "unexpected result: {}", applied);
+    server.write_file("notes.md", "three\n");
+
+    let undone = claude.call_tool("undoLastEdit", json!({})).await;
+    assert_eq!(undone["success"], false);
+    assert!(
+        undone["error"].as_str().unwrap().contains("has changed"),
+        "unexpected result: {}",
+        undone
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn edits_to_files_changed_since_claude_saw_them_are_asked_again() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("seen.txt", "one\n");
+    server.write_file("unseen.txt", "one\n");
+    let diff = |file: &str| format!("--- a/{0}\n+++ b/{0}\n@@ -1 +1 @@\n-one\n+two\n", file);
+
+    server.lsp.pick_message_action("Allow always");
+    let applied = claude
+        .call_tool("applyEdits", json!({ "diff": diff("unseen.txt") }))
+        .await;
+    assert_eq!(applied["permission"], "allowAlways");
+
+    let seen = server.worktree().join("seen.txt");
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": seen, "lineStart": 0, "lineEnd": 0 }),
+        )
+        .await;
+    // The mentioned file is hashed in the background
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server.write_file("seen.txt", "one\n\n");
+
+    server.lsp.pick_message_action("Deny");
+    let denied = claude
+        .call_tool("applyEdits", json!({ "diff": diff("seen.txt") }))
+        .await;
+    assert_eq!(
+        denied["permission"], "deny",
+        "unexpected result: {}",
+        denied
+    );
+    let allowed = claude
+        .call_tool("applyEdits", json!({ "diff": diff("unseen.txt") }))
+        .await;
+    assert_eq!(allowed["permission"], "allowAlways");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn edits_keep_crlf_line_breaks_and_bom() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("win.txt", "\u{feff}one\r\nkeep\r\n");
+    server.lsp.pick_message_action("Allow");
+
+    let diff = "--- a/win.txt\n+++ b/win.txt\n@@ -1,2 +1,3 @@\n-one\n+two\n+three\n keep\n";
+    let applied = claude
+        .call_tool("applyEdits", json!({ "diff": diff }))
+        .await;
+    assert_eq!(applied["success"], true, "unexpected result: {}", applied);
+
+    let request = server.lsp.notification("workspace/applyEdit").await;
+    let uri = url::Url::from_file_path(&path).unwrap();
+    let edit = &request["edit"]["changes"][uri.as_str()][0];
+    assert_eq!(edit["newText"], "two\r\nthree\r\nkeep\r\n");
+    assert_eq!(edit["range"]["start"], json!({ "line": 0, "character": 0 }));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutting_down_removes_the_lock_file() {
+    let server = TestServer::start().await;
+    let lock_file = server.lock_file();
+    let lock: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&lock_file).unwrap()).unwrap();
+    assert!(lock["authToken"].is_string());
+    let folder = lock["workspaceFolders"][0].as_str().unwrap();
+    assert_eq!(Path::new(folder), server.worktree());
+
+    // The port may have been handed to another test's server by now, and with it
+    // the lock file name, so look for one naming this worktree instead
+    let worktree = server.worktree().to_path_buf();
+    server.trigger_shutdown().await;
+    assert_eq!(support::advertised_port(&worktree), None);
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn lock_files_of_crashed_instances_are_removed() {
+    // A process that has exited stands in for a crashed server
+    let mut crashed = std::process::Command::new("true").spawn().unwrap();
+    crashed.wait().unwrap();
+    let ide_dir = support::ide_dir();
+    std::fs::create_dir_all(&ide_dir).unwrap();
+    let stale = ide_dir.join("1.lock");
+    let mut lock = json!({
+        "pid": crashed.id(),
+        "startedAt": 0,
+        "workspaceFolders": ["/crashed"],
+        "ideName": "claude-code-server",
+        "transport": "ws",
+        "runningInWindows": false,
+        "authToken": "stale",
+    });
+    std::fs::write(&stale, lock.to_string()).unwrap();
+    // A running process that got a crashed server's pid doesn't serve its port
+    let mut reused = std::process::Command::new("sleep")
+        .arg("10")
+        .spawn()
+        .unwrap();
+    let unused_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let reused_lock = ide_dir.join(format!("{}.lock", unused_port));
+    lock["pid"] = json!(reused.id());
+    std::fs::write(&reused_lock, lock.to_string()).unwrap();
+    let other_ide = ide_dir.join("2.lock");
+    lock["ideName"] = json!("Visual Studio Code");
+    std::fs::write(&other_ide, lock.to_string()).unwrap();
+
+    let server = TestServer::start().await;
+    assert!(!stale.exists());
+    assert!(!reused_lock.exists());
+    assert!(other_ide.exists());
+    reused.kill().unwrap();
+    reused.wait().unwrap();
+    let lock: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(server.lock_file()).unwrap()).unwrap();
+    assert_eq!(lock["pid"], std::process::id());
+    assert!(lock["startedAt"].as_u64().unwrap() > 0);
+
+    std::fs::remove_file(other_ide).unwrap();
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_busy_port_is_swapped_for_a_free_one() {
+    let busy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let busy_port = busy.local_addr().unwrap().port();
+    let server = TestServer::start_with(|builder| builder.with_mcp_port(busy_port)).await;
+
+    assert_ne!(server.port(), busy_port);
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["port"], server.port());
+    let lock: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(server.lock_file()).unwrap()).unwrap();
+    assert!(lock["authToken"].is_string());
+    let mut claude = server.mcp().await;
+    claude.call_tool("getWorkspaceFolders", json!({})).await;
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn mcp_can_be_served_on_a_unix_socket() {
+    use futures_util::{SinkExt, StreamExt};
+    use std::os::unix::fs::PermissionsExt;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let server = TestServer::start_with(|builder| builder.with_mcp_socket(true)).await;
+    let lock_file = server.lock_file();
+    let lock: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&lock_file).unwrap()).unwrap();
+    let socket_path = std::path::PathBuf::from(lock["socketPath"].as_str().unwrap());
+    let mode = std::fs::metadata(&socket_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(tokio::net::TcpStream::connect(("127.0.0.1", server.port()))
+        .await
+        .is_err());
+
+    let mut request = "ws://localhost/".into_client_request().unwrap();
+    request.headers_mut().insert(
+        "x-claude-code-ide-authorization",
+        server.auth_token().parse().unwrap(),
+    );
+    let stream = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+    let (mut claude, _) = tokio_tungstenite::client_async(request, stream)
+        .await
+        .unwrap();
+    claude
+        .send(Message::Text(
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list", "params": {} }).to_string(),
+        ))
+        .await
+        .unwrap();
+    let response = loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), claude.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+        if message["id"] == 1 {
+            break message;
+        }
+    };
+    assert!(response["result"]["tools"].as_array().unwrap().len() > 1);
+
+    server.trigger_shutdown().await;
+    assert!(!lock_file.exists());
+    assert!(!socket_path.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn a_second_socket_server_leaves_the_first_one_listening() {
+    let socket = |server: &TestServer| {
+        let lock: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(server.lock_file()).unwrap()).unwrap();
+        std::path::PathBuf::from(lock["socketPath"].as_str().unwrap())
+    };
+    let first = TestServer::start_with(|builder| builder.with_mcp_socket(true)).await;
+    let port = first.port();
+    let second =
+        TestServer::start_with(|builder| builder.with_mcp_socket(true).with_mcp_port(port)).await;
+
+    assert_ne!(second.port(), port);
+    assert_ne!(socket(&second), socket(&first));
+    assert!(first.lock_file().exists());
+    assert!(tokio::net::UnixStream::connect(socket(&first))
+        .await
+        .is_ok());
+    assert!(tokio::net::UnixStream::connect(socket(&second))
+        .await
+        .is_ok());
+
+    second.stop().await;
+    first.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sse_clients_get_responses_and_notifications() {
+    let server =
+        TestServer::start_with(|builder| builder.with_mcp_transport(McpTransport::Sse)).await;
+    let lock: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(server.lock_file()).unwrap()).unwrap();
+    assert_eq!(lock["transport"], "sse");
+
+    let mut claude = server.sse().await;
+    let initialized = claude
+        .request(
+            "initialize",
+            json!({
+                "protocolVersion": "2024-11-05",
+                "capabilities": {},
+                "clientInfo": { "name": "test-client", "version": "0.0.0" },
+            }),
+        )
+        .await;
+    assert!(initialized["serverInfo"]["name"].is_string());
+
+    let path = server.write_file("src/main.rs", "fn main() {}\n");
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": path, "lineStart": 0, "lineEnd": 0 }),
+        )
+        .await;
+    let mention = claude.notification("at_mentioned").await;
+    assert_eq!(mention.params["filePath"], path.to_string_lossy().as_ref());
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sse_posts_need_an_open_session() {
+    let server = TestServer::start().await;
+    let ping = json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+
+    // Closing the event stream ends the session
+    let first = server.sse().await;
+    let endpoint = first.endpoint().to_string();
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let second = server.sse().await;
+    assert!(second.post_to(&endpoint, &ping).await.contains("404"));
+    assert!(second.post(&ping).await.contains("202"));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn metrics_count_requests_once_enabled() {
+    // Selections in poison.rs make the debounce task panic
+    let server = TestServer::start_with(|builder| {
+        builder.with_debounce_hook(|notification| {
+            let file_path = notification.params["filePath"].as_str().unwrap_or_default();
+            assert!(!file_path.ends_with("poison.rs"), "poisoned selection");
+        })
+    })
+    .await;
+    let mut claude = server.mcp().await;
+    let (status, _) = server.get("/metrics").await;
+    assert!(status.contains("404"), "unexpected status: {}", status);
+
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "metrics": { "enabled": true } } } }),
+        )
+        .await;
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["debounceRestarts"], 0);
+    claude.call_tool("getWorkspaceFolders", json!({})).await;
+
+    let (status, body) = server.get("/metrics").await;
+    assert!(status.contains("200"), "unexpected status: {}", status);
+    assert!(
+        body.contains(
+            "claude_code_mcp_request_duration_seconds_count{method=\"tools/call getWorkspaceFolders\"} 1"
+        ),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains(
+            "claude_code_command_duration_seconds_count{command=\"claude-code.status\"} 1"
+        ),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("claude_code_debounce_restarts_total 0"),
+        "{}",
+        body
+    );
+
+    // The debounce task is restarted after a panic, and selections still flow
+    let select = |relative: &str| {
+        let uri = url::Url::from_file_path(server.worktree().join(relative)).unwrap();
+        server.lsp.notify(
+            "claude/selectionDidChange",
+            json!({
+                "textDocument": { "uri": uri },
+                "selection": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 2 },
+                },
+            }),
+        )
+    };
+    server.write_file("poison.rs", "fn poison() {}\n");
+    server.write_file("main.rs", "fn main() {}\n");
+    server.open("poison.rs", "rust").await;
+    server.open("main.rs", "rust").await;
+    select("poison.rs").await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["debounceRestarts"], 1);
+    let (_, body) = server.get("/metrics").await;
+    assert!(
+        body.contains("claude_code_debounce_restarts_total 1"),
+        "{}",
+        body
+    );
+    select("main.rs").await;
+    let selection = claude.notification("selection_changed").await;
+    assert!(
+        selection.params["filePath"]
+            .as_str()
+            .unwrap()
+            .ends_with("main.rs"),
+        "{:?}",
+        selection
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ide_context_bundles_the_editor_state() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", "fn main() {\n    let a = 1;\n}\n");
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(server.worktree())
+            .output()
+            .expect("run git")
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "initial"]);
+    server.write_file("src/main.rs", "fn main() {\n    let a = 2;\n}\n");
+    let uri = server.open("src/main.rs", "rust").await;
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({
+                "textDocument": { "uri": uri },
+                "selection": {
+                    "start": { "line": 1, "character": 4 },
+                    "end": { "line": 1, "character": 14 }
+                }
+            }),
+        )
+        .await;
+    claude.notification("selection_changed").await;
+    server
+        .lsp
+        .notify(
+            "claude/publishDiagnostics",
+            json!({
+                "uri": uri,
+                "diagnostics": [{
+                    "range": {
+                        "start": { "line": 1, "character": 8 },
+                        "end": { "line": 1, "character": 9 }
+                    },
+                    "severity": 2,
+                    "message": "unused variable: `a`"
+                }]
+            }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let context = claude.call_tool("getIdeContext", json!({})).await;
+    assert_eq!(context["success"], true);
+    assert_eq!(context["activeFile"]["fileUrl"], uri.as_str());
+    assert_eq!(context["activeFile"]["relativePath"], "src/main.rs");
+    assert_eq!(context["selection"]["text"], "let a = 2;");
+    assert_eq!(context["openEditors"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        context["diagnostics"][0]["diagnostics"][0]["message"],
+        "unused variable: `a`"
+    );
+    let diff = context["gitDiff"]["diff"].as_str().unwrap();
+    assert!(diff.contains("+    let a = 2;"), "{}", diff);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn suggested_commands_become_zed_tasks() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let tasks_file = server.write_file(
+        ".zed/tasks.json",
+        "// Project tasks\n[\n  {\n    \"label\": \"serve\", // the user's own\n    \"command\": \"npm start\"\n  }\n]\n",
+    );
+    std::fs::create_dir_all(server.worktree().join("web")).unwrap();
+
+    let suggested = claude
+        .call_tool(
+            "suggestTask",
+            json!({ "command": "cargo test", "label": "Run tests", "cwd": "web" }),
+        )
+        .await;
+    assert_eq!(suggested["success"], true, "{}", suggested);
+    assert_eq!(suggested["ran"], false);
+    let message = server.lsp.notification("window/showMessage").await;
+    assert!(message["message"]
+        .as_str()
+        .unwrap()
+        .contains("\"Run tests\""));
+
+    // Suggesting it again replaces it
+    claude
+        .call_tool(
+            "suggestTask",
+            json!({ "command": "cargo test --workspace", "label": "Run tests" }),
+        )
+        .await;
+    let text = std::fs::read_to_string(&tasks_file).unwrap();
+    assert!(text.starts_with("// Project tasks\n"), "{}", text);
+    assert!(
+        text.contains("\"label\": \"serve\", // the user's own"),
+        "{}",
+        text
+    );
+    assert_eq!(text.matches("Run tests").count(), 1, "{}", text);
+    assert!(
+        text.contains("\"command\": \"cargo test --workspace\""),
+        "{}",
+        text
+    );
+    assert!(text.contains("\"claude\""), "{}", text);
+
+    // With runCommandsAsTasks, runCommand writes a task rather than running
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "runCommandsAsTasks": true } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let run = claude
+        .call_tool("runCommand", json!({ "command": "touch ran" }))
+        .await;
+    assert_eq!(run["ran"], false, "{}", run);
+    assert_eq!(run["task"]["label"], "touch ran");
+    assert!(!server.worktree().join("ran").exists());
+    let text = std::fs::read_to_string(&tasks_file).unwrap();
+    assert!(text.contains("\"command\": \"touch ran\""), "{}", text);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_tools_go_through_the_editor() {
+    let server = TestServer::start_with_capabilities(json!({
+        "workspace": {
+            "workspaceEdit": {
+                "documentChanges": true,
+                "resourceOperations": ["create", "rename", "delete"]
+            }
+        }
+    }))
+    .await;
+    let mut claude = server.mcp().await;
+    server.lsp.pick_message_action("Allow");
+    let old = server.write_file("src/old.rs", "fn old() {}\n");
+
+    let created = claude
+        .call_tool(
+            "createFile",
+            json!({ "filePath": "src/new.rs", "contents": "fn new() {}\n" }),
+        )
+        .await;
+    assert_eq!(created["success"], true, "{}", created);
+    let edit = server.lsp.notification("workspace/applyEdit").await;
+    let changes = &edit["edit"]["documentChanges"];
+    assert_eq!(changes[0]["kind"], "create");
+    assert!(changes[0]["uri"].as_str().unwrap().ends_with("/src/new.rs"));
+    assert_eq!(changes[1]["edits"][0]["newText"], "fn new() {}\n");
+
+    let renamed = claude
+        .call_tool(
+            "renameFile",
+            json!({ "filePath": "src/old.rs", "newFilePath": "src/older.rs" }),
+        )
+        .await;
+    assert_eq!(renamed["success"], true, "{}", renamed);
+    let edit = server.lsp.notification("workspace/applyEdit").await;
+    let rename = &edit["edit"]["documentChanges"][0];
+    assert_eq!(rename["kind"], "rename");
+    assert!(rename["newUri"]
+        .as_str()
+        .unwrap()
+        .ends_with("/src/older.rs"));
+    // The editor moves the file, not the server
+    assert!(old.exists());
+
+    // Files with unsaved changes are left alone
+    let uri = server.open("src/old.rs", "rust").await;
+    server
+        .lsp
+        .notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "text": "fn changed() {}\n" }]
+            }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let refused = claude
+        .call_tool("deleteFile", json!({ "filePath": "src/old.rs" }))
+        .await;
+    assert_eq!(refused["success"], false);
+    assert!(refused["error"]
+        .as_str()
+        .unwrap()
+        .contains("unsaved changes"));
+
+    // Paths outside the workspace are refused before anyone is asked
+    let outside = claude
+        .call_tool("createFile", json!({ "filePath": "../escape.rs" }))
+        .await;
+    assert_eq!(outside["success"], false, "{}", outside);
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn file_tools_refuse_dangling_symlinks_out_of_the_workspace() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.lsp.pick_message_action("Allow");
+    let outside = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("new.rs"),
+        server.worktree().join("link.rs"),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("missing"),
+        server.worktree().join("dir"),
+    )
+    .unwrap();
+
+    for file_path in ["link.rs", "dir/new.rs"] {
+        let refused = claude
+            .call_tool(
+                "createFile",
+                json!({ "filePath": file_path, "contents": "fn new() {}\n" }),
+            )
+            .await;
+        assert_eq!(refused["success"], false, "{}", refused);
+    }
+    assert!(!outside.path().join("new.rs").exists());
+    assert!(!outside.path().join("missing").exists());
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn project_settings_file_overrides_the_editor() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let tool_names = |tools: serde_json::Value| -> Vec<String> {
+        tools["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": {
+                "enabledTools": ["getWorkspaceFolders", "getCurrentSelection", "runCommand"]
+            } } }),
+        )
+        .await;
+    claude
+        .notification("notifications/tools/list_changed")
+        .await;
+    let tools = claude.request("tools/list", json!({})).await;
+    assert_eq!(
+        tool_names(tools),
+        ["getCurrentSelection", "getWorkspaceFolders", "runCommand"]
+    );
+
+    // Picked up without a restart, overriding the editor's list
+    server.write_file(
+        ".claude/settings.json",
+        r#"{
+            "model": "opus",
+            "claudeCode": {
+                "enabledTools": ["runCommand", "getWorkspaceFolders"],
+                "allowedCommands": ["echo"]
+            }
+        }"#,
+    );
+    claude
+        .notification("notifications/tools/list_changed")
+        .await;
+    let tools = claude.request("tools/list", json!({})).await;
+    assert_eq!(tool_names(tools), ["getWorkspaceFolders", "runCommand"]);
+
+    // Allowed commands run without a prompt, which would be dismissed here
+    let run = claude
+        .call_tool("runCommand", json!({ "command": "echo allowed" }))
+        .await;
+    assert_eq!(run["success"], true, "unexpected result: {}", run);
+    assert_eq!(run["stdout"], "allowed\n");
+    let run = claude
+        .call_tool("runCommand", json!({ "command": "echo chained && echo" }))
+        .await;
+    assert_eq!(run["permission"], "deny");
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_run_in_the_users_shell_environment() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    // A login shell whose startup files greet the user and set up a tool
+    let shell = server.write_file(
+        "bin/login-shell",
+        "#!/bin/sh\necho 'Welcome back'\nexport NODE_VERSION=20.11.0\nexec sh -c \"$4\"\n",
+    );
+    std::fs::set_permissions(&shell, std::fs::Permissions::from_mode(0o755)).unwrap();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({
+                "settings": {
+                    "claudeCode": {
+                        "allowedCommands": ["printenv"],
+                        "shellEnvironment": { "shell": shell },
+                    }
+                }
+            }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let run = claude
+        .call_tool("runCommand", json!({ "command": "printenv NODE_VERSION" }))
+        .await;
+    assert_eq!(run["success"], true, "unexpected result: {}", run);
+    assert_eq!(run["stdout"], "20.11.0\n");
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn tool_results_wait_for_the_next_client() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({
+                "settings": {
+                    "claudeCode": {
+                        "allowedCommands": ["sleep"],
+                        "shellEnvironment": { "enabled": false },
+                    }
+                }
+            }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The client goes away while the command runs
+    let id = claude
+        .start_request(
+            "tools/call",
+            json!({ "name": "runCommand", "arguments": { "command": "sleep 1" } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop(claude);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["notifications"]["queued"], 1);
+
+    // The new client gets it as a notification, as the id may be one of its own
+    let mut claude = server.mcp().await;
+    let queued = claude.notification("tool_result").await;
+    assert_eq!(queued.params["requestId"], id);
+    let text = queued.params["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let run: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(run["success"], true, "unexpected result: {}", run);
+    let folders = claude.call_tool("getWorkspaceFolders", json!({})).await;
+    assert_eq!(folders["success"], true, "unexpected result: {}", folders);
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["notifications"]["queued"], 0);
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn open_file_retries_the_zed_cli_and_reports_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let target = server.write_file("src/main.rs", "fn main() {}\n");
+    // Fails once, as a busy Zed might, then records what it was asked to open
+    let flaky = server.write_file(
+        "bin/flaky-zed",
+        "#!/bin/sh\nif [ -e \"$0.tried\" ]; then echo \"$1\" > \"$0.opened\"; exit 0; fi\ntouch \"$0.tried\"; echo busy >&2; exit 1\n",
+    );
+    let broken = server.write_file("bin/broken-zed", "#!/bin/sh\necho no display >&2\nexit 2\n");
+    for script in [&flaky, &broken] {
+        std::fs::set_permissions(script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let use_zed =
+        |binary: &std::path::Path| json!({ "settings": { "claudeCode": { "zedBinary": binary } } });
+
+    server
+        .lsp
+        .notify("workspace/didChangeConfiguration", use_zed(&flaky))
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let opened = claude
+        .call_tool(
+            "openFile",
+            json!({ "filePath": target, "line": 1, "makeFrontmost": false }),
+        )
+        .await;
+    assert_eq!(opened["success"], true, "unexpected result: {}", opened);
+    assert_eq!(opened["openedInIde"], true);
+    let asked = std::fs::read_to_string(flaky.with_extension("opened")).unwrap();
+    assert_eq!(asked.trim(), format!("{}:1", target.display()));
+
+    server
+        .lsp
+        .notify("workspace/didChangeConfiguration", use_zed(&broken))
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let failed = claude
+        .call_tool("openFile", json!({ "filePath": target }))
+        .await;
+    assert_eq!(failed["success"], false);
+    assert_eq!(failed["openedInIde"], false);
+    let error = failed["error"].as_str().unwrap();
+    assert!(error.contains("no display"), "unexpected error: {}", error);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_file_selects_the_requested_range() {
+    let server = TestServer::start_with_capabilities(json!({
+        "window": { "showDocument": { "support": true } }
+    }))
+    .await;
+    let mut claude = server.mcp().await;
+    let target = server.write_file("src/lib.rs", "fn one() {}\nfn two() {}\nfn three() {}\n");
+
+    let opened = claude
+        .call_tool(
+            "openFile",
+            json!({
+                "filePath": target,
+                "startLine": 2,
+                "startColumn": 4,
+                "endLine": 3,
+                "endColumn": 8,
+                "preview": true,
+                "makeFrontmost": false
+            }),
+        )
+        .await;
+    assert_eq!(opened["openedInIde"], true, "unexpected result: {}", opened);
+    let shown = server.lsp.notification("window/showDocument").await;
+    assert_eq!(shown["takeFocus"], false);
+    assert_eq!(
+        shown["selection"],
+        json!({
+            "start": { "line": 1, "character": 3 },
+            "end": { "line": 2, "character": 8 }
+        })
+    );
+
+    // A line alone selects the whole line
+    claude
+        .call_tool(
+            "openFile",
+            json!({ "filePath": target, "startLine": 1, "endLine": 1 }),
+        )
+        .await;
+    let shown = server.lsp.notification("window/showDocument").await;
+    assert_eq!(
+        shown["selection"],
+        json!({
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 1, "character": 0 }
+        })
+    );
+
+    let backwards = claude
+        .call_tool(
+            "openFile",
+            json!({ "filePath": target, "startLine": 3, "endLine": 2 }),
+        )
+        .await;
+    assert_eq!(backwards["success"], false);
+    assert_eq!(backwards["error"], "The range ends before it starts");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_diff_previews_small_hunks() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file(
+        "src/lib.rs",
+        "fn one() {\n    let a = 1;\n}\n\nfn two() {\n    let b = 2;\n}\n",
+    );
+    let uri = server.open("src/lib.rs", "rust").await;
+
+    let saved = claude
+        .call_tool(
+            "openDiff",
+            json!({
+                "old_file_path": "src/lib.rs",
+                "new_file_path": "src/lib.rs",
+                "new_file_contents": "fn one() {\n    let x = 1;\n}\n\nfn two() {\n    let b = 3;\n}\n",
+                "tab_name": "lib.rs diff"
+            }),
+        )
+        .await;
+    assert_eq!(saved, "FILE_SAVED");
+    let preview = server.lsp.notification("claude/diffPreview").await;
+    assert_eq!(preview["textDocument"]["uri"], json!(uri));
+    assert_eq!(preview["tabName"], "lib.rs diff");
+    let hunks = preview["hunks"].as_array().unwrap();
+    assert_eq!(hunks.len(), 2, "unexpected hunks: {:#}", preview);
+    assert_eq!(hunks[0]["symbol"], "one");
+    assert_eq!(
+        hunks[0]["range"],
+        json!({ "start": { "line": 1, "character": 0 }, "end": { "line": 2, "character": 0 } })
+    );
+    assert_eq!(hunks[0]["newText"], "    let x = 1;\n");
+    assert_eq!(
+        hunks[0]["inlineChanges"],
+        json!([{
+            "range": { "start": { "line": 1, "character": 8 }, "end": { "line": 1, "character": 9 } },
+            "newText": "x"
+        }])
+    );
+    assert_eq!(hunks[1]["symbol"], "two");
+    assert_eq!(hunks[1]["inlineChanges"][0]["newText"], "3");
+
+    // Neighbouring functions changed together still get a hunk each
+    server.write_file(
+        "src/both.rs",
+        "fn one() -> u32 { 1 }\nfn two() -> u32 { 2 }\n",
+    );
+    claude
+        .call_tool(
+            "openDiff",
+            json!({
+                "old_file_path": "src/both.rs",
+                "new_file_path": "src/both.rs",
+                "new_file_contents": "fn one() -> u64 { 10 }\nfn two() -> u64 { 20 }\n",
+                "tab_name": "both.rs diff"
+            }),
+        )
+        .await;
+    let preview = server.lsp.notification("claude/diffPreview").await;
+    let hunks = preview["hunks"].as_array().unwrap();
+    assert_eq!(hunks.len(), 2, "unexpected hunks: {:#}", preview);
+    assert_eq!(hunks[0]["newText"], "fn one() -> u64 { 10 }\n");
+    assert_eq!(hunks[1]["symbol"], "two");
+    assert_eq!(hunks[1]["range"]["start"]["line"], 1);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn untrusted_workspaces_are_read_only() {
+    let server = TestServer::start_with(|builder| builder.with_workspace_trust(None)).await;
+    let mut claude = server.mcp().await;
+    let tool_names = |tools: serde_json::Value| -> Vec<String> {
+        tools["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    // The prompt on startup was dismissed, so the workspace is undecided
+    let prompt = server.lsp.notification("window/showMessageRequest").await;
+    assert!(prompt["message"].as_str().unwrap().contains("trust"));
+    server.write_file(
+        ".claude/settings.json",
+        r#"{ "claudeCode": { "enabledTools": ["getWorkspaceFolders", "runCommand"] } }"#,
+    );
+
+    // Write tools are withheld, and the workspace's settings file is ignored
+    let tools = tool_names(claude.request("tools/list", json!({})).await);
+    assert!(tools.contains(&"getDiagnostics".to_string()), "{:?}", tools);
+    for tool in ["runCommand", "applyEdits", "createFile", "saveDocument"] {
+        assert!(!tools.contains(&tool.to_string()), "{:?}", tools);
+    }
+    let diff = claude
+        .call_tool(
+            "openDiff",
+            json!({
+                "old_file_path": "main.rs",
+                "new_file_path": "main.rs",
+                "new_file_contents": "fn main() {}\n",
+                "tab_name": "main.rs diff"
+            }),
+        )
+        .await;
+    assert_eq!(diff, "DIFF_REJECTED");
+
+    server.lsp.pick_message_action("Trust");
+    let trust = server
+        .lsp
+        .execute_command("claude-code.trust-workspace", json!({}))
+        .await;
+    assert_eq!(trust, "trusted");
+    claude
+        .notification("notifications/tools/list_changed")
+        .await;
+    let tools = tool_names(claude.request("tools/list", json!({})).await);
+    assert_eq!(tools, ["getWorkspaceFolders", "runCommand"]);
+
+    // Remembered outside the workspace
+    let home = std::path::PathBuf::from(std::env::var("HOME").unwrap());
+    let saved = std::fs::read_to_string(home.join(".claude/ide-trusted-workspaces.json")).unwrap();
+    let saved: serde_json::Value = serde_json::from_str(&saved).unwrap();
+    let worktree = server.worktree().canonicalize().unwrap();
+    assert_eq!(saved["workspaces"][worktree.to_str().unwrap()], true);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_proxy_shows_claude_the_language_servers_traffic() {
+    let server = TestServer::start_proxy().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", "fn main() { let x = 1; }\n");
+    let uri = server.open("src/main.rs", "rust").await;
+
+    // Messages pass through both ways, and the editor gets the diagnostics
+    let published = server
+        .lsp
+        .notification("textDocument/publishDiagnostics")
+        .await;
+    assert_eq!(published["diagnostics"][0]["message"], "unused variable");
+    let hover = server
+        .lsp
+        .request(
+            "textDocument/hover",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": 0, "character": 16 }
+            }),
+        )
+        .await;
+    assert_eq!(hover["contents"]["value"], "hover");
+    server.lsp.execute_command("fix", json!({})).await;
+    let edit = server.lsp.notification("workspace/applyEdit").await;
+    assert_eq!(edit["label"], "fix");
+
+    // Claude sees what went by
+    let editors = claude.call_tool("getOpenEditors", json!({})).await;
+    assert_eq!(editors["tabs"][0]["uri"], uri.as_str());
+    let diagnostics = claude
+        .call_tool("getDiagnostics", json!({ "uri": uri }))
+        .await;
+    assert_eq!(diagnostics[0]["diagnostics"][0]["source"], "fake");
+    let activity = claude
+        .call_tool("getLanguageServerActivity", json!({}))
+        .await;
+    assert_eq!(activity["hovers"][0]["uri"], uri.as_str());
+    assert_eq!(activity["hovers"][0]["position"]["character"], 16);
+    assert_eq!(activity["hovers"][0]["contents"], "hover");
+    assert_eq!(activity["edits"][0]["label"], "fix");
+
+    server.stop().await;
+}
@@ -0,0 +1,1727 @@
+//! Editor events arriving over LSP and reaching Claude as notifications
+
+mod support;
+
+use std::path::Path;
+use std::time::Duration;
+
+use claude_code_server_core::{
+    AtMentionedNotification, JsonRpcNotification, NotificationSink, SelectionChangedNotification,
+};
+use serde_json::json;
+use support::TestServer;
+use tokio::io::AsyncBufReadExt;
+
+const SOURCE: &str = "fn main() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    println!(\"{}\", a + b + c);\n}\n";
+
+fn selection(line: u32, end: u32) -> serde_json::Value {
+    json!({
+        "start": { "line": line, "character": 0 },
+        "end": { "line": line, "character": end },
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn selection_bursts_reach_claude_once() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+
+    for line in 1..=3 {
+        server
+            .lsp
+            .notify(
+                "claude/selectionDidChange",
+                json!({ "textDocument": { "uri": uri }, "selection": selection(line, 14) }),
+            )
+            .await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let selections = claude
+        .notifications_within("selection_changed", Duration::from_secs(1))
+        .await;
+    assert_eq!(
+        selections.len(),
+        1,
+        "expected one selection: {:?}",
+        selections
+    );
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(selections[0].params.clone()).unwrap();
+    assert_eq!(selection.selection.start.line, 3);
+    assert_eq!(selection.text, "    let c = 3;");
+    assert_eq!(selection.file_url, uri.as_str());
+    assert_eq!(selection.language_id.as_deref(), Some("rust"));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_cursor_selections_reach_claude_as_one() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({
+                "textDocument": { "uri": uri },
+                "selection": selection(2, 14),
+                "selections": [selection(2, 14), selection(1, 14), selection(3, 0)]
+            }),
+        )
+        .await;
+    let selections = claude
+        .notifications_within("selection_changed", Duration::from_secs(1))
+        .await;
+    assert_eq!(selections.len(), 1, "{:?}", selections);
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(selections[0].params.clone()).unwrap();
+    assert_eq!(selection.text, "    let b = 2;");
+    assert_eq!(selection.selection.start.line, 2);
+    let lines: Vec<u32> = selection
+        .selections
+        .iter()
+        .map(|cursor| cursor.start.line)
+        .collect();
+    assert_eq!(lines, [2, 1, 3]);
+    assert!(selection.selections[2].is_empty);
+
+    // Cursors reported through selectionRange come together as well
+    let ranges = server
+        .lsp
+        .request(
+            "textDocument/selectionRange",
+            json!({
+                "textDocument": { "uri": uri },
+                "positions": [
+                    { "line": 4, "character": 4 },
+                    { "line": 0, "character": 3 }
+                ]
+            }),
+        )
+        .await;
+    assert_eq!(ranges.as_array().unwrap().len(), 2);
+    let selections = claude
+        .notifications_within("selection_changed", Duration::from_secs(1))
+        .await;
+    assert_eq!(selections.len(), 1, "{:?}", selections);
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(selections[0].params.clone()).unwrap();
+    assert_eq!(selection.selection.start.line, 4);
+    assert_eq!(selection.selections.len(), 2);
+    assert_eq!(selection.selections[1].start.line, 0);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn selection_ranges_follow_the_syntax_tree() {
+    let server = TestServer::start().await;
+    server.write_file("src/main.rs", SOURCE);
+    server.write_file("notes.txt", "first line\nsecond line\n");
+    let uri = server.open("src/main.rs", "rust").await;
+    let notes = server.open("notes.txt", "plaintext").await;
+    let expand = |ranges: serde_json::Value| {
+        let mut expansions = Vec::new();
+        let mut range = Some(&ranges[0]);
+        while let Some(current) = range {
+            let (start, end) = (&current["range"]["start"], &current["range"]["end"]);
+            expansions.push((
+                (
+                    start["line"].as_u64().unwrap(),
+                    start["character"].as_u64().unwrap(),
+                ),
+                (
+                    end["line"].as_u64().unwrap(),
+                    end["character"].as_u64().unwrap(),
+                ),
+            ));
+            range = current.get("parent");
+        }
+        expansions
+    };
+
+    // From the `a` in `println!("{}", a + b + c)` out to the whole file
+    let ranges = server
+        .lsp
+        .request(
+            "textDocument/selectionRange",
+            json!({
+                "textDocument": { "uri": uri },
+                "positions": [{ "line": 4, "character": 19 }]
+            }),
+        )
+        .await;
+    let expansions = expand(ranges);
+    assert_eq!(expansions[0], ((4, 19), (4, 20)));
+    assert!(expansions.contains(&((4, 4), (4, 30))), "{:?}", expansions);
+    assert!(expansions.contains(&((0, 0), (5, 1))), "{:?}", expansions);
+    assert_eq!(expansions.last(), Some(&((0, 0), (6, 0))));
+    for pair in expansions.windows(2) {
+        assert!(
+            pair[1].0 <= pair[0].0 && pair[1].1 >= pair[0].1,
+            "{:?}",
+            pair
+        );
+        assert_ne!(pair[0], pair[1]);
+    }
+
+    // Without a grammar, the line and then the file
+    let ranges = server
+        .lsp
+        .request(
+            "textDocument/selectionRange",
+            json!({
+                "textDocument": { "uri": notes },
+                "positions": [{ "line": 1, "character": 3 }]
+            }),
+        )
+        .await;
+    assert_eq!(expand(ranges), [((1, 0), (1, 11)), ((0, 0), (2, 0))]);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mention_of_a_line_range() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/main.rs", SOURCE);
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": path, "lineStart": 1, "lineEnd": 3 }),
+        )
+        .await;
+
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert_eq!(mention.file_path, path.to_string_lossy());
+    assert_eq!((mention.line_start, mention.line_end), (1, 3));
+    assert_eq!(mention.language_id.as_deref(), Some("rust"));
+    assert!(mention.text.is_none());
+    let root = mention
+        .workspace_root
+        .expect("mention carries its workspace root");
+    assert_eq!(Path::new(&root), server.worktree());
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mentions_can_carry_their_lines() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/main.rs", SOURCE);
+    let secrets = server.write_file(".env", "TOKEN=hunter2\n");
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "atMentionText": true } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": path, "lineStart": 1, "lineEnd": 2 }),
+        )
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert_eq!(
+        mention.text.as_deref(),
+        Some("    let a = 1;\n    let b = 2;\n")
+    );
+    assert_eq!(mention.total_lines, Some(6));
+    assert_eq!(mention.relative_path.as_deref(), Some("src/main.rs"));
+
+    // Sensitive files say how long they are, but not what they hold
+    server
+        .lsp
+        .execute_command("claude-code.at-mention", json!({ "filePath": secrets }))
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert!(mention.text.is_none());
+    assert_eq!(mention.total_lines, Some(1));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn text_sent_to_claude_is_counted_and_large_payloads_warned_about() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let lines: String = (0..100).map(|n| format!("let x{} = {};\n", n, n)).collect();
+    let path = server.write_file("src/long.rs", &lines);
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": {
+                "atMentionText": true,
+                "contextBudget": { "warnTokens": 200 }
+            } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": path, "lineStart": 0, "lineEnd": 99 }),
+        )
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    // After the one saying the mention was sent
+    let mut message = server.lsp.notification("window/showMessage").await;
+    while message["type"] != 2 {
+        message = server.lsp.notification("window/showMessage").await;
+    }
+    assert!(
+        message["message"]
+            .as_str()
+            .unwrap()
+            .contains("at_mentioned sent Claude about"),
+        "unexpected message: {}",
+        message
+    );
+
+    // Small tool results are counted without a warning
+    let read = claude
+        .call_tool(
+            "readFileRange",
+            json!({ "filePath": path, "startLine": 0, "endLine": 1 }),
+        )
+        .await;
+    assert_eq!(read["success"], true, "{}", read);
+
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    let mentioned = &report["contextSent"]["at_mentioned"];
+    assert_eq!(mentioned["count"], 1);
+    assert_eq!(
+        mentioned["bytes"],
+        notification.params.to_string().len() as u64
+    );
+    assert!(mentioned["tokens"].as_u64().unwrap() > 200);
+    assert_eq!(report["contextSent"]["readFileRange"]["count"], 1);
+    assert!(report["contextTokens"].as_u64().unwrap() > mentioned["tokens"].as_u64().unwrap());
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mention_without_a_range_covers_the_file() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("script.py", "import sys\n\nprint(sys.argv)\n");
+
+    server
+        .lsp
+        .execute_command("claude-code.at-mention", json!({ "filePath": path }))
+        .await;
+
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert_eq!((mention.line_start, mention.line_end), (0, 2));
+    assert_eq!(mention.language_id.as_deref(), Some("python"));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mention_of_the_diff_covers_each_changed_file() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(server.worktree())
+            .output()
+            .expect("run git")
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "-q"]);
+    server.write_file("src/main.rs", SOURCE);
+    server.write_file("src/unchanged.rs", "fn unchanged() {}\n");
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "Initial commit"]);
+    server.write_file(
+        "src/main.rs",
+        &SOURCE.replace("= 2", "= 20").replace("= 3", "= 30"),
+    );
+    server.write_file("notes.md", "# Notes\nTODO\n");
+
+    let result = server
+        .lsp
+        .execute_command("claude-code.at-mention-diff", json!({}))
+        .await;
+    assert_eq!(result.as_array().map(Vec::len), Some(2), "{}", result);
+
+    let mut mentions: Vec<AtMentionedNotification> = claude
+        .notifications_within("at_mentioned", Duration::from_secs(1))
+        .await
+        .into_iter()
+        .map(|notification| serde_json::from_value(notification.params).unwrap())
+        .collect();
+    mentions.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    let worktree = server.worktree().canonicalize().unwrap();
+    let lines: Vec<(std::path::PathBuf, u32, u32)> = mentions
+        .iter()
+        .map(|mention| {
+            let path = Path::new(&mention.file_path).canonicalize().unwrap();
+            (path, mention.line_start, mention.line_end)
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (worktree.join("notes.md"), 0, 1),
+            (worktree.join("src/main.rs"), 2, 3),
+        ]
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mention_of_a_directory_lists_its_files() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/lib.rs", "pub mod a;\n");
+    server.write_file("src/a.rs", "pub fn a() {}\n");
+    server.write_file("README.md", "# Test\n");
+    let dir = server.worktree().join("src");
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": format!("{}/", dir.display()) }),
+        )
+        .await;
+
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    let mut files = mention.files.expect("directory mention lists files");
+    files.sort();
+    assert_eq!(files.len(), 2, "unexpected files: {:?}", files);
+    assert!(files[0].ends_with("a.rs") && files[1].ends_with("lib.rs"));
+
+    // A relative path is a directory of the workspace, not of the server's cwd
+    server.write_file("web/app.ts", "export {};\n");
+    server
+        .lsp
+        .execute_command("claude-code.at-mention", json!({ "filePath": "web" }))
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    let files = mention.files.expect("directory mention lists files");
+    assert_eq!(files.len(), 1, "unexpected files: {:?}", files);
+    assert!(files[0].ends_with("app.ts"));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mention_matching_nothing_warns_the_editor() {
+    let server = TestServer::start().await;
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": "**/*.nothing" }),
+        )
+        .await;
+
+    let message = server.lsp.notification("window/showMessage").await;
+    assert!(
+        message["message"]
+            .as_str()
+            .unwrap()
+            .contains("no files match"),
+        "unexpected message: {}",
+        message
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_connecting_later_are_caught_up() {
+    let server = TestServer::start().await;
+    let path = server.write_file("src/main.rs", SOURCE);
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": path, "lineStart": 0, "lineEnd": 5 }),
+        )
+        .await;
+    // Mentions are sent right away, but still cross the debouncer task
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut claude = server.mcp().await;
+    let notification = claude.notification("at_mentioned").await;
+    assert_eq!(notification.params["lineEnd"], 5);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn notifications_waiting_for_a_client_expire() {
+    let server = TestServer::start().await;
+    let path = server.write_file("src/main.rs", SOURCE);
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "notifications": { "queueTtlMs": 500 } } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for line_end in [1, 5] {
+        server
+            .lsp
+            .execute_command(
+                "claude-code.at-mention",
+                json!({ "filePath": path, "lineStart": 0, "lineEnd": line_end }),
+            )
+            .await;
+        tokio::time::sleep(Duration::from_millis(400)).await;
+    }
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["notifications"]["queued"], 1);
+    assert_eq!(report["notifications"]["expired"], 1);
+    assert_eq!(report["notifications"]["evicted"], 0);
+
+    let mut claude = server.mcp().await;
+    let mentions = claude
+        .notifications_within("at_mentioned", Duration::from_millis(300))
+        .await;
+    assert_eq!(mentions.len(), 1);
+    assert_eq!(mentions[0].params["lineEnd"], 5);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_full_queue_evicts_its_oldest_notifications() {
+    let server = TestServer::start().await;
+    let path = server.write_file("src/main.rs", SOURCE);
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "notifications": { "queueCapacity": 1 } } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for line_end in [1, 5] {
+        server
+            .lsp
+            .execute_command(
+                "claude-code.at-mention",
+                json!({ "filePath": path, "lineStart": 0, "lineEnd": line_end }),
+            )
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let report = server
+        .lsp
+        .execute_command("claude-code.status", json!({}))
+        .await;
+    assert_eq!(report["notifications"]["queued"], 1);
+    assert_eq!(report["notifications"]["evicted"], 1);
+    assert_eq!(report["notifications"]["expired"], 0);
+
+    server.stop().await;
+}
+
+/// Hands the notifications it gets to the test
+struct ChannelSink(tokio::sync::mpsc::UnboundedSender<JsonRpcNotification>);
+
+#[tower_lsp::async_trait]
+impl NotificationSink for ChannelSink {
+    fn name(&self) -> String {
+        "test channel".to_string()
+    }
+
+    async fn send(&mut self, notification: &JsonRpcNotification) -> std::io::Result<()> {
+        let _ = self.0.send(notification.clone());
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn notifications_are_copied_to_sinks() {
+    let (sender, mut copied) = tokio::sync::mpsc::unbounded_channel();
+    let server =
+        TestServer::start_with(|builder| builder.with_notification_sink(ChannelSink(sender))).await;
+    let path = server.write_file("src/main.rs", SOURCE);
+    let log = server.worktree().join("logs/notifications.jsonl");
+    let socket_path = server.worktree().join("notifications.sock");
+    let socket = tokio::net::UnixListener::bind(&socket_path).unwrap();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "notifications": { "sinks": [
+                { "type": "file", "path": log },
+                { "type": "unixSocket", "path": socket_path },
+            ] } } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": path, "lineStart": 0, "lineEnd": 5 }),
+        )
+        .await;
+
+    let copy = tokio::time::timeout(Duration::from_secs(5), copied.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(copy.method, "at_mentioned");
+    assert_eq!(copy.params["lineEnd"], 5);
+
+    let (stream, _) = tokio::time::timeout(Duration::from_secs(5), socket.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut lines = tokio::io::BufReader::new(stream).lines();
+    let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let line: JsonRpcNotification = serde_json::from_str(&line).unwrap();
+    assert_eq!(line.method, "at_mentioned");
+
+    // The file sink has a task of its own, and may not have written yet
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let written = loop {
+        let written = std::fs::read_to_string(&log).unwrap_or_default();
+        if !written.is_empty() || tokio::time::Instant::now() > deadline {
+            break written;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    let lines: Vec<JsonRpcNotification> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1, "{}", written);
+    assert_eq!(lines[0].params["lineEnd"], 5);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn editor_requests_get_claude_answers() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+
+    let (result, params) = tokio::join!(
+        server.lsp.execute_command(
+            "claude-code.request",
+            json!({ "method": "diff/review", "params": { "accepted": null } }),
+        ),
+        claude.answer("diff/review", json!({ "accepted": true })),
+    );
+    assert_eq!(params, json!({ "accepted": null }));
+    assert_eq!(result, json!({ "accepted": true }));
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn written_tests_follow_the_projects_layout() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = TestServer::start().await;
+    server.write_file("Cargo.toml", "[package]\nname = \"math\"\n");
+    let source = server.write_file(
+        "src/math.rs",
+        "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    server.write_file(
+        "tests/other.rs",
+        "use math::other;\n\n#[test]\nfn other_works() {}\n",
+    );
+    // Keeps the prompt and answers with a canned test
+    let claude = server.write_file(
+        "bin/fake-claude",
+        "#!/bin/sh\ncat > \"$0.prompt\"\nprintf '%s\\n' '{\"type\":\"result\",\"result\":\"```\\n#[test]\\nfn adds() {}\\n```\"}'\n",
+    );
+    std::fs::set_permissions(&claude, std::fs::Permissions::from_mode(0o755)).unwrap();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "claudeBinary": claude } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let uri = url::Url::from_file_path(&source).unwrap();
+    let target = json!({
+        "uri": uri,
+        "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 9 } }
+    });
+
+    // A new file in the crate's tests directory, written like the ones there
+    server
+        .lsp
+        .execute_command("claude-code.write-tests", target.clone())
+        .await;
+    let prompt = std::fs::read_to_string(claude.with_extension("prompt")).unwrap();
+    assert!(prompt.contains("pub fn add"), "{}", prompt);
+    assert!(prompt.contains("fn other_works"), "{}", prompt);
+    let tests = server.worktree().join("tests/math.rs");
+    assert!(prompt.contains(&tests.display().to_string()), "{}", prompt);
+    assert_eq!(
+        std::fs::read_to_string(&tests).unwrap(),
+        "#[test]\nfn adds() {}\n"
+    );
+
+    // Then more tests at its end
+    server
+        .lsp
+        .execute_command("claude-code.write-tests", target)
+        .await;
+    let prompt = std::fs::read_to_string(claude.with_extension("prompt")).unwrap();
+    assert!(prompt.contains("fn adds()"), "{}", prompt);
+    let edit = server.lsp.notification("workspace/applyEdit").await;
+    let tests_uri = url::Url::from_file_path(&tests).unwrap();
+    let change = &edit["edit"]["changes"][tests_uri.as_str()][0];
+    assert_eq!(change["range"]["start"]["line"], 2);
+    assert_eq!(change["newText"], "\n#[test]\nfn adds() {}\n");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_describe_themselves() {
+    let server = TestServer::start().await;
+    let add_docs = server.write_file(".claude/commands/add-docs.md", "Document {{code}}\n");
+    // The server's own commands can't be taken over
+    server.write_file(".claude/commands/request.md", "Ignored {{code}}\n");
+    let changed = url::Url::from_file_path(&add_docs).unwrap();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeWatchedFiles",
+            json!({ "changes": [{ "uri": changed, "type": 1 }] }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let listed = server
+        .lsp
+        .execute_command("claude-code.list-commands", json!({}))
+        .await;
+    let commands = listed["commands"].as_array().unwrap();
+    let find = |name: &str| {
+        commands
+            .iter()
+            .find(|command| command["command"] == name)
+            .unwrap_or_else(|| panic!("{} not listed in {}", name, listed))
+    };
+    let add_docs = find("claude-code.add-docs");
+    assert_eq!(add_docs["title"], "Add docs with Claude");
+    assert_eq!(add_docs["source"], "template");
+    assert_eq!(add_docs["arguments"]["required"], json!(["uri", "range"]));
+    assert_eq!(find("claude-code.explain")["source"], "template");
+    let request = find("claude-code.request");
+    assert_eq!(request["source"], "builtin");
+    assert_eq!(request["arguments"]["required"], json!(["method"]));
+    assert!(find("claude-code.status")["arguments"].is_null());
+    find("claude-code.list-commands");
+    assert_eq!(
+        commands
+            .iter()
+            .filter(|command| command["command"] == "claude-code.request")
+            .count(),
+        1
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changed_templates_and_settings_are_loaded_again() {
+    let server = TestServer::start_with_capabilities(json!({
+        "workspace": {
+            "didChangeWatchedFiles": { "dynamicRegistration": true },
+            "codeLens": { "refreshSupport": true }
+        }
+    }))
+    .await;
+    let mut claude = server.mcp().await;
+    let registration = server.lsp.notification("client/registerCapability").await;
+    let watchers = &registration["registrations"][0]["registerOptions"]["watchers"];
+    for pattern in ["**/.claude/settings.json", "**/.claude/commands/**"] {
+        assert!(
+            watchers
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|watcher| watcher["globPattern"] == pattern),
+            "{} not watched in {}",
+            pattern,
+            watchers
+        );
+    }
+    let changed = |path: &std::path::Path| json!({ "changes": [{ "uri": url::Url::from_file_path(path).unwrap(), "type": 1 }] });
+
+    let template = server.write_file(".claude/commands/add-docs.md", "Document {{code}}\n");
+    server
+        .lsp
+        .notify("workspace/didChangeWatchedFiles", changed(&template))
+        .await;
+    let commands = server.lsp.notification("claude/commandsChanged").await;
+    assert!(
+        commands["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|command| command["command"] == "claude-code.add-docs"),
+        "{}",
+        commands
+    );
+    server.lsp.notification("workspace/codeLens/refresh").await;
+
+    // Settings apply as soon as the editor reports the change
+    let settings = server.write_file(
+        ".claude/settings.json",
+        r#"{ "claudeCode": { "enabledTools": ["readFile"] } }"#,
+    );
+    server
+        .lsp
+        .notify("workspace/didChangeWatchedFiles", changed(&settings))
+        .await;
+    server.lsp.notification("claude/commandsChanged").await;
+    let tools = claude.request("tools/list", json!({})).await;
+    let names: Vec<&str> = tools["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|tool| tool["name"].as_str())
+        .collect();
+    assert_eq!(names, ["readFile"]);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watched_files_are_indexed_like_a_refresh_would() {
+    let server = TestServer::start_with_capabilities(json!({
+        "workspace": {
+            "didChangeWatchedFiles": {
+                "dynamicRegistration": true,
+                "relativePatternSupport": true
+            }
+        }
+    }))
+    .await;
+    let mut claude = server.mcp().await;
+    let registration = server.lsp.notification("client/registerCapability").await;
+    let watchers = &registration["registrations"][0]["registerOptions"]["watchers"];
+    let worktree = url::Url::from_file_path(server.worktree()).unwrap();
+    assert_eq!(watchers[0]["globPattern"]["pattern"], "**/*");
+    assert_eq!(
+        watchers[0]["globPattern"]["baseUri"]["uri"]
+            .as_str()
+            .map(|uri| uri.trim_end_matches('/')),
+        Some(worktree.as_str().trim_end_matches('/'))
+    );
+
+    server.write_file(".ignore", "generated/\n");
+    let written = [
+        server.write_file("src/kept.rs", "fn kept_fn() {}\n"),
+        server.write_file("generated/out.rs", "fn ignored_fn() {}\n"),
+        server.write_file(
+            "big.rs",
+            &format!("fn huge_fn() {{}}\n{}", "// padding\n".repeat(200_000)),
+        ),
+    ];
+    let changes: Vec<_> = written
+        .iter()
+        .map(|path| json!({ "uri": url::Url::from_file_path(path).unwrap(), "type": 1 }))
+        .collect();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeWatchedFiles",
+            json!({ "changes": changes }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let found = |result: &serde_json::Value, name: &str| {
+        result["symbols"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|symbol| symbol["name"] == name)
+    };
+    let result = claude
+        .call_tool("searchSymbols", json!({ "query": "kept_fn" }))
+        .await;
+    assert!(found(&result, "kept_fn"), "{}", result);
+    for name in ["ignored_fn", "huge_fn"] {
+        let result = claude
+            .call_tool("searchSymbols", json!({ "query": name }))
+            .await;
+        assert!(!found(&result, name), "{}", result);
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn command_failures_are_json_rpc_errors() {
+    let server = TestServer::start().await;
+    let missing = server.worktree().join("src/missing.rs");
+    let missing_uri = url::Url::from_file_path(&missing).unwrap();
+
+    // Malformed arguments, with what was expected and received
+    let error = server
+        .lsp
+        .try_execute_command("claude-code.explain", json!({ "uri": 42 }))
+        .await
+        .unwrap_err();
+    assert_eq!(error["code"], -32602);
+    assert_eq!(error["data"]["reason"], "invalidArguments");
+    assert_eq!(error["data"]["command"], "claude-code.explain");
+    assert_eq!(error["data"]["expected"], "{ uri, range }");
+    assert_eq!(error["data"]["received"], json!({ "uri": 42 }));
+    let error = server
+        .lsp
+        .try_execute_command("claude-code.at-mention", json!({ "lineStart": 3 }))
+        .await
+        .unwrap_err();
+    assert_eq!(error["data"]["reason"], "invalidArguments");
+    let error = server
+        .lsp
+        .try_execute_command("claude-code.resend-selection", json!({ "index": "last" }))
+        .await
+        .unwrap_err();
+    assert_eq!(error["data"]["reason"], "invalidArguments");
+
+    // Files that aren't there
+    let error = server
+        .lsp
+        .try_execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": missing, "lineStart": 0 }),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error["code"], -32001);
+    assert_eq!(error["data"]["reason"], "fileNotFound");
+    assert_eq!(error["data"]["path"], missing.to_str().unwrap());
+    let error = server
+        .lsp
+        .try_execute_command("claude-code.review", json!({ "uri": missing_uri }))
+        .await
+        .unwrap_err();
+    assert_eq!(error["data"]["reason"], "fileNotFound");
+    let range =
+        json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 1, "character": 0 } });
+    let error = server
+        .lsp
+        .try_execute_command(
+            "claude-code.fix",
+            json!({ "uri": missing_uri, "range": range }),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error["data"]["reason"], "fileNotFound");
+
+    let error = server
+        .lsp
+        .try_execute_command("claude-code.no-such-command", json!({}))
+        .await
+        .unwrap_err();
+    assert_eq!(error["data"]["reason"], "unknownCommand");
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn explanations_stay_on_the_code_until_cleared() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = TestServer::start().await;
+    let source = server.write_file("src/main.rs", SOURCE);
+    let claude = server.write_file(
+        "bin/fake-claude",
+        "#!/bin/sh\ncat > /dev/null\nprintf '%s\\n' '{\"type\":\"result\",\"result\":\"Binds a to 1.\"}'\n",
+    );
+    std::fs::set_permissions(&claude, std::fs::Permissions::from_mode(0o755)).unwrap();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "claudeBinary": claude } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let uri = url::Url::from_file_path(&source).unwrap();
+    let range =
+        json!({ "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 9 } });
+
+    server
+        .lsp
+        .execute_command("claude-code.explain", json!({ "uri": uri, "range": range }))
+        .await;
+    let explanation = server.lsp.notification("claude/explanation").await;
+    assert_eq!(explanation["textDocument"]["uri"], uri.as_str());
+    assert_eq!(explanation["range"], range);
+    assert_eq!(explanation["explanation"], "Binds a to 1.");
+    let published = server
+        .lsp
+        .notification("textDocument/publishDiagnostics")
+        .await;
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{}", published);
+    assert_eq!(diagnostics[0]["range"], range);
+    assert_eq!(diagnostics[0]["severity"], 4);
+    assert_eq!(diagnostics[0]["source"], "Claude explanation");
+    assert_eq!(diagnostics[0]["message"], "Binds a to 1.");
+
+    server
+        .lsp
+        .execute_command("claude-code.clear-annotations", json!({}))
+        .await;
+    let published = server
+        .lsp
+        .notification("textDocument/publishDiagnostics")
+        .await;
+    assert_eq!(published["uri"], uri.as_str());
+    assert_eq!(published["diagnostics"], json!([]));
+    let cleared = server.lsp.notification("claude/clearAnnotations").await;
+    assert_eq!(cleared["textDocuments"], json!([{ "uri": uri }]));
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn prompts_can_bring_the_files_the_code_imports() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = TestServer::start().await;
+    server.write_file("Cargo.toml", "[package]\nname = \"shapes\"\n");
+    server.write_file("src/lib.rs", "mod area;\nmod circle;\nmod units;\n");
+    server.write_file("src/circle.rs", "pub struct Circle {\n    pub r: f64,\n}\n");
+    server.write_file("src/units.rs", "pub struct Meters(pub f64);\n");
+    let source = server.write_file(
+        "src/area.rs",
+        "use crate::circle::Circle;\nuse crate::units::Meters;\n\npub fn area(c: &Circle) -> f64 {\n    c.r * c.r\n}\n",
+    );
+    // Files outside the workspace, through `..` or a symlink, stay out
+    let outside = tempfile::tempdir().unwrap();
+    let secret = outside.path().join("secret.ts");
+    std::fs::write(&secret, "export const leaked = 'hunter2';\n").unwrap();
+    let up = "../".repeat(server.worktree().join("web").components().count() - 1);
+    server.write_file(
+        "web/app.ts",
+        &format!(
+            "import {{ add }} from './util';\nimport {{ unused }} from './other';\nimport {{ leaked }} from '{}{}';\nimport {{ linked }} from './linked';\n\nexport const sum = add(leaked, linked);\n",
+            up,
+            secret.display().to_string().trim_start_matches('/')
+        ),
+    );
+    std::os::unix::fs::symlink(&secret, server.worktree().join("web/linked.ts")).unwrap();
+    server.write_file(
+        "web/util.ts",
+        "export const add = (a: number, b: number) => a + b;\n",
+    );
+    server.write_file("web/other.ts", "export const unused = 0;\n");
+    let claude = server.write_file(
+        "bin/fake-claude",
+        "#!/bin/sh\ncat > \"$0.prompt\"\nprintf '%s\\n' '{\"type\":\"result\",\"result\":\"Squares r.\"}'\n",
+    );
+    std::fs::set_permissions(&claude, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let configure = |prompt_context| {
+        server.lsp.notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": {
+                "claudeBinary": claude,
+                "promptContext": prompt_context,
+            } } }),
+        )
+    };
+    let (lsp, prompt_file) = (&server.lsp, claude.with_extension("prompt"));
+    let explain = |path: &Path, line: u32| {
+        let uri = url::Url::from_file_path(path).unwrap();
+        let range = json!({ "start": { "line": line, "character": 0 }, "end": { "line": line + 1, "character": 0 } });
+        let prompt_file = &prompt_file;
+        async move {
+            lsp.execute_command("claude-code.explain", json!({ "uri": uri, "range": range }))
+                .await;
+            std::fs::read_to_string(prompt_file).unwrap()
+        }
+    };
+
+    // Off by default
+    configure(json!({})).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let prompt = explain(&source, 3).await;
+    assert!(!prompt.contains("pub r: f64"), "{}", prompt);
+
+    // The module a name the code uses comes from, but not the unused one
+    configure(json!({ "imports": true })).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let prompt = explain(&source, 3).await;
+    assert!(prompt.contains("`src/circle.rs`"), "{}", prompt);
+    assert!(prompt.contains("pub r: f64"), "{}", prompt);
+    assert!(!prompt.contains("Meters"), "{}", prompt);
+
+    // Relative TypeScript specifiers, without their extension
+    let prompt = explain(&server.worktree().join("web/app.ts"), 5).await;
+    assert!(prompt.contains("`web/util.ts`"), "{}", prompt);
+    assert!(!prompt.contains("`web/other.ts`"), "{}", prompt);
+    assert!(!prompt.contains("hunter2"), "{}", prompt);
+
+    // Capped by size, whole lines at a time
+    configure(json!({ "imports": true, "importBytes": 20 })).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let prompt = explain(&source, 3).await;
+    assert!(
+        prompt.contains("`src/circle.rs` (its first lines)"),
+        "{}",
+        prompt
+    );
+    assert!(prompt.contains("pub struct Circle {\n```"), "{}", prompt);
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn commit_messages_describe_the_staged_changes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = TestServer::start().await;
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(server.worktree())
+            .output()
+            .expect("run git")
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "-q"]);
+    server.write_file("README.md", "math\n");
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "docs: describe the project"]);
+    // Keeps the prompt and answers with a fenced message
+    let claude = server.write_file(
+        ".fake-claude",
+        "#!/bin/sh\ncat > \"$0.prompt\"\nprintf '%s\\n' '{\"type\":\"result\",\"result\":\"```\\nfeat: add math\\n```\"}'\n",
+    );
+    std::fs::set_permissions(&claude, std::fs::Permissions::from_mode(0o755)).unwrap();
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "claudeBinary": claude } } }),
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Nothing staged yet
+    let result = server
+        .lsp
+        .execute_command("claude-code.generate-commit-message", json!({}))
+        .await;
+    assert!(result.is_null(), "{}", result);
+    let message = server.lsp.notification("window/showMessage").await;
+    assert!(
+        message["message"]
+            .as_str()
+            .unwrap()
+            .contains("Nothing is staged"),
+        "{}",
+        message
+    );
+
+    server.write_file(
+        "src/math.rs",
+        "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    git(&["add", "src/math.rs"]);
+    let result = server
+        .lsp
+        .execute_command("claude-code.generate-commit-message", json!({}))
+        .await;
+    assert_eq!(result, "feat: add math");
+    let prompt = std::fs::read_to_string(claude.with_extension("prompt")).unwrap();
+    assert!(prompt.contains("+pub fn add"), "{}", prompt);
+    assert!(prompt.contains("docs: describe the project"), "{}", prompt);
+    assert_eq!(
+        std::fs::read_to_string(server.worktree().join(".git/COMMIT_EDITMSG")).unwrap(),
+        "feat: add math\n"
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn typed_path_mentions_are_sent_when_finished() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/main.rs", SOURCE);
+    server.write_file("notes.md", "");
+    let uri = server.open("notes.md", "markdown").await;
+
+    // The mention is only finished by the space after it
+    for (version, (character, text)) in [(0, "see @src/main.rs:2-3"), (20, " ")]
+        .into_iter()
+        .enumerate()
+    {
+        server
+            .lsp
+            .notify(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": uri, "version": version + 2 },
+                    "contentChanges": [{
+                        "range": {
+                            "start": { "line": 0, "character": character },
+                            "end": { "line": 0, "character": character },
+                        },
+                        "text": text,
+                    }],
+                }),
+            )
+            .await;
+    }
+
+    let mentions = claude
+        .notifications_within("at_mentioned", Duration::from_secs(1))
+        .await;
+    assert_eq!(mentions.len(), 1, "expected one mention: {:?}", mentions);
+    let mention: AtMentionedNotification =
+        serde_json::from_value(mentions[0].params.clone()).unwrap();
+    assert_eq!(mention.file_path, path.to_string_lossy());
+    assert_eq!((mention.line_start, mention.line_end), (1, 2));
+    assert_eq!(mention.instruction, None);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn typed_instructions_carry_the_enclosing_code() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+
+    server
+        .lsp
+        .notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{
+                    "range": {
+                        "start": { "line": 2, "character": 14 },
+                        "end": { "line": 2, "character": 14 },
+                    },
+                    "text": " // @claude use a loop\n",
+                }],
+            }),
+        )
+        .await;
+
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert_eq!(mention.instruction.as_deref(), Some("use a loop"));
+    assert_eq!(mention.file_path, uri.path());
+    assert_eq!((mention.line_start, mention.line_end), (0, 6));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn selections_in_sensitive_files_are_redacted() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("config/.env", "API_KEY=hunter2\n");
+    let uri = server.open("config/.env", "dotenv").await;
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(0, 15) }),
+        )
+        .await;
+
+    let notification = claude.notification("selection_changed").await;
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(notification.params).unwrap();
+    assert_eq!(selection.text, "[redacted]");
+    assert_eq!(selection.selection.end.character, 15);
+    assert_eq!(selection.file_url, uri.as_str());
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_sensitive_files_are_redacted_as_resources() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("config/.env", "API_KEY=hunter2\n");
+    server.open("config/.env", "dotenv").await;
+    let resource = format!(
+        "ide://open-files/{}",
+        path.to_string_lossy().trim_start_matches('/')
+    );
+
+    let contents = claude
+        .request("resources/read", json!({ "uri": resource }))
+        .await;
+    assert_eq!(contents["contents"][0]["text"], "[redacted]");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn selections_bring_the_lines_around_them() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "selectionContextLines": 2 } } }),
+        )
+        .await;
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(4, 12) }),
+        )
+        .await;
+
+    let notification = claude.notification("selection_changed").await;
+    assert_eq!(notification.params["text"], "    println!");
+    let surrounding = &notification.params["surroundingLines"];
+    assert_eq!(surrounding["before"], "    let b = 2;\n    let c = 3;\n");
+    assert_eq!(surrounding["beforeStartLine"], 2);
+    // Only one line is left below
+    assert_eq!(surrounding["after"], "}\n");
+    assert_eq!(surrounding["afterEndLine"], 5);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn selections_in_large_files_are_sent_by_reference() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server
+        .lsp
+        .notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "claudeCode": { "contextBudget": { "largeFileBytes": 64 } } } }),
+        )
+        .await;
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(2, 14) }),
+        )
+        .await;
+
+    let notification = claude.notification("selection_changed").await;
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(notification.params).unwrap();
+    assert_eq!(selection.text, "");
+    let reference = selection.reference.expect("a file reference");
+    assert_eq!(reference.range.start.line, 2);
+    assert_eq!(reference.total_bytes, SOURCE.len() as u64);
+
+    let read = claude
+        .call_tool(
+            "readFileRange",
+            json!({
+                "filePath": reference.file_path,
+                "startLine": reference.range.start.line,
+                "endLine": reference.range.end.line,
+                "hash": reference.hash,
+            }),
+        )
+        .await;
+    assert_eq!(read["success"], true, "{}", read);
+    assert_eq!(read["text"], "    let b = 2;\n");
+    assert_eq!(read["totalLines"], 6);
+    assert_eq!(read["stale"], false);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn paths_with_spaces_and_accents_are_decoded() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let path = server.write_file("src/my notes/café.rs", SOURCE);
+    let uri = server.open("src/my notes/café.rs", "rust").await;
+    assert!(uri.as_str().contains("my%20notes/caf%C3%A9.rs"), "{}", uri);
+
+    let opened = claude.notification("opened_file").await;
+    assert_eq!(opened.params["filePath"], path.to_string_lossy().as_ref());
+    assert_eq!(opened.params["relativePath"], "src/my notes/café.rs");
+    assert_eq!(opened.params["fileUrl"], uri.as_str());
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(1, 14) }),
+        )
+        .await;
+    let notification = claude.notification("selection_changed").await;
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(notification.params).unwrap();
+    assert_eq!(selection.text, "    let a = 1;");
+    assert_eq!(selection.file_path, path.to_string_lossy());
+    assert_eq!(
+        selection.relative_path.as_deref(),
+        Some("src/my notes/café.rs")
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn paths_of_drive_letter_uris_keep_their_drive() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let uri = "file:///C:/project/src/main.rs";
+    let path = if cfg!(windows) {
+        r"C:\project\src\main.rs"
+    } else {
+        "C:/project/src/main.rs"
+    };
+    server
+        .lsp
+        .notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": uri, "languageId": "rust", "version": 1, "text": SOURCE }
+            }),
+        )
+        .await;
+
+    let opened = claude.notification("opened_file").await;
+    assert_eq!(opened.params["filePath"], path);
+    assert_eq!(opened.params["fileUrl"], uri);
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(1, 14) }),
+        )
+        .await;
+    let notification = claude.notification("selection_changed").await;
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(notification.params).unwrap();
+    assert_eq!(selection.file_path, path);
+    assert_eq!(selection.file_url, uri);
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn each_client_gets_the_notifications_it_filters_for() {
+    let server = TestServer::start().await;
+    let mut selections_only = server.mcp().await;
+    let mut everything = server.mcp().await;
+    let filter = selections_only
+        .request(
+            "ide/setNotificationFilter",
+            json!({ "methods": ["selection_changed"] }),
+        )
+        .await;
+    assert_eq!(filter["methods"], json!(["selection_changed"]));
+
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+    everything.notification("opened_file").await;
+    let opened = selections_only
+        .notifications_within("opened_file", Duration::from_millis(300))
+        .await;
+    assert!(opened.is_empty(), "filtered client got {:?}", opened);
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(1, 14) }),
+        )
+        .await;
+    let selection = selections_only.notification("selection_changed").await;
+    assert_eq!(selection.params["text"], "    let a = 1;");
+    everything.notification("selection_changed").await;
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn older_clients_get_the_notification_versions_they_understand() {
+    let server = TestServer::start().await;
+    let (mut older, initialized) = server
+        .mcp_with_capabilities(json!({
+            "experimental": { "ideNotifications": { "selection_changed": 1, "unknown": 3 } }
+        }))
+        .await;
+    assert_eq!(
+        initialized["capabilities"]["experimental"]["ideNotifications"],
+        json!({ "selection_changed": 1 })
+    );
+    let (mut latest, initialized) = server.mcp_with_capabilities(json!({})).await;
+    assert_eq!(
+        initialized["capabilities"]["experimental"]["ideNotifications"]["selection_changed"],
+        5
+    );
+
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+    latest.notification("opened_file").await;
+    // Methods the client didn't list aren't sent to it
+    let opened = older
+        .notifications_within("opened_file", Duration::from_millis(300))
+        .await;
+    assert!(opened.is_empty(), "older client got {:?}", opened);
+
+    server
+        .lsp
+        .notify(
+            "claude/selectionDidChange",
+            json!({ "textDocument": { "uri": uri }, "selection": selection(1, 14) }),
+        )
+        .await;
+    let selection = older.notification("selection_changed").await;
+    let mut fields: Vec<_> = selection.params.as_object().unwrap().keys().collect();
+    fields.sort();
+    assert_eq!(fields, ["filePath", "fileUrl", "selection", "text"]);
+    let selection = latest.notification("selection_changed").await;
+    assert_eq!(selection.params["languageId"], "rust");
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn other_servers_diagnostics_get_fix_with_claude() {
+    let server = TestServer::start().await;
+    server.write_file("src/main.rs", SOURCE);
+    let uri = server.open("src/main.rs", "rust").await;
+
+    let diagnostic = json!({
+        "range": { "start": { "line": 2, "character": 8 }, "end": { "line": 2, "character": 9 } },
+        "severity": 2,
+        "source": "rustc",
+        "message": "unused variable: `b`\nhelp: prefix it with an underscore"
+    });
+    let actions = server
+        .lsp
+        .request(
+            "textDocument/codeAction",
+            json!({
+                "textDocument": { "uri": uri },
+                "range": diagnostic["range"],
+                "context": { "diagnostics": [diagnostic] }
+            }),
+        )
+        .await;
+
+    let fix = actions
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|action| action["title"] == "Fix with Claude: unused variable: `b`")
+        .unwrap_or_else(|| panic!("no quick fix in {}", actions));
+    assert_eq!(fix["kind"], "quickfix");
+    assert_eq!(fix["diagnostics"][0]["message"], diagnostic["message"]);
+    assert_eq!(fix["data"]["action"], "fix");
+    assert_eq!(fix["data"]["diagnostic"]["source"], "rustc");
+    // The fix covers the problem's whole line
+    assert_eq!(
+        fix["data"]["range"],
+        json!({ "start": { "line": 2, "character": 0 }, "end": { "line": 3, "character": 0 } })
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn at_mention_lines_are_clamped_and_symbols_resolved() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    let main = server.write_file("src/main.rs", SOURCE);
+    server.write_file("src/lexer.rs", "use std::str::Chars;\n\nfn parse() {}\n");
+    let parser = server.write_file(
+        "src/parser.rs",
+        "pub fn parse(input: &str) {\n    todo!()\n}\n",
+    );
+
+    // Past the end of the file, and backwards
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "filePath": main, "lineStart": 40, "lineEnd": 3 }),
+        )
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert_eq!((mention.line_start, mention.line_end), (3, 5));
+
+    // A qualified name picks the declaration in the module it names
+    server
+        .lsp
+        .execute_command(
+            "claude-code.at-mention",
+            json!({ "symbol": "parser::parse" }),
+        )
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert_eq!(mention.file_path, parser.to_string_lossy());
+    assert_eq!((mention.line_start, mention.line_end), (0, 2));
+    assert_eq!(mention.symbol.as_deref(), Some("parser::parse"));
+
+    // Typed too
+    server.write_file("notes.md", "");
+    let uri = server.open("notes.md", "markdown").await;
+    server
+        .lsp
+        .notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 0 },
+                    },
+                    "text": "see @lexer::parse ",
+                }],
+            }),
+        )
+        .await;
+    let notification = claude.notification("at_mentioned").await;
+    let mention: AtMentionedNotification = serde_json::from_value(notification.params).unwrap();
+    assert!(
+        mention.file_path.ends_with("lexer.rs"),
+        "{}",
+        mention.file_path
+    );
+    assert_eq!((mention.line_start, mention.line_end), (2, 2));
+
+    server.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn earlier_selections_can_be_listed_and_resent() {
+    let server = TestServer::start().await;
+    let mut claude = server.mcp().await;
+    server.write_file("src/a.rs", "fn a() {}\n");
+    server.write_file("src/b.rs", "fn b() {}\nfn c() {}\n");
+    let a = server.open("src/a.rs", "rust").await;
+    let b = server.open("src/b.rs", "rust").await;
+
+    for (uri, line) in [(&a, 0), (&b, 0), (&b, 1)] {
+        server
+            .lsp
+            .notify(
+                "claude/selectionDidChange",
+                json!({ "textDocument": { "uri": uri }, "selection": selection(line, 9) }),
+            )
+            .await;
+        claude.notification("selection_changed").await;
+    }
+
+    let history = claude.call_tool("getSelectionHistory", json!({})).await;
+    assert_eq!(history["success"], true);
+    let texts: Vec<&str> = history["selections"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|selection| selection["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, ["fn c() {}", "fn b() {}", "fn a() {}"]);
+    assert!(history["selections"][0]["selectedAt"].as_u64().unwrap() > 0);
+
+    let history = claude
+        .call_tool(
+            "getSelectionHistory",
+            json!({ "filePath": "src/b.rs", "limit": 1 }),
+        )
+        .await;
+    assert_eq!(history["selections"].as_array().unwrap().len(), 1);
+    assert_eq!(history["selections"][0]["text"], "fn c() {}");
+
+    server
+        .lsp
+        .execute_command("claude-code.resend-selection", json!({ "index": 2 }))
+        .await;
+    let notification = claude.notification("selection_changed").await;
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(notification.params).unwrap();
+    assert_eq!(selection.text, "fn a() {}");
+    assert_eq!(selection.file_url, a.as_str());
+    let latest = claude.call_tool("getLatestSelection", json!({})).await;
+    assert_eq!(latest["text"], "fn a() {}");
+
+    server.stop().await;
+}
@@ -0,0 +1,903 @@
+//! In-process harness for end-to-end tests: runs the hybrid server with its LSP
+//! side on an in-memory duplex stream, plays the editor through `LspClient` and
+//! Claude through `McpClient`.
+
+// Each test binary uses its own part of the harness
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+use claude_code_server_core::{
+    JsonRpcNotification, LanguageServer, LspTransport, ServerBuilder, Shutdown,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::io::{
+    AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+/// How long to wait for anything the server should do
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+const AUTH_HEADER: &str = "x-claude-code-ide-authorization";
+
+static HOME: Once = Once::new();
+
+/// Port naming the lock file whose workspace folders include `worktree`
+pub fn advertised_port(worktree: &Path) -> Option<u16> {
+    let dir = dirs::home_dir()?.join(".claude").join("ide");
+    std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let path = entry.path();
+        let port = path.file_stem()?.to_str()?.parse().ok()?;
+        if path.extension()? != "lock" {
+            return None;
+        }
+        let lock: Value = serde_json::from_slice(&std::fs::read(&path).ok()?).ok()?;
+        let folders = lock["workspaceFolders"].as_array()?;
+        folders
+            .iter()
+            .any(|folder| {
+                folder
+                    .as_str()
+                    .is_some_and(|folder| Path::new(folder) == worktree)
+            })
+            .then_some(port)
+    })
+}
+
+/// Give the test binary a home directory of its own, so lock files never land in
+/// the real ~/.claude/ide
+fn isolate_home() {
+    HOME.call_once(|| {
+        let home = tempfile::tempdir().expect("create home directory").keep();
+        std::env::set_var("HOME", home);
+    });
+}
+
+/// Where the servers of this test binary write their lock files
+pub fn ide_dir() -> PathBuf {
+    isolate_home();
+    dirs::home_dir()
+        .expect("home directory")
+        .join(".claude")
+        .join("ide")
+}
+
+/// Ask the OS for a port nothing listens on
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind a free port");
+    listener.local_addr().expect("local address").port()
+}
+
+/// A hybrid server running in-process, with an initialized LSP session
+pub struct TestServer {
+    pub lsp: LspClient,
+    port: u16,
+    worktree: TempDir,
+    shutdown: Shutdown,
+    server: JoinHandle<anyhow::Result<()>>,
+}
+
+impl TestServer {
+    /// Start a server on a free port for an empty worktree, and wait until Claude
+    /// could find it
+    pub async fn start() -> Self {
+        Self::start_with(|builder| builder).await
+    }
+
+    /// Like `start`, with more settings applied to the server
+    pub async fn start_with(configure: impl FnOnce(ServerBuilder) -> ServerBuilder) -> Self {
+        Self::launch(configure, json!({})).await
+    }
+
+    /// Like `start`, for an editor declaring `capabilities` in initialize
+    pub async fn start_with_capabilities(capabilities: Value) -> Self {
+        Self::launch(|builder| builder, capabilities).await
+    }
+
+    /// Start a server in proxy mode, in front of `fake_language_server`
+    pub async fn start_proxy() -> Self {
+        let (proxy, language_server) = tokio::io::duplex(64 * 1024);
+        fake_language_server(language_server);
+        let (input, output) = tokio::io::split(proxy);
+        let language_server = LanguageServer::Transport(LspTransport::new(input, output));
+        Self::launch_with(
+            |builder| builder.with_language_server(language_server),
+            json!({}),
+            ServerBuilder::run_proxy,
+        )
+        .await
+    }
+
+    async fn launch(
+        configure: impl FnOnce(ServerBuilder) -> ServerBuilder,
+        capabilities: Value,
+    ) -> Self {
+        Self::launch_with(configure, capabilities, ServerBuilder::run_hybrid).await
+    }
+
+    async fn launch_with<F>(
+        configure: impl FnOnce(ServerBuilder) -> ServerBuilder,
+        capabilities: Value,
+        run: impl FnOnce(ServerBuilder) -> F,
+    ) -> Self
+    where
+        F: std::future::Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        isolate_home();
+        let worktree = tempfile::tempdir().expect("create worktree");
+        let port = free_port();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (input, output) = tokio::io::split(server);
+        let shutdown = Shutdown::default();
+
+        // Trusted, so tests can use every tool without answering the trust prompt
+        let builder = ServerBuilder::new()
+            .with_worktree(worktree.path())
+            .with_mcp_port(port)
+            .with_lsp_transport(LspTransport::new(input, output))
+            .with_shutdown(shutdown.clone())
+            .with_workspace_trust(Some(true));
+        let server = tokio::spawn(run(configure(builder)));
+
+        let lsp = LspClient::new(client);
+        let root = Url::from_directory_path(worktree.path()).expect("worktree URI");
+        lsp.request(
+            "initialize",
+            json!({ "processId": null, "rootUri": root, "capabilities": capabilities }),
+        )
+        .await;
+        lsp.notify("initialized", json!({})).await;
+
+        // The server picks another port when the one it was given is busy, so
+        // look for the lock file advertising this worktree
+        let deadline = Instant::now() + TIMEOUT;
+        let port = loop {
+            if let Some(port) = advertised_port(worktree.path()) {
+                break port;
+            }
+            assert!(Instant::now() < deadline, "lock file never appeared");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        Self {
+            lsp,
+            port,
+            worktree,
+            shutdown,
+            server,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn lock_file(&self) -> PathBuf {
+        ide_dir().join(format!("{}.lock", self.port))
+    }
+
+    pub fn worktree(&self) -> &Path {
+        self.worktree.path()
+    }
+
+    /// Create a file in the worktree, returning its absolute path
+    pub fn write_file(&self, relative: &str, contents: &str) -> PathBuf {
+        let path = self.worktree.path().join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("create parent directories");
+        }
+        std::fs::write(&path, contents).expect("write file");
+        path
+    }
+
+    /// Open a worktree file in the editor, returning its URI
+    pub async fn open(&self, relative: &str, language_id: &str) -> Url {
+        let path = self.worktree.path().join(relative);
+        let text = std::fs::read_to_string(&path).expect("read file");
+        let uri = Url::from_file_path(&path).expect("file URI");
+        self.lsp
+            .notify(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": language_id,
+                        "version": 1,
+                        "text": text,
+                    }
+                }),
+            )
+            .await;
+        uri
+    }
+
+    /// The auth token the lock file gives clients
+    pub fn auth_token(&self) -> String {
+        let lock_file: Value =
+            serde_json::from_slice(&std::fs::read(self.lock_file()).expect("read lock file"))
+                .expect("parse lock file");
+        lock_file["authToken"]
+            .as_str()
+            .expect("auth token")
+            .to_string()
+    }
+
+    /// Connect to the MCP server the way the Claude CLI does: with the token from
+    /// the lock file, then the initialize handshake
+    pub async fn mcp(&self) -> McpClient {
+        self.mcp_with_capabilities(json!({})).await.0
+    }
+
+    /// Connect like `mcp`, initializing with `capabilities`, and return the
+    /// initialize result as well
+    pub async fn mcp_with_capabilities(&self, capabilities: Value) -> (McpClient, Value) {
+        let token = self.auth_token();
+
+        let mut request = format!("ws://127.0.0.1:{}", self.port)
+            .into_client_request()
+            .expect("WebSocket request");
+        request
+            .headers_mut()
+            .insert(AUTH_HEADER, token.parse().expect("header value"));
+        let (stream, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .expect("connect to the MCP server");
+
+        let mut client = McpClient {
+            stream,
+            next_id: 1,
+            notifications: VecDeque::new(),
+            responses: VecDeque::new(),
+        };
+        let initialized = client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": capabilities,
+                    "clientInfo": { "name": "test-client", "version": "0.0.0" },
+                }),
+            )
+            .await;
+        client.notify("notifications/initialized", json!({})).await;
+        (client, initialized)
+    }
+
+    /// Open an MCP session over HTTP+SSE instead of the WebSocket
+    pub async fn sse(&self) -> SseClient {
+        let token = self.auth_token();
+        let mut stream = BufReader::new(
+            TcpStream::connect(("127.0.0.1", self.port))
+                .await
+                .expect("connect to the MCP server"),
+        );
+        let request = format!(
+            "GET /sse HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nAccept: text/event-stream\r\n{}: {}\r\n\r\n",
+            self.port, AUTH_HEADER, token
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .expect("open the event stream");
+
+        let mut client = SseClient {
+            port: self.port,
+            token,
+            stream,
+            endpoint: String::new(),
+            next_id: 1,
+        };
+        let status = client.line().await;
+        assert!(status.contains("200"), "unexpected status: {}", status);
+        while !client.line().await.is_empty() {}
+        let (name, endpoint) = client.event().await.expect("endpoint event");
+        assert_eq!(name, "endpoint");
+        client.endpoint = endpoint;
+        client
+    }
+
+    /// GET a plain HTTP path next to the WebSocket, returning the status line and
+    /// body
+    pub async fn get(&self, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port))
+            .await
+            .expect("connect to the MCP server");
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\n\r\n",
+            path, self.port
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .expect("send the request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read the response");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+        let status = head.lines().next().unwrap_or_default().to_string();
+        (status, body.to_string())
+    }
+
+    /// Shut down the way an editor does, through the LSP shutdown request and exit
+    /// notification, and wait for the server to stop
+    pub async fn stop(self) {
+        self.lsp.request("shutdown", Value::Null).await;
+        self.lsp.notify("exit", Value::Null).await;
+        tokio::time::timeout(TIMEOUT * 2, self.server)
+            .await
+            .expect("server did not stop")
+            .expect("server task panicked")
+            .expect("server failed");
+    }
+
+    /// Stop without going through the editor, as a signal would
+    pub async fn trigger_shutdown(self) {
+        self.shutdown.trigger();
+        tokio::time::timeout(TIMEOUT * 2, self.server)
+            .await
+            .expect("server did not stop")
+            .expect("server task panicked")
+            .expect("server failed");
+    }
+}
+
+type Pending = Arc<std::sync::Mutex<HashMap<i64, oneshot::Sender<Value>>>>;
+
+/// The editor's end of the LSP connection. Requests from the server (e.g.
+/// workspace/configuration) are answered with null, except for
+/// window/showMessageRequest once a button is picked for it, and
+/// workspace/applyEdit and window/showDocument, which are reported as done
+/// without changing anything (unless the edit touches a file edits are refused for).
+/// They can be awaited like notifications.
+pub struct LspClient {
+    writer: Arc<Mutex<WriteHalf<DuplexStream>>>,
+    pending: Pending,
+    next_id: AtomicI64,
+    notifications: Mutex<mpsc::UnboundedReceiver<Value>>,
+    /// Button picked for window/showMessageRequest; none dismisses the message
+    message_action: Arc<std::sync::Mutex<Option<String>>>,
+    /// URIs of files workspace/applyEdit fails for
+    refused_edits: Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl LspClient {
+    fn new(stream: DuplexStream) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        let writer = Arc::new(Mutex::new(writer));
+        let pending = Pending::default();
+        let (notification_sender, notifications) = mpsc::unbounded_channel();
+
+        let reader_writer = writer.clone();
+        let reader_pending = pending.clone();
+        let message_action = Arc::new(std::sync::Mutex::new(None));
+        let reader_message_action = message_action.clone();
+        let refused_edits = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+        let reader_refused_edits = refused_edits.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(reader);
+            while let Some(message) = read_message(&mut reader).await {
+                match (message.get("id"), message.get("method")) {
+                    (Some(id), Some(method)) => {
+                        let action = reader_message_action.lock().unwrap().clone();
+                        let edit = message["params"]["edit"].to_string();
+                        let refused = reader_refused_edits
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .any(|uri| edit.contains(uri.as_str()));
+                        let result = match action {
+                            _ if method == "workspace/applyEdit" && refused => json!({
+                                "applied": false,
+                                "failureReason": "refused by the test"
+                            }),
+                            Some(title) if method == "window/showMessageRequest" => {
+                                json!({ "title": title })
+                            }
+                            _ if method == "workspace/applyEdit" => json!({ "applied": true }),
+                            _ if method == "window/showDocument" => json!({ "success": true }),
+                            _ => Value::Null,
+                        };
+                        let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+                        write_message(&reader_writer, &response).await;
+                        let _ = notification_sender.send(message);
+                    }
+                    (Some(id), None) => {
+                        let sender = id
+                            .as_i64()
+                            .and_then(|id| reader_pending.lock().unwrap().remove(&id));
+                        if let Some(sender) = sender {
+                            let _ = sender.send(message);
+                        }
+                    }
+                    _ => {
+                        let _ = notification_sender.send(message);
+                    }
+                }
+            }
+        });
+
+        Self {
+            writer,
+            pending,
+            next_id: AtomicI64::new(1),
+            notifications: Mutex::new(notifications),
+            message_action,
+            refused_edits,
+        }
+    }
+
+    /// Fail every workspace/applyEdit touching `path` from now on
+    pub fn refuse_edits_to(&self, path: &Path) {
+        let uri = Url::from_file_path(path).expect("file URI");
+        self.refused_edits.lock().unwrap().push(uri.to_string());
+    }
+
+    /// Answer every window/showMessageRequest from now on with the button `title`
+    pub fn pick_message_action(&self, title: &str) {
+        *self.message_action.lock().unwrap() = Some(title.to_string());
+    }
+
+    /// Send a request and return its result, failing the test on an error response.
+    /// Null params are left out, as for shutdown.
+    pub async fn request(&self, method: &str, params: Value) -> Value {
+        self.try_request(method, params)
+            .await
+            .unwrap_or_else(|error| panic!("{} failed: {}", method, error))
+    }
+
+    /// Send a request and wait for its result, or its error object
+    pub async fn try_request(&self, method: &str, params: Value) -> Result<Value, Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let mut message = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if !params.is_null() {
+            message["params"] = params;
+        }
+        write_message(&self.writer, &message).await;
+
+        let response = tokio::time::timeout(TIMEOUT, receiver)
+            .await
+            .unwrap_or_else(|_| panic!("no response to {}", method))
+            .expect("LSP connection closed");
+        match response.get("error") {
+            Some(error) => Err(error.clone()),
+            None => Ok(response["result"].clone()),
+        }
+    }
+
+    /// Send a notification; null params are left out, as for exit
+    pub async fn notify(&self, method: &str, params: Value) {
+        let mut message = json!({ "jsonrpc": "2.0", "method": method });
+        if !params.is_null() {
+            message["params"] = params;
+        }
+        write_message(&self.writer, &message).await;
+    }
+
+    /// Wait for the server to send the notification `method`, skipping others
+    pub async fn notification(&self, method: &str) -> Value {
+        let mut notifications = self.notifications.lock().await;
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let notification = tokio::time::timeout_at(deadline, notifications.recv())
+                .await
+                .unwrap_or_else(|_| panic!("no {} notification", method))
+                .expect("LSP connection closed");
+            if notification["method"] == method {
+                return notification["params"].clone();
+            }
+        }
+    }
+
+    pub async fn execute_command(&self, command: &str, arguments: Value) -> Value {
+        self.request(
+            "workspace/executeCommand",
+            json!({ "command": command, "arguments": [arguments] }),
+        )
+        .await
+    }
+
+    /// Run a command, returning its error object if it fails
+    pub async fn try_execute_command(
+        &self,
+        command: &str,
+        arguments: Value,
+    ) -> Result<Value, Value> {
+        self.try_request(
+            "workspace/executeCommand",
+            json!({ "command": command, "arguments": [arguments] }),
+        )
+        .await
+    }
+}
+
+/// A language server for proxy mode that answers initialize, hovers with the
+/// word "hover" and shutdown, reports one warning for every document opened, and
+/// for the command "fix" asks the editor to apply an edit. Stops on exit.
+fn fake_language_server(stream: DuplexStream) {
+    let (reader, writer) = tokio::io::split(stream);
+    let writer = Mutex::new(writer);
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        while let Some(message) = read_message(&mut reader).await {
+            let (Some(id), Some(method)) = (message.get("id"), message["method"].as_str()) else {
+                if message["method"] == "exit" {
+                    break;
+                }
+                if message["method"] == "textDocument/didOpen" {
+                    let uri = &message["params"]["textDocument"]["uri"];
+                    let diagnostics = json!({
+                        "uri": uri,
+                        "diagnostics": [{
+                            "range": {
+                                "start": { "line": 0, "character": 0 },
+                                "end": { "line": 0, "character": 1 }
+                            },
+                            "severity": 2,
+                            "source": "fake",
+                            "message": "unused variable"
+                        }]
+                    });
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "textDocument/publishDiagnostics",
+                        "params": diagnostics
+                    });
+                    write_message(&writer, &notification).await;
+                }
+                continue;
+            };
+            let result = match method {
+                "initialize" => json!({ "capabilities": { "hoverProvider": true } }),
+                "textDocument/hover" => json!({
+                    "contents": { "kind": "markdown", "value": "hover" }
+                }),
+                "workspace/executeCommand" => {
+                    let edit = json!({
+                        "jsonrpc": "2.0",
+                        "id": "fix",
+                        "method": "workspace/applyEdit",
+                        "params": { "label": "fix", "edit": { "changes": {} } }
+                    });
+                    write_message(&writer, &edit).await;
+                    Value::Null
+                }
+                _ => Value::Null,
+            };
+            let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            write_message(&writer, &response).await;
+        }
+    });
+}
+
+async fn write_message(writer: &Mutex<WriteHalf<DuplexStream>>, message: &Value) {
+    let body = message.to_string();
+    let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    writer
+        .lock()
+        .await
+        .write_all(framed.as_bytes())
+        .await
+        .expect("write to the LSP server");
+}
+
+async fn read_message(reader: &mut BufReader<ReadHalf<DuplexStream>>) -> Option<Value> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).await.ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+/// Claude's end of the MCP WebSocket
+pub struct McpClient {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: i64,
+    /// Notifications that arrived while waiting for a response
+    notifications: VecDeque<JsonRpcNotification>,
+    /// Responses that arrived while waiting for another one
+    responses: VecDeque<Value>,
+}
+
+impl McpClient {
+    /// Send a request and return its result, failing the test on an error response
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.start_request(method, params).await;
+        let message = self
+            .response(id)
+            .await
+            .unwrap_or_else(|| panic!("no response to {}", method));
+        if let Some(error) = message.get("error") {
+            panic!("{} failed: {}", method, error);
+        }
+        message["result"].clone()
+    }
+
+    /// Send a request without waiting for its response, returning its id
+    pub async fn start_request(&mut self, method: &str, params: Value) -> i64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await;
+        id
+    }
+
+    /// The response with `id`, or None when none arrives in time
+    pub async fn response(&mut self, id: i64) -> Option<Value> {
+        if let Some(index) = self
+            .responses
+            .iter()
+            .position(|response| response["id"] == id)
+        {
+            return self.responses.remove(index);
+        }
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let message = self.next_message(deadline).await?;
+            if message.get("method").is_some() {
+                if let Ok(notification) = serde_json::from_value(message) {
+                    self.notifications.push_back(notification);
+                }
+            } else if message["id"] == id {
+                return Some(message);
+            } else {
+                self.responses.push_back(message);
+            }
+        }
+    }
+
+    pub async fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await;
+    }
+
+    /// Call a tool and parse the JSON in its text content
+    pub async fn call_tool(&mut self, name: &str, arguments: Value) -> Value {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await;
+        let text = result["content"][0]["text"]
+            .as_str()
+            .unwrap_or_else(|| panic!("{} returned no text: {}", name, result));
+        serde_json::from_str(text).unwrap_or_else(|_| json!(text))
+    }
+
+    /// Wait for the editor side to send the request `method`, and answer it with
+    /// `result`. Returns the request's params.
+    pub async fn answer(&mut self, method: &str, result: Value) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let message = self
+                .next_message(deadline)
+                .await
+                .unwrap_or_else(|| panic!("no {} request", method));
+            if message["method"] == method && message.get("id").is_some() {
+                self.send(json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))
+                    .await;
+                return message["params"].clone();
+            }
+            if let Ok(notification) = serde_json::from_value(message) {
+                self.notifications.push_back(notification);
+            }
+        }
+    }
+
+    /// Wait for the notification `method`, skipping others
+    pub async fn notification(&mut self, method: &str) -> JsonRpcNotification {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let notification = self
+                .next_notification(deadline)
+                .await
+                .unwrap_or_else(|| panic!("no {} notification", method));
+            if notification.method == method {
+                return notification;
+            }
+        }
+    }
+
+    /// Every notification `method` received within `window`
+    pub async fn notifications_within(
+        &mut self,
+        method: &str,
+        window: Duration,
+    ) -> Vec<JsonRpcNotification> {
+        let deadline = Instant::now() + window;
+        let mut received = Vec::new();
+        while let Some(notification) = self.next_notification(deadline).await {
+            if notification.method == method {
+                received.push(notification);
+            }
+        }
+        received
+    }
+
+    async fn next_notification(&mut self, deadline: Instant) -> Option<JsonRpcNotification> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Some(notification);
+        }
+        loop {
+            let message = self.next_message(deadline).await?;
+            // Responses nobody waits for any more are dropped
+            if message.get("method").is_some() && message.get("id").is_none() {
+                return serde_json::from_value(message).ok();
+            }
+        }
+    }
+
+    async fn next_message(&mut self, deadline: Instant) -> Option<Value> {
+        loop {
+            let message = tokio::time::timeout_at(deadline, self.stream.next())
+                .await
+                .ok()?
+                .expect("MCP connection closed")
+                .expect("MCP connection failed");
+            if let Message::Text(text) = message {
+                return Some(serde_json::from_str(&text).expect("MCP message is JSON"));
+            }
+        }
+    }
+
+    async fn send(&mut self, message: Value) {
+        self.stream
+            .send(Message::Text(message.to_string()))
+            .await
+            .expect("send to the MCP server");
+    }
+}
+
+/// Claude's end of the MCP HTTP+SSE transport
+pub struct SseClient {
+    port: u16,
+    token: String,
+    stream: BufReader<TcpStream>,
+    /// Where messages are POSTed, as announced by the endpoint event
+    endpoint: String,
+    next_id: i64,
+}
+
+impl SseClient {
+    /// Where this session's messages are POSTed
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// POST a message to this session, returning the status line of the answer
+    pub async fn post(&self, message: &Value) -> String {
+        self.post_to(&self.endpoint, message).await
+    }
+
+    /// POST a message to `endpoint`, returning the status line of the answer
+    pub async fn post_to(&self, endpoint: &str, message: &Value) -> String {
+        let body = message.to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}: {}\r\n\r\n{}",
+            endpoint,
+            self.port,
+            body.len(),
+            AUTH_HEADER,
+            self.token,
+            body
+        );
+        let mut stream = BufReader::new(
+            TcpStream::connect(("127.0.0.1", self.port))
+                .await
+                .expect("connect to the MCP server"),
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .expect("post a message");
+        let mut status = String::new();
+        stream
+            .read_line(&mut status)
+            .await
+            .expect("read the status line");
+        status.trim_end().to_string()
+    }
+
+    /// Send a request and return its result, which arrives on the event stream
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let status = self
+            .post(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await;
+        assert!(status.contains("202"), "unexpected status: {}", status);
+
+        loop {
+            let message = self
+                .message()
+                .await
+                .unwrap_or_else(|| panic!("no response to {}", method));
+            if message.get("id") == Some(&json!(id)) {
+                return message["result"].clone();
+            }
+        }
+    }
+
+    /// Wait for the notification `method`, skipping other messages
+    pub async fn notification(&mut self, method: &str) -> JsonRpcNotification {
+        loop {
+            let message = self
+                .message()
+                .await
+                .unwrap_or_else(|| panic!("no {} notification", method));
+            if message["method"] == method {
+                return serde_json::from_value(message).expect("notification");
+            }
+        }
+    }
+
+    /// The next message event, or None on timeout
+    async fn message(&mut self) -> Option<Value> {
+        loop {
+            let (name, data) = self.event().await?;
+            if name == "message" {
+                return Some(serde_json::from_str(&data).expect("message event is JSON"));
+            }
+        }
+    }
+
+    /// The next event as its name and data, skipping comments; None on timeout
+    async fn event(&mut self) -> Option<(String, String)> {
+        let (mut name, mut data) = (String::new(), Vec::new());
+        loop {
+            let line = tokio::time::timeout(TIMEOUT, self.line()).await.ok()?;
+            if line.is_empty() {
+                if !data.is_empty() {
+                    return Some((name, data.join("\n")));
+                }
+            } else if let Some(value) = line.strip_prefix("event: ") {
+                name = value.to_string();
+            } else if let Some(value) = line.strip_prefix("data: ") {
+                data.push(value.to_string());
+            }
+        }
+    }
+
+    async fn line(&mut self) -> String {
+        let mut line = String::new();
+        let read = self
+            .stream
+            .read_line(&mut line)
+            .await
+            .expect("read the event stream");
+        assert!(read > 0, "event stream closed");
+        line.trim_end().to_string()
+    }
+}
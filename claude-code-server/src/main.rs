@@ -1,14 +1,8 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use claude_code_server_core::{logging, LanguageServer, McpTransport, ServerBuilder, Shutdown};
 use std::path::PathBuf;
-use tracing::{error, info};
-
-mod lsp;
-mod mcp;
-mod websocket;
-
-use lsp::{run_lsp_server, run_lsp_server_with_notifications};
-use websocket::{run_websocket_server, run_websocket_server_with_notifications};
+use tracing::info;
 
 #[derive(Parser)]
 #[command(name = "claude-code-server")]
@@ -39,15 +33,52 @@ enum Mode {
         /// WebSocket server port (default: 59791)
         #[arg(long, short)]
         port: Option<u16>,
+        /// MCP transport advertised to the Claude CLI: ws or sse
+        #[arg(long, default_value = "ws")]
+        transport: McpTransport,
+        /// Serve MCP on a Unix socket in ~/.claude/ide rather than on the port
+        #[arg(long)]
+        socket: bool,
     },
     /// Run both LSP and WebSocket servers
     Hybrid {
         /// WebSocket server port (default: 59791)
         #[arg(long, short)]
         port: Option<u16>,
+        /// MCP transport advertised to the Claude CLI: ws or sse
+        #[arg(long, default_value = "ws")]
+        transport: McpTransport,
+        /// Serve MCP on a Unix socket in ~/.claude/ide rather than on the port
+        #[arg(long)]
+        socket: bool,
         /// Worktree root path
         #[arg(long)]
         worktree: Option<PathBuf>,
+        /// Launch the claude CLI connected to this server and restart it if it exits
+        #[arg(long)]
+        launch_claude: bool,
+    },
+    /// Stand between Zed and the project's language server, passing its messages
+    /// through while serving what they show to Claude over WebSocket
+    Proxy {
+        /// WebSocket server port (default: 59791)
+        #[arg(long, short)]
+        port: Option<u16>,
+        /// MCP transport advertised to the Claude CLI: ws or sse
+        #[arg(long, default_value = "ws")]
+        transport: McpTransport,
+        /// Serve MCP on a Unix socket in ~/.claude/ide rather than on the port
+        #[arg(long)]
+        socket: bool,
+        /// Worktree root path
+        #[arg(long)]
+        worktree: Option<PathBuf>,
+        /// Launch the claude CLI connected to this server and restart it if it exits
+        #[arg(long)]
+        launch_claude: bool,
+        /// The language server and its arguments, after --
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
     },
 }
 
@@ -70,17 +101,19 @@ async fn main() -> Result<()> {
         }
     };
 
-    let subscriber = tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(true)
-        .with_target(false)
-        .with_writer(std::io::stderr) // Force all logs to stderr for LSP compatibility
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+    // Per-component log files go under the worktree, when there is one
+    let log_workspace = match &cli.mode {
+        Some(Mode::Lsp { worktree })
+        | Some(Mode::Hybrid { worktree, .. })
+        | Some(Mode::Proxy { worktree, .. }) => cli.worktree.clone().or_else(|| worktree.clone()),
+        _ => cli.worktree.clone(),
+    };
+    logging::init(log_level, log_workspace.as_deref())?;
 
     info!("Logging initialized at level: {:?}", log_level);
+    if let Some(log_dir) = logging::log_dir() {
+        info!("Writing log files to {}", log_dir.display());
+    }
 
     info!("Claude Code Server starting...");
 
@@ -89,10 +122,39 @@ async fn main() -> Result<()> {
             let worktree_path = cli.worktree.or(worktree);
             run_lsp_server(worktree_path).await
         }
-        Some(Mode::Websocket { port }) => run_websocket_server(port).await,
-        Some(Mode::Hybrid { port, worktree }) => {
+        Some(Mode::Websocket {
+            port,
+            transport,
+            socket,
+        }) => run_websocket_server(port, transport, socket).await,
+        Some(Mode::Hybrid {
+            port,
+            transport,
+            socket,
+            worktree,
+            launch_claude,
+        }) => {
             let worktree_path = cli.worktree.or(worktree);
-            run_hybrid_server(port, worktree_path).await
+            run_hybrid_server(port, transport, socket, worktree_path, launch_claude).await
+        }
+        Some(Mode::Proxy {
+            port,
+            transport,
+            socket,
+            worktree,
+            launch_claude,
+            command,
+        }) => {
+            let worktree_path = cli.worktree.or(worktree);
+            run_proxy_server(
+                port,
+                transport,
+                socket,
+                worktree_path,
+                launch_claude,
+                command,
+            )
+            .await
         }
         None => {
             // Default mode: try to detect what we should run based on arguments
@@ -101,55 +163,86 @@ async fn main() -> Result<()> {
                 run_lsp_server(cli.worktree).await
             } else {
                 info!("No mode specified, running in hybrid mode...");
-                run_hybrid_server(None, cli.worktree).await
+                run_hybrid_server(None, McpTransport::default(), false, cli.worktree, false).await
             }
         }
     }
 }
 
-async fn run_hybrid_server(port: Option<u16>, worktree: Option<PathBuf>) -> Result<()> {
-    info!("Starting hybrid server (LSP + WebSocket)");
-    if let Some(path) = &worktree {
-        info!("Worktree path: {}", path.display());
+fn server(worktree: Option<PathBuf>, port: Option<u16>) -> ServerBuilder {
+    let mut builder = ServerBuilder::new();
+    if let Some(worktree) = worktree {
+        builder = builder.with_worktree(worktree);
     }
-
-    // Create notification channel for LSP -> WebSocket communication
-    let (notification_sender, notification_receiver) = tokio::sync::broadcast::channel(100);
-    let notification_sender = std::sync::Arc::new(notification_sender);
-
-    // Create command channel for WebSocket -> LSP communication (bidirectional!)
-    let (command_sender, command_receiver) = tokio::sync::mpsc::channel(100);
-
-    // In hybrid mode, we run both servers with notification bridge
-    let websocket_handle = tokio::spawn(run_websocket_server_with_notifications(
-        port,
-        worktree.clone(),
-        Some(notification_receiver),
-        Some(command_sender),
-    ));
-    let lsp_handle = tokio::spawn(run_lsp_server_with_notifications(
-        worktree,
-        Some(notification_sender),
-        Some(command_receiver),
-    ));
-
-    // Wait for either to complete (or fail)
-    tokio::select! {
-        result = websocket_handle => {
-            match result {
-                Ok(Ok(())) => info!("WebSocket server completed"),
-                Ok(Err(e)) => error!("WebSocket server error: {}", e),
-                Err(e) => error!("WebSocket server task panicked: {}", e),
-            }
-        }
-        result = lsp_handle => {
-            match result {
-                Ok(Ok(())) => info!("LSP server completed"),
-                Ok(Err(e)) => error!("LSP server error: {}", e),
-                Err(e) => error!("LSP server task panicked: {}", e),
-            }
-        }
+    if let Some(port) = port {
+        builder = builder.with_mcp_port(port);
     }
+    builder
+}
+
+async fn run_lsp_server(worktree: Option<PathBuf>) -> Result<()> {
+    server(worktree, None).run_lsp().await
+}
+
+async fn run_websocket_server(
+    port: Option<u16>,
+    transport: McpTransport,
+    socket: bool,
+) -> Result<()> {
+    let shutdown = Shutdown::default();
+    shutdown.trigger_on_signal();
+    server(None, port)
+        .with_mcp_transport(transport)
+        .with_mcp_socket(socket)
+        .with_shutdown(shutdown)
+        .run_mcp()
+        .await
+}
+
+async fn run_hybrid_server(
+    port: Option<u16>,
+    transport: McpTransport,
+    socket: bool,
+    worktree: Option<PathBuf>,
+    launch_claude: bool,
+) -> Result<()> {
+    // Triggered by the LSP shutdown request, the editor closing stdin or a signal
+    let shutdown = Shutdown::default();
+    shutdown.trigger_on_signal();
+
+    server(worktree, port)
+        .with_mcp_transport(transport)
+        .with_mcp_socket(socket)
+        .with_launch_claude(launch_claude)
+        .with_shutdown(shutdown)
+        .run_hybrid()
+        .await?;
+
+    // Blocking tasks such as the stdin reader would keep the runtime from stopping
+    std::process::exit(0)
+}
 
-    Ok(())
-}
\ No newline at end of file
+async fn run_proxy_server(
+    port: Option<u16>,
+    transport: McpTransport,
+    socket: bool,
+    worktree: Option<PathBuf>,
+    launch_claude: bool,
+    command: Vec<String>,
+) -> Result<()> {
+    // Triggered by the editor closing stdin, the language server exiting or a signal
+    let shutdown = Shutdown::default();
+    shutdown.trigger_on_signal();
+
+    server(worktree, port)
+        .with_mcp_transport(transport)
+        .with_mcp_socket(socket)
+        .with_launch_claude(launch_claude)
+        .with_language_server(LanguageServer::Command(command))
+        .with_shutdown(shutdown)
+        .run_proxy()
+        .await?;
+
+    // Blocking tasks such as the stdin reader would keep the runtime from stopping
+    std::process::exit(0)
+}